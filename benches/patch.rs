@@ -0,0 +1,35 @@
+//! Benchmarks of `patch_lua` on a cart that uses the Pico-8 shorthands and on
+//! one written in plain Lua, where every rewrite is skipped.
+use criterion::{Criterion, criterion_group, criterion_main};
+use pico8_to_lua::patch_lua;
+use std::hint::black_box;
+
+const PICO8: &str = "function _update()
+  if (btnp(❎)) score += 1
+  x += 0b10 -- move
+  if (x != y) ?\"hit\"
+  while (t < 3) t += 1
+end
+";
+
+const PLAIN: &str = "function _update()
+  if btnp(5) then score = score + 1 end
+  x = x + 2 -- move
+  if x ~= y then print(\"hit\") end
+  while t < 3 do t = t + 1 end
+end
+";
+
+fn patch(c: &mut Criterion) {
+    let pico8 = PICO8.repeat(500);
+    let plain = PLAIN.repeat(500);
+    c.bench_function("patch pico-8 lua", |b| {
+        b.iter(|| patch_lua(black_box(pico8.as_str())))
+    });
+    c.bench_function("patch plain lua", |b| {
+        b.iter(|| patch_lua(black_box(plain.as_str())))
+    });
+}
+
+criterion_group!(benches, patch);
+criterion_main!(benches);
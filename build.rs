@@ -0,0 +1,5 @@
+fn main() {
+    // Node.js modules are linked against the symbols of the running node.
+    #[cfg(feature = "node")]
+    napi_build::setup();
+}
@@ -0,0 +1,31 @@
+/* The C interface of pico8-to-lua, built with the "cdylib" feature. */
+#ifndef PICO8_TO_LUA_H
+#define PICO8_TO_LUA_H
+
+#include <stddef.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Convert len bytes of Pico-8 Lua to plain Lua. Returns a NUL-terminated
+   string to free with pico8_to_lua_free and stores its length in out_len if
+   it is not NULL, or returns NULL on error. */
+char *pico8_to_lua_patch(const char *code, size_t len, size_t *out_len);
+
+/* Convert len bytes of a ".p8" cart, returning the converted cart, or only
+   its Lua if lua_only is not 0, like pico8_to_lua_patch. */
+char *pico8_to_lua_convert_cart(const char *cart, size_t len, int lua_only, size_t *out_len);
+
+/* The error of the last call on this thread that returned NULL, or NULL.
+   Valid until the next call. */
+const char *pico8_to_lua_last_error(void);
+
+/* Free a string returned by pico8_to_lua_patch or pico8_to_lua_convert_cart. */
+void pico8_to_lua_free(char *s);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,80 @@
+//! Converting Pico-8 carts to plain Lua at compile time.
+//!
+//! [include_pico8!] reads a ".p8" cart or Pico-8 Lua file, resolves its
+//! includes, converts its code with
+//! [pico8-to-lua](https://docs.rs/pico8-to-lua), and expands to the converted
+//! Lua as a string literal, so a game can bake its carts into the binary:
+//!
+//! ```ignore
+//! const GAME_LUA: &str = pico8_to_lua_macros::include_pico8!("assets/game.p8");
+//! ```
+use std::{fs, path::PathBuf};
+
+use pico8_to_lua::{
+    Cart, FsResolver, Options, batch::Converter, cart::CART_HEADER, split_tab, try_patch_includes,
+};
+use proc_macro::{Literal, TokenStream};
+use syn::{LitStr, parse_macro_input};
+
+/// Expand to an error at the macro with `message`.
+fn compile_error(message: &str) -> TokenStream {
+    format!("compile_error!({})", Literal::string(message))
+        .parse()
+        .expect("a compile_error! call is valid tokens")
+}
+
+/// Convert the cart or Pico-8 Lua file at a path relative to the crate's
+/// manifest and expand to its Lua as a `&'static str`.
+///
+/// Includes are resolved next to the cart. The crate is rebuilt when the cart
+/// or any file it includes changes.
+#[proc_macro]
+pub fn include_pico8(input: TokenStream) -> TokenStream {
+    let path = parse_macro_input!(input as LitStr).value();
+    let root = std::env::var_os("CARGO_MANIFEST_DIR").map_or(PathBuf::new(), PathBuf::from);
+    let cart = root.join(&path);
+    let text = match fs::read_to_string(&cart) {
+        Ok(text) => text,
+        Err(e) => return compile_error(&format!("cannot read {}: {e}", cart.display())),
+    };
+    let mut resolver = FsResolver::for_cart(&cart, Vec::<PathBuf>::new());
+    // Every file read, so that changing any of them rebuilds the crate.
+    let mut read = vec![cart.clone()];
+    let lua = if text.starts_with(CART_HEADER) {
+        match Cart::parse(&text) {
+            Ok(parsed) => parsed.lua().unwrap_or("").to_string(),
+            Err(e) => return compile_error(&format!("cannot parse {path}: {e}")),
+        }
+    } else {
+        text
+    };
+    let lua = try_patch_includes(lua.as_str(), |include| {
+        read.extend(resolver.find(split_tab(include).0));
+        resolver.resolve(include)
+    });
+    let lua = match lua {
+        Ok(lua) => lua,
+        Err(e) => return compile_error(&format!("{path}: {e}")),
+    };
+    let options = Options {
+        lua_only: true,
+        ..Options::default()
+    };
+    let converted = match Converter::new(options).convert(&lua) {
+        Ok(converted) => converted,
+        Err(e) => return compile_error(&format!("cannot convert {path}: {e}")),
+    };
+    let mut out = String::from("{ ");
+    for file in read {
+        if let Some(file) = file.to_str() {
+            out.push_str(&format!(
+                "const _: &[u8] = include_bytes!({}); ",
+                Literal::string(file)
+            ));
+        }
+    }
+    out.push_str(&Literal::string(&converted).to_string());
+    out.push_str(" }");
+    out.parse()
+        .expect("a block with a string literal is valid tokens")
+}
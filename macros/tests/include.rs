@@ -0,0 +1,8 @@
+use pico8_to_lua_macros::include_pico8;
+
+const GAME_LUA: &str = include_pico8!("tests/game.p8");
+
+#[test]
+fn test_include_pico8() {
+    assert_eq!(GAME_LUA, "function inc(n) return n + 1 end\n\nx = x + (inc(1))\n");
+}
@@ -0,0 +1 @@
+function inc(n) return n + 1 end
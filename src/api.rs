@@ -0,0 +1,157 @@
+//! The names Pico-8 defines for carts.
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, BTreeSet},
+};
+
+use crate::{
+    lexer::{Token, TokenKind, tokenize},
+    span::{TextEdit, apply_edits},
+};
+
+/// The functions Pico-8 calls if a cart defines them.
+pub const CALLBACKS: &[&str] = &["_init", "_update", "_update60", "_draw"];
+
+/// The functions of Pico-8's API.
+pub const FUNCTIONS: &[&str] = &[
+    // System
+    "load", "save", "folder", "ls", "run", "stop", "resume", "assert", "reboot", "reset", "info",
+    "flip", "printh", "time", "t", "stat", "extcmd",
+    // Program structure
+    "cocreate", "coresume", "costatus", "yield", "setmetatable", "getmetatable", "rawequal",
+    "rawget", "rawlen", "rawset", "select", "type", "tostr", "tonum", "chr", "ord", "sub", "split",
+    "pairs", "ipairs", "next", "unpack", "pack",
+    // Graphics
+    "camera", "circ", "circfill", "oval", "ovalfill", "clip", "cls", "color", "cursor", "fget",
+    "fillp", "fset", "line", "pal", "palt", "pget", "print", "pset", "rect", "rectfill", "sget",
+    "spr", "sset", "sspr", "tline",
+    // Tables
+    "add", "all", "count", "del", "deli", "foreach",
+    // Input
+    "btn", "btnp",
+    // Sound
+    "sfx", "music",
+    // Map
+    "map", "mget", "mset", "mapdraw",
+    // Memory
+    "cstore", "memcpy", "memset", "peek", "peek2", "peek4", "poke", "poke2", "poke4", "reload",
+    "serial",
+    // Math
+    "abs", "atan2", "band", "bnot", "bor", "bxor", "ceil", "cos", "flr", "lshr", "max", "mid",
+    "min", "rnd", "rotl", "rotr", "sgn", "shl", "shr", "sin", "sqrt", "srand",
+    // Cartridge data
+    "cartdata", "dget", "dset",
+    // GPIO
+    "menuitem",
+];
+
+/// The functions of Pico-8's API that plain Lua has as globals too and that
+/// work the same in both.
+pub const LUA_GLOBALS: &[&str] = &[
+    "assert", "getmetatable", "ipairs", "next", "pairs", "rawequal", "rawget", "rawlen", "rawset",
+    "select", "setmetatable", "type",
+];
+
+/// Return true if `name` is defined by Pico-8, either as a function or as a
+/// callback.
+pub fn is_api(name: &str) -> bool {
+    FUNCTIONS.contains(&name) || CALLBACKS.contains(&name)
+}
+
+/// Count the calls of each function of Pico-8's API in `lua`, e.g. to know
+/// which functions a port must provide.
+///
+/// A function the cart defines itself, like `function spr() end` or
+/// `local add`, is not Pico-8's and is not counted. Methods and fields like
+/// `obj:spr()` or `t.add()` are not counted either.
+///
+/// ```
+/// use pico8_to_lua::api::calls;
+/// let calls = calls("cls() spr(1, x, y) spr(2, x, y) print \"hi\" t.add(1)");
+/// assert_eq!(calls.into_iter().collect::<Vec<_>>(), vec![("cls", 1), ("print", 1), ("spr", 2)]);
+/// ```
+pub fn calls(lua: &str) -> BTreeMap<&'static str, usize> {
+    let tokens: Vec<Token> = tokenize(lua)
+        .filter(|t| t.kind != TokenKind::Comment)
+        .collect();
+    let mut counts = BTreeMap::new();
+    for (name, _) in api_calls(&tokens) {
+        *counts.entry(name).or_insert(0) += 1;
+    }
+    counts
+}
+
+/// Rewrite the calls to Pico-8's API in `lua` as calls to the functions of
+/// the table `namespace`, e.g. `spr(1, x, y)` as `pico8.spr(1, x, y)`, so
+/// the API need not be global. The calls counted by [calls] are rewritten
+/// except for those in [LUA_GLOBALS].
+///
+/// ```
+/// use pico8_to_lua::api::namespace_calls;
+/// assert_eq!(
+///     namespace_calls("cls() for k in pairs(t) do spr(k, 0, 0) end", "pico8"),
+///     "pico8.cls() for k in pairs(t) do pico8.spr(k, 0, 0) end"
+/// );
+/// ```
+pub fn namespace_calls<'h>(lua: impl Into<Cow<'h, str>>, namespace: &str) -> Cow<'h, str> {
+    let lua = lua.into();
+    let tokens: Vec<Token> = tokenize(&lua)
+        .filter(|t| t.kind != TokenKind::Comment)
+        .collect();
+    let edits: Vec<TextEdit> = api_calls(&tokens)
+        .filter(|(name, _)| !LUA_GLOBALS.contains(name))
+        .map(|(name, i)| TextEdit::new(tokens[i].span, format!("{namespace}.{name}")))
+        .collect();
+    if edits.is_empty() {
+        return lua;
+    }
+    Cow::Owned(apply_edits(&lua, &edits))
+}
+
+/// Return each call to Pico-8's API in `tokens`, without comments, with the
+/// index of the token of the function's name.
+pub(crate) fn api_calls<'t>(
+    tokens: &'t [Token],
+) -> impl Iterator<Item = (&'static str, usize)> + 't {
+    let mut defined = BTreeSet::new();
+    for pair in tokens.windows(2) {
+        if (pair[0].is("function") || pair[0].is("local")) && pair[1].kind == TokenKind::Name {
+            defined.insert(pair[1].text);
+        }
+    }
+    tokens.iter().enumerate().filter_map(move |(i, token)| {
+        if token.kind != TokenKind::Name || defined.contains(token.text) {
+            return None;
+        }
+        let name = *FUNCTIONS.iter().find(|f| **f == token.text)?;
+        let field = i > 0 && (tokens[i - 1].is(".") || tokens[i - 1].is(":"));
+        let called = tokens
+            .get(i + 1)
+            .is_some_and(|t| t.is("(") || t.is("{") || t.kind == TokenKind::String);
+        (called && !field).then_some((name, i))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_calls() {
+        let lua = "function sfx(n) end\nlocal add = 1\nsfx(1) -- spr(1)\n\
+                   s = \"map()\"\nfoo{} map{} add(t, 1)\nobj:spr()";
+        let calls: Vec<_> = calls(lua).into_iter().collect();
+        assert_eq!(calls, vec![("map", 1)]);
+    }
+
+    #[test]
+    fn test_namespace_calls() {
+        let lua = "function sfx(n) end\nsfx(1) print\"hi\" map{} -- spr(1)\n\
+                   obj:spr() setmetatable(t, m)";
+        assert_eq!(
+            namespace_calls(lua, "api"),
+            "function sfx(n) end\nsfx(1) api.print\"hi\" api.map{} -- spr(1)\n\
+             obj:spr() setmetatable(t, m)"
+        );
+    }
+}
@@ -0,0 +1,133 @@
+//! Building a ".p8" cart from Lua files, the inverse of resolving includes.
+//!
+//! The code of a main Lua file is split at each of its "#include"
+//! statements, and each included file goes in a tab of its own between them,
+//! so the tabs run in the order the includes would. A "-->8" line in any of
+//! the files starts a new tab too, so the boundaries can be marked by hand.
+//! The data sections, like `__gfx__` and `__map__`, come from another cart.
+use crate::{
+    cart::{Cart, CartHeader, Section, join_tabs, tabs},
+    parse_includes,
+};
+
+/// The header of an assembled cart that has no data cart to take it from,
+/// [CartHeader::default].
+pub const DEFAULT_HEADER: &str = "pico-8 cartridge // http://www.pico-8.com\nversion 41\n";
+
+/// Return the tabs of the code of `main` with each of its includes, which
+/// `resolve` reads, in a tab of its own. Tabs with only whitespace are left
+/// out.
+///
+/// ```
+/// use pico8_to_lua::assemble::assemble_tabs;
+/// let tabs = assemble_tabs("x = 1\n#include player.lua\nupdate()\n", |path| {
+///     Ok::<_, ()>(format!("-- {path}\n"))
+/// });
+/// assert_eq!(tabs, Ok(vec!["x = 1\n".into(), "-- player.lua\n".into(), "update()\n".into()]));
+/// ```
+pub fn assemble_tabs<E>(
+    main: &str,
+    mut resolve: impl FnMut(&str) -> Result<String, E>,
+) -> Result<Vec<String>, E> {
+    fn push(code: &str, out: &mut Vec<String>) {
+        for tab in tabs(code).into_iter().filter(|tab| !tab.trim().is_empty()) {
+            let mut tab = tab.to_string();
+            if !tab.ends_with('\n') {
+                tab.push('\n');
+            }
+            out.push(tab);
+        }
+    }
+    let mut out = Vec::new();
+    let mut last = 0;
+    for include in parse_includes(main) {
+        push(&main[last..include.span.start], &mut out);
+        let path = match include.tab {
+            Some(tab) => format!("{}:{tab}", include.path),
+            None => include.path.to_string(),
+        };
+        push(&resolve(&path)?, &mut out);
+        // The line break after the statement goes with it.
+        let rest = &main[include.span.end..];
+        let rest = rest
+            .strip_prefix("\r\n")
+            .or_else(|| rest.strip_prefix('\n'))
+            .unwrap_or(rest);
+        last = main.len() - rest.len();
+    }
+    push(&main[last..], &mut out);
+    Ok(out)
+}
+
+/// Build a cart from `main` and the files it includes, which `resolve`
+/// reads, split into tabs by [assemble_tabs]. The header and every section
+/// but the Lua come from `data`, if given.
+///
+/// ```
+/// use pico8_to_lua::{Cart, assemble::assemble};
+/// let data = Cart::parse("pico-8 cartridge\nversion 41\n__lua__\nold()\n__gff__\n00\n").unwrap();
+/// let cart = assemble("#include a.lua\n#include b.lua\n", |path| Ok::<_, ()>(format!("-- {path}")), Some(&data));
+/// assert_eq!(cart.unwrap().to_string(),
+///            "pico-8 cartridge\nversion 41\n__lua__\n-- a.lua\n-->8\n-- b.lua\n__gff__\n00\n");
+/// ```
+pub fn assemble<E>(
+    main: &str,
+    resolve: impl FnMut(&str) -> Result<String, E>,
+    data: Option<&Cart>,
+) -> Result<Cart, E> {
+    assemble_with(main, resolve, data, &CartHeader::default())
+}
+
+/// Build a cart like [assemble] with `header` if there is no `data`, or with
+/// the lines of `header` that the header of `data` lacks. See
+/// [CartHeader::complete].
+pub fn assemble_with<E>(
+    main: &str,
+    resolve: impl FnMut(&str) -> Result<String, E>,
+    data: Option<&Cart>,
+    header: &CartHeader,
+) -> Result<Cart, E> {
+    let lua = Section {
+        name: "lua".into(),
+        body: join_tabs(&assemble_tabs(main, resolve)?),
+    };
+    let mut cart = data.cloned().unwrap_or_default();
+    cart.header = header.complete(&cart.header);
+    cart.sections.retain(|section| section.name != "lua");
+    cart.sections.insert(0, lua);
+    Ok(cart)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_assemble_tabs() {
+        let main = "-- game\n-->8\n#include lib.p8:1 -- utils\n\n#include a.lua\nrun()";
+        let tabs = assemble_tabs(main, |path| match path {
+            "lib.p8:1" => Ok("f = 1\n-->8\ng = 2\n".to_string()),
+            "a.lua" => Ok("  \n".to_string()),
+            _ => Err(path.to_string()),
+        });
+        assert_eq!(
+            tabs,
+            Ok(vec![
+                "-- game\n".into(),
+                "f = 1\n".into(),
+                "g = 2\n".into(),
+                "run()\n".into()
+            ])
+        );
+        assert_eq!(
+            assemble_tabs("#include b.lua", |path| Err::<String, _>(path.to_string())),
+            Err("b.lua".into())
+        );
+        let cart = assemble("x = 1", |_| Ok::<_, ()>(String::new()), None).unwrap();
+        assert_eq!(
+            cart.to_string(),
+            format!("{DEFAULT_HEADER}__lua__\nx = 1\n")
+        );
+        assert_eq!(CartHeader::default().to_string(), DEFAULT_HEADER);
+    }
+}
@@ -0,0 +1,1116 @@
+//! A parser for Pico-8 Lua and a conversion backend built on it.
+//!
+//! [patch_lua](crate::patch_lua) rewrites code with regular expressions,
+//! which is fast but best-effort. [patch_lua_ast] parses the whole program
+//! first, so every rewrite is applied to exactly the construct it is meant
+//! for, and code that does not parse is reported instead of mangled. Only the
+//! Pico-8 constructs are rewritten; everything else, including comments and
+//! formatting, is left as it is.
+//!
+//! The parser accepts Lua 5.2 plus Pico-8's additions: `!=`, `//` comments,
+//! compound assignment, shorthand `if` and `while`, `?` print, binary
+//! literals, glyph identifiers, and the bitwise, integer division, and peek
+//! operators.
+use std::{error::Error, fmt};
+
+use crate::{
+    binary_to_hex, glyph,
+    lexer::{Token, TokenKind, tokenize},
+    span::{Span, TextEdit, apply_edits},
+};
+
+/// An error in the syntax of the code.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    /// What went wrong.
+    pub message: String,
+    /// Where it went wrong.
+    pub span: Span,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at {}", self.message, self.span)
+    }
+}
+
+impl Error for ParseError {}
+
+/// A sequence of statements.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Block {
+    /// The statements in order.
+    pub stats: Vec<Stat>,
+    /// Where the block is.
+    pub span: Span,
+}
+
+/// A statement and where it is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stat {
+    /// The statement.
+    pub kind: StatKind,
+    /// Where the statement is.
+    pub span: Span,
+}
+
+/// The kinds of statement.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StatKind {
+    /// `a, b = 1, 2`
+    Assign {
+        /// The variables assigned to.
+        targets: Vec<Expr>,
+        /// The values assigned.
+        values: Vec<Expr>,
+    },
+    /// `a += 1`
+    CompoundAssign {
+        /// The variable assigned to.
+        target: Expr,
+        /// The operator without "=", e.g. "+".
+        op: String,
+        /// The right hand side.
+        value: Expr,
+    },
+    /// A function or method call.
+    Call(Expr),
+    /// `local a, b = 1, 2`
+    Local {
+        /// The names declared.
+        names: Vec<String>,
+        /// The values assigned, if any.
+        values: Vec<Expr>,
+    },
+    /// `local function f() end`
+    LocalFunction {
+        /// The name of the function.
+        name: String,
+        /// The function.
+        func: Function,
+    },
+    /// `function a.b:c() end`
+    Function {
+        /// The dotted path of the name, e.g. `["a", "b"]`.
+        path: Vec<String>,
+        /// The method name after ":", if any.
+        method: Option<String>,
+        /// The function.
+        func: Function,
+    },
+    /// `do ... end`
+    Do(Block),
+    /// `while cond do ... end` or `while (cond) ...`
+    While {
+        /// The condition.
+        cond: Expr,
+        /// The body.
+        body: Block,
+        /// Whether this is Pico-8's one line form.
+        shorthand: bool,
+    },
+    /// `repeat ... until cond`
+    Repeat {
+        /// The body.
+        body: Block,
+        /// The condition.
+        cond: Expr,
+    },
+    /// `if cond then ... end` or `if (cond) ...`
+    If {
+        /// The condition and body of the `if` and each `elseif`.
+        branches: Vec<(Expr, Block)>,
+        /// The body of the `else`, if any.
+        otherwise: Option<Block>,
+        /// Whether this is Pico-8's one line form.
+        shorthand: bool,
+    },
+    /// `for i = 1, 10, 2 do ... end`
+    NumericFor {
+        /// The loop variable.
+        var: String,
+        /// The first value.
+        start: Expr,
+        /// The last value.
+        end: Expr,
+        /// The step, if any.
+        step: Option<Expr>,
+        /// The body.
+        body: Block,
+    },
+    /// `for k, v in pairs(t) do ... end`
+    GenericFor {
+        /// The loop variables.
+        vars: Vec<String>,
+        /// The expressions after `in`.
+        exprs: Vec<Expr>,
+        /// The body.
+        body: Block,
+    },
+    /// `return a, b`
+    Return(Vec<Expr>),
+    /// `break`
+    Break,
+    /// `goto label`
+    Goto(String),
+    /// `::label::`
+    Label(String),
+    /// Pico-8's `?a, b`
+    Print(Vec<Expr>),
+}
+
+/// The parameters and body of a function.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Function {
+    /// The named parameters.
+    pub params: Vec<String>,
+    /// Whether the parameters end with `...`.
+    pub vararg: bool,
+    /// The body.
+    pub body: Block,
+}
+
+/// An expression and where it is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expr {
+    /// The expression.
+    pub kind: ExprKind,
+    /// Where the expression is.
+    pub span: Span,
+}
+
+/// The kinds of expression.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExprKind {
+    /// `nil`
+    Nil,
+    /// `true`
+    True,
+    /// `false`
+    False,
+    /// `...`
+    Vararg,
+    /// A number as written.
+    Number(String),
+    /// A string literal as written, including its quotes.
+    String(String),
+    /// A variable.
+    Name(String),
+    /// `function() end`
+    Function(Box<Function>),
+    /// `{1, a = 2, [b] = 3}`
+    Table(Vec<Field>),
+    /// `a + b`
+    Binary {
+        /// The operator, e.g. "+" or "and".
+        op: String,
+        /// The left operand.
+        lhs: Box<Expr>,
+        /// The right operand.
+        rhs: Box<Expr>,
+    },
+    /// `-a`
+    Unary {
+        /// The operator, e.g. "-" or "not".
+        op: String,
+        /// The operand.
+        operand: Box<Expr>,
+    },
+    /// `(a)`
+    Paren(Box<Expr>),
+    /// `a[b]`
+    Index {
+        /// The table.
+        object: Box<Expr>,
+        /// The key.
+        key: Box<Expr>,
+    },
+    /// `a.b`
+    Field {
+        /// The table.
+        object: Box<Expr>,
+        /// The field name.
+        name: String,
+    },
+    /// `f(a, b)`
+    Call {
+        /// The function.
+        func: Box<Expr>,
+        /// The arguments.
+        args: Vec<Expr>,
+    },
+    /// `a:f(b)`
+    Method {
+        /// The object.
+        object: Box<Expr>,
+        /// The method name.
+        name: String,
+        /// The arguments.
+        args: Vec<Expr>,
+    },
+}
+
+/// A field of a table constructor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Field {
+    /// `{a}`
+    Positional(Expr),
+    /// `{a = b}`
+    Named(String, Expr),
+    /// `{[a] = b}`
+    Keyed(Expr, Expr),
+}
+
+/// Parse Pico-8 Lua.
+pub fn parse(lua: &str) -> Result<Block, ParseError> {
+    Parser::new(lua).chunk().map(|(block, _)| block)
+}
+
+/// Convert Pico-8 Lua to plain Lua by parsing it.
+///
+/// Unlike [patch_lua](crate::patch_lua) this fails on code that does not
+/// parse. Shorthand `if` and `while` keep their parentheses, e.g.
+/// `if (a) b()` becomes `if (a) then b() end`.
+///
+/// ```
+/// use pico8_to_lua::patch_lua_ast;
+/// assert_eq!(patch_lua_ast("if (a != b) x += y or 1").unwrap(),
+///            "if (a ~= b) then x = x + (y or 1) end");
+/// ```
+pub fn patch_lua_ast(lua: &str) -> Result<String, ParseError> {
+    let (_, edits) = Parser::new(lua).chunk()?;
+    Ok(apply_edits(lua, &edits))
+}
+
+// Lua's binary operator priorities, extended with Pico-8's operators, as
+// (left, right) pairs. Right associative operators have a lower right
+// priority.
+fn binary_priority(token: &Token) -> Option<(u8, u8)> {
+    if !matches!(token.kind, TokenKind::Punct | TokenKind::Keyword) {
+        return None;
+    }
+    Some(match token.text {
+        "or" => (1, 1),
+        "and" => (2, 2),
+        "<" | ">" | "<=" | ">=" | "~=" | "!=" | "==" => (3, 3),
+        "|" => (4, 4),
+        "~" | "^^" => (5, 5),
+        "&" => (6, 6),
+        "<<" | ">>" | ">>>" | "<<>" | ">><" => (7, 7),
+        ".." => (9, 8),
+        "+" | "-" => (10, 10),
+        "*" | "/" | "\\" | "%" => (11, 11),
+        "^" => (14, 13),
+        _ => return None,
+    })
+}
+
+const UNARY_PRIORITY: u8 = 12;
+
+fn is_unary(token: &Token) -> bool {
+    matches!(token.kind, TokenKind::Punct | TokenKind::Keyword)
+        && matches!(token.text, "not" | "-" | "#" | "~" | "@" | "%" | "$")
+}
+
+const COMPOUND_OPS: &[&str] = &[
+    "+=", "-=", "*=", "/=", "\\=", "%=", "^=", "..=", "|=", "&=", "^^=", "<<=", ">>=", ">>>=",
+    "<<>=", ">><=",
+];
+
+struct Parser<'a> {
+    src: &'a str,
+    tokens: Vec<Token<'a>>,
+    pos: usize,
+    // Tokens that start at or after this offset are hidden, which ends the
+    // body of a shorthand `if` or `while` at the end of its line.
+    limit: usize,
+    edits: Vec<TextEdit>,
+}
+
+type Result<T, E = ParseError> = std::result::Result<T, E>;
+
+impl<'a> Parser<'a> {
+    fn new(src: &'a str) -> Self {
+        let mut edits = Vec::new();
+        let mut tokens = Vec::new();
+        for token in tokenize(src) {
+            if token.kind == TokenKind::Comment {
+                if token.text.starts_with("//") {
+                    let start = token.span.start;
+                    edits.push(TextEdit::new(Span::new(start, start + 2), "--"));
+                }
+            } else {
+                tokens.push(token);
+            }
+        }
+        Parser {
+            src,
+            tokens,
+            pos: 0,
+            limit: src.len(),
+            edits,
+        }
+    }
+
+    fn chunk(mut self) -> Result<(Block, Vec<TextEdit>)> {
+        let block = self.block()?;
+        if let Some(t) = self.peek() {
+            return Err(self.error(format!("unexpected {:?}", t.text), t.span));
+        }
+        Ok((block, self.edits))
+    }
+
+    fn error(&self, message: String, span: Span) -> ParseError {
+        ParseError { message, span }
+    }
+
+    fn peek_at(&self, i: usize) -> Option<Token<'a>> {
+        self.tokens
+            .get(i)
+            .filter(|t| t.span.start < self.limit)
+            .copied()
+    }
+
+    fn peek(&self) -> Option<Token<'a>> {
+        self.peek_at(self.pos)
+    }
+
+    fn check(&self, s: &str) -> bool {
+        self.peek().is_some_and(|t| t.is(s))
+    }
+
+    fn bump(&mut self) -> Token<'a> {
+        let token = self.peek().expect("bump past the end");
+        self.pos += 1;
+        token
+    }
+
+    fn accept(&mut self, s: &str) -> bool {
+        let found = self.check(s);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn here(&self) -> Span {
+        match self.peek() {
+            Some(t) => t.span,
+            None => {
+                let end = self.prev_end().max(self.limit.min(self.src.len()));
+                Span::new(end, end)
+            }
+        }
+    }
+
+    fn prev_end(&self) -> usize {
+        self.pos
+            .checked_sub(1)
+            .map_or(0, |p| self.tokens[p].span.end)
+    }
+
+    fn expect(&mut self, s: &str) -> Result<Token<'a>> {
+        if self.check(s) {
+            Ok(self.bump())
+        } else {
+            Err(self.unexpected(&format!("{s:?}")))
+        }
+    }
+
+    fn unexpected(&self, wanted: &str) -> ParseError {
+        match self.peek() {
+            Some(t) => self.error(format!("expected {wanted} but found {:?}", t.text), t.span),
+            None => self.error(format!("expected {wanted}"), self.here()),
+        }
+    }
+
+    fn name(&mut self) -> Result<String> {
+        match self.peek() {
+            Some(t) if t.kind == TokenKind::Name => Ok(self.bump().text.to_string()),
+            _ => Err(self.unexpected("a name")),
+        }
+    }
+
+    fn insert(&mut self, at: usize, text: &str) {
+        self.edits.push(TextEdit::new(Span::new(at, at), text));
+    }
+
+    /// Return the source of `span` with the edits made inside it so far.
+    fn rendered(&self, span: Span) -> String {
+        let inner: Vec<TextEdit> = self
+            .edits
+            .iter()
+            .filter(|e| e.span.start >= span.start && e.span.end <= span.end)
+            .map(|e| TextEdit::new(e.span.shift(-(span.start as isize)), e.replacement.clone()))
+            .collect();
+        apply_edits(&self.src[span.range()], &inner)
+    }
+
+    fn block_ends(&self) -> bool {
+        match self.peek() {
+            None => true,
+            Some(t) => ["end", "else", "elseif", "until"].iter().any(|k| t.is(k)),
+        }
+    }
+
+    fn block(&mut self) -> Result<Block> {
+        let start = self.here().start;
+        let mut stats = Vec::new();
+        while !self.block_ends() {
+            if self.accept(";") {
+                continue;
+            }
+            let is_return = self.check("return");
+            stats.push(self.statement()?);
+            if is_return {
+                break;
+            }
+        }
+        let end = self.prev_end().max(start);
+        Ok(Block {
+            stats,
+            span: Span::new(start, end),
+        })
+    }
+
+    /// Return true if the `if` or `while` at the current position is Pico-8's
+    /// one line form: a parenthesized condition followed on the same line by
+    /// a statement.
+    fn is_shorthand(&self) -> bool {
+        let open = self.pos + 1;
+        if !self.peek_at(open).is_some_and(|t| t.is("(")) {
+            return false;
+        }
+        let mut depth = 0;
+        let mut close = None;
+        for (i, t) in self.tokens.iter().enumerate().skip(open) {
+            if t.is("(") {
+                depth += 1;
+            } else if t.is(")") {
+                depth -= 1;
+                if depth == 0 {
+                    close = Some(i);
+                    break;
+                }
+            }
+        }
+        let Some(close) = close else {
+            return false;
+        };
+        let Some(next) = self.peek_at(close + 1) else {
+            return false;
+        };
+        if self.src[self.tokens[close].span.end..next.span.start].contains('\n') {
+            return false;
+        }
+        let is_while = self.tokens[self.pos].is("while");
+        match next.kind {
+            TokenKind::Name => true,
+            TokenKind::Keyword => {
+                matches!(
+                    next.text,
+                    "return"
+                        | "break"
+                        | "local"
+                        | "goto"
+                        | "if"
+                        | "for"
+                        | "while"
+                        | "repeat"
+                        | "function"
+                ) || (next.is("do") && !is_while)
+            }
+            TokenKind::Punct => next.is("?") || next.is("::"),
+            _ => false,
+        }
+    }
+
+    fn line_end(&self, at: usize) -> usize {
+        self.src[at..].find('\n').map_or(self.src.len(), |i| at + i)
+    }
+
+    /// Parse the rest of the line as the body of a shorthand `if` or `while`.
+    fn shorthand_body(&mut self, allow_else: bool) -> Result<(Block, Option<Block>)> {
+        let saved = self.limit;
+        self.limit = self.line_end(self.prev_end()).min(saved);
+        let body = self.block();
+        let otherwise = match body {
+            Ok(_) if allow_else && self.accept("else") => Some(self.block()),
+            _ => None,
+        };
+        self.limit = saved;
+        let body = body?;
+        let otherwise = otherwise.transpose()?;
+        Ok((body, otherwise))
+    }
+
+    fn statement(&mut self) -> Result<Stat> {
+        let first = self.peek().expect("statement at the end");
+        let start = first.span.start;
+        let kind = match first.kind {
+            TokenKind::Keyword => match first.text {
+                "break" => {
+                    self.bump();
+                    StatKind::Break
+                }
+                "goto" => {
+                    self.bump();
+                    StatKind::Goto(self.name()?)
+                }
+                "do" => {
+                    self.bump();
+                    let body = self.block()?;
+                    self.expect("end")?;
+                    StatKind::Do(body)
+                }
+                "while" => self.while_stat()?,
+                "repeat" => {
+                    self.bump();
+                    let body = self.block()?;
+                    self.expect("until")?;
+                    let cond = self.expr()?;
+                    StatKind::Repeat { body, cond }
+                }
+                "if" => self.if_stat()?,
+                "for" => self.for_stat()?,
+                "function" => self.function_stat()?,
+                "local" => self.local_stat()?,
+                "return" => {
+                    self.bump();
+                    let values = if self.block_ends() || self.check(";") {
+                        Vec::new()
+                    } else {
+                        self.expr_list()?
+                    };
+                    self.accept(";");
+                    StatKind::Return(values)
+                }
+                _ => self.expr_stat()?,
+            },
+            TokenKind::Punct if first.is("::") => {
+                self.bump();
+                let name = self.name()?;
+                self.expect("::")?;
+                StatKind::Label(name)
+            }
+            TokenKind::Punct if first.is("?") => self.print_stat()?,
+            _ => self.expr_stat()?,
+        };
+        Ok(Stat {
+            kind,
+            span: Span::new(start, self.prev_end()),
+        })
+    }
+
+    fn while_stat(&mut self) -> Result<StatKind> {
+        let shorthand = self.is_shorthand();
+        self.bump();
+        let cond = self.expr()?;
+        if shorthand {
+            self.insert(cond.span.end, " do");
+            let (body, _) = self.shorthand_body(false)?;
+            self.insert(self.prev_end(), " end");
+            return Ok(StatKind::While {
+                cond,
+                body,
+                shorthand,
+            });
+        }
+        self.expect("do")?;
+        let body = self.block()?;
+        self.expect("end")?;
+        Ok(StatKind::While {
+            cond,
+            body,
+            shorthand,
+        })
+    }
+
+    fn if_stat(&mut self) -> Result<StatKind> {
+        let shorthand = self.is_shorthand();
+        self.bump();
+        let cond = self.expr()?;
+        if shorthand {
+            self.insert(cond.span.end, " then");
+            let (body, otherwise) = self.shorthand_body(true)?;
+            self.insert(self.prev_end(), " end");
+            return Ok(StatKind::If {
+                branches: vec![(cond, body)],
+                otherwise,
+                shorthand,
+            });
+        }
+        self.expect("then")?;
+        let mut branches = vec![(cond, self.block()?)];
+        while self.accept("elseif") {
+            let cond = self.expr()?;
+            self.expect("then")?;
+            branches.push((cond, self.block()?));
+        }
+        let otherwise = if self.accept("else") {
+            Some(self.block()?)
+        } else {
+            None
+        };
+        self.expect("end")?;
+        Ok(StatKind::If {
+            branches,
+            otherwise,
+            shorthand,
+        })
+    }
+
+    fn for_stat(&mut self) -> Result<StatKind> {
+        self.bump();
+        let var = self.name()?;
+        if self.accept("=") {
+            let start = self.expr()?;
+            self.expect(",")?;
+            let end = self.expr()?;
+            let step = if self.accept(",") {
+                Some(self.expr()?)
+            } else {
+                None
+            };
+            self.expect("do")?;
+            let body = self.block()?;
+            self.expect("end")?;
+            return Ok(StatKind::NumericFor {
+                var,
+                start,
+                end,
+                step,
+                body,
+            });
+        }
+        let mut vars = vec![var];
+        while self.accept(",") {
+            vars.push(self.name()?);
+        }
+        self.expect("in")?;
+        let exprs = self.expr_list()?;
+        self.expect("do")?;
+        let body = self.block()?;
+        self.expect("end")?;
+        Ok(StatKind::GenericFor { vars, exprs, body })
+    }
+
+    fn function_stat(&mut self) -> Result<StatKind> {
+        self.bump();
+        let mut path = vec![self.name()?];
+        while self.accept(".") {
+            path.push(self.name()?);
+        }
+        let method = if self.accept(":") {
+            Some(self.name()?)
+        } else {
+            None
+        };
+        let func = self.function_body()?;
+        Ok(StatKind::Function { path, method, func })
+    }
+
+    fn local_stat(&mut self) -> Result<StatKind> {
+        self.bump();
+        if self.accept("function") {
+            let name = self.name()?;
+            let func = self.function_body()?;
+            return Ok(StatKind::LocalFunction { name, func });
+        }
+        let mut names = vec![self.name()?];
+        while self.accept(",") {
+            names.push(self.name()?);
+        }
+        let values = if self.accept("=") {
+            self.expr_list()?
+        } else {
+            Vec::new()
+        };
+        Ok(StatKind::Local { names, values })
+    }
+
+    fn print_stat(&mut self) -> Result<StatKind> {
+        let question = self.bump();
+        let saved = self.limit;
+        self.limit = self.line_end(question.span.end).min(saved);
+        let values = self.expr_list();
+        self.limit = saved;
+        let values = values?;
+        self.edits.push(TextEdit::new(question.span, "print("));
+        self.insert(self.prev_end(), ")");
+        Ok(StatKind::Print(values))
+    }
+
+    fn expr_stat(&mut self) -> Result<StatKind> {
+        let target = self.suffixed_expr()?;
+        if self.check("=") || self.check(",") {
+            let mut targets = vec![target];
+            while self.accept(",") {
+                targets.push(self.suffixed_expr()?);
+            }
+            self.expect("=")?;
+            for target in &targets {
+                self.check_assignable(target)?;
+            }
+            let values = self.expr_list()?;
+            return Ok(StatKind::Assign { targets, values });
+        }
+        if let Some(op) = self
+            .peek()
+            .filter(|t| t.kind == TokenKind::Punct && COMPOUND_OPS.contains(&t.text))
+        {
+            self.bump();
+            self.check_assignable(&target)?;
+            let value = self.expr()?;
+            let binary = &op.text[..op.text.len() - 1];
+            let lhs = self.rendered(target.span);
+            // Take the spaces after the operator too unless a comment is
+            // among them.
+            let gap = &self.src[op.span.end..value.span.start];
+            let end = if gap.trim().is_empty() {
+                value.span.start
+            } else {
+                op.span.end
+            };
+            self.edits.push(TextEdit::new(
+                Span::new(op.span.start, end),
+                format!("= {lhs} {binary} ("),
+            ));
+            self.insert(value.span.end, ")");
+            return Ok(StatKind::CompoundAssign {
+                target,
+                op: binary.to_string(),
+                value,
+            });
+        }
+        match target.kind {
+            ExprKind::Call { .. } | ExprKind::Method { .. } => Ok(StatKind::Call(target)),
+            _ => Err(self.unexpected("\"=\" or a call")),
+        }
+    }
+
+    fn check_assignable(&self, target: &Expr) -> Result<()> {
+        match target.kind {
+            ExprKind::Name(_) | ExprKind::Index { .. } | ExprKind::Field { .. } => Ok(()),
+            _ => Err(self.error("cannot assign to this expression".into(), target.span)),
+        }
+    }
+
+    fn function_body(&mut self) -> Result<Function> {
+        self.expect("(")?;
+        let mut params = Vec::new();
+        let mut vararg = false;
+        if !self.check(")") {
+            loop {
+                if self.accept("...") {
+                    vararg = true;
+                    break;
+                }
+                params.push(self.name()?);
+                if !self.accept(",") {
+                    break;
+                }
+            }
+        }
+        self.expect(")")?;
+        let body = self.block()?;
+        self.expect("end")?;
+        Ok(Function {
+            params,
+            vararg,
+            body,
+        })
+    }
+
+    fn expr_list(&mut self) -> Result<Vec<Expr>> {
+        let mut exprs = vec![self.expr()?];
+        while self.accept(",") {
+            exprs.push(self.expr()?);
+        }
+        Ok(exprs)
+    }
+
+    fn expr(&mut self) -> Result<Expr> {
+        self.sub_expr(0)
+    }
+
+    fn sub_expr(&mut self, limit: u8) -> Result<Expr> {
+        let mut lhs = match self.peek() {
+            Some(op) if is_unary(&op) => {
+                self.bump();
+                let operand = self.sub_expr(UNARY_PRIORITY)?;
+                Expr {
+                    span: Span::new(op.span.start, operand.span.end),
+                    kind: ExprKind::Unary {
+                        op: op.text.to_string(),
+                        operand: Box::new(operand),
+                    },
+                }
+            }
+            _ => self.simple_expr()?,
+        };
+        while let Some(op) = self.peek() {
+            let Some((left, right)) = binary_priority(&op) else {
+                break;
+            };
+            if left <= limit {
+                break;
+            }
+            self.bump();
+            if op.text == "!=" {
+                self.edits.push(TextEdit::new(op.span, "~="));
+            }
+            let rhs = self.sub_expr(right)?;
+            lhs = Expr {
+                span: Span::new(lhs.span.start, rhs.span.end),
+                kind: ExprKind::Binary {
+                    op: op.text.to_string(),
+                    lhs: Box::new(lhs),
+                    rhs: Box::new(rhs),
+                },
+            };
+        }
+        Ok(lhs)
+    }
+
+    fn simple_expr(&mut self) -> Result<Expr> {
+        let Some(token) = self.peek() else {
+            return Err(self.unexpected("an expression"));
+        };
+        let kind = match token.kind {
+            TokenKind::Number => {
+                let text = token.text;
+                let hex = text
+                    .strip_prefix("0b")
+                    .or_else(|| text.strip_prefix("0B"))
+                    .and_then(binary_to_hex);
+                if let Some(hex) = hex {
+                    self.edits.push(TextEdit::new(token.span, hex));
+                }
+                ExprKind::Number(text.to_string())
+            }
+            TokenKind::String => ExprKind::String(token.text.to_string()),
+            TokenKind::Keyword if token.is("nil") => ExprKind::Nil,
+            TokenKind::Keyword if token.is("true") => ExprKind::True,
+            TokenKind::Keyword if token.is("false") => ExprKind::False,
+            TokenKind::Punct if token.is("...") => ExprKind::Vararg,
+            TokenKind::Punct if token.is("{") => return self.table(),
+            TokenKind::Keyword if token.is("function") => {
+                self.bump();
+                let func = self.function_body()?;
+                return Ok(Expr {
+                    kind: ExprKind::Function(Box::new(func)),
+                    span: Span::new(token.span.start, self.prev_end()),
+                });
+            }
+            _ => return self.suffixed_expr(),
+        };
+        self.bump();
+        Ok(Expr {
+            kind,
+            span: token.span,
+        })
+    }
+
+    fn primary_expr(&mut self) -> Result<Expr> {
+        match self.peek() {
+            Some(t) if t.kind == TokenKind::Name => {
+                self.bump();
+                if let Some((_, value)) = glyph::button(t.text) {
+                    self.edits.push(TextEdit::new(t.span, value.to_string()));
+                }
+                Ok(Expr {
+                    kind: ExprKind::Name(t.text.to_string()),
+                    span: t.span,
+                })
+            }
+            Some(t) if t.is("(") => {
+                self.bump();
+                let inner = self.expr()?;
+                self.expect(")")?;
+                Ok(Expr {
+                    kind: ExprKind::Paren(Box::new(inner)),
+                    span: Span::new(t.span.start, self.prev_end()),
+                })
+            }
+            _ => Err(self.unexpected("an expression")),
+        }
+    }
+
+    fn suffixed_expr(&mut self) -> Result<Expr> {
+        let mut expr = self.primary_expr()?;
+        let start = expr.span.start;
+        loop {
+            let kind = match self.peek() {
+                Some(t) if t.is(".") => {
+                    self.bump();
+                    ExprKind::Field {
+                        object: Box::new(expr),
+                        name: self.name()?,
+                    }
+                }
+                Some(t) if t.is("[") => {
+                    self.bump();
+                    let key = self.expr()?;
+                    self.expect("]")?;
+                    ExprKind::Index {
+                        object: Box::new(expr),
+                        key: Box::new(key),
+                    }
+                }
+                Some(t) if t.is(":") => {
+                    self.bump();
+                    let name = self.name()?;
+                    ExprKind::Method {
+                        object: Box::new(expr),
+                        name,
+                        args: self.args()?,
+                    }
+                }
+                Some(t) if t.is("(") || t.is("{") || t.kind == TokenKind::String => {
+                    ExprKind::Call {
+                        func: Box::new(expr),
+                        args: self.args()?,
+                    }
+                }
+                _ => return Ok(expr),
+            };
+            expr = Expr {
+                kind,
+                span: Span::new(start, self.prev_end()),
+            };
+        }
+    }
+
+    fn args(&mut self) -> Result<Vec<Expr>> {
+        match self.peek() {
+            Some(t) if t.kind == TokenKind::String => Ok(vec![self.simple_expr()?]),
+            Some(t) if t.is("{") => Ok(vec![self.table()?]),
+            Some(t) if t.is("(") => {
+                self.bump();
+                let args = if self.check(")") {
+                    Vec::new()
+                } else {
+                    self.expr_list()?
+                };
+                self.expect(")")?;
+                Ok(args)
+            }
+            _ => Err(self.unexpected("arguments")),
+        }
+    }
+
+    fn table(&mut self) -> Result<Expr> {
+        let open = self.expect("{")?;
+        let mut fields = Vec::new();
+        while !self.check("}") {
+            let field = match self.peek() {
+                Some(t)
+                    if t.kind == TokenKind::Name
+                        && self.peek_at(self.pos + 1).is_some_and(|n| n.is("=")) =>
+                {
+                    self.bump();
+                    self.bump();
+                    Field::Named(t.text.to_string(), self.expr()?)
+                }
+                Some(t) if t.is("[") => {
+                    self.bump();
+                    let key = self.expr()?;
+                    self.expect("]")?;
+                    self.expect("=")?;
+                    Field::Keyed(key, self.expr()?)
+                }
+                _ => Field::Positional(self.expr()?),
+            };
+            fields.push(field);
+            if !self.accept(",") && !self.accept(";") {
+                break;
+            }
+        }
+        self.expect("}")?;
+        Ok(Expr {
+            kind: ExprKind::Table(fields),
+            span: Span::new(open.span.start, self.prev_end()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_patch(pico8: &str, lua: &str) {
+        assert_eq!(patch_lua_ast(pico8).unwrap(), lua);
+    }
+
+    #[test]
+    fn test_patch() {
+        assert_patch("x += 1", "x = x + (1)");
+        assert_patch("a.b[c] ..= \"!\" // hi", "a.b[c] = a.b[c] .. (\"!\") -- hi");
+        assert_patch("if (a != b) x = 0b101", "if (a ~= b) then x = 0x5 end");
+        assert_patch("?x, y\nz = 1", "print(x, y)\nz = 1");
+        assert_patch("if btn(⬅️) then end", "if btn(0) then end");
+        assert_patch(
+            "accum += f.delay or self.delay",
+            "accum = accum + (f.delay or self.delay)",
+        );
+    }
+
+    #[test]
+    fn test_shorthand() {
+        assert_patch(
+            "if (a) b() else c()\nd()",
+            "if (a) then b() else c() end\nd()",
+        );
+        assert_patch("while (i < 3) i += 1", "while (i < 3) do i = i + (1) end");
+        assert_patch("if (a) if (b) c()", "if (a) then if (b) then c() end end");
+        assert_patch(
+            "if (a) and\n  (b) then c() end",
+            "if (a) and\n  (b) then c() end",
+        );
+        assert_patch(
+            "if (a) then\n?\"x != y\"\nend",
+            "if (a) then\nprint(\"x != y\")\nend",
+        );
+    }
+
+    #[test]
+    fn test_compound_lhs_is_patched() {
+        assert_patch("t[0b1] += 1", "t[0x1] = t[0x1] + (1)");
+        assert_patch("x |= 1", "x = x | (1)");
+        assert_patch("s..=\"!\"", "s= s .. (\"!\")");
+    }
+
+    #[test]
+    fn test_parse() {
+        let block =
+            parse("local a, b = 1, {2, x = 3, [4] = 5}\nfunction o:m(...) return ... end").unwrap();
+        assert_eq!(block.stats.len(), 2);
+        assert!(matches!(&block.stats[0].kind, StatKind::Local { names, .. } if names.len() == 2));
+        assert!(matches!(
+            &block.stats[1].kind,
+            StatKind::Function { method: Some(m), func, .. } if m == "m" && func.vararg
+        ));
+    }
+
+    #[test]
+    fn test_precedence() {
+        let block = parse("x = a .. b .. c + d * -e ^ f").unwrap();
+        let StatKind::Assign { values, .. } = &block.stats[0].kind else {
+            panic!("not an assignment");
+        };
+        let ExprKind::Binary { op, rhs, .. } = &values[0].kind else {
+            panic!("not a binary expression");
+        };
+        assert_eq!(op, "..");
+        assert!(matches!(&rhs.kind, ExprKind::Binary { op, .. } if op == ".."));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(patch_lua_ast("if a then").is_err());
+        let error = patch_lua_ast("x = = 1").unwrap_err();
+        assert_eq!(error.span, Span::new(4, 5));
+        assert!(patch_lua_ast("f() = 1").is_err());
+    }
+}
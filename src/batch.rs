@@ -0,0 +1,797 @@
+//! Converting many carts.
+//!
+//! [convert_many] is the high-throughput entry point for servers and batch
+//! tools. Unlike the one-shot functions such as [patch_lua](crate::patch_lua),
+//! it converts lazily as its output is pulled, so a slow consumer naturally
+//! holds back the producer, and it sizes each output from the ones before it
+//! to avoid regrowing buffers.
+//!
+//! With the `rayon` feature, [par_convert_many] and [par_convert_files]
+//! convert on every core instead, for archives of thousands of carts.
+//! [convert_many_cached] and [par_convert_files_cached] skip the inputs a
+//! [Cache] holds the output of, so converting an archive again only converts
+//! what changed.
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    fmt::Write as _,
+    fs,
+    io::{self, BufRead, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    Cart, CartError, CartHeader, Error, FsResolver, PatchOptions,
+    bitwise::LuaTarget,
+    cart::is_cart,
+    format::FormatOptions,
+    glyph::ButtonMap,
+    is_converted,
+    license::{License, lua_licenses, with_license_header},
+    limits::Limits,
+    marker::with_marker,
+    p8scii::{ControlCodes, GlyphStyle},
+    patch_lua,
+    pipeline::{Patcher, fold_inserted_lines},
+    sink::OutputSink,
+    symbols::DEFAULT_PREFIX,
+    try_patch_includes,
+};
+
+/// How to convert.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Options {
+    /// Output only the converted Lua of a cart instead of the whole cart.
+    pub lua_only: bool,
+    /// Which of the Pico-8 syntax rewrites to do. See
+    /// [patch_lua_with](crate::patch_lua_with).
+    pub patch: PatchOptions,
+    /// Copy the cart's license into the converted Lua's comments and tag it
+    /// with its SPDX identifier. See [with_license_header].
+    pub license_header: bool,
+    /// The prefix of every name the converter adds, [DEFAULT_PREFIX] by
+    /// default. See [Namer](crate::symbols::Namer).
+    pub prefix: String,
+    /// Convert for this Lua: translate the bitwise operators and integer
+    /// division for it unless [bitwise](Options::bitwise) or
+    /// [integer_division](Options::integer_division) say otherwise, and
+    /// rewrite `goto continue` loops and hex floats if it lacks them. See
+    /// [patch_goto_continue](crate::goto::patch_goto_continue) and
+    /// [patch_hex_floats](crate::version::patch_hex_floats).
+    pub target: Option<LuaTarget>,
+    /// Rewrite the API calls of carts from before Pico-8 0.2.0, like `band`
+    /// and `mapdraw`, with their current equivalents. See
+    /// [migrate_deprecated](crate::deprecated::migrate_deprecated).
+    pub deprecated_api: bool,
+    /// Emulate Pico-8's 16.16 fixed point arithmetic. See
+    /// [patch_fixed_point](crate::fixed::patch_fixed_point).
+    pub fixed_point: bool,
+    /// Translate the bitwise operators for this Lua, or leave them as they
+    /// are if `None`. See
+    /// [patch_bitwise_with](crate::bitwise::patch_bitwise_with).
+    pub bitwise: Option<LuaTarget>,
+    /// Translate the integer division operator for this Lua, or leave it as
+    /// it is if `None`. See
+    /// [patch_integer_division](crate::division::patch_integer_division).
+    pub integer_division: Option<LuaTarget>,
+    /// Rewrite calls to Pico-8's math functions like `flr` with Lua's
+    /// standard library. See [inline_math](crate::builtins::inline_math).
+    pub inline_math: bool,
+    /// Rewrite calls to `sin`, `cos`, and `atan2` as calls to helpers that
+    /// take and return turns like Pico-8's. See [patch_trig](crate::trig::patch_trig).
+    pub trigonometry: bool,
+    /// Rewrite `all` loops and `foreach` statements as `ipairs` loops. See
+    /// [patch_all_loops](crate::loops::patch_all_loops).
+    pub all_loops: bool,
+    /// Rewrite calls to `add`, `del`, `deli`, and `count` with Lua's `table`
+    /// library. See [patch_table_builtins](crate::tables::patch_table_builtins).
+    pub table_builtins: bool,
+    /// Rewrite calls to `cocreate`, `coresume`, `costatus`, and `yield` with
+    /// Lua's `coroutine` library. See
+    /// [patch_coroutines](crate::coroutines::patch_coroutines).
+    pub coroutines: bool,
+    /// Delete the statements that call `printh()`, `assert()`, or `stop()`.
+    /// See [strip_debug](crate::debug::strip_debug).
+    pub strip_debug: bool,
+    /// Call Pico-8's API as the functions of this table instead of as
+    /// globals, or leave the calls as they are if `None`. See
+    /// [namespace_calls](crate::api::namespace_calls).
+    pub namespace: Option<String>,
+    /// Replace button glyphs used as values with their numbers instead of
+    /// generated names. See [number_glyphs](crate::symbols::number_glyphs).
+    pub glyph_numbers: bool,
+    /// Rewrite the first arguments of `btn()` and `btnp()` calls with this
+    /// map before the built-in glyphs, if the button-arguments pass is on. See
+    /// [patch_buttons](crate::glyph::patch_buttons).
+    pub buttons: Option<ButtonMap>,
+    /// Rewrite the P8SCII glyphs in string literals in this style, or leave
+    /// them as they are if `None`. See
+    /// [patch_string_glyphs](crate::p8scii::patch_string_glyphs).
+    pub string_glyphs: Option<GlyphStyle>,
+    /// Rewrite the P8SCII control codes in string literals, or keep them as
+    /// they are if `None`. See
+    /// [patch_control_codes](crate::p8scii::patch_control_codes).
+    pub control_codes: Option<ControlCodes>,
+    /// Keep the number of lines of the Lua so that line numbers in errors
+    /// match the source. Helper definitions, glyph names, and the license
+    /// header are joined to the first line of code instead of put on lines
+    /// of their own. Includes are not resolved by the converter and are left
+    /// to the caller.
+    pub preserve_lines: bool,
+    /// Indent and space the converted Lua, or leave it as the passes wrote it
+    /// if `None`. See [format_lua](crate::format::format_lua).
+    pub format: Option<FormatOptions>,
+    /// Mark the converted Lua with [MARKER](crate::marker::MARKER) so that
+    /// converting it again leaves it as it is. Input that
+    /// [is_converted](crate::is_converted) is left as it is either way.
+    pub marker: bool,
+    /// Refuse to convert past these limits, or convert anything if `None`.
+    /// See [Limits].
+    pub limits: Option<Limits>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            lua_only: false,
+            patch: PatchOptions::default(),
+            license_header: false,
+            prefix: DEFAULT_PREFIX.to_string(),
+            target: None,
+            deprecated_api: false,
+            fixed_point: false,
+            bitwise: None,
+            integer_division: None,
+            inline_math: false,
+            trigonometry: false,
+            all_loops: false,
+            table_builtins: false,
+            coroutines: false,
+            strip_debug: false,
+            namespace: None,
+            glyph_numbers: false,
+            buttons: None,
+            string_glyphs: None,
+            control_codes: None,
+            preserve_lines: false,
+            format: None,
+            marker: false,
+            limits: None,
+        }
+    }
+}
+
+/// One cart or Lua file to convert.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Input {
+    /// A name to identify the input in its [Output], e.g. its path.
+    pub name: String,
+    /// The text of a ".p8" cart or of Pico-8 Lua.
+    pub text: String,
+}
+
+/// The result of converting one [Input].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Output {
+    /// The name of the input.
+    pub name: String,
+    /// The converted cart or Lua.
+    pub result: Result<String, CartError>,
+}
+
+/// Converts one input after another.
+#[derive(Debug, Default)]
+pub struct Converter {
+    patcher: Patcher,
+    capacity: usize,
+}
+
+impl Converter {
+    /// Create a converter.
+    pub fn new(options: Options) -> Self {
+        Converter {
+            patcher: Patcher::new(options),
+            capacity: 0,
+        }
+    }
+
+    /// Convert the text of a ".p8" cart or of Pico-8 Lua.
+    pub fn convert(&mut self, text: &str) -> Result<String, CartError> {
+        if !is_cart(text) {
+            if is_converted(text) {
+                return Ok(text.to_string());
+            }
+            let licenses = if self.patcher.options().license_header {
+                lua_licenses(text)
+            } else {
+                Vec::new()
+            };
+            self.patcher.set_cart_version(None);
+            let patched = self.patcher.try_patch(text).map_err(CartError::Limit)?;
+            return Ok(self.headers(patched, &licenses).into_owned());
+        }
+        if is_converted(text) && !self.patcher.options().lua_only {
+            return Ok(text.to_string());
+        }
+        let mut cart = Cart::parse(text)?;
+        self.patcher.set_cart_version(cart.meta().version);
+        let licenses = if self.patcher.options().license_header {
+            cart.licenses()
+        } else {
+            Vec::new()
+        };
+        let Some(lua) = cart.sections.iter_mut().find(|s| s.name == "lua") else {
+            return Ok(if self.patcher.options().lua_only {
+                String::new()
+            } else {
+                text.to_string()
+            });
+        };
+        let patched = if is_converted(&lua.body) {
+            lua.body.clone()
+        } else {
+            let patched = self.patcher.try_patch(&lua.body).map_err(CartError::Limit)?;
+            self.headers(patched, &licenses).into_owned()
+        };
+        if self.patcher.options().lua_only {
+            return Ok(patched);
+        }
+        lua.body = patched;
+        let mut out = String::with_capacity(self.capacity.max(text.len()));
+        write!(out, "{cart}").expect("writing to a String cannot fail");
+        self.capacity = self.capacity.max(out.len());
+        Ok(out)
+    }
+
+    /// Add the license header and the marker, folded into the first line of
+    /// code if the lines are preserved.
+    fn headers<'h>(&self, lua: Cow<'h, str>, licenses: &[License]) -> Cow<'h, str> {
+        let options = self.patcher.options();
+        let before = options.preserve_lines.then(|| lua.to_string());
+        let lua = with_license_header(lua, licenses);
+        let lua = if options.marker { with_marker(lua) } else { lua };
+        match before {
+            Some(before) => fold_inserted_lines(&before, lua),
+            None => lua,
+        }
+    }
+}
+
+/// Read the cart or Lua file at `path`, resolve its includes, however deep,
+/// from next to it, and convert it.
+///
+/// With [Options::limits] the file is checked against
+/// [max_input](Limits::max_input) before its includes are resolved, and the
+/// code they add against the include limits.
+///
+/// ```no_run
+/// use pico8_to_lua::{Options, convert_file};
+/// fn main() -> Result<(), pico8_to_lua::Error> {
+///     print!("{}", convert_file("game.p8", &Options::default())?);
+///     Ok(())
+/// }
+/// ```
+pub fn convert_file(path: impl AsRef<Path>, options: &Options) -> Result<String, Error> {
+    let path = path.as_ref();
+    let text = fs::read_to_string(path)?;
+    let mut options = options.clone();
+    if let Some(limits) = &mut options.limits {
+        limits.check_input(&text).map_err(CartError::Limit)?;
+        limits.max_input = limits.max_input.saturating_add(limits.max_include_bytes);
+    }
+    let mut resolver = FsResolver::for_cart(path, Vec::<PathBuf>::new());
+    resolver.limits = options.limits;
+    let mut resolve = |include: &str| resolver.resolve_nested(include);
+    let text = if is_cart(&text) {
+        let mut cart = Cart::parse(&text)?;
+        if let Some(lua) = cart.sections.iter_mut().find(|s| s.name == "lua") {
+            lua.body = try_patch_includes(lua.body.as_str(), &mut resolve)?.into_owned();
+        }
+        cart.to_string()
+    } else {
+        try_patch_includes(text.as_str(), &mut resolve)?.into_owned()
+    };
+    Ok(Converter::new(options).convert(&text)?)
+}
+
+/// Read the Pico-8 Lua or ".p8" cart at `path` and convert it with
+/// [patch_lua].
+///
+/// ```no_run
+/// fn main() -> std::io::Result<()> {
+///     print!("{}", pico8_to_lua::patch_lua_file("game.lua")?);
+///     Ok(())
+/// }
+/// ```
+pub fn patch_lua_file(path: impl AsRef<Path>) -> io::Result<String> {
+    let text = fs::read_to_string(path)?;
+    Ok(patch_lua(text.as_str()).into_owned())
+}
+
+/// Convert the file `input` like [convert_file] and write it to `output` as
+/// its extension asks: a ".p8" cart, which keeps the other sections of
+/// `input` if it is a cart, or else the Lua alone.
+///
+/// ```no_run
+/// use pico8_to_lua::{Options, convert_cart_file};
+/// fn main() -> Result<(), pico8_to_lua::Error> {
+///     convert_cart_file("game.p8", "game.lua", &Options::default())
+/// }
+/// ```
+pub fn convert_cart_file(
+    input: impl AsRef<Path>,
+    output: impl AsRef<Path>,
+    options: &Options,
+) -> Result<(), Error> {
+    let converted = convert_file(input, options)?;
+    let output = output.as_ref();
+    let to_cart = output
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("p8"));
+    let text = match (to_cart, is_cart(&converted)) {
+        (true, false) => Cart::with_lua(&CartHeader::default(), converted).to_string(),
+        (false, true) => Cart::parse(&converted)?.lua().unwrap_or("").to_string(),
+        _ => converted,
+    };
+    fs::write(output, text)?;
+    Ok(())
+}
+
+/// Convert each input, write it to `sink` under its name, and finish the sink.
+///
+/// Returns the name and error of each input that could not be converted.
+///
+/// ```
+/// use pico8_to_lua::{Options, batch::{Input, convert_to_sink}, sink::Memory};
+/// let inputs = vec![Input { name: "a.lua".into(), text: "x += 1".into() }];
+/// let mut memory = Memory::default();
+/// let failed = convert_to_sink(inputs, &Options::default(), &mut memory).unwrap();
+/// assert!(failed.is_empty());
+/// assert_eq!(memory.files["a.lua"], b"x = x + (1)");
+/// ```
+pub fn convert_to_sink(
+    inputs: impl IntoIterator<Item = Input>,
+    options: &Options,
+    sink: &mut impl OutputSink,
+) -> io::Result<Vec<(String, CartError)>> {
+    let mut failed = Vec::new();
+    for output in convert_many(inputs, options) {
+        match output.result {
+            Ok(text) => sink.write(&output.name, text.as_bytes())?,
+            Err(e) => failed.push((output.name, e)),
+        }
+    }
+    sink.finish()?;
+    Ok(failed)
+}
+
+/// Convert each input lazily as the returned iterator is advanced.
+pub fn convert_many<'a>(
+    inputs: impl IntoIterator<Item = Input> + 'a,
+    options: &Options,
+) -> impl Iterator<Item = Output> + 'a {
+    let mut converter = Converter::new(options.clone());
+    inputs.into_iter().map(move |input| Output {
+        result: converter.convert(&input.text),
+        name: input.name,
+    })
+}
+
+/// A store of converted outputs, keyed by [cache_key], that lets a batch skip
+/// the inputs it has converted before with the same options.
+pub trait Cache {
+    /// Return the output stored under `key`, if any.
+    fn get(&mut self, key: &str) -> Option<String>;
+    /// Store `output` under `key`.
+    fn put(&mut self, key: &str, output: &str);
+}
+
+impl Cache for HashMap<String, String> {
+    fn get(&mut self, key: &str) -> Option<String> {
+        HashMap::get(self, key).cloned()
+    }
+
+    fn put(&mut self, key: &str, output: &str) {
+        self.insert(key.to_string(), output.to_string());
+    }
+}
+
+/// A [Cache] that keeps each output in a file named after its key in a
+/// directory, so it lasts from one run to the next. An output that can't be
+/// written is converted again next time.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirCache {
+    dir: PathBuf,
+}
+
+impl DirCache {
+    /// Create a cache in `dir`, which is created when an output is first
+    /// stored.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        DirCache { dir: dir.into() }
+    }
+}
+
+impl Cache for DirCache {
+    fn get(&mut self, key: &str) -> Option<String> {
+        fs::read_to_string(self.dir.join(key)).ok()
+    }
+
+    fn put(&mut self, key: &str, output: &str) {
+        let _ = fs::create_dir_all(&self.dir).and_then(|_| fs::write(self.dir.join(key), output));
+    }
+}
+
+/// Return the key of converting `text` with `options`: a hash of the text,
+/// the options, and the version of this crate, whose conversions may differ.
+///
+/// ```
+/// use pico8_to_lua::{Options, batch::cache_key};
+/// let options = Options::default();
+/// assert_eq!(cache_key("x += 1", &options), cache_key("x += 1", &options));
+/// assert_ne!(cache_key("x += 1", &options), cache_key("x += 2", &options));
+/// ```
+pub fn cache_key(text: &str, options: &Options) -> String {
+    // FNV-1a, which unlike std's hasher is the same from one build to the
+    // next.
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    let options = format!("{}\0{options:?}\0", env!("CARGO_PKG_VERSION"));
+    for byte in options.bytes().chain(text.bytes()) {
+        hash = (hash ^ byte as u64).wrapping_mul(0x100_0000_01b3);
+    }
+    format!("{hash:016x}")
+}
+
+/// Convert each input lazily like [convert_many], taking the output from
+/// `cache` if the input was converted before with the same options and
+/// storing it there if not.
+///
+/// ```
+/// use std::collections::HashMap;
+/// use pico8_to_lua::{Options, batch::{Input, convert_many_cached}};
+/// let mut cache = HashMap::new();
+/// let inputs = vec![Input { name: "a.lua".into(), text: "x += 1".into() }];
+/// let outputs: Vec<_> = convert_many_cached(inputs.clone(), &Options::default(), &mut cache).collect();
+/// assert_eq!(cache.len(), 1);
+/// let again: Vec<_> = convert_many_cached(inputs, &Options::default(), &mut cache).collect();
+/// assert_eq!(again, outputs);
+/// ```
+pub fn convert_many_cached<'a>(
+    inputs: impl IntoIterator<Item = Input> + 'a,
+    options: &Options,
+    cache: &'a mut impl Cache,
+) -> impl Iterator<Item = Output> + 'a {
+    let mut converter = Converter::new(options.clone());
+    let options = options.clone();
+    inputs.into_iter().map(move |input| {
+        let key = cache_key(&input.text, &options);
+        let result = match cache.get(&key) {
+            Some(output) => Ok(output),
+            None => converter.convert(&input.text).inspect(|output| cache.put(&key, output)),
+        };
+        Output {
+            result,
+            name: input.name,
+        }
+    })
+}
+
+/// Convert each input on every core, with a [Converter] per thread, and
+/// return the outputs in the order of the inputs.
+#[cfg(feature = "rayon")]
+pub fn par_convert_many(inputs: Vec<Input>, options: &Options) -> Vec<Output> {
+    use rayon::prelude::*;
+    inputs
+        .into_par_iter()
+        .map_init(
+            || Converter::new(options.clone()),
+            |converter, input| Output {
+                result: converter.convert(&input.text),
+                name: input.name,
+            },
+        )
+        .collect()
+}
+
+/// Read and convert the file at each path on every core like
+/// [par_convert_many], naming each output after its path. A file that can't
+/// be read as UTF-8 text gives its [io::Error] instead.
+#[cfg(feature = "rayon")]
+pub fn par_convert_files<P: AsRef<Path> + Sync>(
+    paths: &[P],
+    options: &Options,
+) -> Vec<io::Result<Output>> {
+    use rayon::prelude::*;
+    paths
+        .par_iter()
+        .map_init(
+            || Converter::new(options.clone()),
+            |converter, path| {
+                let path = path.as_ref();
+                let text = fs::read_to_string(path)?;
+                Ok(Output {
+                    name: path.display().to_string(),
+                    result: converter.convert(&text),
+                })
+            },
+        )
+        .collect()
+}
+
+/// Read and convert the file at each path on every core like
+/// [par_convert_files], taking the output from `cache` if the file was
+/// converted before with the same options and storing it there if not.
+#[cfg(feature = "rayon")]
+pub fn par_convert_files_cached<P: AsRef<Path> + Sync>(
+    paths: &[P],
+    options: &Options,
+    cache: &mut impl Cache,
+) -> Vec<io::Result<Output>> {
+    use rayon::prelude::*;
+    let texts: Vec<io::Result<String>> = paths.par_iter().map(fs::read_to_string).collect();
+    let keys: Vec<Option<String>> = texts
+        .iter()
+        .map(|text| Some(cache_key(text.as_ref().ok()?, options)))
+        .collect();
+    let hits: Vec<Option<String>> = keys
+        .iter()
+        .map(|key| cache.get(key.as_ref()?))
+        .collect();
+    let outputs: Vec<io::Result<Output>> = paths
+        .par_iter()
+        .zip(texts)
+        .zip(&hits)
+        .map_init(
+            || Converter::new(options.clone()),
+            |converter, ((path, text), hit)| {
+                let text = text?;
+                Ok(Output {
+                    name: path.as_ref().display().to_string(),
+                    result: match hit {
+                        Some(output) => Ok(output.clone()),
+                        None => converter.convert(&text),
+                    },
+                })
+            },
+        )
+        .collect();
+    for ((key, hit), output) in keys.iter().zip(&hits).zip(&outputs) {
+        if let (Some(key), None, Ok(Output { result: Ok(text), .. })) = (key, hit, output) {
+            cache.put(key, text);
+        }
+    }
+    outputs
+}
+
+/// Convert the length-prefixed requests read from `input`, writing each
+/// response to `output` as soon as it is ready.
+///
+/// A request is a line with the byte length of the text and a name, followed
+/// by the text, e.g. "6 a.lua\nx += 1". A response is a line with "ok" or
+/// "error", the byte length of its body, and the name, followed by the body,
+/// which is the converted text or the error message. Blank lines between
+/// requests are skipped. The stream ends with `input`. This lets a build daemon keep one converter process running
+/// instead of spawning one per file.
+///
+/// ```
+/// use pico8_to_lua::{Options, batch::convert_stream};
+/// let mut output = Vec::new();
+/// convert_stream(&b"6 a.lua\nx += 1"[..], &mut output, &Options::default()).unwrap();
+/// assert_eq!(output, b"ok 11 a.lua\nx = x + (1)");
+/// ```
+pub fn convert_stream(
+    mut input: impl BufRead,
+    mut output: impl Write,
+    options: &Options,
+) -> io::Result<()> {
+    let mut converter = Converter::new(options.clone());
+    let mut header = String::new();
+    loop {
+        header.clear();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(());
+        }
+        let line = header.trim_end_matches(['\r', '\n']);
+        if line.is_empty() {
+            continue;
+        }
+        let (len, name) = line.split_once(' ').unwrap_or((line, ""));
+        let len: usize = len.parse().map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("bad request header {line:?}"),
+            )
+        })?;
+        let mut text = vec![0; len];
+        input.read_exact(&mut text)?;
+        let (status, body) = match String::from_utf8(text) {
+            Ok(text) => match converter.convert(&text) {
+                Ok(lua) => ("ok", lua),
+                Err(e) => ("error", e.to_string()),
+            },
+            Err(_) => ("error", "text is not UTF-8".to_string()),
+        };
+        writeln!(output, "{status} {} {name}", body.len())?;
+        output.write_all(body.as_bytes())?;
+        output.flush()?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input(name: &str, text: &str) -> Input {
+        Input {
+            name: name.into(),
+            text: text.into(),
+        }
+    }
+
+    #[test]
+    fn test_convert_many() {
+        let inputs = vec![
+            input("a.lua", "x += 1"),
+            input("b.p8", "pico-8 cartridge\n__lua__\nx != 1\n__gfx__\n00\n"),
+            input("c.p8", "pico-8 cartridge\n__sfx__\n0\n"),
+            input("d.p8", "pico-8 cartridge"),
+        ];
+        let outputs: Vec<_> = convert_many(inputs, &Options::default()).collect();
+        assert_eq!(outputs[0].result.as_deref(), Ok("x = x + (1)"));
+        assert_eq!(
+            outputs[1].result.as_deref(),
+            Ok("pico-8 cartridge\n__lua__\nx ~= 1\n__gfx__\n00\n")
+        );
+        assert_eq!(outputs[1].name, "b.p8");
+        assert_eq!(outputs[2].result.as_deref(), Ok("pico-8 cartridge\n__sfx__\n0\n"));
+        assert!(outputs[3].result.is_ok());
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_par_convert_many() {
+        let inputs = (0..100).map(|i| input(&i.to_string(), &format!("x += {i}")));
+        let outputs = par_convert_many(inputs.collect(), &Options::default());
+        assert_eq!(outputs.len(), 100);
+        assert_eq!(outputs[42].name, "42");
+        assert_eq!(outputs[42].result.as_deref(), Ok("x = x + (42)"));
+    }
+
+    #[test]
+    fn test_lua_only() {
+        let options = Options {
+            lua_only: true,
+            ..Options::default()
+        };
+        let mut converter = Converter::new(options);
+        assert_eq!(
+            converter.convert("pico-8 cartridge\n__lua__\nx != 1\n__gfx__\n00\n"),
+            Ok("x ~= 1\n".into())
+        );
+    }
+
+    #[test]
+    fn test_license_header() {
+        let options = Options {
+            lua_only: true,
+            license_header: true,
+            ..Options::default()
+        };
+        let mut converter = Converter::new(options);
+        assert_eq!(
+            converter.convert("pico-8 cartridge\n__lua__\nx += 1\n__meta:bbs__\nlicense: CC BY-NC-SA 4.0\n"),
+            Ok("-- license: CC BY-NC-SA 4.0\n-- SPDX-License-Identifier: CC-BY-NC-SA-4.0\nx = x + (1)\n".into())
+        );
+    }
+
+    #[test]
+    fn test_marker() {
+        let options = Options {
+            fixed_point: true,
+            marker: true,
+            ..Options::default()
+        };
+        let mut converter = Converter::new(options);
+        let once = converter.convert("-- game\nx = 1 + 2\n").unwrap();
+        assert!(once.starts_with("-- game\n-- converted by pico8-to-lua\n"));
+        assert_eq!(converter.convert(&once), Ok(once.clone()));
+        let cart = format!("pico-8 cartridge\n__lua__\n{once}__gfx__\n00\n");
+        assert_eq!(converter.convert(&cart), Ok(cart.clone()));
+    }
+
+    #[test]
+    fn test_prefix() {
+        let options = Options {
+            prefix: "_p_".into(),
+            ..Options::default()
+        };
+        let mut converter = Converter::new(options);
+        assert_eq!(
+            converter.convert("b = ⬅️\nif btn(⬅️) then _p_left = 1 end"),
+            Ok("local _p_left_2 = 0\nb = _p_left_2\nif btn(0) then _p_left = 1 end".into())
+        );
+    }
+
+    #[test]
+    fn test_bitwise() {
+        let options = Options {
+            bitwise: Some(LuaTarget::Lua52),
+            ..Options::default()
+        };
+        let mut converter = Converter::new(options);
+        let lua = converter.convert("x |= 1 << b").unwrap();
+        assert!(lua.ends_with("\nx = __p8_bor(x, (__p8_lshift(1, b)))"));
+        assert!(lua.contains("local r = bit32.bor(a, b)"));
+    }
+
+    #[test]
+    fn test_convert_stream() {
+        let input = b"6 a.lua\nx += 1\n2 c.lua\n\xff\xfe\n0 d.lua\n";
+        let mut output = Vec::new();
+        convert_stream(&input[..], &mut output, &Options::default()).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "ok 11 a.lua\nx = x + (1)error 17 c.lua\ntext is not UTF-8ok 0 d.lua\n"
+        );
+        assert!(convert_stream(&b"x a.lua\n"[..], &mut Vec::new(), &Options::default()).is_err());
+    }
+
+    #[test]
+    fn test_preserve_lines() {
+        let options = Options {
+            lua_only: true,
+            license_header: true,
+            preserve_lines: true,
+            ..Options::default()
+        };
+        let mut converter = Converter::new(options);
+        assert_eq!(
+            converter.convert("pico-8 cartridge\n__lua__\nx += 1\n__meta:bbs__\nlicense: MIT\n"),
+            Ok("--[[ license: MIT ]] --[[ SPDX-License-Identifier: MIT ]] x = x + (1)\n".into())
+        );
+    }
+
+    #[test]
+    fn test_convert_cart_file() {
+        let dir = std::env::temp_dir().join(format!("pico8-to-lua-file-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("a.p8"),
+            "pico-8 cartridge\n__lua__\nx += 1\n__gfx__\n00\n",
+        )
+        .unwrap();
+        convert_cart_file(dir.join("a.p8"), dir.join("a.lua"), &Options::default()).unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.join("a.lua")).unwrap(),
+            "x = x + (1)\n"
+        );
+        convert_cart_file(dir.join("a.lua"), dir.join("b.p8"), &Options::default()).unwrap();
+        assert!(is_cart(&fs::read_to_string(dir.join("b.p8")).unwrap()));
+        assert_eq!(
+            patch_lua_file(dir.join("a.p8")).unwrap(),
+            "pico-8 cartridge\n__lua__\nx = x + (1)\n__gfx__\n00\n"
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_par_convert_files_cached() {
+        let dir = std::env::temp_dir().join(format!("pico8-to-lua-cache-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a.lua"), "x += 1").unwrap();
+        let paths = [dir.join("a.lua"), dir.join("missing.lua")];
+        let mut cache = DirCache::new(dir.join("cache"));
+        let options = Options::default();
+        let outputs = par_convert_files_cached(&paths, &options, &mut cache);
+        assert_eq!(outputs[0].as_ref().unwrap().result.as_deref(), Ok("x = x + (1)"));
+        assert!(outputs[1].is_err());
+        let key = cache_key("x += 1", &options);
+        assert_eq!(cache.get(&key).as_deref(), Some("x = x + (1)"));
+        // A hit is taken as it is, not converted again.
+        cache.put(&key, "cached");
+        let outputs = par_convert_files_cached(&paths, &options, &mut cache);
+        assert_eq!(outputs[0].as_ref().unwrap().result.as_deref(), Ok("cached"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,153 @@
+//! Fetching carts from the Lexaloffle BBS, with the `net` feature.
+//!
+//! A cart is named by its id, e.g. "celeste-0", written as it is, as
+//! "bbs:celeste-0" or "#celeste-0" as it appears on the BBS, or as a BBS URL
+//! with a `lid` parameter or ending in ".p8.png".
+//!
+//! ```no_run
+//! use pico8_to_lua::{bbs::fetch_code, patch_lua};
+//! let lua = patch_lua(fetch_code("#celeste-0").unwrap());
+//! ```
+//!
+//! [FsResolver](crate::FsResolver) downloads an include like
+//! `#include https://example.com/lib.lua` or `#include bbs:celeste-0` with
+//! [fetch_include].
+use std::{
+    error::Error,
+    fmt,
+    io::{self, Read},
+};
+
+use crate::p8png::{self, P8PngError};
+
+/// An error encountered while fetching a cart.
+#[derive(Debug)]
+pub enum FetchError {
+    /// The text names no cart.
+    BadId(String),
+    /// The cart could not be downloaded.
+    Http(Box<ureq::Error>),
+    /// The download was cut short.
+    Io(io::Error),
+    /// The download is not a ".p8.png" cart.
+    Png(P8PngError),
+    /// The download is larger than the limit, in bytes.
+    TooLarge(u64),
+}
+
+impl fmt::Display for FetchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FetchError::BadId(id) => write!(f, "{id:?} is not a BBS cart id or URL"),
+            FetchError::Http(e) => write!(f, "could not download the cart: {e}"),
+            FetchError::Io(e) => write!(f, "could not download the cart: {e}"),
+            FetchError::Png(e) => write!(f, "could not read the cart: {e}"),
+            FetchError::TooLarge(limit) => write!(f, "the download is over {limit} bytes"),
+        }
+    }
+}
+
+impl Error for FetchError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            FetchError::BadId(_) | FetchError::TooLarge(_) => None,
+            FetchError::Http(e) => Some(e),
+            FetchError::Io(e) => Some(e),
+            FetchError::Png(e) => Some(e),
+        }
+    }
+}
+
+impl From<ureq::Error> for FetchError {
+    fn from(e: ureq::Error) -> Self {
+        FetchError::Http(Box::new(e))
+    }
+}
+
+impl From<io::Error> for FetchError {
+    fn from(e: io::Error) -> Self {
+        FetchError::Io(e)
+    }
+}
+
+impl From<P8PngError> for FetchError {
+    fn from(e: P8PngError) -> Self {
+        FetchError::Png(e)
+    }
+}
+
+fn is_id(id: &str) -> bool {
+    !id.is_empty()
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Return the cart id that `text` names, if it names one.
+///
+/// ```
+/// use pico8_to_lua::bbs::cart_id;
+/// assert_eq!(cart_id("#celeste-0").as_deref(), Some("celeste-0"));
+/// assert_eq!(cart_id("https://www.lexaloffle.com/bbs/?lid=celeste-0").as_deref(), Some("celeste-0"));
+/// assert_eq!(cart_id("cart.p8"), None);
+/// ```
+pub fn cart_id(text: &str) -> Option<String> {
+    if text.starts_with("https://") || text.starts_with("http://") {
+        if !text.contains("lexaloffle.com/") {
+            return None;
+        }
+        let query = text.split_once('?').map_or("", |(_, q)| q);
+        let lid = query
+            .split(['&', '#'])
+            .find_map(|param| param.strip_prefix("lid="));
+        let file = text
+            .rsplit('/')
+            .next()
+            .and_then(|name| name.strip_suffix(".p8.png"));
+        return lid.or(file).filter(|id| is_id(id)).map(String::from);
+    }
+    let id = text
+        .strip_prefix("bbs:")
+        .or_else(|| text.strip_prefix('#'))
+        .unwrap_or(text);
+    // A bare id must not be mistaken for a file name like "cart.p8".
+    is_id(id).then(|| id.to_string())
+}
+
+/// Return the URL of the ".p8.png" cart with `id`.
+pub fn cart_url(id: &str) -> String {
+    format!("https://www.lexaloffle.com/bbs/get_cart.php?cat=7&lid={id}")
+}
+
+/// Download the ".p8.png" cart that `text` names and return its code.
+pub fn fetch_code(text: &str) -> Result<String, FetchError> {
+    let id = cart_id(text).ok_or_else(|| FetchError::BadId(text.to_string()))?;
+    let response = ureq::get(&cart_url(&id)).call()?;
+    let code = p8png::read_code(response.into_reader())?;
+    Ok(crate::charset::to_utf8(&code))
+}
+
+/// The most bytes [fetch_include] downloads for an include unless
+/// [Limits](crate::limits::Limits) say otherwise.
+pub const MAX_INCLUDE_DOWNLOAD: u64 = 1 << 20;
+
+/// Download what the include `path` names, an "http://" or "https://" URL or
+/// a BBS cart as "bbs:celeste-0" or its BBS URL, refusing more than
+/// `max_bytes`.
+pub fn fetch_include(path: &str, max_bytes: u64) -> Result<Vec<u8>, FetchError> {
+    let url = match cart_id(path) {
+        Some(id) if path.contains("://") || path.starts_with("bbs:") => cart_url(&id),
+        _ if path.contains("://") => path.to_string(),
+        _ => return Err(FetchError::BadId(path.to_string())),
+    };
+    let mut bytes = Vec::new();
+    ureq::get(&url)
+        .call()?
+        .into_reader()
+        .take(max_bytes + 1)
+        .read_to_end(&mut bytes)?;
+    if bytes.len() as u64 > max_bytes {
+        return Err(FetchError::TooLarge(max_bytes));
+    }
+    Ok(bytes)
+}
@@ -0,0 +1,223 @@
+//! Loading carts as Bevy assets with the `bevy` feature.
+//!
+//! [Pico8Plugin] registers a [CartLoader] for ".p8" and ".p8.png" files that
+//! converts a cart as it loads into a [LuaCart], so a game that embeds carts
+//! needs no conversion step of its own. The files a cart includes are read
+//! as assets next to it, so hot reloading converts the cart again when it or
+//! one of them changes. With [CartLoaderSettings] the loader also decodes
+//! the spritesheet and the map of a ".p8" cart as the labeled assets "gfx"
+//! and "map".
+use std::{borrow::Cow, collections::HashMap, io, path::Path};
+
+use bevy_app::{App, Plugin};
+use bevy_asset::{Asset, AssetApp, AssetLoader, LoadContext, io::Reader};
+use bevy_reflect::TypePath;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    Cart, Error, IncludeError, Options,
+    cart::{Spritesheet, TileMap, is_cart, tabs},
+    convert::{Converted, convert_text, read_text},
+    find_includes,
+    sourcemap::SourceMap,
+    split_tab, try_patch_includes,
+};
+
+/// A cart converted to Lua.
+#[derive(Asset, TypePath, Debug, Clone)]
+pub struct LuaCart {
+    /// The converted Lua.
+    pub lua: String,
+    /// The cart with its Lua converted, or `None` for a ".p8.png" cart or
+    /// Lua.
+    pub cart: Option<Cart>,
+    /// The source of each line of [lua](LuaCart::lua).
+    pub source_map: SourceMap,
+}
+
+/// The spritesheet of a cart, its "gfx" asset.
+#[derive(Asset, TypePath, Debug, Clone)]
+pub struct CartGfx(pub Spritesheet);
+
+/// The map of a cart, its "map" asset.
+#[derive(Asset, TypePath, Debug, Clone)]
+pub struct CartMap(pub TileMap);
+
+/// What a [CartLoader] decodes besides the Lua.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct CartLoaderSettings {
+    /// Decode the spritesheet as the labeled asset "gfx".
+    pub gfx: bool,
+    /// Decode the map as the labeled asset "map".
+    pub map: bool,
+}
+
+/// Loads ".p8" and ".p8.png" carts as [LuaCart] assets.
+#[derive(Debug, Clone, Default)]
+pub struct CartLoader {
+    /// How to convert.
+    pub options: Options,
+}
+
+impl AssetLoader for CartLoader {
+    type Asset = LuaCart;
+    type Settings = CartLoaderSettings;
+    type Error = Error;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        settings: &CartLoaderSettings,
+        load_context: &mut LoadContext<'_>,
+    ) -> Result<LuaCart, Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        let text = read_text(&bytes)?;
+        let files = read_includes(&text, load_context).await?;
+        let Converted {
+            lua,
+            cart,
+            source_map,
+            ..
+        } = convert_text(&text, &self.options, |path| {
+            resolve(&files, path, &mut Vec::new())
+        })?;
+        if let Some(cart) = &cart {
+            if settings.gfx {
+                load_context.add_labeled_asset("gfx".into(), CartGfx(cart.gfx()?));
+            }
+            if settings.map {
+                load_context.add_labeled_asset("map".into(), CartMap(cart.map()?));
+            }
+        }
+        Ok(LuaCart {
+            lua,
+            cart,
+            source_map,
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["p8", "p8.png"]
+    }
+}
+
+/// Registers the [CartLoader], converting with `options`, and its assets.
+#[derive(Debug, Clone, Default)]
+pub struct Pico8Plugin {
+    /// How to convert.
+    pub options: Options,
+}
+
+impl Plugin for Pico8Plugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<LuaCart>()
+            .init_asset::<CartGfx>()
+            .init_asset::<CartMap>()
+            .register_asset_loader(CartLoader {
+                options: self.options.clone(),
+            });
+    }
+}
+
+/// Return the Lua of the text of a cart or of Lua.
+fn lua_of(text: String) -> Result<String, Error> {
+    if is_cart(&text) {
+        Ok(Cart::parse(&text)?.lua().unwrap_or("").to_string())
+    } else {
+        Ok(text)
+    }
+}
+
+/// Return the files that `text` includes, however deep, by path, read as
+/// assets next to the cart being loaded so that changing one reloads it.
+async fn read_includes(
+    text: &str,
+    load_context: &mut LoadContext<'_>,
+) -> Result<HashMap<String, String>, Error> {
+    let dir = load_context
+        .path()
+        .parent()
+        .unwrap_or(Path::new(""))
+        .to_path_buf();
+    let mut files = HashMap::new();
+    let includes = |lua: &str| -> Vec<String> {
+        find_includes(lua)
+            .map(|path| split_tab(path).0.to_string())
+            .collect()
+    };
+    let mut pending = includes(&lua_of(text.to_string())?);
+    while let Some(file) = pending.pop() {
+        if files.contains_key(&file) {
+            continue;
+        }
+        let bytes = load_context
+            .read_asset_bytes(dir.join(&file))
+            .await
+            .map_err(|e| IncludeError {
+                path: file.clone(),
+                chain: Vec::new(),
+                error: io::Error::other(e),
+            })?;
+        let lua = lua_of(read_text(&bytes)?)?;
+        pending.extend(includes(&lua));
+        files.insert(file, lua);
+    }
+    Ok(files)
+}
+
+/// Return the Lua that `path` includes from `files` with the includes in it
+/// resolved too. `chain` is the includes that led to it.
+fn resolve(
+    files: &HashMap<String, String>,
+    path: &str,
+    chain: &mut Vec<String>,
+) -> Result<String, IncludeError> {
+    let error = |chain: &[String], kind: io::ErrorKind, message: &str| IncludeError {
+        path: path.to_string(),
+        chain: chain.to_vec(),
+        error: io::Error::new(kind, message),
+    };
+    if chain.iter().any(|p| p == path) {
+        return Err(error(
+            chain,
+            io::ErrorKind::InvalidData,
+            "it includes itself",
+        ));
+    }
+    let (file, tab) = split_tab(path);
+    let lua = files
+        .get(file)
+        .ok_or_else(|| error(chain, io::ErrorKind::NotFound, "not read"))?;
+    let lua = match tab {
+        None => lua.as_str(),
+        Some(tab) => tabs(lua)
+            .get(tab)
+            .copied()
+            .ok_or_else(|| error(chain, io::ErrorKind::NotFound, "no such tab"))?,
+    };
+    chain.push(path.to_string());
+    let lua = try_patch_includes(lua, |inner| resolve(files, inner, chain)).map(Cow::into_owned);
+    chain.pop();
+    lua
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve() {
+        let files = HashMap::from([
+            ("a.lua".to_string(), "#include b.p8:1\nx = 1".to_string()),
+            ("b.p8".to_string(), "y = 1\n-->8\nz = 2\n".to_string()),
+            ("c.lua".to_string(), "#include c.lua".to_string()),
+        ]);
+        assert_eq!(
+            resolve(&files, "a.lua", &mut Vec::new()).unwrap(),
+            "z = 2\n\nx = 1"
+        );
+        let error = resolve(&files, "c.lua", &mut Vec::new()).unwrap_err();
+        assert_eq!(error.chain, ["c.lua"]);
+    }
+}
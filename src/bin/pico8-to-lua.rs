@@ -1,64 +1,1397 @@
+use clap::{Args, CommandFactory, Parser, Subcommand, ValueEnum};
+use pico8_to_lua::detect::{read_lua, read_lua_with_offset};
+use pico8_to_lua::sink::OutputSink;
 use pico8_to_lua::*;
 use std::env;
+use std::ffi::OsString;
 use std::fs;
 use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread;
+use std::time::{Duration, SystemTime};
 
-fn split<'a>(s: &'a str, delimiter: &str) -> Vec<&'a str> {
-    s.split(delimiter).collect()
+fn is_p8(path: &Path) -> bool {
+    detect::Format::from_path(path) == Some(detect::Format::P8)
+}
+
+fn is_p8png(path: &Path) -> bool {
+    detect::Format::from_path(path) == Some(detect::Format::P8Png)
+}
+
+/// Collect the files under `dir` that satisfy `want` recursively in sorted
+/// order.
+fn cart_files(
+    dir: &Path,
+    want: fn(&Path) -> bool,
+    files: &mut Vec<PathBuf>,
+) -> Result<(), io::Error> {
+    let mut entries = fs::read_dir(dir)?
+        .map(|e| e.map(|e| e.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            cart_files(&path, want, files)?;
+        } else if want(&path) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn grep(converted: bool, pattern: &str, paths: &[PathBuf]) -> Result<(), io::Error> {
+    let regex = lazy_regex::regex::Regex::new(pattern).unwrap_or_else(|e| {
+        eprintln!("ERROR: Invalid pattern: {}", e);
+        std::process::exit(1);
+    });
+    let mut files = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            cart_files(path, |p| is_p8(p) || is_p8png(p), &mut files)?;
+        } else {
+            files.push(path.to_path_buf());
+        }
+    }
+    let mut found = false;
+    for path in files {
+        // A cart's lines are numbered from the top of the file.
+        let (lua, offset) = match read_lua_with_offset(&path) {
+            Ok(read) => read,
+            Err(e) => {
+                eprintln!("WARNING: Skipping {}: {}", path.display(), e);
+                continue;
+            }
+        };
+        let lua = if converted { patch_lua(lua) } else { lua.into() };
+        for (i, line) in lua.lines().enumerate() {
+            if regex.is_match(line) {
+                found = true;
+                println!("{}:{}:{}", path.display(), offset + i + 1, line);
+            }
+        }
+    }
+    if !found {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+fn dedupe(dir: &Path) -> Result<(), io::Error> {
+    let mut files = Vec::new();
+    cart_files(dir, is_p8, &mut files)?;
+    let mut fingerprints = Vec::new();
+    for path in files {
+        let text = fs::read_to_string(&path)?;
+        match Cart::parse(&text) {
+            Ok(cart) => fingerprints.push((path, dedupe::Fingerprint::of(&cart))),
+            Err(e) => eprintln!("WARNING: Skipping {}: {}", path.display(), e),
+        }
+    }
+    for group in dedupe::find_duplicates(fingerprints) {
+        match group.kind {
+            dedupe::DuplicateKind::Exact => println!("exact duplicates:"),
+            dedupe::DuplicateKind::SameCode => println!("same code, different data:"),
+        }
+        for path in group.members {
+            println!("  {}", path.display());
+        }
+    }
+    Ok(())
+}
+
+/// Converts Pico-8's dialect of Lua to plain Lua.
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+    /// Run again whenever an input changes.
+    #[arg(long, global = true)]
+    watch: bool,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Convert a cart or a Lua file.
+    Convert(Box<ConvertArgs>),
+    /// Print a diff of what converting would change and fail if anything
+    /// would, e.g. in CI or a pre-commit hook.
+    Check {
+        /// The carts or Lua files.
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+    },
+    /// List the files a cart or a Lua file includes.
+    Includes {
+        /// The cart or Lua file.
+        path: PathBuf,
+        /// Print the code with its includes resolved instead.
+        #[arg(long)]
+        flatten: bool,
+    },
+    /// Print a section of a cart.
+    Extract {
+        /// The cart.
+        path: PathBuf,
+        /// The section to print, e.g. "lua" or "gfx", if no file is given.
+        #[arg(long, default_value = "lua")]
+        section: String,
+        /// Write the Lua to this file.
+        #[arg(long)]
+        lua: Option<PathBuf>,
+        /// Write the spritesheet to this PNG file.
+        #[arg(long)]
+        gfx: Option<PathBuf>,
+        /// Write the map to this JSON file.
+        #[arg(long)]
+        map: Option<PathBuf>,
+        /// Write the label to this PNG file.
+        #[arg(long)]
+        label: Option<PathBuf>,
+    },
+    /// Measure the code of a cart against Pico-8's limits.
+    Tokens {
+        /// The cart or Lua file.
+        path: PathBuf,
+    },
+    /// Check that the converted code uses a safe subset of Lua.
+    Verify {
+        /// The cart or Lua file, or a directory to check every cart under.
+        path: PathBuf,
+        /// Also check that it compiles.
+        #[cfg(feature = "mlua")]
+        #[arg(long)]
+        compile: bool,
+    },
+    /// Answer length-prefixed conversion requests on stdin.
+    Batch {
+        /// Answer with only the converted Lua of each cart.
+        #[arg(long)]
+        lua_only: bool,
+    },
+    /// List the passes of the conversion.
+    Passes,
+    /// Report what may behave differently after converting a cart.
+    Report {
+        /// The cart.
+        path: PathBuf,
+        /// Print JSON instead of Markdown.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Compare two carts section by section.
+    Diff {
+        /// The old cart.
+        old: PathBuf,
+        /// The new cart.
+        new: PathBuf,
+        /// Print JSON instead of a diff of the converted code and a summary.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Build a cart from a main Lua file, putting each file it includes in a
+    /// tab of its own.
+    Assemble {
+        /// The main Lua file.
+        main: PathBuf,
+        /// Take the header and the data sections, like gfx and map, from this
+        /// cart.
+        #[arg(long)]
+        data: Option<PathBuf>,
+        /// Look for included files next to the main file and then in these
+        /// directories in order.
+        #[arg(long, value_name = "DIR")]
+        include_path: Vec<PathBuf>,
+        /// Write the cart to this file instead of stdout.
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// The cart format version of the "version N" header line.
+        #[arg(long, default_value_t = cart::DEFAULT_VERSION)]
+        cart_version: u32,
+    },
+    /// List the API functions a cart calls.
+    Api {
+        /// The cart or Lua file.
+        path: PathBuf,
+    },
+    /// List the other carts and the save data a cart uses.
+    Data {
+        /// The cart or Lua file.
+        path: PathBuf,
+    },
+    /// List the functions a cart never calls and the tokens deleting them
+    /// would reclaim.
+    DeadCode {
+        /// The cart or Lua file.
+        path: PathBuf,
+    },
+    /// Port a cart to LÖVE.
+    Love {
+        /// The cart.
+        path: PathBuf,
+        /// The directory, or ".love" or ".zip" file, to write.
+        #[arg(long)]
+        output: Option<String>,
+    },
+    /// Port a cart to TIC-80.
+    Tic80 {
+        /// The cart.
+        path: PathBuf,
+        /// The ".tic" file to write.
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+    /// Search the code of carts.
+    Grep {
+        /// Search the converted code.
+        #[arg(long)]
+        converted: bool,
+        /// The regular expression to search for.
+        pattern: String,
+        /// The carts, Lua files, or directories of carts.
+        paths: Vec<PathBuf>,
+    },
+    /// Find duplicate carts in a directory.
+    Dedupe {
+        /// The directory.
+        dir: PathBuf,
+    },
+    /// Serve conversions over HTTP.
+    Serve {
+        /// The address to listen on.
+        #[arg(default_value = "127.0.0.1:8008")]
+        addr: String,
+        /// Listen on this port instead of the address's.
+        #[arg(long)]
+        port: Option<u16>,
+    },
+    /// Publish diagnostics to an editor over the Language Server Protocol on
+    /// stdin and stdout.
+    Lsp,
+}
+
+#[derive(Args)]
+struct ConvertArgs {
+    /// The carts or Lua files, directories of them, or globs like
+    /// "carts/**/*.p8", or "-" for stdin.
+    #[arg(required = true)]
+    paths: Vec<String>,
+    /// Convert every file into this directory, keeping the directories they
+    /// are in relative to the directory or glob they were found by.
+    #[arg(long)]
+    out_dir: Option<PathBuf>,
+    /// With "--out-dir", keep the converted files in this directory and skip
+    /// converting the files whose text and options have not changed.
+    #[arg(long, requires = "out_dir")]
+    cache: Option<PathBuf>,
+    /// Output only the converted Lua of a cart, like "--format=lua".
+    #[arg(long)]
+    lua_only: bool,
+    /// Output a cart, with a plain Lua input put in a new one, or only Lua,
+    /// or whichever the input is.
+    #[arg(long, value_enum, default_value_t = Format::Auto)]
+    format: Format,
+    /// The cart format version of the "version N" header line of a cart made
+    /// from a Lua file.
+    #[arg(long, default_value_t = cart::DEFAULT_VERSION)]
+    cart_version: u32,
+    /// Write to this file, or to a zip archive if it ends with ".zip".
+    #[arg(short, long, conflicts_with = "in_place")]
+    output: Option<String>,
+    /// Overwrite the file with its conversion, keeping its permissions.
+    #[arg(long)]
+    in_place: bool,
+    /// Copy the file to the same path with ".bak" added before overwriting
+    /// it.
+    #[arg(long, requires = "in_place")]
+    backup: bool,
+    /// Fail on every construct that may not convert as meant.
+    #[arg(long)]
+    strict: bool,
+    /// Print warnings and what each pass changed to stderr.
+    #[arg(long)]
+    verbose: bool,
+    /// Replace button glyphs used as values with their numbers.
+    #[arg(long)]
+    glyph_numbers: bool,
+    /// Also read TEXT as the button VALUE in btn() and btnp() calls, e.g.
+    /// "(x)=5" for a placeholder of the glyph.
+    #[arg(long = "button", value_name = "TEXT=VALUE", value_parser = parse_button)]
+    buttons: Vec<(String, u8)>,
+    /// Remove comments, except the cart's leading title and author comments.
+    #[arg(long)]
+    strip_comments: bool,
+    /// Mark the output as converted so converting it again leaves it as it
+    /// is.
+    #[arg(long)]
+    marker: bool,
+    /// Rewrite the glyphs in strings as "escape" or "chr".
+    #[arg(long, value_parser = parse_glyph_style)]
+    string_glyphs: Option<p8scii::GlyphStyle>,
+    /// Rewrite the control codes in strings: "strip", "bytes", or
+    /// "wrap:<function>".
+    #[arg(long, value_parser = parse_control_codes)]
+    control_codes: Option<p8scii::ControlCodes>,
+    /// Indent the converted Lua by this many spaces per level, or "tab", and
+    /// collapse the extra spaces between tokens.
+    #[arg(long, value_name = "WIDTH|tab")]
+    indent: Option<format::Indent>,
+    /// Rewrite the API calls of carts from before Pico-8 0.2.0, like band()
+    /// and mapdraw(), with their current equivalents.
+    #[arg(long)]
+    deprecated_api: bool,
+    /// Emulate Pico-8's fixed point numbers.
+    #[arg(long)]
+    fixed_point: bool,
+    /// Convert for this Lua, e.g. "5.1", "5.4", or "luajit".
+    #[arg(long)]
+    target: Option<bitwise::LuaTarget>,
+    /// Translate the bitwise operators for this Lua.
+    #[arg(long)]
+    bitwise: Option<bitwise::LuaTarget>,
+    /// Rewrite calls to math functions like `flr` with Lua's standard
+    /// library.
+    #[arg(long)]
+    inline_math: bool,
+    /// Rewrite calls to sin(), cos(), and atan2() as calls to helpers that
+    /// take and return turns like Pico-8's.
+    #[arg(long)]
+    trigonometry: bool,
+    /// Rewrite `all` loops and `foreach` statements as `ipairs` loops, for
+    /// carts that do not delete while they iterate.
+    #[arg(long)]
+    all_loops: bool,
+    /// Rewrite calls to add(), del(), deli(), and count() with Lua's table
+    /// library.
+    #[arg(long)]
+    table_builtins: bool,
+    /// Rewrite calls to cocreate(), coresume(), costatus(), and yield() with
+    /// Lua's coroutine library.
+    #[arg(long)]
+    coroutines: bool,
+    /// Delete the statements that call printh(), assert(), or stop(), for a
+    /// release build.
+    #[arg(long)]
+    strip_debug: bool,
+    /// Call the Pico-8 API as the functions of this table, e.g. "pico8",
+    /// instead of as globals.
+    #[arg(long, value_name = "TABLE")]
+    namespace: Option<String>,
+    /// Prepend the shims the code needs, or write them to this file.
+    #[arg(long, num_args = 0..=1, require_equals = true)]
+    shims: Option<Option<PathBuf>>,
+    /// Check that the output compiles.
+    #[cfg(feature = "mlua")]
+    #[arg(long)]
+    check: bool,
+    /// Resolve the includes, however deep, looking next to the cart and
+    /// then in these directories in order.
+    #[arg(long, value_name = "DIR")]
+    include_path: Vec<PathBuf>,
+    /// Resolve the includes and surround the code of each with
+    /// "-- >>> include foo.p8" and "-- <<< include foo.p8" comments.
+    #[arg(long, conflicts_with = "require")]
+    include_markers: bool,
+    /// Resolve the includes and include each file only the first time,
+    /// leaving a comment in place of each later include of it.
+    #[arg(long, conflicts_with = "require")]
+    include_once: bool,
+    /// Rewrite each include as a require() of its module instead of
+    /// resolving it, e.g. `require("lib.util")` for "lib/util.lua".
+    #[arg(long, conflicts_with = "include_path")]
+    require: bool,
+    /// Name the modules of the included paths that start with FROM as if
+    /// they started with TO instead. May be repeated.
+    #[arg(long, value_name = "FROM=TO", requires = "require", value_parser = parse_module_map)]
+    module_map: Vec<(String, String)>,
+    /// Convert each file the code requires into this directory as a module
+    /// of its own, however deep.
+    #[arg(long, value_name = "DIR", requires = "require")]
+    modules_dir: Option<PathBuf>,
+    /// Convert each tab into this directory as a module of its own, named
+    /// from the comment that opens it, with a "main.lua" that requires them
+    /// in order.
+    #[arg(
+        long,
+        value_name = "DIR",
+        conflicts_with_all = ["output", "in_place", "include_path", "require"]
+    )]
+    split_tabs: Option<PathBuf>,
+    /// Print what the conversion did to stderr, as text or JSON.
+    #[arg(
+        long,
+        value_name = "FORMAT",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "text"
+    )]
+    report: Option<ReportFormat>,
+    /// Write the cart line of each converted line as JSON to this file.
+    #[arg(long)]
+    source_map: Option<PathBuf>,
+}
+
+/// What `convert` outputs.
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Format {
+    P8,
+    Lua,
+    Auto,
+}
+
+/// How `convert --report` prints its stats.
+#[derive(Clone, Copy, ValueEnum)]
+enum ReportFormat {
+    Text,
+    Json,
+}
+
+fn parse_module_map(map: &str) -> Result<(String, String), String> {
+    map.split_once('=')
+        .map(|(from, to)| (from.to_string(), to.to_string()))
+        .ok_or_else(|| format!("expected FROM=TO, not {map:?}"))
+}
+
+fn parse_button(button: &str) -> Result<(String, u8), String> {
+    button
+        .rsplit_once('=')
+        .and_then(|(text, value)| Some((text.to_string(), value.parse().ok()?)))
+        .filter(|(text, _)| !text.is_empty())
+        .ok_or_else(|| format!("expected TEXT=VALUE with a value from 0 to 255, not {button:?}"))
+}
+
+/// Return the built-in button glyphs with the `--button`s of `args`, if
+/// there are any.
+fn button_map(args: &ConvertArgs) -> Option<glyph::ButtonMap> {
+    (!args.buttons.is_empty()).then(|| {
+        args.buttons
+            .iter()
+            .fold(glyph::ButtonMap::default(), |map, (text, value)| map.with(text.as_str(), *value))
+    })
+}
+
+/// Return the module of the included `path` after the first of `maps` that
+/// its start matches.
+fn map_module(maps: &[(String, String)], path: &str) -> String {
+    let path = maps
+        .iter()
+        .find_map(|(from, to)| Some(format!("{to}{}", path.strip_prefix(from.as_str())?)))
+        .unwrap_or_else(|| path.to_string());
+    module_name(&path)
+}
+
+fn parse_glyph_style(style: &str) -> Result<p8scii::GlyphStyle, String> {
+    match style {
+        "escape" => Ok(p8scii::GlyphStyle::Escape),
+        "chr" => Ok(p8scii::GlyphStyle::Chr),
+        _ => Err(format!("unknown glyph style {:?}; expected escape or chr", style)),
+    }
+}
+
+fn parse_control_codes(mode: &str) -> Result<p8scii::ControlCodes, String> {
+    match mode {
+        "strip" => Ok(p8scii::ControlCodes::Strip),
+        "bytes" => Ok(p8scii::ControlCodes::Bytes),
+        _ => match mode.strip_prefix("wrap:") {
+            Some(function) => Ok(p8scii::ControlCodes::Wrap(function.to_string())),
+            None => Err(format!(
+                "unknown control code mode {:?}; expected strip, bytes, or wrap:<function>",
+                mode
+            )),
+        },
+    }
+}
+
+/// Return `args` with the forms from before the subcommands rewritten to
+/// them, so "pico8-to-lua cart.p8" still converts and "--list-passes" and
+/// "--stdin-batch" still work.
+fn upgrade_args(mut args: Vec<OsString>) -> Vec<OsString> {
+    let Some(first) = args.get(1).and_then(|a| a.to_str()).map(str::to_string) else {
+        return args;
+    };
+    match first.as_str() {
+        "--list-passes" => args[1] = "passes".into(),
+        "--stdin-batch" => args[1] = "batch".into(),
+        "-" => args.insert(1, "convert".into()),
+        _ if first.starts_with('-') || first == "help" => {}
+        _ if Cli::command()
+            .get_subcommands()
+            .any(|c| c.get_name() == first.as_str()) => {}
+        _ => args.insert(1, "convert".into()),
+    }
+    args
+}
+
+/// Exit with `error` about `path`.
+fn fail(path: &Path, error: impl std::fmt::Display) -> ! {
+    eprintln!("ERROR: {}: {}", path.display(), error);
+    std::process::exit(1);
+}
+
+fn read_cart(path: &Path) -> Result<Cart, io::Error> {
+    Ok(Cart::parse(&fs::read_to_string(path)?).unwrap_or_else(|e| fail(path, e)))
+}
+
+fn includes(path: &Path, flatten: bool) -> Result<(), io::Error> {
+    let lua = read_lua(path).unwrap_or_else(|e| fail(path, e));
+    let mut resolver = FsResolver::for_cart(path, Vec::<PathBuf>::new());
+    if !flatten {
+        for include in find_includes(&lua) {
+            match resolver.expand(include) {
+                Some(paths) => paths.iter().for_each(|path| println!("{}", path)),
+                None => println!("{}", include),
+            }
+        }
+        return Ok(());
+    }
+    let lua = try_patch_includes(lua, |include| resolver.resolve(include))
+        .unwrap_or_else(|e| fail(path, e));
+    print!("{}", lua);
+    Ok(())
+}
+
+fn check(paths: &[PathBuf]) -> Result<(), io::Error> {
+    let mut changed = false;
+    for path in paths {
+        let format = detect::detect_path(path).unwrap_or_else(|e| fail(path, e));
+        let text = if matches!(format, detect::Format::P8Png | detect::Format::Rom) {
+            read_lua(path).unwrap_or_else(|e| fail(path, e))
+        } else {
+            fs::read_to_string(path)?
+        };
+        let (patched, outcome) =
+            patch_lua_with_outcome(text.as_str(), &PatchOptions::default());
+        if !outcome.is_changed() || patched == text {
+            continue;
+        }
+        changed = true;
+        let name = path.display().to_string();
+        eprintln!("{}: {}", name, outcome);
+        let diff = similar::TextDiff::from_lines(text.as_str(), patched.as_ref());
+        print!("{}", diff.unified_diff().header(&name, &name));
+    }
+    if changed {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+fn diff(old: &Path, new: &Path, json: bool) -> Result<(), io::Error> {
+    let (old_cart, new_cart) = (read_cart(old)?, read_cart(new)?);
+    let diff = cart_diff::diff_carts(&old_cart, &new_cart).unwrap_or_else(|e| fail(new, e));
+    if json {
+        println!("{}", diff.to_json());
+        return Ok(());
+    }
+    if let Some((old_lua, new_lua)) = &diff.lua {
+        let (old, new) = (old.display().to_string(), new.display().to_string());
+        let text = similar::TextDiff::from_lines(old_lua.as_str(), new_lua.as_str());
+        print!("{}", text.unified_diff().header(&old, &new));
+    }
+    print!("{diff}");
+    Ok(())
+}
+
+/// Write the spritesheet and the label of `cart` as PNG files, if asked to.
+#[cfg(feature = "png")]
+fn extract_images(
+    path: &Path,
+    cart: &Cart,
+    gfx: Option<PathBuf>,
+    label: Option<PathBuf>,
+) -> Result<(), io::Error> {
+    let create = |out: &Path| Ok::<_, io::Error>(io::BufWriter::new(fs::File::create(out)?));
+    if let Some(out) = gfx {
+        let sheet = cart.gfx().unwrap_or_else(|e| fail(path, e));
+        sheet.write_png(create(&out)?).unwrap_or_else(|e| fail(&out, e));
+    }
+    if let Some(out) = label {
+        match cart.label().unwrap_or_else(|e| fail(path, e)) {
+            Some(image) => image.write_png(create(&out)?).unwrap_or_else(|e| fail(&out, e)),
+            None => fail(path, "no __label__ section"),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "png"))]
+fn extract_images(
+    path: &Path,
+    _cart: &Cart,
+    gfx: Option<PathBuf>,
+    label: Option<PathBuf>,
+) -> Result<(), io::Error> {
+    if gfx.is_some() || label.is_some() {
+        fail(path, "writing PNG files requires the \"png\" feature");
+    }
+    Ok(())
+}
+
+fn tokens(path: &Path) -> Result<(), io::Error> {
+    let lua = read_lua(path).unwrap_or_else(|e| fail(path, e));
+    let report = size_report(&lua);
+    println!("tokens:     {:5} / {}", report.tokens, size::TOKEN_LIMIT);
+    println!("chars:      {:5} / {}", report.chars, size::CHAR_LIMIT);
+    println!("compressed: {:5} / {}", report.compressed, size::COMPRESSED_LIMIT);
+    let mut tabs: Vec<(String, usize)> = cart::tabs(&lua)
+        .iter()
+        .enumerate()
+        .map(|(i, tab)| (format!("tab {i}"), size::tokens(tab)))
+        .collect();
+    let mut functions = size::function_tokens(&lua);
+    for (heading, rows) in [("tabs", &mut tabs), ("functions", &mut functions)] {
+        rows.sort_by_key(|row| std::cmp::Reverse(row.1));
+        let width = rows.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+        println!("\n{heading}:");
+        for (name, tokens) in rows.iter() {
+            println!("  {name:width$}  {tokens:5}");
+        }
+    }
+    if !report.fits() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+fn verify(path: &Path, #[cfg(feature = "mlua")] compile: bool) -> Result<(), io::Error> {
+    if path.is_dir() {
+        return verify_dir(
+            path,
+            #[cfg(feature = "mlua")]
+            compile,
+        );
+    }
+    let lua = read_lua(path).unwrap_or_else(|e| fail(path, e));
+    let lua = patch_lua(lua);
+    let violations = verify::verify(&lua, &verify::Policy::default());
+    for violation in &violations {
+        let (line, column) = violation.span.line_col(&lua);
+        eprintln!("{}:{}:{}: {}", path.display(), line, column, violation.kind);
+    }
+    #[cfg(feature = "mlua")]
+    if compile {
+        if let Err(e) = compile::verify(&lua) {
+            fail(path, e);
+        }
+    }
+    if !violations.is_empty() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// Verify every cart under `dir` like [verify] and print whether each passed
+/// with the first problem of each that failed.
+fn verify_dir(dir: &Path, #[cfg(feature = "mlua")] compile: bool) -> Result<(), io::Error> {
+    let mut files = Vec::new();
+    cart_files(dir, |p| is_p8(p) || is_p8png(p), &mut files)?;
+    let width = files
+        .iter()
+        .map(|f| f.display().to_string().len())
+        .max()
+        .unwrap_or(0);
+    let mut failed = 0;
+    for file in &files {
+        let name = file.display().to_string();
+        match first_problem(
+            file,
+            #[cfg(feature = "mlua")]
+            compile,
+        ) {
+            None => println!("pass  {name}"),
+            Some(problem) => {
+                failed += 1;
+                println!("FAIL  {name:width$}  {problem}");
+            }
+        }
+    }
+    println!("{} passed, {failed} failed", files.len() - failed);
+    if failed > 0 {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// Return the first problem with the converted code of the cart at `path`,
+/// or `None` if it has none.
+fn first_problem(path: &Path, #[cfg(feature = "mlua")] compile: bool) -> Option<String> {
+    let lua = match read_lua(path) {
+        Ok(lua) => patch_lua(lua),
+        Err(e) => return Some(e.to_string()),
+    };
+    if let Some(violation) = verify::verify(&lua, &verify::Policy::default()).first() {
+        let (line, column) = violation.span.line_col(&lua);
+        return Some(format!("{}:{}: {}", line, column, violation.kind));
+    }
+    #[cfg(feature = "mlua")]
+    if compile {
+        if let Err(e) = compile::verify(&lua) {
+            return Some(e.to_string());
+        }
+    }
+    None
+}
+
+/// How often to look for changes to the watched files.
+const POLL: Duration = Duration::from_millis(250);
+/// How long the watched files must be left alone before converting, so that
+/// an editor saving several files converts once.
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+impl Command {
+    /// The inputs to watch, or `None` if the subcommand has none.
+    fn inputs(&self) -> Option<Vec<String>> {
+        let path = |p: &PathBuf| vec![p.to_string_lossy().into_owned()];
+        Some(match self {
+            Command::Convert(args) => args.paths.clone(),
+            Command::Check { paths } => paths.iter().flat_map(&path).collect(),
+            Command::Includes { path: p, .. }
+            | Command::Extract { path: p, .. }
+            | Command::Tokens { path: p }
+            | Command::Verify { path: p, .. }
+            | Command::Report { path: p, .. }
+            | Command::Api { path: p }
+            | Command::Data { path: p }
+            | Command::DeadCode { path: p }
+            | Command::Love { path: p, .. }
+            | Command::Tic80 { path: p, .. } => path(p),
+            Command::Diff { old, new, .. } => [path(old), path(new)].concat(),
+            Command::Assemble { main, data, .. } => {
+                [path(main), data.iter().flat_map(&path).collect()].concat()
+            }
+            Command::Grep { paths, .. } => paths.iter().flat_map(&path).collect(),
+            Command::Dedupe { dir } => path(dir),
+            Command::Batch { .. } | Command::Passes | Command::Serve { .. } | Command::Lsp => {
+                return None;
+            }
+        })
+    }
+}
+
+/// The modification time of every file `inputs` names, to tell when one
+/// changes. Globs are expanded again each time so new files are seen.
+fn snapshot(inputs: &[String]) -> Vec<(PathBuf, Option<SystemTime>)> {
+    let mut files = Vec::new();
+    for input in inputs {
+        // A glob that no longer matches or a missing file is just not there.
+        let _ = expand(input, &mut files);
+    }
+    files
+        .into_iter()
+        .map(|(path, _)| {
+            let modified = fs::metadata(&path).and_then(|m| m.modified()).ok();
+            (path, modified)
+        })
+        .collect()
+}
+
+/// Run the command `args` gives without "--watch" each time an input
+/// changes, reporting whether it failed, until interrupted.
+fn watch(inputs: &[String], args: Vec<OsString>) -> Result<(), io::Error> {
+    if inputs.iter().any(|i| i == "-") {
+        eprintln!("ERROR: Cannot watch stdin");
+        process::exit(1);
+    }
+    // Each run would change the file it watches.
+    if args.iter().any(|a| a == "--in-place") {
+        eprintln!("ERROR: Cannot watch a file converted in place");
+        process::exit(1);
+    }
+    let exe = env::current_exe()?;
+    let args: Vec<_> = args.into_iter().skip(1).filter(|a| a != "--watch").collect();
+    let mut last = None;
+    loop {
+        let now = snapshot(inputs);
+        if last.as_ref() != Some(&now) {
+            thread::sleep(DEBOUNCE);
+            if snapshot(inputs) == now {
+                // Each run is its own process so an error ends only it.
+                let status = process::Command::new(&exe).args(&args).status()?;
+                if status.success() {
+                    eprintln!("watch: done; waiting for changes");
+                } else {
+                    eprintln!("watch: failed ({}); waiting for changes", status);
+                }
+                last = Some(now);
+            }
+        }
+        thread::sleep(POLL);
+    }
 }
 
 fn main() -> Result<(), io::Error> {
-    let args: Vec<String> = env::args().collect();
-    if args.len() < 2 {
-        eprintln!("ERROR: Must provide filename argument");
+    let args = upgrade_args(env::args_os().collect());
+    let cli = Cli::parse_from(args.clone());
+    if cli.watch {
+        let Some(inputs) = cli.command.inputs() else {
+            eprintln!("ERROR: This subcommand has no inputs to watch");
+            process::exit(1);
+        };
+        return watch(&inputs, args);
+    }
+    match cli.command {
+        Command::Convert(args) => convert(*args),
+        Command::Check { paths } => check(&paths),
+        Command::Includes { path, flatten } => includes(&path, flatten),
+        Command::Extract {
+            path,
+            section,
+            lua,
+            gfx,
+            map,
+            label,
+        } => {
+            let cart = read_cart(&path)?;
+            if lua.is_none() && gfx.is_none() && map.is_none() && label.is_none() {
+                match cart.section(&section) {
+                    Some(body) => print!("{}", body),
+                    None => fail(&path, format!("no __{}__ section", section)),
+                }
+                return Ok(());
+            }
+            if let Some(out) = lua {
+                fs::write(out, cart.lua().unwrap_or(""))?;
+            }
+            if let Some(out) = map {
+                let tiles = cart.map().unwrap_or_else(|e| fail(&path, e));
+                fs::write(out, tiles.to_json())?;
+            }
+            extract_images(&path, &cart, gfx, label)
+        }
+        Command::Tokens { path } => tokens(&path),
+        #[cfg(feature = "mlua")]
+        Command::Verify { path, compile } => verify(&path, compile),
+        #[cfg(not(feature = "mlua"))]
+        Command::Verify { path } => verify(&path),
+        Command::Batch { lua_only } => {
+            let options = Options {
+                lua_only,
+                ..Options::default()
+            };
+            batch::convert_stream(io::stdin().lock(), io::stdout().lock(), &options)
+        }
+        Command::Passes => {
+            for pass in pipeline::Patcher::new(Options::default()).describe() {
+                let off = if pass.enabled { "" } else { " (off)" };
+                println!("{:2} {:<20} {}{}", pass.order, pass.name, pass.category, off);
+            }
+            Ok(())
+        }
+        Command::Report { path, json } => {
+            let text = fs::read_to_string(&path)?;
+            let report = report::PortingReport::new(&path.to_string_lossy(), &text);
+            if json {
+                println!("{}", report.to_json());
+            } else {
+                print!("{}", report.to_markdown());
+            }
+            Ok(())
+        }
+        Command::Diff { old, new, json } => diff(&old, &new, json),
+        Command::Assemble {
+            main,
+            data,
+            include_path,
+            output,
+            cart_version,
+        } => {
+            let lua = fs::read_to_string(&main)?;
+            let data = data.map(|path| read_cart(&path)).transpose()?;
+            let mut resolver = FsResolver::for_cart(&main, &include_path);
+            let header = CartHeader {
+                version: cart_version,
+                ..CartHeader::default()
+            };
+            let resolve = |path: &str| resolver.resolve_nested(path);
+            let cart = assemble::assemble_with(&lua, resolve, data.as_ref(), &header)
+                .unwrap_or_else(|e| fail(&main, e));
+            match output {
+                Some(output) => fs::write(output, cart.to_string()),
+                None => {
+                    print!("{cart}");
+                    Ok(())
+                }
+            }
+        }
+        Command::Api { path } => {
+            let lua = read_lua(&path).unwrap_or_else(|e| fail(&path, e));
+            let mut calls: Vec<_> = api::calls(&lua).into_iter().collect();
+            calls.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+            for (name, count) in calls {
+                println!("{:5} {}", count, name);
+            }
+            Ok(())
+        }
+        Command::Data { path } => {
+            let lua = read_lua(&path).unwrap_or_else(|e| fail(&path, e));
+            print!("{}", data_usage::data_usage(&lua));
+            Ok(())
+        }
+        Command::DeadCode { path } => {
+            let lua = read_lua(&path).unwrap_or_else(|e| fail(&path, e));
+            print!("{}", dead_code::dead_code(&lua));
+            Ok(())
+        }
+        Command::Love { path, output } => {
+            let cart = read_cart(&path)?;
+            let output = output.unwrap_or_else(|| {
+                let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+                format!("{}-love", stem)
+            });
+            // A ".love" file is a zip archive that LÖVE runs as is.
+            let result = if output.ends_with(".love") || output.ends_with(".zip") {
+                let file = io::BufWriter::new(fs::File::create(&output)?);
+                love::export(&cart, &mut sink::Archive::new(file))
+            } else {
+                love::export(&cart, &mut sink::Files::new(&output))
+            };
+            if let Err(e) = result {
+                fail(&path, e);
+            }
+            Ok(())
+        }
+        Command::Tic80 { path, output } => {
+            let cart = read_cart(&path)?;
+            let tic = tic80::to_tic(&cart).unwrap_or_else(|e| fail(&path, e));
+            fs::write(output.unwrap_or_else(|| path.with_extension("tic")), tic)
+        }
+        Command::Grep {
+            converted,
+            pattern,
+            paths,
+        } => grep(converted, &pattern, &paths),
+        Command::Dedupe { dir } => dedupe(&dir),
+        Command::Serve { addr, port } => {
+            #[cfg(feature = "serve")]
+            return serve::serve(serve::with_port(&addr, port));
+            #[cfg(not(feature = "serve"))]
+            {
+                let port = port.map(|port| format!(" on port {port}")).unwrap_or_default();
+                eprintln!("ERROR: Cannot serve {}{}; build with the \"serve\" feature", addr, port);
+                std::process::exit(1);
+            }
+        }
+        Command::Lsp => {
+            #[cfg(feature = "lsp")]
+            return lsp::serve_stdio();
+            #[cfg(not(feature = "lsp"))]
+            {
+                eprintln!("ERROR: Cannot serve diagnostics; build with the \"lsp\" feature");
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+fn is_lua(path: &Path) -> bool {
+    detect::Format::from_path(path) == Some(detect::Format::Lua)
+}
+
+/// Add the files `arg` names to `files`, each with its path relative to
+/// where it was found: a directory's carts and Lua files relative to it, a
+/// glob's matches relative to the directory before its first wildcard, and a
+/// file by its name.
+fn expand(arg: &str, files: &mut Vec<(PathBuf, PathBuf)>) -> Result<(), io::Error> {
+    let path = Path::new(arg);
+    let relative = |file: &Path, base: &Path| {
+        file.strip_prefix(base).unwrap_or(file).to_path_buf()
+    };
+    let is_wild = |s: &str| s.contains(['*', '?', '[']);
+    if path.is_dir() {
+        let mut found = Vec::new();
+        cart_files(path, |p| is_p8(p) || is_lua(p), &mut found)?;
+        files.extend(found.into_iter().map(|file| {
+            let relative = relative(&file, path);
+            (file, relative)
+        }));
+    } else if is_wild(arg) {
+        let base: PathBuf = path
+            .components()
+            .take_while(|c| !is_wild(&c.as_os_str().to_string_lossy()))
+            .collect();
+        let matches =
+            glob::glob(arg).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        for file in matches {
+            let file = file?;
+            if file.is_file() {
+                files.push((file.clone(), relative(&file, &base)));
+            }
+        }
+    } else {
+        let name = path.file_name().unwrap_or(path.as_os_str());
+        files.push((path.to_path_buf(), PathBuf::from(name)));
+    }
+    Ok(())
+}
+
+/// Convert every file `args` names into `out_dir` on every core.
+fn convert_files(args: &ConvertArgs, out_dir: &Path) -> Result<(), io::Error> {
+    let single_only = args.output.is_some()
+        || args.in_place
+        || args.strict
+        || args.verbose
+        || args.shims.is_some()
+        || args.source_map.is_some()
+        || !args.include_path.is_empty()
+        || args.include_markers
+        || args.include_once
+        || args.require
+        || args.split_tabs.is_some()
+        || args.report.is_some();
+    if single_only || args.paths.iter().any(|p| p == "-") {
+        eprintln!(
+            "ERROR: --output, --in-place, --strict, --verbose, --shims, --source-map, \
+             --include-path, --include-markers, --include-once, --require, --split-tabs, --report, \
+             and stdin take one file and not --out-dir"
+        );
         std::process::exit(1);
     }
+    let mut files = Vec::new();
+    for arg in &args.paths {
+        expand(arg, &mut files)?;
+    }
+    let lua_only = args.lua_only || args.format == Format::Lua;
+    let options = batch_options(args, lua_only);
+    let paths: Vec<&PathBuf> = files.iter().map(|(path, _)| path).collect();
+    let outputs = match &args.cache {
+        Some(dir) => {
+            batch::par_convert_files_cached(&paths, &options, &mut batch::DirCache::new(dir))
+        }
+        None => batch::par_convert_files(&paths, &options),
+    };
+    let mut failed = false;
+    for ((path, relative), output) in files.iter().zip(outputs) {
+        let result = output
+            .map_err(|e| e.to_string())
+            .and_then(|o| o.result.map_err(|e| e.to_string()));
+        let text = match result {
+            Ok(text) => text,
+            Err(e) => {
+                eprintln!("ERROR: {}: {}", path.display(), e);
+                failed = true;
+                continue;
+            }
+        };
+        let target = if lua_only {
+            out_dir.join(relative).with_extension("lua")
+        } else {
+            out_dir.join(relative)
+        };
+        if let Some(dir) = target.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(target, text)?;
+    }
+    if failed {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// Download the code of the BBS cart that `filename` names, unless it is a
+/// file.
+#[cfg(feature = "net")]
+fn fetch(filename: &str, in_place: bool) -> Option<String> {
+    if Path::new(filename).exists() {
+        return None;
+    }
+    pico8_to_lua::bbs::cart_id(filename)?;
+    if in_place {
+        eprintln!("ERROR: Cannot convert a BBS cart in place");
+        std::process::exit(1);
+    }
+    Some(pico8_to_lua::bbs::fetch_code(filename).unwrap_or_else(|e| fail(Path::new(filename), e)))
+}
+
+#[cfg(not(feature = "net"))]
+fn fetch(_filename: &str, _in_place: bool) -> Option<String> {
+    None
+}
+
+/// Return the options of [batch] conversions that `args` asks for.
+fn batch_options(args: &ConvertArgs, lua_only: bool) -> Options {
+    Options {
+        lua_only,
+        patch: PatchOptions {
+            strip_comments: args.strip_comments,
+            ..PatchOptions::default()
+        },
+        target: args.target,
+        bitwise: args.bitwise,
+        deprecated_api: args.deprecated_api,
+        fixed_point: args.fixed_point,
+        inline_math: args.inline_math,
+        trigonometry: args.trigonometry,
+        all_loops: args.all_loops,
+        table_builtins: args.table_builtins,
+        coroutines: args.coroutines,
+        strip_debug: args.strip_debug,
+        namespace: args.namespace.clone(),
+        glyph_numbers: args.glyph_numbers,
+        buttons: button_map(args),
+        string_glyphs: args.string_glyphs.clone(),
+        control_codes: args.control_codes.clone(),
+        format: args.indent.map(|indent| format::FormatOptions { indent }),
+        marker: args.marker,
+        ..Options::default()
+    }
+}
+
+/// Rewrite the includes of `lua` as requires and, with `--modules-dir`,
+/// convert each module into it.
+fn require_modules(args: &ConvertArgs, filename: &str, lua: String) -> String {
+    let module = |path: &str| map_module(&args.module_map, path);
+    let Some(dir) = &args.modules_dir else {
+        return require_includes(lua.as_str(), module).into_owned();
+    };
+    let mut resolver = FsResolver::for_cart(filename, Vec::<PathBuf>::new());
+    let (lua, modules) = resolver
+        .modules(&lua, module)
+        .unwrap_or_else(|e| fail(Path::new(filename), e));
+    let mut converter = batch::Converter::new(batch_options(args, true));
+    let mut sink = sink::Files::new(dir);
+    for module in modules {
+        let converted = converter
+            .convert(&module.lua)
+            .unwrap_or_else(|e| fail(Path::new(&module.path), e));
+        let file = module.file().to_string_lossy().into_owned();
+        sink.write(&file, converted.as_bytes())
+            .unwrap_or_else(|e| fail(&dir.join(&file), e));
+    }
+    lua.into_owned()
+}
 
-    let filename = &args[1];
-    let output_lua_only = args.len() > 2 && args[2] == "--lua-only";
+fn convert(args: ConvertArgs) -> Result<(), io::Error> {
+    if let Some(out_dir) = &args.out_dir {
+        return convert_files(&args, out_dir);
+    }
+    if args.paths.len() > 1 {
+        eprintln!("ERROR: Converting several files needs --out-dir");
+        std::process::exit(1);
+    }
+    let filename = &args.paths[0];
 
+    if args.in_place && filename == "-" {
+        eprintln!("ERROR: Cannot convert stdin in place");
+        std::process::exit(1);
+    }
     let input = if filename == "-" {
         let mut buffer = String::new();
         io::stdin().read_to_string(&mut buffer)?;
         buffer
+    } else if let Some(code) = fetch(filename, args.in_place) {
+        code
     } else {
         fs::read_to_string(filename).unwrap_or_else(|_| {
             eprintln!("ERROR: File {} not found", filename);
             std::process::exit(1);
         })
     };
+    if let Some(dir) = &args.split_tabs {
+        let (loader, tabs) = split::convert_tabs(&input, &batch_options(&args, true))
+            .unwrap_or_else(|e| fail(Path::new(filename), e));
+        let mut sink = sink::Files::new(dir);
+        sink.write(&format!("{}.lua", split::LOADER), loader.as_bytes())?;
+        for tab in tabs {
+            sink.write(&tab.file().to_string_lossy(), tab.lua.as_bytes())?;
+        }
+        return Ok(());
+    }
+    let crlf = uses_crlf(&input);
 
-    let mut before_lua = None;
-    let mut after_lua = None;
-
-    let is_p8_file = input.starts_with("pico-8 cartridge");
-    let pico8_lua = if is_p8_file {
-        let before_delimiter = "__lua__\n";
-        let after_delimiter = "__gfx__";
+    let is_p8_file = cart::is_cart(&input);
+    let format = match args.format {
+        Format::Auto if args.lua_only || !is_p8_file => Format::Lua,
+        Format::Auto => Format::P8,
+        format => format,
+    };
+    // The lines of a cart before its Lua, for the source map.
+    let lines_before_lua = cart::lua_span(&input)
+        .map_or(0, |span| input[..span.start].matches('\n').count());
+    // Every section of a cart is kept as it is but the Lua.
+    let mut parsed = is_p8_file
+        .then(|| Cart::parse(&input).unwrap_or_else(|e| fail(Path::new(filename), e)));
+    let pico8_lua = match &parsed {
+        Some(cart) => cart.lua().unwrap_or("").to_string(),
+        None => input,
+    };
 
-        let t1 = split(&input, before_delimiter);
-        if t1.len() > 1 {
-            before_lua = Some(t1[0].to_string());
-            let t2 = split(t1[1], after_delimiter);
-            if t2.len() > 1 {
-                after_lua = Some(t2[1].to_string());
+    let mut included = Vec::new();
+    let pico8_lua = if args.require {
+        require_modules(&args, filename, pico8_lua)
+    } else if args.include_path.is_empty() && !args.include_markers && !args.include_once {
+        pico8_lua
+    } else {
+        let mut resolver = FsResolver::for_cart(filename, &args.include_path);
+        resolver.markers = args.include_markers;
+        resolver.once = args.include_once;
+        try_patch_includes(pico8_lua, |path| {
+            included.push(path.to_string());
+            resolver.resolve_nested(path)
+        })
+        .unwrap_or_else(|e| fail(Path::new(filename), e))
+        .into_owned()
+    };
+    let source = pico8_lua.clone();
+    // Converting the output of a conversion again would rewrite the helpers
+    // it added, so marked code is left as it is.
+    let converted = is_converted(&pico8_lua);
+    // The passes run as they do for --out-dir, in the order of the pipeline.
+    let mut patcher = pipeline::Patcher::new(batch_options(&args, false));
+    patcher.set_cart_version(parsed.as_ref().and_then(|cart| cart.meta().version));
+    let patch_options = patcher.options().patch;
+    if (args.strict || args.verbose) && !converted {
+        let checked = match button_map(&args) {
+            Some(buttons) => glyph::patch_buttons(pico8_lua.as_str().into(), &buttons),
+            None => pico8_lua.as_str().into(),
+        };
+        if args.strict {
+            let found = diagnostics::diagnose_strict(&checked);
+            if !found.is_empty() {
+                for diagnostic in found {
+                    eprintln!("ERROR: {}", diagnostic);
+                }
+                std::process::exit(1);
             }
-            t2[0].to_string()
         } else {
-            input
+            for diagnostic in diagnostics::diagnose(&checked) {
+                eprintln!("{}", diagnostic);
+            }
+            for diagnostic in diagnostics::diagnose_numbers(&checked) {
+                eprintln!("{}", diagnostic);
+            }
+            let (_, report) = patch_lua_with_report(checked.as_ref(), &patch_options);
+            eprint!("{}", report);
         }
+    }
+    let out_str = patcher.patch(&pico8_lua);
+    let version_error = args
+        .target
+        .filter(|_| !converted)
+        .and_then(|target| version::check_version(&out_str, target.into()).err());
+    if let Some(e) = version_error {
+        eprintln!("ERROR: code does not run on {}", e.target);
+        for requirement in e.requirements {
+            let (line, column) = requirement.span.line_col(&out_str);
+            eprintln!(
+                "{}:{}: {} needs {}",
+                line,
+                column,
+                requirement.feature,
+                requirement.feature.version()
+            );
+        }
+        std::process::exit(1);
+    }
+    let out_str = match args.shims.as_ref().filter(|_| !converted) {
+        Some(None) => shim::with_shims(out_str),
+        Some(Some(path)) => {
+            fs::write(path, shim::module(&shim::needed(&out_str)))?;
+            out_str
+        }
+        None => out_str,
+    };
+    let out_str = if args.marker {
+        marker::with_marker(out_str)
     } else {
-        input
+        out_str
     };
-
-    let out_str = patch_lua(pico8_lua);
-    if is_p8_file && !output_lua_only {
-        print!("{}__lua__\n{}", before_lua.unwrap_or("".into()), out_str);
-        if after_lua.is_some() {
-            print!("__gfx__{}", after_lua.unwrap());
+    let source_map = || {
+        let flattened = Flattened {
+            lua: source.clone(),
+            regions: Vec::new(),
+        };
+        let mut map = sourcemap::SourceMap::new(&flattened, &out_str);
+        // A cart's Lua starts after its header and the "__lua__" line.
+        let skip = lines_before_lua;
+        for line in &mut map.lines {
+            line.line += skip;
+        }
+        map
+    };
+    #[cfg(feature = "mlua")]
+    if args.check {
+        if let Err(e) = compile::verify_with_map(&out_str, &source_map()) {
+            eprintln!("ERROR: {}", e);
+            std::process::exit(1);
+        }
+    }
+    if let Some(format) = args.report {
+        let stats =
+            stats::ConversionStats::new(&source, &out_str, included, &patch_options);
+        match format {
+            ReportFormat::Text => eprint!("{}", stats),
+            ReportFormat::Json => eprintln!("{}", stats.to_json()),
+        }
+    }
+    if let Some(path) = &args.source_map {
+        fs::write(path, source_map().to_json())?;
+    }
+    let out = match format {
+        Format::P8 if is_p8_file => {
+            let mut cart = parsed.take().expect("a parsed cart");
+            if let Some(lua) = cart.sections.iter_mut().find(|s| s.name == "lua") {
+                lua.body = out_str.into_owned();
+            }
+            cart.to_string()
+        }
+        Format::P8 => {
+            let header = CartHeader {
+                version: args.cart_version,
+                ..CartHeader::default()
+            };
+            Cart::with_lua(&header, out_str).to_string()
         }
+        _ => out_str.into_owned(),
+    };
+    // Lines the conversion added end like the lines of the input.
+    let out = if crlf {
+        out.replace("\r\n", "\n").replace('\n', "\r\n")
     } else {
-        print!("{}", out_str);
+        out
+    };
+    if args.in_place {
+        if args.backup {
+            // Copying keeps the permissions of the original on the backup.
+            fs::copy(filename, format!("{}.bak", filename))?;
+        }
+        // Writing over the file rather than replacing it keeps its
+        // permissions.
+        return fs::write(filename, out);
     }
-    Ok(())
+    let name = Path::new(filename)
+        .file_name()
+        .filter(|_| filename != "-")
+        .map_or("stdin.lua".into(), |n| n.to_string_lossy());
+    let mut sink = output_sink(args.output.as_deref())?;
+    sink.write(&name, out.as_bytes())?;
+    sink.finish()
+}
+
+/// Return true if every line of `text` ends with "\r\n".
+fn uses_crlf(text: &str) -> bool {
+    let lines = text.matches('\n').count();
+    lines > 0 && text.matches("\r\n").count() == lines
+}
+
+/// Return where to write output: a zip archive if `output` ends with ".zip",
+/// the file `output` otherwise, or stdout if there is none.
+fn output_sink(output: Option<&str>) -> Result<Box<dyn OutputSink>, io::Error> {
+    /// Writes every file to one path.
+    struct File(String);
+
+    impl OutputSink for File {
+        fn write(&mut self, _name: &str, contents: &[u8]) -> io::Result<()> {
+            sink::Files::new("").write(&self.0, contents)
+        }
+    }
+
+    Ok(match output {
+        Some(path) if path.ends_with(".zip") => {
+            Box::new(sink::Archive::new(io::BufWriter::new(fs::File::create(path)?)))
+        }
+        Some(path) => Box::new(File(path.to_string())),
+        None => Box::new(sink::Stdout),
+    })
 }
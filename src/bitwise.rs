@@ -0,0 +1,342 @@
+//! Translation of Pico-8's bitwise operators.
+//!
+//! Pico-8 has `&`, `|`, `^^`, `~`, `<<`, `>>`, `>>>`, `<<>`, and `>><`, and
+//! their compound assignments like `&=`. They work on the 32 bits of a 16.16
+//! fixed point number, so `5 >> 1` is `2.5` and `0.5 | 1` is `1.5`.
+//! [patch_bitwise] rewrites each into a call to a helper defined at the top of
+//! the code that scales its operands by 0x10000, does the operation on the
+//! bits for a [LuaTarget], and scales the result back: with the `bit` library
+//! of LuaJIT for Lua 5.1, with `bit32` for Lua 5.2, with native operators for
+//! Lua 5.3 and later, or with arithmetic alone.
+//!
+//! Run [patch_lua](crate::patch_lua) first; this pass only handles the
+//! bitwise operators.
+use std::{borrow::Cow, error::Error, fmt, str::FromStr};
+
+use crate::{
+    operators::{Operators, Translation, rewrite_operators},
+    symbols::{DEFAULT_PREFIX, Namer},
+    version::{Feature, LuaVersion},
+};
+
+/// The Lua that operators are translated for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LuaTarget {
+    /// Lua 5.1 with LuaJIT's `bit` library.
+    Lua51,
+    /// Lua 5.2 with the `bit32` library.
+    Lua52,
+    /// Lua 5.3 with native bitwise operators.
+    Lua53,
+    /// Lua 5.4 with native bitwise operators.
+    Lua54,
+    /// LuaJIT, which is Lua 5.1 with the `bit` library, `goto`, and hex
+    /// floats.
+    LuaJit,
+    /// Any Lua, with helpers written with arithmetic alone for hosts that have
+    /// no bitwise library.
+    Portable,
+}
+
+impl LuaTarget {
+    /// The name of the target, e.g. "lua52".
+    pub fn name(self) -> &'static str {
+        match self {
+            LuaTarget::Lua51 => "lua51",
+            LuaTarget::Lua52 => "lua52",
+            LuaTarget::Lua53 => "lua53",
+            LuaTarget::Lua54 => "lua54",
+            LuaTarget::LuaJit => "luajit",
+            LuaTarget::Portable => "portable",
+        }
+    }
+
+    /// Return whether the target's Lua has `feature`.
+    ///
+    /// ```
+    /// use pico8_to_lua::{bitwise::LuaTarget, version::Feature};
+    /// assert!(LuaTarget::LuaJit.supports(Feature::Goto));
+    /// assert!(!LuaTarget::Lua51.supports(Feature::Goto));
+    /// assert!(LuaTarget::Lua54.supports(Feature::IntegerDivision));
+    /// ```
+    pub fn supports(self, feature: Feature) -> bool {
+        match self {
+            LuaTarget::LuaJit => matches!(feature, Feature::Goto | Feature::HexFloat),
+            target => feature.version() <= LuaVersion::from(target),
+        }
+    }
+}
+
+impl fmt::Display for LuaTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// A target name that is not one of "lua51", "lua52", "lua53", "lua54",
+/// "luajit", or "portable".
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownTarget(pub String);
+
+impl fmt::Display for UnknownTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "unknown target {:?}; expected lua51, lua52, lua53, lua54, luajit, or portable",
+            self.0
+        )
+    }
+}
+
+impl Error for UnknownTarget {}
+
+impl FromStr for LuaTarget {
+    type Err = UnknownTarget;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "lua51" => Ok(LuaTarget::Lua51),
+            "lua52" => Ok(LuaTarget::Lua52),
+            "lua53" => Ok(LuaTarget::Lua53),
+            "lua54" => Ok(LuaTarget::Lua54),
+            "luajit" => Ok(LuaTarget::LuaJit),
+            "portable" => Ok(LuaTarget::Portable),
+            _ => Err(UnknownTarget(s.to_string())),
+        }
+    }
+}
+
+struct Bitwise(LuaTarget);
+
+impl Operators for Bitwise {
+    fn translate(&self, op: &str) -> Option<Translation> {
+        Some(Translation::Helper(match op {
+            "&" => "band",
+            "|" => "bor",
+            "^^" | "~" => "bxor",
+            "<<" => "lshift",
+            ">>" => "arshift",
+            ">>>" => "rshift",
+            "<<>" => "rotl",
+            ">><" => "rotr",
+            _ => return None,
+        }))
+    }
+
+    fn unary(&self, op: &str) -> Option<Translation> {
+        (op == "~").then_some(Translation::Helper("bnot"))
+    }
+
+    fn helper_definition(&self, helper: &str, name: &str) -> String {
+        // The operands are the 32 bits of a 16.16 fixed point number, and a
+        // shift or rotation is by a whole number of bits.
+        let (params, operands) = match helper {
+            "bnot" => ("a", "a = math.floor(a * 65536) "),
+            "band" | "bor" | "bxor" => ("a, b", "a = math.floor(a * 65536) b = math.floor(b * 65536) "),
+            _ => ("a, b", "a = math.floor(a * 65536) b = math.floor(b) "),
+        };
+        let bits = match self.0 {
+            LuaTarget::Lua51 | LuaTarget::LuaJit => library_bits("bit", helper),
+            LuaTarget::Lua52 => library_bits("bit32", helper),
+            LuaTarget::Lua53 | LuaTarget::Lua54 => native_bits(helper).to_string(),
+            LuaTarget::Portable => portable_bits(helper),
+        };
+        format!(
+            "local function {name}({params}) {operands}{bits} r = r % 4294967296 \
+             if r >= 2147483648 then r = r - 4294967296 end return r / 65536 end\n"
+        )
+    }
+}
+
+// The statements that set `r` to the bits of a helper's result with the
+// library `bit`, as LuaJIT's, or `bit32`.
+fn library_bits(library: &str, helper: &str) -> String {
+    let function = match (library, helper) {
+        ("bit", "rotl") => "rol",
+        ("bit", "rotr") => "ror",
+        (_, "rotl") => "lrotate",
+        (_, "rotr") => "rrotate",
+        _ => helper,
+    };
+    let params = if helper == "bnot" { "a" } else { "a, b" };
+    format!("local r = {library}.{function}({params})")
+}
+
+// The statements that set `r` to the bits of a helper's result with Lua
+// 5.3's native operators.
+fn native_bits(helper: &str) -> &'static str {
+    match helper {
+        "band" => "local r = a & b",
+        "bor" => "local r = a | b",
+        "bxor" => "local r = a ~ b",
+        "bnot" => "local r = ~a",
+        "lshift" => "local r = a << b",
+        "rshift" => "local r = (a & 0xffffffff) >> b",
+        "arshift" => "local r = math.floor(a / 2 ^ b)",
+        "rotl" => "a = a & 0xffffffff b = b & 31 local r = (a << b) | (a >> (32 - b))",
+        _ => "a = a & 0xffffffff b = b & 31 local r = (a >> b) | (a << (32 - b))",
+    }
+}
+
+// The statements that set `r` to the bits of a helper's result for
+// [LuaTarget::Portable], with arithmetic alone.
+fn portable_bits(helper: &str) -> String {
+    // The condition on the bits `x` and `y` for the bitwise operators.
+    let condition = match helper {
+        "band" => Some("x + y == 2"),
+        "bor" => Some("x + y > 0"),
+        "bxor" => Some("x + y == 1"),
+        _ => None,
+    };
+    if let Some(condition) = condition {
+        return format!(
+            "a = a % 4294967296 b = b % 4294967296 local r, p = 0, 1 for _ = 1, 32 do local x, y = a % 2, b % 2 if {condition} then r = r + p end a, b, p = (a - x) / 2, (b - y) / 2, p * 2 end"
+        );
+    }
+    match helper {
+        "bnot" => "local r = 4294967295 - a % 4294967296",
+        "lshift" => "local r = a * 2 ^ b",
+        "rshift" => "local r = math.floor(a % 4294967296 / 2 ^ b)",
+        "arshift" => "local r = math.floor(a / 2 ^ b)",
+        "rotl" => "a = a % 4294967296 b = b % 32 local r = a * 2 ^ b % 4294967296 + math.floor(a / 2 ^ (32 - b))",
+        _ => "a = a % 4294967296 b = b % 32 local r = math.floor(a / 2 ^ b) + a * 2 ^ (32 - b) % 4294967296",
+    }
+    .to_string()
+}
+
+/// Translate Pico-8's bitwise operators for `target`, naming any helpers with
+/// [DEFAULT_PREFIX].
+///
+/// ```
+/// use pico8_to_lua::bitwise::{LuaTarget, patch_bitwise};
+/// let lua = patch_bitwise("x = a & b | ~c", LuaTarget::Lua52);
+/// assert!(lua.ends_with("x = __p8_bor(__p8_band(a, b), __p8_bnot(c))"));
+/// assert!(lua.contains("local function __p8_bnot(a) a = math.floor(a * 65536) local r = bit32.bnot(a)"));
+/// assert!(patch_bitwise("x ^^= 1", LuaTarget::Lua53).ends_with("x = __p8_bxor(x, 1)"));
+/// ```
+pub fn patch_bitwise<'h>(lua: impl Into<Cow<'h, str>>, target: LuaTarget) -> Cow<'h, str> {
+    let lua = lua.into();
+    let mut namer = Namer::new(DEFAULT_PREFIX, &lua);
+    patch_bitwise_with(lua, target, &mut namer)
+}
+
+/// Translate Pico-8's bitwise operators for `target`, naming any helpers with
+/// `namer`.
+pub fn patch_bitwise_with<'h>(
+    lua: impl Into<Cow<'h, str>>,
+    target: LuaTarget,
+    namer: &mut Namer,
+) -> Cow<'h, str> {
+    rewrite_operators(lua.into(), &Bitwise(target), namer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Return `lua` translated for `target` without the helper definitions.
+    fn calls(lua: &str, target: LuaTarget) -> String {
+        let lua = patch_bitwise(lua, target);
+        lua.lines()
+            .filter(|line| !line.starts_with("local function __p8_"))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    #[test]
+    fn test_calls() {
+        use LuaTarget::*;
+        assert_eq!(calls("x = a & b", Lua51), "x = __p8_band(a, b)");
+        assert_eq!(calls("x = a & b & c", Lua52), "x = __p8_band(__p8_band(a, b), c)");
+        assert_eq!(
+            calls("x = t.a[1] << 2 + n >>> 1", Lua52),
+            "x = __p8_rshift(__p8_lshift(t.a[1], 2 + n), 1)"
+        );
+        assert_eq!(
+            calls("if a == b & 1 then f(-a ^^ b) end", Lua51),
+            "if a == __p8_band(b, 1) then f(__p8_bxor(-a, b)) end"
+        );
+        assert_eq!(calls("y = 1 x = a | b", Lua54), "y = 1 x = __p8_bor(a, b)");
+        assert!(matches!(
+            patch_bitwise("s = \"a & b\" -- a & b", Lua53),
+            Cow::Borrowed(_)
+        ));
+    }
+
+    #[test]
+    fn test_bnot() {
+        use LuaTarget::*;
+        assert_eq!(
+            calls("if a~=~b then x = ~t.v[1] end", Lua51),
+            "if a~=__p8_bnot(b) then x = __p8_bnot(t.v[1]) end"
+        );
+        assert_eq!(
+            calls("x = ~a ^^ b ~= c", Lua53),
+            "x = __p8_bxor(__p8_bnot(a), b) ~= c"
+        );
+    }
+
+    #[test]
+    fn test_helpers() {
+        use LuaTarget::*;
+        assert_eq!(
+            patch_bitwise("-- game\nx = a <<> 1\ny = a >> 2\nz = b <<> 3\n", Lua53),
+            "-- game\n".to_string()
+                + &Bitwise(Lua53).helper_definition("rotl", "__p8_rotl")
+                + &Bitwise(Lua53).helper_definition("arshift", "__p8_arshift")
+                + "x = __p8_rotl(a, 1)\ny = __p8_arshift(a, 2)\nz = __p8_rotl(b, 3)\n"
+        );
+        // The operands are scaled to their 16.16 bits and the result back.
+        assert_eq!(
+            Bitwise(Lua51).helper_definition("band", "f"),
+            "local function f(a, b) a = math.floor(a * 65536) b = math.floor(b * 65536) \
+             local r = bit.band(a, b) r = r % 4294967296 \
+             if r >= 2147483648 then r = r - 4294967296 end return r / 65536 end\n"
+        );
+        assert!(Bitwise(LuaJit).helper_definition("rotr", "f").contains("local r = bit.ror(a, b)"));
+        assert!(Bitwise(Lua52).helper_definition("rotl", "f").contains("local r = bit32.lrotate(a, b)"));
+        assert!(
+            Bitwise(Lua54)
+                .helper_definition("lshift", "f")
+                .starts_with("local function f(a, b) a = math.floor(a * 65536) b = math.floor(b) local r = a << b ")
+        );
+    }
+
+    #[test]
+    fn test_portable() {
+        let lua = patch_bitwise("x = ~a & b", LuaTarget::Portable);
+        let bitwise = Bitwise(LuaTarget::Portable);
+        assert_eq!(
+            lua,
+            bitwise.helper_definition("bnot", "__p8_bnot")
+                + &bitwise.helper_definition("band", "__p8_band")
+                + "x = __p8_band(__p8_bnot(a), b)"
+        );
+    }
+
+    #[test]
+    fn test_target_names() {
+        assert_eq!("LuaJIT".parse(), Ok(LuaTarget::LuaJit));
+        assert_eq!("lua54".parse(), Ok(LuaTarget::Lua54));
+        assert!(patch_bitwise("x = a << 1", LuaTarget::LuaJit).contains("bit.lshift(a, b)"));
+        assert!(patch_bitwise("x = a << 1", LuaTarget::Lua54).contains("local r = a << b"));
+        assert_eq!(
+            "portable".parse::<LuaTarget>().map(|t| t.to_string()),
+            Ok("portable".into())
+        );
+        assert_eq!(
+            "lua50".parse::<LuaTarget>(),
+            Err(UnknownTarget("lua50".into()))
+        );
+    }
+
+    #[test]
+    fn test_compound() {
+        use LuaTarget::*;
+        assert_eq!(calls("x.y &= 1", Lua51), "x.y = __p8_band(x.y, 1)");
+        assert_eq!(
+            calls("if c then t[a & 1] |= b << 2 end", Lua52),
+            "if c then t[__p8_band(a, 1)] = __p8_bor(t[__p8_band(a, 1)], __p8_lshift(b, 2)) end"
+        );
+    }
+}
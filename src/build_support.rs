@@ -0,0 +1,212 @@
+//! Converting a directory of carts from a build script.
+//!
+//! ```no_run
+//! // build.rs
+//! use pico8_to_lua::{Options, build_support::convert_dir};
+//! let out = std::env::var("OUT_DIR").unwrap();
+//! convert_dir("assets", out, &Options::default()).unwrap();
+//! ```
+//!
+//! [convert_dir] converts every ".p8" cart under a directory, and every
+//! ".p8.png" cart with the `png` feature, resolving their includes, and
+//! prints the `cargo:rerun-if-changed` lines that rebuild the crate when any
+//! of them or the files they include change.
+use std::{
+    error::Error,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    FsResolver, IncludeError, Options,
+    batch::Converter,
+    cart::{self, Cart, CartError},
+    split_tab, try_patch_includes,
+};
+
+/// An error encountered while converting a cart for a build.
+#[derive(Debug)]
+pub enum BuildError {
+    /// A file or directory could not be read or written.
+    Io(PathBuf, io::Error),
+    /// A cart could not be parsed.
+    Cart(PathBuf, CartError),
+    /// A file a cart includes could not be read.
+    Include(PathBuf, IncludeError),
+    /// A ".p8.png" cart could not be read.
+    #[cfg(feature = "png")]
+    Png(PathBuf, crate::p8png::P8PngError),
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BuildError::Io(path, e) => write!(f, "{}: {e}", path.display()),
+            BuildError::Cart(path, e) => write!(f, "{}: {e}", path.display()),
+            BuildError::Include(path, e) => write!(f, "{}: {e}", path.display()),
+            #[cfg(feature = "png")]
+            BuildError::Png(path, e) => write!(f, "{}: {e}", path.display()),
+        }
+    }
+}
+
+impl Error for BuildError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            BuildError::Io(_, e) => Some(e),
+            BuildError::Cart(_, e) => Some(e),
+            BuildError::Include(_, e) => Some(e),
+            #[cfg(feature = "png")]
+            BuildError::Png(_, e) => Some(e),
+        }
+    }
+}
+
+/// Return true if `path` is a cart that can be converted.
+fn is_cart(path: &Path) -> bool {
+    let Some(name) = path.to_str() else {
+        return false;
+    };
+    name.ends_with(".p8") || cfg!(feature = "png") && name.ends_with(".p8.png")
+}
+
+/// Collect the carts under `dir` recursively in sorted order.
+fn carts(dir: &Path, files: &mut Vec<PathBuf>) -> Result<(), BuildError> {
+    let error = |e| BuildError::Io(dir.to_path_buf(), e);
+    let mut entries = fs::read_dir(dir)
+        .map_err(error)?
+        .map(|e| e.map(|e| e.path()))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(error)?;
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            carts(&path, files)?;
+        } else if is_cart(&path) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Return the name of the converted `cart`: ".lua" for its Lua alone, which
+/// is all a ".p8.png" cart gives, and ".p8" otherwise.
+fn output_name(cart: &Path, lua_only: bool) -> PathBuf {
+    let name = cart.to_string_lossy();
+    match name.strip_suffix(".p8.png") {
+        Some(stem) => PathBuf::from(format!("{stem}.lua")),
+        None if lua_only => cart.with_extension("lua"),
+        None => cart.to_path_buf(),
+    }
+}
+
+/// Return the text of the cart at `path`, the code alone for a ".p8.png".
+fn read_cart(path: &Path) -> Result<String, BuildError> {
+    #[cfg(feature = "png")]
+    if path.to_string_lossy().ends_with(".p8.png") {
+        let file = fs::File::open(path).map_err(|e| BuildError::Io(path.to_path_buf(), e))?;
+        let code =
+            crate::p8png::read_code(file).map_err(|e| BuildError::Png(path.to_path_buf(), e))?;
+        return Ok(crate::charset::to_utf8(&code));
+    }
+    fs::read_to_string(path).map_err(|e| BuildError::Io(path.to_path_buf(), e))
+}
+
+/// Convert every cart under `src` into the same place under `dst` and
+/// return the files read and the files written.
+fn convert_tree(
+    src: &Path,
+    dst: &Path,
+    options: &Options,
+) -> Result<(Vec<PathBuf>, Vec<PathBuf>), BuildError> {
+    let mut files = Vec::new();
+    carts(src, &mut files)?;
+    let mut read = files.clone();
+    let mut written = Vec::new();
+    let mut converter = Converter::new(options.clone());
+    for path in files {
+        let mut resolver = FsResolver::for_cart(&path, [src]);
+        let mut resolve = |include: &str| {
+            read.extend(resolver.find(split_tab(include).0));
+            resolver.resolve(include)
+        };
+        let text = read_cart(&path)?;
+        let include_error = |e| BuildError::Include(path.clone(), e);
+        let text = if cart::is_cart(&text) {
+            let mut cart = Cart::parse(&text).map_err(|e| BuildError::Cart(path.clone(), e))?;
+            if let Some(lua) = cart.sections.iter_mut().find(|s| s.name == "lua") {
+                lua.body = try_patch_includes(lua.body.as_str(), &mut resolve)
+                    .map_err(include_error)?
+                    .into_owned();
+            }
+            cart.to_string()
+        } else {
+            try_patch_includes(text.as_str(), &mut resolve)
+                .map_err(include_error)?
+                .into_owned()
+        };
+        let out = converter
+            .convert(&text)
+            .map_err(|e| BuildError::Cart(path.clone(), e))?;
+        let relative = path.strip_prefix(src).unwrap_or(&path);
+        let target = dst.join(output_name(relative, options.lua_only));
+        if let Some(dir) = target.parent() {
+            fs::create_dir_all(dir).map_err(|e| BuildError::Io(dir.to_path_buf(), e))?;
+        }
+        fs::write(&target, out).map_err(|e| BuildError::Io(target.clone(), e))?;
+        written.push(target);
+    }
+    read.sort();
+    read.dedup();
+    Ok((read, written))
+}
+
+/// Convert every cart under `src` with `options` into the same place under
+/// `dst`, resolving includes next to each cart and then in `src`, and print
+/// the `cargo:rerun-if-changed` lines for `src` and every file read.
+///
+/// A ".p8" cart is written as a ".p8" cart, or as ".lua" with
+/// [Options::lua_only]. A ".p8.png" cart is written as ".lua". Returns the
+/// files written.
+pub fn convert_dir(
+    src: impl AsRef<Path>,
+    dst: impl AsRef<Path>,
+    options: &Options,
+) -> Result<Vec<PathBuf>, BuildError> {
+    let src = src.as_ref();
+    let (read, written) = convert_tree(src, dst.as_ref(), options)?;
+    println!("cargo:rerun-if-changed={}", src.display());
+    for path in read {
+        println!("cargo:rerun-if-changed={}", path.display());
+    }
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_convert_tree() {
+        let root = std::env::temp_dir().join(format!("pico8-to-lua-build-{}", std::process::id()));
+        let (src, dst) = (root.join("assets"), root.join("out"));
+        fs::create_dir_all(src.join("levels")).unwrap();
+        fs::write(src.join("util.lua"), "a != b").unwrap();
+        fs::write(
+            src.join("levels/one.p8"),
+            "pico-8 cartridge\n__lua__\n#include util.lua\nx += 1\n",
+        )
+        .unwrap();
+        let options = Options {
+            lua_only: true,
+            ..Options::default()
+        };
+        let (read, written) = convert_tree(&src, &dst, &options).unwrap();
+        assert_eq!(read, vec![src.join("levels/one.p8"), src.join("util.lua")]);
+        assert_eq!(written, vec![dst.join("levels/one.lua")]);
+        assert_eq!(
+            fs::read_to_string(&written[0]).unwrap(),
+            "a ~= b\nx = x + (1)\n"
+        );
+    }
+}
@@ -0,0 +1,162 @@
+//! Inlining of Pico-8's math functions.
+//!
+//! Pico-8 has `flr`, `ceil`, `abs`, `max`, `min`, `sqrt`, and `sgn` built in,
+//! so a cart that only computes needs shims for them to run as plain Lua.
+//! [inline_math] rewrites calls to them with the standard library instead,
+//! e.g. `flr(x)` as `math.floor(x)` and `sgn(x)` as `(x<0 and -1 or 1)`.
+//!
+//! Pico-8 fills in missing arguments with 0, so only calls with as many
+//! arguments as the standard function takes are rewritten. A function the
+//! cart defines or assigns itself is left alone, as are strings and comments.
+use std::{borrow::Cow, collections::HashSet};
+
+use crate::{
+    lexer::{Token, TokenKind, tokenize},
+    span::{Span, TextEdit, apply_edits},
+};
+
+/// The functions rewritten as standard ones, the standard ones, and how many
+/// arguments a call must have.
+const FUNCTIONS: &[(&str, &str, usize)] = &[
+    ("flr", "math.floor", 1),
+    ("ceil", "math.ceil", 1),
+    ("abs", "math.abs", 1),
+    ("sqrt", "math.sqrt", 1),
+    ("max", "math.max", 2),
+    ("min", "math.min", 2),
+    ("sgn", "", 1),
+];
+
+/// Rewrite calls to Pico-8's math functions with Lua's standard library.
+///
+/// ```
+/// use pico8_to_lua::builtins::inline_math;
+/// assert_eq!(inline_math("x = flr(max(a, 0))"), "x = math.floor(math.max(a, 0))");
+/// assert_eq!(inline_math("dx = sgn(x - 1)"), "dx = (x - 1<0 and -1 or 1)");
+/// assert_eq!(inline_math("y = max(x)"), "y = max(x)");
+/// ```
+pub fn inline_math<'h>(lua: impl Into<Cow<'h, str>>) -> Cow<'h, str> {
+    let lua = lua.into();
+    let tokens: Vec<Token> = tokenize(&lua)
+        .filter(|t| t.kind != TokenKind::Comment)
+        .collect();
+    let defined = defined(&tokens);
+    let mut edits = Vec::new();
+    for (i, token) in tokens.iter().enumerate() {
+        let Some(&(name, standard, arity)) = FUNCTIONS
+            .iter()
+            .find(|(name, _, _)| token.kind == TokenKind::Name && *name == token.text)
+        else {
+            continue;
+        };
+        if defined.contains(name) || (i > 0 && (tokens[i - 1].is(".") || tokens[i - 1].is(":"))) {
+            continue;
+        }
+        let Some((close, args)) = arguments(&tokens, i + 1) else {
+            continue;
+        };
+        if args != arity {
+            continue;
+        }
+        if name != "sgn" {
+            edits.push(TextEdit::new(token.span, standard));
+            continue;
+        }
+        let (before, after) = if binds_looser(&tokens[i + 2..close]) {
+            ("((", ")<0 and -1 or 1)")
+        } else {
+            ("(", "<0 and -1 or 1)")
+        };
+        let (open, close) = (tokens[i + 1].span, tokens[close].span);
+        edits.push(TextEdit::new(Span::new(token.span.start, open.end), before));
+        edits.push(TextEdit::new(close, after));
+    }
+    if edits.is_empty() {
+        return lua;
+    }
+    Cow::Owned(apply_edits(&lua, &edits))
+}
+
+/// Return the math functions that `tokens` define or assign, e.g. with
+/// `function flr(x)`, `local abs`, or `max = f`.
+fn defined(tokens: &[Token]) -> HashSet<&'static str> {
+    FUNCTIONS
+        .iter()
+        .map(|(name, _, _)| *name)
+        .filter(|name| {
+            tokens.iter().enumerate().any(|(i, token)| {
+                token.kind == TokenKind::Name
+                    && token.text == *name
+                    && (i > 0 && (tokens[i - 1].is("function") || tokens[i - 1].is("local"))
+                        || tokens.get(i + 1).is_some_and(|next| next.is("=")))
+            })
+        })
+        .collect()
+}
+
+/// Return whether the expression `tokens` has a comparison, `and`, or `or`
+/// outside of brackets, so it needs parentheses to be compared with `<`.
+fn binds_looser(tokens: &[Token]) -> bool {
+    let mut depth = 0;
+    tokens.iter().any(|token| {
+        match token.text {
+            "(" | "[" | "{" if token.kind == TokenKind::Punct => depth += 1,
+            ")" | "]" | "}" if token.kind == TokenKind::Punct => depth -= 1,
+            _ => {}
+        }
+        depth == 0
+            && ["and", "or", "<", ">", "<=", ">=", "==", "~=", "!="]
+                .iter()
+                .any(|op| token.is(op))
+    })
+}
+
+/// If `tokens[open]` opens the arguments of a call, return the index of the
+/// parenthesis that closes them and how many there are. Arguments with a
+/// vararg `...` may be any number, so they are not counted.
+fn arguments(tokens: &[Token], open: usize) -> Option<(usize, usize)> {
+    if !tokens.get(open)?.is("(") {
+        return None;
+    }
+    let mut depth = 0;
+    let mut commas = 0;
+    for (i, token) in tokens.iter().enumerate().skip(open + 1) {
+        match token.text {
+            "(" | "[" | "{" if token.kind == TokenKind::Punct => depth += 1,
+            ")" if depth == 0 && token.kind == TokenKind::Punct => {
+                let args = if i == open + 1 { 0 } else { commas + 1 };
+                return Some((i, args));
+            }
+            ")" | "]" | "}" if token.kind == TokenKind::Punct => depth -= 1,
+            "," if depth == 0 && token.kind == TokenKind::Punct => commas += 1,
+            "..." if token.kind == TokenKind::Punct => return None,
+            _ => {}
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inline_math() {
+        assert_eq!(
+            inline_math("a = abs(t.x) + sqrt(ceil(y)) + min(f(1, 2), t[1])"),
+            "a = math.abs(t.x) + math.sqrt(math.ceil(y)) + math.min(f(1, 2), t[1])"
+        );
+        assert_eq!(
+            inline_math("s = sgn(sgn(x)) + sgn(a or b)"),
+            "s = ((x<0 and -1 or 1)<0 and -1 or 1) + ((a or b)<0 and -1 or 1)"
+        );
+        assert!(matches!(
+            inline_math("s = \"flr(x)\" -- flr(x)\nx = v.flr(1) + max(...)"),
+            Cow::Borrowed(_)
+        ));
+        assert_eq!(
+            inline_math("function flr(x) return x end\ny = flr(2) + abs(-1)"),
+            "function flr(x) return x end\ny = flr(2) + math.abs(-1)"
+        );
+    }
+}
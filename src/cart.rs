@@ -0,0 +1,1281 @@
+//! A model of the ".p8" text cartridge format.
+//!
+//! A cart is a header followed by sections, each introduced by a line like
+//! `__lua__` or `__gfx__`. [Cart] keeps every section verbatim so that writing
+//! it back out with [Display](std::fmt::Display) reproduces the input, and
+//! offers structured decoders for the sections that hold data.
+use std::{collections::BTreeMap, error::Error, fmt};
+
+use crate::{
+    detect,
+    label::{LABEL_SIZE, Label},
+    license::{License, lua_licenses, meta_licenses},
+    limits::LimitError,
+    span::Span,
+};
+
+/// The first line of every ".p8" cart.
+pub const CART_HEADER: &str = "pico-8 cartridge";
+
+/// The cart format version that new carts are written with.
+pub const DEFAULT_VERSION: u32 = 41;
+
+/// An error encountered while reading a cart.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CartError {
+    /// The text does not start with "pico-8 cartridge".
+    MissingHeader,
+    /// A line of a data section was not valid hex.
+    InvalidHex {
+        /// The section name without underscores, e.g. "sfx".
+        section: String,
+        /// The zero-based line within the section.
+        line: usize,
+    },
+    /// A line of a data section was not the expected length.
+    BadLength {
+        /// The section name without underscores, e.g. "sfx".
+        section: String,
+        /// The zero-based line within the section.
+        line: usize,
+        /// The expected number of characters.
+        expected: usize,
+        /// The actual number of characters.
+        found: usize,
+    },
+    /// The conversion went over one of its [Limits](crate::limits::Limits).
+    Limit(LimitError),
+}
+
+impl fmt::Display for CartError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CartError::MissingHeader => write!(f, "missing \"{CART_HEADER}\" header"),
+            CartError::InvalidHex { section, line } => {
+                write!(f, "invalid hex in __{section}__ line {}", line + 1)
+            }
+            CartError::BadLength {
+                section,
+                line,
+                expected,
+                found,
+            } => write!(
+                f,
+                "__{section}__ line {} has {found} characters; expected {expected}",
+                line + 1
+            ),
+            CartError::Limit(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl Error for CartError {}
+
+/// A named section of a cart.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Section {
+    /// The name without underscores, e.g. "lua" for `__lua__`.
+    pub name: String,
+    /// Everything after the section's marker line up to the next marker.
+    pub body: String,
+}
+
+/// A ".p8" cartridge split into its header and sections.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Cart {
+    /// Everything before the first section, including the trailing newline.
+    pub header: String,
+    /// The sections in the order they appear.
+    pub sections: Vec<Section>,
+}
+
+/// The header lines of a new cart: [CART_HEADER] with a URL, which Pico-8
+/// writes, and the "version N" line of the cart format. Pico-8 refuses to
+/// load a cart without them.
+///
+/// ```
+/// use pico8_to_lua::cart::CartHeader;
+/// assert_eq!(CartHeader::default().to_string(), "pico-8 cartridge // http://www.pico-8.com\nversion 41\n");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CartHeader {
+    /// The URL after "//" on the first line, or none.
+    pub url: Option<String>,
+    /// The cart format version.
+    pub version: u32,
+}
+
+impl Default for CartHeader {
+    fn default() -> Self {
+        CartHeader {
+            url: Some("http://www.pico-8.com".into()),
+            version: DEFAULT_VERSION,
+        }
+    }
+}
+
+impl fmt::Display for CartHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(CART_HEADER)?;
+        if let Some(url) = &self.url {
+            write!(f, " // {url}")?;
+        }
+        writeln!(f, "\nversion {}", self.version)
+    }
+}
+
+impl CartHeader {
+    /// Return the header of an existing cart, `header`, with the lines that
+    /// it lacks from this one: the first line if it does not start with
+    /// [CART_HEADER] and the "version N" line if it has none.
+    ///
+    /// ```
+    /// use pico8_to_lua::cart::CartHeader;
+    /// assert_eq!(CartHeader::default().complete("pico-8 cartridge\n"), "pico-8 cartridge\nversion 41\n");
+    /// ```
+    pub fn complete(&self, header: &str) -> String {
+        let mut out = String::new();
+        let mut rest = header;
+        if is_cart(header) {
+            let end = header.find('\n').map_or(header.len(), |i| i + 1);
+            out.push_str(&header[..end]);
+            rest = &header[end..];
+        } else {
+            out.push_str(CART_HEADER);
+            if let Some(url) = &self.url {
+                out.push_str(" // ");
+                out.push_str(url);
+            }
+        }
+        if !out.ends_with('\n') {
+            out.push('\n');
+        }
+        if !rest.lines().any(|line| line.starts_with("version ")) {
+            out.push_str(&format!("version {}\n", self.version));
+        }
+        out.push_str(rest);
+        out
+    }
+}
+
+/// What launchers and uploaders show of a cart, from its header, its
+/// `__meta:*__` sections, and the comments that open its code. See
+/// [Cart::meta].
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CartMeta {
+    /// The URL after "//" on the header line, e.g. "http://www.pico-8.com".
+    pub url: Option<String>,
+    /// The cart format version from the "version N" line.
+    pub version: Option<u32>,
+    /// The title. See [Cart::title].
+    pub title: Option<String>,
+    /// The author without a leading "by". See [Cart::author].
+    pub author: Option<String>,
+    /// The body of each `__meta:*__` section by its name after "meta:", e.g.
+    /// "title" for `__meta:title__`. See [meta_fields] for the fields of one.
+    pub sections: BTreeMap<String, String>,
+}
+
+/// Return the "key: value" fields of the body of a `__meta:*__` section,
+/// trimmed, in order. Lines without a colon are skipped.
+///
+/// ```
+/// use pico8_to_lua::cart::meta_fields;
+/// let fields: Vec<_> = meta_fields("license: CC0\ntags: platformer\n").collect();
+/// assert_eq!(fields, [("license", "CC0"), ("tags", "platformer")]);
+/// ```
+pub fn meta_fields(body: &str) -> impl Iterator<Item = (&str, &str)> {
+    body.lines().filter_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        Some((key.trim(), value.trim()))
+    })
+}
+
+pub(crate) fn section_marker(line: &str) -> Option<&str> {
+    let line = line.trim_end_matches(['\r', '\n']);
+    let name = line.strip_prefix("__")?.strip_suffix("__")?;
+    if !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == ':' || c == '_')
+    {
+        Some(name)
+    } else {
+        None
+    }
+}
+
+impl Cart {
+    /// Create a cart with `header` and `lua` as its only section.
+    ///
+    /// ```
+    /// use pico8_to_lua::{Cart, cart::CartHeader};
+    /// let header = CartHeader { url: None, version: 42 };
+    /// assert_eq!(Cart::with_lua(&header, "x = 1").to_string(), "pico-8 cartridge\nversion 42\n__lua__\nx = 1\n");
+    /// ```
+    pub fn with_lua(header: &CartHeader, lua: impl Into<String>) -> Cart {
+        let mut body = lua.into();
+        if !body.is_empty() && !body.ends_with('\n') {
+            body.push('\n');
+        }
+        Cart {
+            header: header.to_string(),
+            sections: vec![Section {
+                name: "lua".into(),
+                body,
+            }],
+        }
+    }
+
+    /// Split a ".p8" cart into its sections.
+    pub fn parse(text: &str) -> Result<Cart, CartError> {
+        if !is_cart(text) {
+            return Err(CartError::MissingHeader);
+        }
+        let mut cart = Cart::default();
+        let mut current: Option<Section> = None;
+        for line in text.split_inclusive('\n') {
+            if let Some(name) = section_marker(line) {
+                cart.sections.extend(current.take());
+                current = Some(Section {
+                    name: name.to_string(),
+                    body: String::new(),
+                });
+            } else if let Some(section) = current.as_mut() {
+                section.body.push_str(line);
+            } else {
+                cart.header.push_str(line);
+            }
+        }
+        cart.sections.extend(current);
+        Ok(cart)
+    }
+
+    /// Split the bytes of a ".p8" cart into its sections, reading them as
+    /// P8SCII if they are not UTF-8.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Cart, CartError> {
+        match std::str::from_utf8(bytes) {
+            Ok(text) => Cart::parse(text),
+            Err(_) => Cart::parse(&crate::p8scii::decode(bytes)),
+        }
+    }
+
+    /// Return the body of the section `name`, e.g. "lua" or "gfx".
+    pub fn section(&self, name: &str) -> Option<&str> {
+        self.sections
+            .iter()
+            .find(|s| s.name == name)
+            .map(|s| s.body.as_str())
+    }
+
+    /// Return the Lua code.
+    pub fn lua(&self) -> Option<&str> {
+        self.section("lua")
+    }
+
+    /// Return the number of lines in the cart's text before its Lua code,
+    /// that is, through its `__lua__` line, or `None` if it has none.
+    ///
+    /// ```
+    /// use pico8_to_lua::Cart;
+    /// let cart = Cart::parse("pico-8 cartridge\nversion 42\n__lua__\nx = 1\n").unwrap();
+    /// assert_eq!(cart.lua_line(), Some(3));
+    /// ```
+    pub fn lua_line(&self) -> Option<usize> {
+        let mut lines = self.header.matches('\n').count();
+        for section in &self.sections {
+            lines += 1;
+            if section.name == "lua" {
+                return Some(lines);
+            }
+            lines += section.body.matches('\n').count();
+        }
+        None
+    }
+
+    /// Count the tokens of the Lua code the way Pico-8 does. See
+    /// [tokens](crate::size::tokens).
+    pub fn tokens(&self) -> usize {
+        crate::size::tokens(self.lua().unwrap_or(""))
+    }
+
+    /// Return the code of each tab of Pico-8's code editor. See [tabs].
+    pub fn tabs(&self) -> Vec<&str> {
+        tabs(self.lua().unwrap_or(""))
+    }
+
+    /// Count the tokens of each tab the way Pico-8 does.
+    pub fn tab_tokens(&self) -> Vec<usize> {
+        self.tabs().into_iter().map(crate::size::tokens).collect()
+    }
+
+    /// Measure the Lua code by Pico-8's limits. See
+    /// [size_report](crate::size::size_report).
+    pub fn size_report(&self) -> crate::size::SizeReport {
+        crate::size::size_report(self.lua().unwrap_or(""))
+    }
+
+    /// Return the title, which Pico-8 takes from the first line of code if it
+    /// is a comment.
+    pub fn title(&self) -> Option<&str> {
+        comment_lines(self.lua()?).next()
+    }
+
+    /// Return the author, which Pico-8 takes from the second line of code if
+    /// it and the first line are comments.
+    pub fn author(&self) -> Option<&str> {
+        comment_lines(self.lua()?).nth(1)
+    }
+
+    /// Return the body of the section `__meta:name__`.
+    pub fn meta_section(&self, name: &str) -> Option<&str> {
+        self.sections
+            .iter()
+            .find(|s| s.name.strip_prefix("meta:") == Some(name))
+            .map(|s| s.body.as_str())
+    }
+
+    /// Return the URL, version, title, author, and `__meta:*__` sections of
+    /// the cart. A `__meta:title__` section, which newer versions of Pico-8
+    /// write, gives the title and author on its first two lines instead of
+    /// the code's comments.
+    ///
+    /// ```
+    /// use pico8_to_lua::Cart;
+    /// let cart = Cart::parse("pico-8 cartridge // http://www.pico-8.com\nversion 41\n__lua__\n-- jump\n-- by ana\n").unwrap();
+    /// let meta = cart.meta();
+    /// assert_eq!((meta.version, meta.title.as_deref(), meta.author.as_deref()), (Some(41), Some("jump"), Some("ana")));
+    /// ```
+    pub fn meta(&self) -> CartMeta {
+        let mut lines = self.header.lines();
+        let url = lines
+            .next()
+            .and_then(|line| line.split_once("//"))
+            .map(|(_, url)| url.trim().to_string())
+            .filter(|url| !url.is_empty());
+        let version = lines
+            .find_map(|line| line.strip_prefix("version "))
+            .and_then(|version| version.trim().parse().ok());
+        let mut title_lines = self
+            .meta_section("title")
+            .into_iter()
+            .flat_map(str::lines)
+            .map(str::trim)
+            .filter(|line| !line.is_empty());
+        let title = title_lines.next().or_else(|| self.title());
+        let author = title_lines.next().or_else(|| self.author()).map(|author| {
+            let by = author.get(..3).is_some_and(|by| by.eq_ignore_ascii_case("by "));
+            if by { author[3..].trim_start() } else { author }.to_string()
+        });
+        let sections = self
+            .sections
+            .iter()
+            .filter_map(|s| Some((s.name.strip_prefix("meta:")?.to_string(), s.body.clone())))
+            .collect();
+        CartMeta {
+            url,
+            version,
+            title: title.map(String::from),
+            author,
+            sections,
+        }
+    }
+
+    /// Find the licenses stated in the comments that open the code and in the
+    /// `__meta:*__` sections.
+    pub fn licenses(&self) -> Vec<License> {
+        let mut licenses = self.lua().map(lua_licenses).unwrap_or_default();
+        for section in self.sections.iter().filter(|s| s.name.starts_with("meta:")) {
+            licenses.extend(meta_licenses(&section.body));
+        }
+        licenses
+    }
+
+    /// Decode the `__sfx__` section. Returns an empty list if there is none.
+    pub fn sfx(&self) -> Result<Vec<Sfx>, CartError> {
+        data_lines(self.section("sfx"))
+            .enumerate()
+            .map(|(i, line)| Sfx::parse(line).map_err(|e| e.at("sfx", i)))
+            .collect()
+    }
+
+    /// Decode the `__label__` section, if there is one.
+    pub fn label(&self) -> Result<Option<Label>, CartError> {
+        let Some(body) = self.section("label") else {
+            return Ok(None);
+        };
+        let mut label = Label::default();
+        for (y, line) in data_lines(Some(body)).enumerate().take(LABEL_SIZE) {
+            label.parse_line(y, line).map_err(|e| e.at("label", y))?;
+        }
+        Ok(Some(label))
+    }
+
+    /// Decode the `__music__` section. Returns an empty list if there is none.
+    pub fn music(&self) -> Result<Vec<Pattern>, CartError> {
+        data_lines(self.section("music"))
+            .enumerate()
+            .map(|(i, line)| Pattern::parse(line).map_err(|e| e.at("music", i)))
+            .collect()
+    }
+
+    /// Decode the `__gff__` section. Sprites without flags in the cart have
+    /// none set.
+    pub fn sprite_flags(&self) -> Result<SpriteFlags, CartError> {
+        let mut flags = SpriteFlags::default();
+        for (i, line) in data_lines(self.section("gff")).enumerate().take(2) {
+            let bytes = check_len(line, 256)
+                .and_then(|_| hex_bytes(line))
+                .map_err(|e| e.at("gff", i))?;
+            flags.0[i * 128..(i + 1) * 128].copy_from_slice(&bytes);
+        }
+        Ok(flags)
+    }
+
+    /// Decode the `__gfx__` section. Pixels missing from the cart are color 0.
+    pub fn gfx(&self) -> Result<Spritesheet, CartError> {
+        let mut sheet = Spritesheet::default();
+        for (y, line) in data_lines(self.section("gfx")).enumerate().take(SHEET_SIZE) {
+            check_len(line, SHEET_SIZE).map_err(|e| e.at("gfx", y))?;
+            for (x, c) in line.bytes().enumerate() {
+                sheet.pixels[y * SHEET_SIZE + x] = hex_nibble(c).map_err(|e| e.at("gfx", y))?;
+            }
+        }
+        Ok(sheet)
+    }
+
+    /// Decode the map: the upper half from the `__map__` section and the
+    /// lower half from the lower half of the `__gfx__` section, where Pico-8
+    /// keeps it. Tiles missing from the cart are sprite 0.
+    pub fn map(&self) -> Result<TileMap, CartError> {
+        let mut map = TileMap::default();
+        for (y, line) in data_lines(self.section("map")).enumerate().take(MAP_HEIGHT / 2) {
+            let bytes = check_len(line, MAP_WIDTH * 2)
+                .and_then(|_| hex_bytes(line))
+                .map_err(|e| e.at("map", y))?;
+            map.tiles[y * MAP_WIDTH..(y + 1) * MAP_WIDTH].copy_from_slice(&bytes);
+        }
+        // Each byte of the shared memory is two pixels, low nibble first.
+        let sheet = self.gfx()?;
+        let shared = &sheet.pixels[SHEET_SIZE * SHEET_SIZE / 2..];
+        for (i, pair) in shared.chunks(2).enumerate() {
+            map.tiles[MAP_WIDTH * MAP_HEIGHT / 2 + i] = pair[0] | pair[1] << 4;
+        }
+        Ok(map)
+    }
+
+    /// Set the body of the section `name`, e.g. "gfx", adding the section
+    /// where Pico-8 would write it if the cart has none.
+    ///
+    /// ```
+    /// use pico8_to_lua::Cart;
+    /// let mut cart = Cart::parse("pico-8 cartridge\n__lua__\nx = 1\n__sfx__\n").unwrap();
+    /// cart.set_section("gff", "01\n");
+    /// assert_eq!(cart.to_string(), "pico-8 cartridge\n__lua__\nx = 1\n__gff__\n01\n__sfx__\n");
+    /// ```
+    pub fn set_section(&mut self, name: &str, body: impl Into<String>) {
+        let body = body.into();
+        if let Some(section) = self.sections.iter_mut().find(|s| s.name == name) {
+            section.body = body;
+            return;
+        }
+        let rank = |name: &str| {
+            SECTION_ORDER
+                .iter()
+                .position(|&n| n == name)
+                .unwrap_or(SECTION_ORDER.len())
+        };
+        let at = self
+            .sections
+            .iter()
+            .position(|s| rank(&s.name) > rank(name))
+            .unwrap_or(self.sections.len());
+        self.sections.insert(
+            at,
+            Section {
+                name: name.to_string(),
+                body,
+            },
+        );
+    }
+
+    /// Encode `sheet` into the `__gfx__` section. Its lower half is also the
+    /// lower half of the map, as in Pico-8's memory.
+    pub fn set_gfx(&mut self, sheet: &Spritesheet) {
+        let body: String = sheet
+            .pixels
+            .chunks(SHEET_SIZE)
+            .map(|row| row.iter().map(|c| format!("{:x}", c & 0xf)).collect::<String>() + "\n")
+            .collect();
+        self.set_section("gfx", body);
+    }
+
+    /// Encode the upper half of `map` into the `__map__` section and its lower
+    /// half into the lower half of the `__gfx__` section, where Pico-8 keeps
+    /// it.
+    ///
+    /// ```
+    /// use pico8_to_lua::{Cart, cart::TileMap};
+    /// let mut cart = Cart::parse("pico-8 cartridge\n__lua__\n").unwrap();
+    /// let mut map = TileMap::default();
+    /// map.set(1, 0, 7);
+    /// map.set(0, 40, 0x21);
+    /// cart.set_map(&map).unwrap();
+    /// assert_eq!(cart.map().unwrap(), map);
+    /// assert!(cart.section("map").unwrap().starts_with("0007"));
+    /// assert_eq!(cart.section("map").unwrap().lines().count(), 32);
+    /// assert_eq!((cart.gfx().unwrap().get(0, 80), cart.gfx().unwrap().get(1, 80)), (1, 2));
+    /// ```
+    pub fn set_map(&mut self, map: &TileMap) -> Result<(), CartError> {
+        let body: String = map.tiles[..MAP_WIDTH * MAP_HEIGHT / 2]
+            .chunks(MAP_WIDTH)
+            .map(|row| row.iter().map(|t| format!("{t:02x}")).collect::<String>() + "\n")
+            .collect();
+        let mut sheet = self.gfx()?;
+        let shared = &mut sheet.pixels[SHEET_SIZE * SHEET_SIZE / 2..];
+        for (pair, tile) in shared.chunks_mut(2).zip(&map.tiles[MAP_WIDTH * MAP_HEIGHT / 2..]) {
+            pair[0] = tile & 0xf;
+            pair[1] = tile >> 4;
+        }
+        self.set_section("map", body);
+        self.set_gfx(&sheet);
+        Ok(())
+    }
+
+    /// Encode `flags` into the `__gff__` section.
+    pub fn set_sprite_flags(&mut self, flags: &SpriteFlags) {
+        let body: String = flags
+            .0
+            .chunks(128)
+            .map(|row| row.iter().map(|f| format!("{f:02x}")).collect::<String>() + "\n")
+            .collect();
+        self.set_section("gff", body);
+    }
+
+    /// Set the 8x8 `pixels` of `sprite`, in row-major order, in the
+    /// `__gfx__` section, keeping the rest of the spritesheet.
+    ///
+    /// ```
+    /// use pico8_to_lua::Cart;
+    /// let mut cart = Cart::parse("pico-8 cartridge\n__lua__\n").unwrap();
+    /// cart.set_sprite(17, &[8; 64]).unwrap();
+    /// let sheet = cart.gfx().unwrap();
+    /// assert_eq!((sheet.get(8, 8), sheet.get(15, 15), sheet.get(16, 8)), (8, 8, 0));
+    /// ```
+    pub fn set_sprite(&mut self, sprite: u8, pixels: &[u8; 64]) -> Result<(), CartError> {
+        let mut sheet = self.gfx()?;
+        sheet.set_sprite(sprite, pixels);
+        self.set_gfx(&sheet);
+        Ok(())
+    }
+}
+
+impl fmt::Display for Cart {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.header)?;
+        for section in &self.sections {
+            write!(f, "__{}__\n{}", section.name, section.body)?;
+        }
+        Ok(())
+    }
+}
+
+/// The data sections in the order Pico-8 writes them. Sections it does not
+/// know, like `__meta:*__`, come after them.
+const SECTION_ORDER: &[&str] = &["lua", "gfx", "label", "gff", "map", "sfx", "music"];
+
+/// Return whether `text` is a ".p8" cart: whether it starts with
+/// [CART_HEADER], after a byte order mark if it has one.
+///
+/// ```
+/// use pico8_to_lua::cart::is_cart;
+/// assert!(is_cart("\u{feff}pico-8 cartridge // http://www.pico-8.com\r\n"));
+/// assert!(!is_cart("x = 1"));
+/// ```
+pub fn is_cart(text: &str) -> bool {
+    detect::detect(text.as_bytes()) == Some(detect::Format::P8)
+}
+
+/// Return where the body of the `__lua__` section is in the text of a ".p8"
+/// cart, or `None` if the text is not a cart or has no Lua.
+///
+/// ```
+/// use pico8_to_lua::{Span, cart::lua_span};
+/// let text = "pico-8 cartridge\n__lua__\nx = 1\n__gfx__\n00\n";
+/// assert_eq!(lua_span(text), Some(Span::new(25, 31)));
+/// ```
+pub fn lua_span(text: &str) -> Option<Span> {
+    if !is_cart(text) {
+        return None;
+    }
+    let mut start = None;
+    let mut offset = 0;
+    for line in text.split_inclusive('\n') {
+        if let Some(name) = section_marker(line) {
+            if let Some(start) = start {
+                return Some(Span::new(start, offset));
+            }
+            if name == "lua" {
+                start = Some(offset + line.len());
+            }
+        }
+        offset += line.len();
+    }
+    start.map(|start| Span::new(start, text.len()))
+}
+
+/// Split Lua into the tabs of Pico-8's code editor, which it separates with
+/// "-->8" lines. The separators are not part of any tab.
+///
+/// ```
+/// use pico8_to_lua::cart::tabs;
+/// assert_eq!(tabs("a = 1\n-->8\nb = 2\n"), vec!["a = 1\n", "b = 2\n"]);
+/// ```
+pub fn tabs(lua: &str) -> Vec<&str> {
+    let mut tabs = Vec::new();
+    let mut start = 0;
+    let mut offset = 0;
+    for line in lua.split_inclusive('\n') {
+        if line.trim_end_matches(['\r', '\n']) == "-->8" {
+            tabs.push(&lua[start..offset]);
+            start = offset + line.len();
+        }
+        offset += line.len();
+    }
+    tabs.push(&lua[start..]);
+    tabs
+}
+
+/// Join tabs split by [tabs] back into Lua, separating them with "-->8" lines.
+///
+/// ```
+/// use pico8_to_lua::{cart::{join_tabs, tabs}, patch_lua};
+/// let lua = "a += 1\n-->8\nb += 2\n";
+/// let patched: Vec<_> = tabs(lua).into_iter().map(patch_lua).collect();
+/// assert_eq!(join_tabs(&patched), "a = a + (1)\n-->8\nb = b + (2)\n");
+/// ```
+pub fn join_tabs(tabs: &[impl AsRef<str>]) -> String {
+    let mut lua = String::new();
+    for (i, tab) in tabs.iter().enumerate() {
+        if i > 0 {
+            if !lua.is_empty() && !lua.ends_with('\n') {
+                lua.push('\n');
+            }
+            lua.push_str("-->8\n");
+        }
+        lua.push_str(tab.as_ref());
+    }
+    lua
+}
+
+/// Return the run of "--" comment lines at the very start of `lua`,
+/// including their line endings. A block comment like "--[[" is a comment
+/// line only if it closes on its line with nothing after it.
+///
+/// Pico-8 and tools that build BBS descriptions read the cart's title and
+/// author from these lines, so the converter never alters them.
+pub fn comment_header(lua: &str) -> &str {
+    let len = lua
+        .split_inclusive('\n')
+        .take_while(|line| {
+            let open = line.strip_prefix("--[").and_then(|rest| {
+                let level = rest.bytes().take_while(|&b| b == b'=').count();
+                rest[level..].starts_with('[').then_some(level)
+            });
+            let code = open.is_some_and(|level| {
+                let close = format!("]{}]", "=".repeat(level));
+                line.find(&close)
+                    .is_none_or(|i| !line[i + close.len()..].trim().is_empty())
+            });
+            line.starts_with("--") && !code
+        })
+        .map(str::len)
+        .sum();
+    &lua[..len]
+}
+
+/// The text of each line of the comment header without "--" and surrounding
+/// whitespace.
+pub(crate) fn comment_lines(lua: &str) -> impl Iterator<Item = &str> {
+    comment_header(lua)
+        .lines()
+        .map(|line| line.trim_start_matches('-').trim())
+}
+
+/// The non-empty lines of a data section.
+pub(crate) fn data_lines(body: Option<&str>) -> impl Iterator<Item = &str> {
+    body.unwrap_or("")
+        .lines()
+        .map(|l| l.trim_end_matches('\r'))
+        .filter(|l| !l.is_empty())
+}
+
+/// A line-level decoding error that does not yet know where it happened.
+#[derive(Debug)]
+pub(crate) enum LineError {
+    InvalidHex,
+    BadLength { expected: usize, found: usize },
+}
+
+impl LineError {
+    pub(crate) fn at(self, section: &str, line: usize) -> CartError {
+        let section = section.to_string();
+        match self {
+            LineError::InvalidHex => CartError::InvalidHex { section, line },
+            LineError::BadLength { expected, found } => CartError::BadLength {
+                section,
+                line,
+                expected,
+                found,
+            },
+        }
+    }
+}
+
+pub(crate) fn check_len(line: &str, expected: usize) -> Result<(), LineError> {
+    if line.len() == expected {
+        Ok(())
+    } else {
+        Err(LineError::BadLength {
+            expected,
+            found: line.len(),
+        })
+    }
+}
+
+/// Decode a string of hex digits two at a time.
+pub(crate) fn hex_bytes(hex: &str) -> Result<Vec<u8>, LineError> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return Err(LineError::InvalidHex);
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| LineError::InvalidHex))
+        .collect()
+}
+
+pub(crate) fn hex_nibble(c: u8) -> Result<u8, LineError> {
+    (c as char)
+        .to_digit(16)
+        .map(|d| d as u8)
+        .ok_or(LineError::InvalidHex)
+}
+
+/// One note of a sound effect.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Note {
+    /// Pitch from 0 (C-0) to 63 (D#5).
+    pub pitch: u8,
+    /// Waveform from 0 to 7, or 8 to 15 for a custom instrument that plays
+    /// sfx `waveform - 8`.
+    pub waveform: u8,
+    /// Volume from 0 (silent) to 7.
+    pub volume: u8,
+    /// Effect from 0 (none) to 7.
+    pub effect: u8,
+}
+
+impl Note {
+    /// Return the sfx index used as an instrument if this note plays a custom
+    /// instrument.
+    pub fn custom_instrument(&self) -> Option<u8> {
+        self.waveform.checked_sub(8)
+    }
+
+    /// Return true if the note makes no sound.
+    pub fn is_silent(&self) -> bool {
+        self.volume == 0
+    }
+}
+
+/// A sound effect from the `__sfx__` section.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Sfx {
+    /// The editor mode; 0 is pitch mode, 1 is tracker mode.
+    pub editor_mode: u8,
+    /// Duration of each note in ticks of 1/128 second.
+    pub speed: u8,
+    /// The index of the first note of the loop.
+    pub loop_start: u8,
+    /// The index after the last note of the loop. When less than or equal to
+    /// `loop_start` the sfx does not loop.
+    pub loop_end: u8,
+    /// The 32 notes.
+    pub notes: [Note; 32],
+}
+
+impl Sfx {
+    /// The length of one `__sfx__` line: an 8 digit header and 32 five digit
+    /// notes.
+    pub const LINE_LEN: usize = 8 + 32 * 5;
+
+    pub(crate) fn parse(line: &str) -> Result<Sfx, LineError> {
+        check_len(line, Self::LINE_LEN)?;
+        let header = hex_bytes(&line[..8])?;
+        let mut notes = [Note::default(); 32];
+        for (note, chunk) in notes.iter_mut().zip(line.as_bytes()[8..].chunks(5)) {
+            *note = Note {
+                pitch: (hex_nibble(chunk[0])? << 4) | hex_nibble(chunk[1])?,
+                waveform: hex_nibble(chunk[2])?,
+                volume: hex_nibble(chunk[3])?,
+                effect: hex_nibble(chunk[4])?,
+            };
+        }
+        Ok(Sfx {
+            editor_mode: header[0],
+            speed: header[1],
+            loop_start: header[2],
+            loop_end: header[3],
+            notes,
+        })
+    }
+
+    /// Return true if the sfx loops.
+    pub fn loops(&self) -> bool {
+        self.loop_end > self.loop_start
+    }
+
+    /// Return true if every note is silent.
+    pub fn is_empty(&self) -> bool {
+        self.notes.iter().all(Note::is_silent)
+    }
+}
+
+/// The flags of all 256 sprites from the `__gff__` section.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpriteFlags(pub [u8; 256]);
+
+impl Default for SpriteFlags {
+    fn default() -> Self {
+        SpriteFlags([0; 256])
+    }
+}
+
+impl SpriteFlags {
+    /// Return all eight flags of `sprite` as a byte like `fget(sprite)`.
+    pub fn get(&self, sprite: u8) -> u8 {
+        self.0[sprite as usize]
+    }
+
+    /// Return whether flag `bit` (0 to 7) of `sprite` is set like
+    /// `fget(sprite, bit)`.
+    pub fn flag(&self, sprite: u8, bit: u8) -> bool {
+        bit < 8 && self.get(sprite) & (1 << bit) != 0
+    }
+
+    /// Set all eight flags of `sprite` like `fset(sprite, flags)`.
+    pub fn set(&mut self, sprite: u8, flags: u8) {
+        self.0[sprite as usize] = flags;
+    }
+}
+
+/// The width and height of the spritesheet in pixels.
+pub const SHEET_SIZE: usize = 128;
+
+/// The width of the map in tiles.
+pub const MAP_WIDTH: usize = 128;
+
+/// The height of the map in tiles, including the lower half that shares
+/// memory with the lower half of the spritesheet.
+pub const MAP_HEIGHT: usize = 64;
+
+/// The spritesheet from the `__gfx__` section.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Spritesheet {
+    /// The colors, 0 to 15, of each pixel in row-major order.
+    pub pixels: Vec<u8>,
+}
+
+impl Default for Spritesheet {
+    fn default() -> Self {
+        Spritesheet {
+            pixels: vec![0; SHEET_SIZE * SHEET_SIZE],
+        }
+    }
+}
+
+impl Spritesheet {
+    /// Read the spritesheet from its 0x2000 bytes of Pico-8's memory, two
+    /// pixels to a byte with the left one in the low nibble.
+    ///
+    /// ```
+    /// use pico8_to_lua::cart::Spritesheet;
+    /// let mut bytes = [0; 0x2000];
+    /// bytes[0] = 0x21;
+    /// let sheet = Spritesheet::from_bytes(&bytes);
+    /// assert_eq!((sheet.get(0, 0), sheet.get(1, 0)), (1, 2));
+    /// ```
+    pub fn from_bytes(bytes: &[u8; 0x2000]) -> Spritesheet {
+        Spritesheet {
+            pixels: bytes.iter().flat_map(|b| [b & 0xf, b >> 4]).collect(),
+        }
+    }
+
+    /// Return the color at `(x, y)`.
+    pub fn get(&self, x: usize, y: usize) -> u8 {
+        self.pixels[y * SHEET_SIZE + x]
+    }
+
+    /// Set the color at `(x, y)` like `sset(x, y, color)`. Only the low four
+    /// bits of `color` are kept.
+    pub fn set(&mut self, x: usize, y: usize, color: u8) {
+        self.pixels[y * SHEET_SIZE + x] = color & 0xf;
+    }
+
+    /// Set the 8x8 `pixels` of `sprite` in row-major order.
+    pub fn set_sprite(&mut self, sprite: u8, pixels: &[u8; 64]) {
+        let (left, top) = (sprite as usize % 16 * 8, sprite as usize / 16 * 8);
+        for (i, &color) in pixels.iter().enumerate() {
+            self.set(left + i % 8, top + i / 8, color);
+        }
+    }
+
+    /// Return the pixels as 8-bit RGB triples in row-major order.
+    pub fn to_rgb(&self) -> Vec<u8> {
+        self.pixels.iter().flat_map(|&c| crate::label::rgb(c)).collect()
+    }
+
+    /// Write the spritesheet as a 128x128 RGB PNG.
+    #[cfg(feature = "png")]
+    pub fn write_png(&self, w: impl std::io::Write) -> Result<(), png::EncodingError> {
+        let mut encoder = png::Encoder::new(w, SHEET_SIZE as u32, SHEET_SIZE as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.to_rgb())?;
+        writer.finish()
+    }
+}
+
+/// The map from the `__map__` section and the lower half of the `__gfx__`
+/// section.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TileMap {
+    /// The sprite of each tile in row-major order.
+    pub tiles: Vec<u8>,
+}
+
+impl Default for TileMap {
+    fn default() -> Self {
+        TileMap {
+            tiles: vec![0; MAP_WIDTH * MAP_HEIGHT],
+        }
+    }
+}
+
+impl TileMap {
+    /// Return the sprite at `(x, y)` like `mget(x, y)`.
+    pub fn get(&self, x: usize, y: usize) -> u8 {
+        self.tiles[y * MAP_WIDTH + x]
+    }
+
+    /// Set the sprite at `(x, y)` like `mset(x, y, sprite)`.
+    pub fn set(&mut self, x: usize, y: usize, sprite: u8) {
+        self.tiles[y * MAP_WIDTH + x] = sprite;
+    }
+
+    /// Render the map as JSON with its `width` and `height` in tiles and the
+    /// sprite of each tile in row-major order as `tiles`.
+    pub fn to_json(&self) -> String {
+        let tiles: Vec<String> = self.tiles.iter().map(u8::to_string).collect();
+        format!(
+            "{{\"width\":{MAP_WIDTH},\"height\":{MAP_HEIGHT},\"tiles\":[{}]}}",
+            tiles.join(",")
+        )
+    }
+}
+
+/// A music pattern from the `__music__` section.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Pattern {
+    /// Playback jumps back to the latest pattern with this flag when a
+    /// pattern with `end_loop` finishes.
+    pub begin_loop: bool,
+    /// Jump back to the loop's beginning after this pattern.
+    pub end_loop: bool,
+    /// Stop the music after this pattern.
+    pub stop: bool,
+    /// The sfx index played on each of the four channels or `None` if the
+    /// channel is disabled.
+    pub channels: [Option<u8>; 4],
+}
+
+impl Pattern {
+    /// The length of one `__music__` line, e.g. "00 41424344".
+    pub const LINE_LEN: usize = 11;
+
+    pub(crate) fn parse(line: &str) -> Result<Pattern, LineError> {
+        check_len(line, Self::LINE_LEN)?;
+        if line.as_bytes()[2] != b' ' {
+            return Err(LineError::InvalidHex);
+        }
+        let flags = hex_bytes(&line[..2])?[0];
+        let bytes = hex_bytes(&line[3..])?;
+        let mut channels = [None; 4];
+        for (channel, byte) in channels.iter_mut().zip(bytes) {
+            *channel = (byte & 0x40 == 0).then_some(byte & 0x3f);
+        }
+        Ok(Pattern {
+            begin_loop: flags & 1 != 0,
+            end_loop: flags & 2 != 0,
+            stop: flags & 4 != 0,
+            channels,
+        })
+    }
+
+    /// Iterate over the channels that are enabled as `(channel, sfx)` pairs.
+    pub fn sfx(&self) -> impl Iterator<Item = (usize, u8)> + '_ {
+        self.channels
+            .iter()
+            .enumerate()
+            .filter_map(|(i, c)| c.map(|sfx| (i, sfx)))
+    }
+
+    /// Return true if no channel is enabled.
+    pub fn is_empty(&self) -> bool {
+        self.channels.iter().all(Option::is_none)
+    }
+}
+
+/// Iterate over the patterns in the order they are played starting from
+/// pattern `start`, following loops at most once so that the iterator ends.
+pub fn play_order(patterns: &[Pattern], start: usize) -> impl Iterator<Item = usize> + '_ {
+    let mut next = Some(start);
+    let mut loop_start = start;
+    let mut looped = false;
+    std::iter::from_fn(move || {
+        let current = next.filter(|&i| i < patterns.len())?;
+        let pattern = &patterns[current];
+        if pattern.begin_loop {
+            loop_start = current;
+        }
+        next = if pattern.stop {
+            None
+        } else if pattern.end_loop {
+            if looped {
+                None
+            } else {
+                looped = true;
+                Some(loop_start)
+            }
+        } else {
+            Some(current + 1)
+        };
+        Some(current)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CART: &str = "pico-8 cartridge // http://www.pico-8.com
+version 41
+__lua__
+print(\"hi\")
+__gfx__
+00000000
+__sfx__
+000a0000180501a0501c0500000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000
+";
+
+    #[test]
+    fn test_round_trip() {
+        let cart = Cart::parse(CART).unwrap();
+        assert_eq!(cart.to_string(), CART);
+        assert_eq!(cart.lua(), Some("print(\"hi\")\n"));
+        assert_eq!(cart.section("gfx"), Some("00000000\n"));
+    }
+
+    #[test]
+    fn test_round_trip_any_order() {
+        let text = "pico-8 cartridge\n__label__\n00\n__lua__\nx = 1\n\
+            __meta:bbs__\nlicense: CC0\n__sfx__\n00\n";
+        let mut cart = Cart::parse(text).unwrap();
+        assert_eq!(cart.to_string(), text);
+        cart.sections[1].body = "x = 2\n".into();
+        assert_eq!(cart.to_string(), text.replace("x = 1", "x = 2"));
+    }
+
+    #[test]
+    fn test_tabs() {
+        let cart =
+            Cart::parse("pico-8 cartridge\n__lua__\nx = 1\n-->8\n-->8\nprint(x)\n").unwrap();
+        assert_eq!(cart.tabs(), vec!["x = 1\n", "", "print(x)\n"]);
+        assert_eq!(cart.tab_tokens(), vec![3, 0, 3]);
+        assert_eq!(join_tabs(&cart.tabs()), cart.lua().unwrap());
+        assert_eq!(join_tabs(&["a", "b"]), "a\n-->8\nb");
+        assert_eq!(tabs(""), vec![""]);
+    }
+
+    #[test]
+    fn test_title_author() {
+        let cart =
+            Cart::parse("pico-8 cartridge\n__lua__\n-- my game\n-- by someone\nx = 1\n").unwrap();
+        assert_eq!(cart.title(), Some("my game"));
+        assert_eq!(cart.author(), Some("by someone"));
+        assert_eq!(Cart::parse(CART).unwrap().title(), None);
+        assert_eq!(comment_header("-- a\r\n--b\nc\n--d"), "-- a\r\n--b\n");
+    }
+
+    #[test]
+    fn test_meta() {
+        let meta = Cart::parse(CART).unwrap().meta();
+        assert_eq!(meta.url.as_deref(), Some("http://www.pico-8.com"));
+        assert_eq!(meta.version, Some(41));
+        assert_eq!(meta.title, None);
+        let cart = Cart::parse("pico-8 cartridge\n__lua__\n-- game\n-- By someone\n").unwrap();
+        assert_eq!(
+            cart.meta(),
+            CartMeta {
+                title: Some("game".into()),
+                author: Some("someone".into()),
+                ..CartMeta::default()
+            }
+        );
+        let text = "pico-8 cartridge\n__lua__\n-- game\n__meta:title__\nnew game\nby me\n\
+                    __meta:bbs__\nlicense: CC0\n";
+        let cart = Cart::parse(text).unwrap();
+        let meta = cart.meta();
+        assert_eq!(meta.title.as_deref(), Some("new game"));
+        assert_eq!(meta.author.as_deref(), Some("me"));
+        assert_eq!(meta.sections["bbs"], "license: CC0\n");
+        assert_eq!(cart.to_string(), text);
+    }
+
+    #[test]
+    fn test_licenses() {
+        let cart = Cart::parse(
+            "pico-8 cartridge\n__lua__\n-- my game\n-- mit license\nx = 1\n__meta:bbs__\nlicense: cc0\n",
+        )
+        .unwrap();
+        let ids: Vec<_> = cart.licenses().into_iter().map(|l| l.spdx).collect();
+        assert_eq!(ids, vec![Some("MIT".into()), Some("CC0-1.0".into())]);
+    }
+
+    #[test]
+    fn test_set_data() {
+        let mut cart = Cart::parse("pico-8 cartridge\n__label__\n00\n__lua__\nx = 1\n__meta:bbs__\n\n").unwrap();
+        let mut sheet = Spritesheet::default();
+        sheet.set(127, 127, 0x1c);
+        cart.set_gfx(&sheet);
+        let mut flags = SpriteFlags::default();
+        flags.set(255, 0x81);
+        cart.set_sprite_flags(&flags);
+        cart.set_section("music", "00 41424344\n");
+        let names: Vec<_> = cart.sections.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["gfx", "label", "lua", "gff", "music", "meta:bbs"]);
+        let cart = Cart::parse(&cart.to_string()).unwrap();
+        assert_eq!(cart.gfx().unwrap(), sheet);
+        assert_eq!(cart.gfx().unwrap().get(127, 127), 0xc);
+        assert_eq!(cart.sprite_flags().unwrap(), flags);
+        assert_eq!(cart.music().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_missing_header() {
+        assert_eq!(Cart::parse("print(1)"), Err(CartError::MissingHeader));
+    }
+
+    #[test]
+    fn test_from_bytes() {
+        let cart = Cart::from_bytes(b"pico-8 cartridge\n__lua__\ns = \"\x87\"\n").unwrap();
+        assert_eq!(cart.lua(), Some("s = \"♥\"\n"));
+    }
+
+    #[test]
+    fn test_sfx() {
+        let cart = Cart::parse(CART).unwrap();
+        let sfx = cart.sfx().unwrap();
+        assert_eq!(sfx.len(), 1);
+        assert_eq!(sfx[0].speed, 10);
+        assert!(!sfx[0].loops());
+        assert_eq!(
+            sfx[0].notes[0],
+            Note {
+                pitch: 0x18,
+                waveform: 0,
+                volume: 5,
+                effect: 0
+            }
+        );
+        assert_eq!(sfx[0].notes[2].pitch, 0x1c);
+        assert!(sfx[0].notes[3].is_silent());
+    }
+
+    #[test]
+    fn test_music() {
+        let cart = Cart::parse(
+            "pico-8 cartridge\n__music__\n01 00014344\n00 02034244\n06 41424344\n",
+        )
+        .unwrap();
+        let music = cart.music().unwrap();
+        assert_eq!(music.len(), 3);
+        assert!(music[0].begin_loop);
+        assert_eq!(music[0].channels, [Some(0), Some(1), None, None]);
+        assert_eq!(music[1].sfx().collect::<Vec<_>>(), vec![(0, 2), (1, 3)]);
+        assert!(music[2].end_loop && music[2].stop);
+        assert!(music[2].is_empty());
+        assert_eq!(play_order(&music, 0).collect::<Vec<_>>(), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn test_play_order_loop() {
+        let a = Pattern {
+            begin_loop: true,
+            ..Pattern::default()
+        };
+        let b = Pattern {
+            end_loop: true,
+            ..Pattern::default()
+        };
+        let patterns = [Pattern::default(), a, b];
+        assert_eq!(play_order(&patterns, 0).collect::<Vec<_>>(), vec![0, 1, 2, 1, 2]);
+    }
+
+    #[test]
+    fn test_sprite_flags() {
+        let gff = format!("0103{}\n{}80\n", "0".repeat(252), "0".repeat(254));
+        let cart = Cart::parse(&format!("pico-8 cartridge\n__gff__\n{gff}")).unwrap();
+        let flags = cart.sprite_flags().unwrap();
+        assert_eq!(flags.get(0), 1);
+        assert!(flags.flag(1, 0) && flags.flag(1, 1) && !flags.flag(1, 2));
+        assert!(flags.flag(255, 7));
+        assert!(!flags.flag(2, 0));
+    }
+
+    #[test]
+    fn test_gfx_and_map() {
+        let mut gfx = format!("0a{}\n", "0".repeat(126));
+        for _ in 1..64 {
+            gfx.push_str(&"0".repeat(128));
+            gfx.push('\n');
+        }
+        gfx.push_str(&format!("12{}\n", "0".repeat(126)));
+        let map = format!("ff{}\n", "0".repeat(254));
+        let cart = Cart::parse(&format!("pico-8 cartridge\n__gfx__\n{gfx}__map__\n{map}")).unwrap();
+        let sheet = cart.gfx().unwrap();
+        assert_eq!(sheet.get(1, 0), 10);
+        assert_eq!(sheet.get(1, 64), 2);
+        let map = cart.map().unwrap();
+        assert_eq!(map.get(0, 0), 255);
+        assert_eq!(map.get(0, 32), 0x21);
+        assert_eq!(map.get(1, 32), 0);
+        assert!(map.to_json().starts_with("{\"width\":128,\"height\":64,\"tiles\":[255,0,"));
+    }
+
+    #[test]
+    fn test_sfx_bad_length() {
+        let cart = Cart::parse("pico-8 cartridge\n__sfx__\n0000\n").unwrap();
+        assert!(matches!(cart.sfx(), Err(CartError::BadLength { line: 0, .. })));
+    }
+}
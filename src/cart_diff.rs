@@ -0,0 +1,169 @@
+//! Comparing two carts section by section.
+//!
+//! The code is compared after converting it, and the data sections by the
+//! pixels, tiles, flags, notes, and patterns that changed, so a review sees
+//! what changed instead of lines of hex.
+use std::fmt;
+
+use crate::{
+    cart::{Cart, CartError, Sfx},
+    json::json_string,
+    patch_lua,
+};
+
+/// What changed from one cart to another.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CartDiff {
+    /// The converted code of the old and the new cart, if it changed.
+    pub lua: Option<(String, String)>,
+    /// The spritesheet pixels that changed.
+    pub pixels: usize,
+    /// The map tiles that changed.
+    pub tiles: usize,
+    /// The sprites whose flags changed.
+    pub flags: usize,
+    /// The sfx notes that changed, counting every note of an added or removed
+    /// sfx.
+    pub notes: usize,
+    /// The music patterns that changed, added, or removed.
+    pub patterns: usize,
+    /// The names of the other sections that changed, were added, or were
+    /// removed, e.g. "label".
+    pub sections: Vec<String>,
+}
+
+impl CartDiff {
+    /// Return true if nothing changed.
+    pub fn is_empty(&self) -> bool {
+        *self == CartDiff::default()
+    }
+
+    /// Render the diff as JSON. The code is only marked as changed, as
+    /// `"lua":true`.
+    pub fn to_json(&self) -> String {
+        let sections: Vec<String> = self.sections.iter().map(|s| json_string(s)).collect();
+        format!(
+            "{{\"lua\":{},\"pixels\":{},\"tiles\":{},\"flags\":{},\"notes\":{},\"patterns\":{},\
+             \"sections\":[{}]}}",
+            self.lua.is_some(),
+            self.pixels,
+            self.tiles,
+            self.flags,
+            self.notes,
+            self.patterns,
+            sections.join(",")
+        )
+    }
+}
+
+impl fmt::Display for CartDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "no changes");
+        }
+        if self.lua.is_some() {
+            writeln!(f, "lua: changed")?;
+        }
+        let counts = [
+            ("gfx", self.pixels, "pixel"),
+            ("map", self.tiles, "tile"),
+            ("gff", self.flags, "sprite"),
+            ("sfx", self.notes, "note"),
+            ("music", self.patterns, "pattern"),
+        ];
+        for (section, n, unit) in counts.into_iter().filter(|(_, n, _)| *n > 0) {
+            writeln!(
+                f,
+                "{section}: {n} {unit}{} changed",
+                if n == 1 { "" } else { "s" }
+            )?;
+        }
+        for section in &self.sections {
+            writeln!(f, "{section}: changed")?;
+        }
+        Ok(())
+    }
+}
+
+/// Count the places where `a` and `b` differ, counting each item only one of
+/// them has as `unmatched` changes.
+fn count_changes<T: PartialEq>(a: &[T], b: &[T], unmatched: usize) -> usize {
+    let changed = a.iter().zip(b).filter(|(a, b)| a != b).count();
+    changed + a.len().abs_diff(b.len()) * unmatched
+}
+
+/// Compare the cart `old` with the cart `new`.
+///
+/// ```
+/// use pico8_to_lua::{Cart, cart_diff::diff_carts};
+/// let cart = |gff: &str| format!("pico-8 cartridge\n__lua__\nx += 1\n__gff__\n{gff:0<256}\n");
+/// let old = Cart::parse(&cart("01")).unwrap();
+/// let new = Cart::parse(&cart("0203")).unwrap();
+/// let diff = diff_carts(&old, &new).unwrap();
+/// assert_eq!((diff.lua, diff.flags), (None, 2));
+/// ```
+pub fn diff_carts(old: &Cart, new: &Cart) -> Result<CartDiff, CartError> {
+    let (old_lua, new_lua) = (
+        patch_lua(old.lua().unwrap_or("")),
+        patch_lua(new.lua().unwrap_or("")),
+    );
+    let known = ["lua", "gfx", "map", "gff", "sfx", "music"];
+    let mut sections: Vec<String> = Vec::new();
+    for section in old.sections.iter().chain(&new.sections) {
+        let name = section.name.as_str();
+        if known.contains(&name) || sections.iter().any(|s| s == name) {
+            continue;
+        }
+        if old.section(name) != new.section(name) {
+            sections.push(name.to_string());
+        }
+    }
+    Ok(CartDiff {
+        lua: (old_lua != new_lua).then(|| (old_lua.into_owned(), new_lua.into_owned())),
+        pixels: count_changes(&old.gfx()?.pixels, &new.gfx()?.pixels, 1),
+        tiles: count_changes(&old.map()?.tiles, &new.map()?.tiles, 1),
+        flags: count_changes(&old.sprite_flags()?.0, &new.sprite_flags()?.0, 1),
+        notes: {
+            let (old, new) = (old.sfx()?, new.sfx()?);
+            let notes = |sfx: &[Sfx]| -> Vec<_> { sfx.iter().flat_map(|s| s.notes).collect() };
+            count_changes(&notes(&old), &notes(&new), 1)
+        },
+        patterns: count_changes(&old.music()?, &new.music()?, 1),
+        sections,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff_carts() {
+        let cart = |lua: &str, gfx: &str, meta: &str| {
+            let cart = format!(
+                "pico-8 cartridge\n__lua__\n{lua}\n__gfx__\n{gfx:0<128}\n__label__\n00\n__meta:a__\n{meta}\n"
+            );
+            Cart::parse(&cart).unwrap()
+        };
+        let old = cart("x += 1", "01", "b");
+        let new = cart("x += 2", "001", "c");
+        let diff = diff_carts(&old, &new).unwrap();
+        assert_eq!(
+            diff.lua,
+            Some(("x = x + (1)\n".into(), "x = x + (2)\n".into()))
+        );
+        assert_eq!(diff.pixels, 2);
+        assert_eq!(diff.sections, vec!["meta:a"]);
+        assert_eq!(
+            diff.to_json(),
+            "{\"lua\":true,\"pixels\":2,\"tiles\":0,\"flags\":0,\"notes\":0,\"patterns\":0,\
+             \"sections\":[\"meta:a\"]}"
+        );
+        assert_eq!(
+            diff.to_string(),
+            "lua: changed\ngfx: 2 pixels changed\nmeta:a: changed\n"
+        );
+        assert!(diff_carts(&old, &old).unwrap().is_empty());
+    }
+}
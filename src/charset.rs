@@ -0,0 +1,109 @@
+//! Pico-8's one-byte charset.
+//!
+//! A ".p8" cart is UTF-8, but the code in a ".p8.png" cart, a ".rom", or
+//! Pico-8's memory is one byte per character. [CHARSET] gives the Unicode
+//! that Pico-8 writes for each byte, so [to_utf8] shows a decoded cart with
+//! its glyphs and [from_utf8] turns it back into the same bytes.
+use std::{error::Error, fmt};
+
+use crate::glyph::{is_selector, strip_selectors};
+
+/// The Unicode Pico-8 writes for each byte, with the emoji variation
+/// selector after the button glyphs it saves with one.
+#[rustfmt::skip]
+pub const CHARSET: [&str; 256] = [
+    "\0", "¹", "²", "³", "⁴", "⁵", "⁶", "⁷", "⁸", "\t", "\n", "ᵇ", "ᶜ", "\r", "ᵉ", "ᶠ",
+    "▮", "■", "□", "⁙", "⁘", "‖", "◀", "▶", "「", "」", "¥", "•", "、", "。", "゛", "゜",
+    " ", "!", "\"", "#", "$", "%", "&", "'", "(", ")", "*", "+", ",", "-", ".", "/",
+    "0", "1", "2", "3", "4", "5", "6", "7", "8", "9", ":", ";", "<", "=", ">", "?",
+    "@", "A", "B", "C", "D", "E", "F", "G", "H", "I", "J", "K", "L", "M", "N", "O",
+    "P", "Q", "R", "S", "T", "U", "V", "W", "X", "Y", "Z", "[", "\\", "]", "^", "_",
+    "`", "a", "b", "c", "d", "e", "f", "g", "h", "i", "j", "k", "l", "m", "n", "o",
+    "p", "q", "r", "s", "t", "u", "v", "w", "x", "y", "z", "{", "|", "}", "~", "○",
+    "█", "▒", "🐱", "⬇\u{fe0f}", "░", "✽", "●", "♥", "☉", "웃", "⌂", "⬅\u{fe0f}", "😐", "♪", "🅾\u{fe0f}", "◆",
+    "…", "➡\u{fe0f}", "★", "⧗", "⬆\u{fe0f}", "ˇ", "∧", "❎", "▤", "▥", "あ", "い", "う", "え", "お", "か",
+    "き", "く", "け", "こ", "さ", "し", "す", "せ", "そ", "た", "ち", "つ", "て", "と", "な", "に",
+    "ぬ", "ね", "の", "は", "ひ", "ふ", "へ", "ほ", "ま", "み", "む", "め", "も", "や", "ゆ", "よ",
+    "ら", "り", "る", "れ", "ろ", "わ", "を", "ん", "っ", "ゃ", "ゅ", "ょ", "ア", "イ", "ウ", "エ",
+    "オ", "カ", "キ", "ク", "ケ", "コ", "サ", "シ", "ス", "セ", "ソ", "タ", "チ", "ツ", "テ", "ト",
+    "ナ", "ニ", "ヌ", "ネ", "ノ", "ハ", "ヒ", "フ", "ヘ", "ホ", "マ", "ミ", "ム", "メ", "モ", "ヤ",
+    "ユ", "ヨ", "ラ", "リ", "ル", "レ", "ロ", "ワ", "ヲ", "ン", "ッ", "ャ", "ュ", "ョ", "◜", "◝",
+];
+
+/// A character with no byte in Pico-8's charset.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CharsetError {
+    /// The character.
+    pub ch: char,
+    /// Its byte offset in the text.
+    pub offset: usize,
+}
+
+impl fmt::Display for CharsetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?} at byte {} is not in Pico-8's charset",
+            self.ch, self.offset
+        )
+    }
+}
+
+impl Error for CharsetError {}
+
+/// Return the byte of `c` in Pico-8's charset, or `None` if it has none.
+///
+/// ```
+/// use pico8_to_lua::charset::byte_of;
+/// assert_eq!(byte_of('a'), Some(97));
+/// assert_eq!(byte_of('♥'), Some(135));
+/// assert_eq!(byte_of('⬅'), Some(139));
+/// assert_eq!(byte_of('€'), None);
+/// ```
+pub fn byte_of(c: char) -> Option<u8> {
+    if c == ' ' || c.is_ascii_graphic() {
+        return Some(c as u8);
+    }
+    CHARSET
+        .iter()
+        .position(|g| strip_selectors(g).chars().eq([c]))
+        .map(|i| i as u8)
+}
+
+/// Decode bytes in Pico-8's charset.
+///
+/// ```
+/// use pico8_to_lua::charset::to_utf8;
+/// assert_eq!(to_utf8(b"s = \"\x87\x8b\""), "s = \"♥⬅\u{fe0f}\"");
+/// ```
+pub fn to_utf8(bytes: &[u8]) -> String {
+    bytes.iter().map(|&b| CHARSET[b as usize]).collect()
+}
+
+/// Encode `text` in Pico-8's charset, skipping variation selectors, or
+/// return the first character that has no byte.
+///
+/// ```
+/// use pico8_to_lua::charset::from_utf8;
+/// assert_eq!(from_utf8("♥⬅\u{fe0f}⬅").unwrap(), b"\x87\x8b\x8b");
+/// assert_eq!(from_utf8("€").unwrap_err().offset, 0);
+/// ```
+pub fn from_utf8(text: &str) -> Result<Vec<u8>, CharsetError> {
+    text.char_indices()
+        .filter(|(_, c)| !is_selector(*c))
+        .map(|(offset, ch)| byte_of(ch).ok_or(CharsetError { ch, offset }))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let bytes: Vec<u8> = (0..=255).collect();
+        let text = to_utf8(&bytes);
+        assert_eq!(from_utf8(&text).unwrap(), bytes);
+        assert_eq!(text.chars().filter(|c| !is_selector(*c)).count(), 256);
+    }
+}
@@ -0,0 +1,103 @@
+//! Checking that converted Lua compiles, with the `mlua` feature.
+//!
+//! [verify] loads the converted code into a Lua 5.4 state without running it,
+//! so a conversion that produced invalid Lua fails at once instead of when
+//! the game reaches the line. [verify_with_map] points the error at the line
+//! of the source it came from.
+use std::{error::Error, fmt};
+
+use crate::sourcemap::SourceMap;
+
+/// The name the code is loaded under, which Lua puts before its errors.
+const CHUNK_NAME: &str = "lua";
+
+/// Lua that does not compile.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyntaxError {
+    /// The path of the "#include" the line is from, if the error was mapped
+    /// to one.
+    pub path: Option<String>,
+    /// The one-based line of the error, if Lua gave one.
+    pub line: Option<usize>,
+    /// Lua's message without the line.
+    pub message: String,
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.path, self.line) {
+            (Some(path), Some(line)) => write!(f, "{path}:{line}: {}", self.message),
+            (None, Some(line)) => write!(f, "line {line}: {}", self.message),
+            _ => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl Error for SyntaxError {}
+
+impl SyntaxError {
+    /// Read an error message of Lua, e.g. `lua:3: unexpected symbol near 'x'`.
+    fn parse(message: &str) -> SyntaxError {
+        let prefix = format!("{CHUNK_NAME}:");
+        let parsed = message
+            .strip_prefix(&prefix)
+            .and_then(|rest| rest.split_once(": "))
+            .and_then(|(line, rest)| Some((line.parse().ok()?, rest)));
+        let (line, message) = match parsed {
+            Some((line, rest)) => (Some(line), rest),
+            None => (None, message),
+        };
+        SyntaxError {
+            path: None,
+            line,
+            message: message.to_string(),
+        }
+    }
+}
+
+/// Check that converted `lua` compiles, without running it.
+///
+/// ```
+/// use pico8_to_lua::{compile::verify, patch_lua};
+/// assert!(verify(&patch_lua("if (a != b) x += 1")).is_ok());
+/// let error = verify("x = 1\ny = = 2").unwrap_err();
+/// assert_eq!(error.line, Some(2));
+/// ```
+pub fn verify(lua: &str) -> Result<(), SyntaxError> {
+    let state = mlua::Lua::new();
+    let chunk = state.load(lua).set_name(format!("={CHUNK_NAME}"));
+    match chunk.into_function() {
+        Ok(_) => Ok(()),
+        Err(mlua::Error::SyntaxError { message, .. }) => Err(SyntaxError::parse(&message)),
+        Err(e) => Err(SyntaxError::parse(&e.to_string())),
+    }
+}
+
+/// Check that converted `lua` compiles like [verify], giving the line of the
+/// error in the source `map` maps it to.
+pub fn verify_with_map(lua: &str, map: &SourceMap) -> Result<(), SyntaxError> {
+    verify(lua).map_err(|mut e| {
+        if let Some(source) = e.line.and_then(|line| map.lookup(line)) {
+            e.path = source.path.clone();
+            e.line = Some(source.line);
+        }
+        e
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sourcemap::patch_with_source_map;
+
+    #[test]
+    fn test_verify_with_map() {
+        let (lua, map) =
+            patch_with_source_map("#include a.lua\nx += 1", |_| "a = 1\nb = = 2".into());
+        let error = verify_with_map(&lua, &map).unwrap_err();
+        assert_eq!(error.path.as_deref(), Some("a.lua"));
+        assert_eq!(error.line, Some(2));
+        assert!(error.to_string().starts_with("a.lua:2: "));
+        assert_eq!(SyntaxError::parse("out of memory").line, None);
+    }
+}
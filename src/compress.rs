@@ -0,0 +1,328 @@
+//! Pico-8's code compression formats.
+//!
+//! Carts saved as ".p8.png" or ".rom" store their code compressed in either
+//! the current "pxa" format or the legacy ":c:" format. Plain text code is
+//! stored as is, terminated by a zero byte.
+use std::{error::Error, fmt};
+
+/// The header of the current compression format.
+pub const PXA_HEADER: &[u8; 4] = b"\0pxa";
+/// The header of the legacy compression format.
+pub const LEGACY_HEADER: &[u8; 4] = b":c:\0";
+
+/// An error encountered while decompressing code.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecompressError {
+    /// The data ended before the code did.
+    Truncated,
+    /// A back reference pointed before the start of the code.
+    BadOffset,
+}
+
+impl fmt::Display for DecompressError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecompressError::Truncated => write!(f, "compressed code is truncated"),
+            DecompressError::BadOffset => write!(f, "compressed code has an invalid back reference"),
+        }
+    }
+}
+
+impl Error for DecompressError {}
+
+/// Decompress the code region of a cart's ROM, whichever format it is in, and
+/// return its bytes in Pico-8's charset.
+pub fn decompress(rom_code: &[u8]) -> Result<Vec<u8>, DecompressError> {
+    if rom_code.starts_with(PXA_HEADER) {
+        decompress_pxa(rom_code)
+    } else if rom_code.starts_with(LEGACY_HEADER) {
+        decompress_legacy(rom_code)
+    } else {
+        let end = rom_code.iter().position(|&b| b == 0).unwrap_or(rom_code.len());
+        Ok(rom_code[..end].to_vec())
+    }
+}
+
+fn copy_back(out: &mut Vec<u8>, offset: usize, count: usize) -> Result<(), DecompressError> {
+    if offset == 0 || offset > out.len() {
+        return Err(DecompressError::BadOffset);
+    }
+    for _ in 0..count {
+        out.push(out[out.len() - offset]);
+    }
+    Ok(())
+}
+
+const LEGACY_LUT: &[u8] = b"\n 0123456789abcdefghijklmnopqrstuvwxyz!#%(){}[]<>+=/*:;.,~_";
+
+/// Decompress the legacy ":c:" format.
+pub fn decompress_legacy(data: &[u8]) -> Result<Vec<u8>, DecompressError> {
+    let header = data.get(..8).ok_or(DecompressError::Truncated)?;
+    let len = u16::from_be_bytes([header[4], header[5]]) as usize;
+    let mut bytes = data[8..].iter().copied();
+    let mut next = || bytes.next().ok_or(DecompressError::Truncated);
+    let mut out = Vec::with_capacity(len);
+    while out.len() < len {
+        match next()? {
+            0 => out.push(next()?),
+            b @ 1..=0x3b => out.push(LEGACY_LUT[b as usize - 1]),
+            b => {
+                let b2 = next()?;
+                let offset = (b as usize - 0x3c) * 16 + (b2 & 0xf) as usize;
+                let count = (b2 >> 4) as usize + 2;
+                copy_back(&mut out, offset, count)?;
+            }
+        }
+    }
+    out.truncate(len);
+    Ok(out)
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl BitReader<'_> {
+    fn bit(&mut self) -> Result<u32, DecompressError> {
+        let byte = self.data.get(self.pos >> 3).ok_or(DecompressError::Truncated)?;
+        let bit = (byte >> (self.pos & 7)) & 1;
+        self.pos += 1;
+        Ok(bit as u32)
+    }
+
+    fn bits(&mut self, n: u32) -> Result<u32, DecompressError> {
+        let mut value = 0;
+        for i in 0..n {
+            value |= self.bit()? << i;
+        }
+        Ok(value)
+    }
+}
+
+/// Decompress the "pxa" format.
+pub fn decompress_pxa(data: &[u8]) -> Result<Vec<u8>, DecompressError> {
+    let header = data.get(..8).ok_or(DecompressError::Truncated)?;
+    let len = u16::from_be_bytes([header[4], header[5]]) as usize;
+    let mut mtf: Vec<u8> = (0..=255).collect();
+    let mut r = BitReader {
+        data: &data[8..],
+        pos: 0,
+    };
+    let mut out = Vec::with_capacity(len);
+    while out.len() < len {
+        if r.bit()? == 1 {
+            let mut extra = 0;
+            while r.bit()? == 1 {
+                extra += 1;
+                if extra > 4 {
+                    return Err(DecompressError::BadOffset);
+                }
+            }
+            let index = r.bits(4 + extra)? as usize + (((1 << extra) - 1) << 4);
+            let byte = mtf.remove(index.min(255));
+            mtf.insert(0, byte);
+            out.push(byte);
+        } else {
+            let offset_bits = if r.bit()? == 1 {
+                if r.bit()? == 1 { 5 } else { 10 }
+            } else {
+                15
+            };
+            let offset = r.bits(offset_bits)? as usize + 1;
+            if offset_bits == 10 && offset == 1 {
+                loop {
+                    match r.bits(8)? {
+                        0 => break,
+                        byte => out.push(byte as u8),
+                    }
+                }
+            } else {
+                let mut count = 3;
+                loop {
+                    let part = r.bits(3)?;
+                    count += part as usize;
+                    if part != 7 {
+                        break;
+                    }
+                }
+                copy_back(&mut out, offset, count)?;
+            }
+        }
+    }
+    out.truncate(len);
+    Ok(out)
+}
+
+struct BitWriter {
+    bytes: Vec<u8>,
+    bit: usize,
+}
+
+impl BitWriter {
+    fn bits(&mut self, value: u32, n: u32) {
+        for i in 0..n {
+            if self.bit.is_multiple_of(8) {
+                self.bytes.push(0);
+            }
+            if (value >> i) & 1 != 0 {
+                *self.bytes.last_mut().unwrap() |= 1 << (self.bit % 8);
+            }
+            self.bit += 1;
+        }
+    }
+}
+
+/// The largest distance a back reference can reach.
+const MAX_OFFSET: usize = 1 << 15;
+/// How many earlier positions with the same prefix to try for a match.
+const MAX_CHAIN: usize = 64;
+
+/// Return the unary prefix length and bit width used for move-to-front index
+/// `index`.
+fn literal_extra(index: usize) -> u32 {
+    let mut extra = 0;
+    while index >= ((1 << (extra + 1)) - 1) << 4 {
+        extra += 1;
+    }
+    extra
+}
+
+fn literal_cost(index: usize) -> usize {
+    let extra = literal_extra(index) as usize;
+    2 + extra + 4 + extra
+}
+
+fn offset_bits(offset: usize) -> u32 {
+    match offset - 1 {
+        0..32 => 5,
+        32..1024 => 10,
+        _ => 15,
+    }
+}
+
+fn match_cost(offset: usize, len: usize) -> usize {
+    let header = match offset_bits(offset) {
+        5 => 3,
+        10 => 3,
+        _ => 2,
+    };
+    header + offset_bits(offset) as usize + 3 * ((len - 3) / 7 + 1)
+}
+
+/// Compress `code` in the "pxa" format.
+///
+/// This is a greedy compressor, so its output may be a little larger than
+/// Pico-8's own, but it is always decompressible by Pico-8 and by
+/// [decompress_pxa].
+pub fn compress_pxa(code: &[u8]) -> Vec<u8> {
+    let mut w = BitWriter {
+        bytes: Vec::new(),
+        bit: 0,
+    };
+    let mut mtf: Vec<u8> = (0..=255).collect();
+    let mut chains: std::collections::HashMap<[u8; 3], Vec<usize>> = Default::default();
+    let mut pos = 0;
+    while pos < code.len() {
+        let mut best: Option<(usize, usize)> = None;
+        if let Some(key) = code.get(pos..pos + 3) {
+            let key = [key[0], key[1], key[2]];
+            for &start in chains.get(&key).into_iter().flatten().rev().take(MAX_CHAIN) {
+                let offset = pos - start;
+                if offset > MAX_OFFSET {
+                    break;
+                }
+                let len = code[pos..]
+                    .iter()
+                    .zip(&code[start..])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                if best.is_none_or(|(_, l)| len > l) {
+                    best = Some((offset, len));
+                }
+            }
+        }
+        let literal_bits = mtf.iter().position(|&b| b == code[pos]).map_or(0, literal_cost);
+        let step = match best {
+            Some((offset, len)) if match_cost(offset, len) < literal_bits * len => {
+                w.bits(0, 1);
+                match offset_bits(offset) {
+                    5 => w.bits(0b11, 2),
+                    10 => w.bits(0b01, 2),
+                    _ => w.bits(0, 1),
+                }
+                w.bits((offset - 1) as u32, offset_bits(offset));
+                let mut rest = len - 3;
+                loop {
+                    let part = rest.min(7);
+                    w.bits(part as u32, 3);
+                    rest -= part;
+                    if part != 7 {
+                        break;
+                    }
+                }
+                len
+            }
+            _ => {
+                let index = mtf.iter().position(|&b| b == code[pos]).unwrap();
+                let extra = literal_extra(index);
+                w.bits(1, 1);
+                w.bits((1 << extra) - 1, extra);
+                w.bits(0, 1);
+                w.bits((index - ((((1 << extra) - 1) << 4) as usize)) as u32, 4 + extra);
+                let byte = mtf.remove(index);
+                mtf.insert(0, byte);
+                1
+            }
+        };
+        for p in pos..(pos + step).min(code.len().saturating_sub(2)) {
+            chains.entry([code[p], code[p + 1], code[p + 2]]).or_default().push(p);
+        }
+        pos += step;
+    }
+    let mut out = Vec::with_capacity(8 + w.bytes.len());
+    out.extend_from_slice(PXA_HEADER);
+    out.extend_from_slice(&(code.len() as u16).to_be_bytes());
+    out.extend_from_slice(&((8 + w.bytes.len()) as u16).to_be_bytes());
+    out.extend_from_slice(&w.bytes);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plain() {
+        assert_eq!(decompress(b"x=1\0\0\0").unwrap(), b"x=1");
+    }
+
+    #[test]
+    fn test_legacy() {
+        // "ab" as LUT indices, then a back reference of offset 2, count 2.
+        let data = [b':', b'c', b':', 0, 0, 4, 0, 0, 13, 14, 0x3c, 0x02];
+        assert_eq!(decompress(&data).unwrap(), b"abab");
+    }
+
+    #[test]
+    fn test_pxa_round_trip() {
+        let code = b"function _update()\n x += 1\n y += 1\n x += 1\nend\n\x80\xff";
+        let compressed = compress_pxa(code);
+        assert!(compressed.starts_with(PXA_HEADER));
+        assert_eq!(decompress(&compressed).unwrap(), code);
+    }
+
+    #[test]
+    fn test_pxa_long_repeat() {
+        let code = "abc".repeat(1000);
+        let compressed = compress_pxa(code.as_bytes());
+        assert!(compressed.len() < 200, "{}", compressed.len());
+        assert_eq!(decompress(&compressed).unwrap(), code.as_bytes());
+    }
+
+    #[test]
+    fn test_legacy_truncated() {
+        let data = [b':', b'c', b':', 0, 0, 4, 0, 0, 13];
+        assert_eq!(decompress(&data), Err(DecompressError::Truncated));
+    }
+}
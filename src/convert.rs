@@ -0,0 +1,154 @@
+//! Converting a cart in one call.
+//!
+//! [convert] takes the bytes of a ".p8" cart, a ".p8.png" cart, a ".rom", or
+//! Pico-8 Lua, tells which it is, resolves its includes, runs the passes, and
+//! returns the Lua with the cart, the [PatchReport], and the [SourceMap] that
+//! otherwise take several functions called in the right order.
+use std::path::PathBuf;
+
+use crate::{
+    Cart, CartError, Error, Flattened, FsResolver, IncludeError, Options, batch::Converter,
+    cart::is_cart,
+    detect::{Format, ReadError, detect, lua_from_bytes},
+    patch_lua_with_report, pipeline::PatchReport, sourcemap::SourceMap,
+};
+
+/// Everything a conversion gives.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Converted {
+    /// The converted Lua.
+    pub lua: String,
+    /// The cart with its Lua converted if the input was a ".p8" cart, or
+    /// `None` for a ".p8.png" cart, whose other sections are not read, or
+    /// for Lua.
+    pub cart: Option<Cart>,
+    /// What each Pico-8 syntax rewrite changed.
+    pub report: PatchReport,
+    /// The source of each line of [lua](Converted::lua).
+    pub source_map: SourceMap,
+}
+
+/// Convert a ".p8" cart, a ".p8.png" cart, or Pico-8 Lua with `options`,
+/// resolving its includes from the current directory.
+///
+/// ```
+/// use pico8_to_lua::{Options, convert};
+/// let converted = convert("pico-8 cartridge\n__lua__\nx += 1\n", &Options::default()).unwrap();
+/// assert_eq!(converted.lua, "x = x + (1)\n");
+/// assert_eq!(converted.report.count("compound-assignment"), 1);
+/// assert_eq!(converted.source_map.lookup(1).unwrap().line, 1);
+/// ```
+pub fn convert(input: impl AsRef<[u8]>, options: &Options) -> Result<Converted, Error> {
+    convert_with_resolver(input, options, &mut FsResolver::new([PathBuf::from(".")]))
+}
+
+/// Convert like [convert], resolving the includes with `resolver`.
+pub fn convert_with_resolver(
+    input: impl AsRef<[u8]>,
+    options: &Options,
+    resolver: &mut FsResolver,
+) -> Result<Converted, Error> {
+    if resolver.limits.is_none() {
+        resolver.limits = options.limits;
+    }
+    let text = read_text(input.as_ref())?;
+    convert_text(&text, options, |path| resolver.resolve_nested(path))
+}
+
+/// Convert the text of a ".p8" cart or Lua like [convert], resolving each
+/// include with its includes with `resolve`.
+pub(crate) fn convert_text(
+    text: &str,
+    options: &Options,
+    mut resolve: impl FnMut(&str) -> Result<String, IncludeError>,
+) -> Result<Converted, Error> {
+    let mut options = Options {
+        lua_only: true,
+        ..options.clone()
+    };
+    if let Some(limits) = &mut options.limits {
+        limits.check_input(text).map_err(CartError::Limit)?;
+        limits.max_input = limits.max_input.saturating_add(limits.max_include_bytes);
+    }
+    let mut cart = if is_cart(text) {
+        Some(Cart::parse(text)?)
+    } else {
+        None
+    };
+    let lua = match &cart {
+        Some(cart) => cart.lua().unwrap_or(""),
+        None => text,
+    };
+    let mut failed: Option<IncludeError> = None;
+    let flattened = Flattened::new(lua, |path| {
+        resolve(path).unwrap_or_else(|e| {
+            failed.get_or_insert(e);
+            String::new()
+        })
+    });
+    if let Some(e) = failed {
+        return Err(e.into());
+    }
+    let (_, report) = patch_lua_with_report(flattened.lua.as_str(), &options.patch);
+    let mut converter = Converter::new(options);
+    let lua = match &mut cart {
+        Some(cart) => {
+            if let Some(section) = cart.sections.iter_mut().find(|s| s.name == "lua") {
+                section.body = flattened.lua.clone();
+            }
+            let lua = converter.convert(&cart.to_string())?;
+            if let Some(section) = cart.sections.iter_mut().find(|s| s.name == "lua") {
+                section.body = lua.clone();
+            }
+            lua
+        }
+        None => converter.convert(&flattened.lua)?,
+    };
+    let source_map = SourceMap::new(&flattened, &lua);
+    Ok(Converted {
+        lua,
+        cart,
+        report,
+        source_map,
+    })
+}
+
+/// Return the text of a ".p8" cart or Lua, decoding it as P8SCII if it is not
+/// UTF-8, or the Lua of a ".p8.png" cart or a ".rom".
+pub(crate) fn read_text(input: &[u8]) -> Result<String, Error> {
+    match detect(input) {
+        Some(format @ (Format::P8Png | Format::Rom)) => {
+            lua_from_bytes(format, input.to_vec()).map_err(|e| match e {
+                ReadError::Io(e) => Error::Io(e),
+                #[cfg(feature = "png")]
+                ReadError::P8Png(e) => Error::Png(e),
+                e => Error::Io(std::io::Error::new(std::io::ErrorKind::InvalidData, e)),
+            })
+        }
+        _ => Ok(match std::str::from_utf8(input) {
+            Ok(text) => text.to_string(),
+            Err(_) => crate::p8scii::decode(input),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_convert() {
+        let cart = "pico-8 cartridge\n__lua__\nif (a != b) c()\n__gfx__\n00\n";
+        let converted = convert(cart.as_bytes(), &Options::default()).unwrap();
+        assert_eq!(converted.lua, "if a ~= b then c() end\n");
+        assert_eq!(
+            converted.cart.unwrap().to_string(),
+            "pico-8 cartridge\n__lua__\nif a ~= b then c() end\n__gfx__\n00\n"
+        );
+        assert_eq!(converted.report.count("not-equal"), 1);
+        let lua = convert(b"s = \"\x87\"", &Options::default()).unwrap().lua;
+        assert_eq!(lua, "s = \"♥\"");
+        let missing = convert("#include missing.lua\n", &Options::default());
+        assert!(matches!(missing, Err(Error::Include(_))));
+    }
+}
@@ -0,0 +1,64 @@
+//! Rewriting Pico-8's coroutine functions with Lua's `coroutine` library.
+//!
+//! Pico-8 names `coroutine.create`, `coroutine.resume`, `coroutine.status`,
+//! and `coroutine.yield` as the globals `cocreate`, `coresume`, `costatus`,
+//! and `yield`, so a cart that plays its cutscenes in coroutines fails on the
+//! first `cocreate` in plain Lua. [patch_coroutines] calls Lua's library
+//! instead, which behaves the same, so no shims are needed for them.
+use std::borrow::Cow;
+
+use crate::{
+    api::api_calls,
+    lexer::{Token, TokenKind, tokenize},
+    span::{TextEdit, apply_edits},
+};
+
+/// Pico-8's coroutine functions and the functions of Lua's library they are.
+const FUNCTIONS: &[(&str, &str)] = &[
+    ("cocreate", "coroutine.create"),
+    ("coresume", "coroutine.resume"),
+    ("costatus", "coroutine.status"),
+    ("yield", "coroutine.yield"),
+];
+
+/// Rewrite calls to `cocreate`, `coresume`, `costatus`, and `yield` as calls
+/// to Lua's `coroutine` library.
+///
+/// ```
+/// use pico8_to_lua::coroutines::patch_coroutines;
+/// assert_eq!(
+///     patch_coroutines("c = cocreate(function() yield() end) coresume(c)"),
+///     "c = coroutine.create(function() coroutine.yield() end) coroutine.resume(c)"
+/// );
+/// ```
+pub fn patch_coroutines<'h>(lua: impl Into<Cow<'h, str>>) -> Cow<'h, str> {
+    let lua = lua.into();
+    let tokens: Vec<Token> = tokenize(&lua)
+        .filter(|t| t.kind != TokenKind::Comment)
+        .collect();
+    let edits: Vec<TextEdit> = api_calls(&tokens)
+        .filter_map(|(name, i)| {
+            let (_, standard) = FUNCTIONS.iter().find(|(f, _)| *f == name)?;
+            Some(TextEdit::new(tokens[i].span, *standard))
+        })
+        .collect();
+    if edits.is_empty() {
+        return lua;
+    }
+    Cow::Owned(apply_edits(&lua, &edits))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_patch_coroutines() {
+        assert_eq!(
+            patch_coroutines("if costatus(c) != \"dead\" then coresume(c, 1) end\n-- yield()"),
+            "if coroutine.status(c) != \"dead\" then coroutine.resume(c, 1) end\n-- yield()"
+        );
+        let lua = "local function yield() end\nyield() s = \"cocreate(f)\" t.cocreate(f)";
+        assert_eq!(patch_coroutines(lua), lua);
+    }
+}
@@ -0,0 +1,219 @@
+//! The carts and save data a cart uses besides its own.
+//!
+//! `reload()` and `cstore()` take an optional fourth argument naming another
+//! cart to read from or write to, and `cartdata()` opens the persistent data
+//! that `dget()` and `dset()` read and write in 64 numbered slots. A port
+//! must bundle those carts and emulate that data, so [data_usage] lists them
+//! from the calls' literal arguments and the lines where an argument is
+//! computed and can't be known without running the cart.
+use std::{collections::BTreeSet, fmt};
+
+use crate::{
+    lexer::{Token, TokenKind, tokenize},
+    span::Span,
+};
+
+/// The number of `dget()` and `dset()` slots of a cart's persistent data.
+pub const SLOTS: usize = 64;
+
+/// The carts and save data a cart uses.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct DataUsage {
+    /// The carts that `reload()` reads from, by file name.
+    pub reads: BTreeSet<String>,
+    /// The carts that `cstore()` writes to, by file name.
+    pub writes: BTreeSet<String>,
+    /// Whether `reload()` or `cstore()` use the cart's own data.
+    pub own_cart: bool,
+    /// The ids passed to `cartdata()`.
+    pub cartdata: BTreeSet<String>,
+    /// The slots that `dget()` and `dset()` use.
+    pub slots: BTreeSet<usize>,
+    /// The one-based lines of the calls with a cart, id, or slot that is not
+    /// a literal.
+    pub unknown: Vec<usize>,
+}
+
+impl DataUsage {
+    /// The bytes of persistent data to emulate: enough for the highest slot
+    /// used, or all of them if a slot is not known.
+    ///
+    /// ```
+    /// use pico8_to_lua::data_usage::data_usage;
+    /// assert_eq!(data_usage("cartdata(\"game\") dset(3, 1)").save_bytes(), 16);
+    /// assert_eq!(data_usage("cartdata(\"game\") dset(i, 1)").save_bytes(), 256);
+    /// ```
+    pub fn save_bytes(&self) -> usize {
+        if self.cartdata.is_empty() && self.slots.is_empty() {
+            return 0;
+        }
+        if !self.unknown.is_empty() {
+            return SLOTS * 4;
+        }
+        self.slots.last().map_or(0, |slot| (slot + 1) * 4)
+    }
+}
+
+impl fmt::Display for DataUsage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for cart in &self.reads {
+            writeln!(f, "reads: {cart}")?;
+        }
+        for cart in &self.writes {
+            writeln!(f, "writes: {cart}")?;
+        }
+        if self.own_cart {
+            writeln!(f, "reads or writes its own cart")?;
+        }
+        for id in &self.cartdata {
+            writeln!(f, "cartdata: {id}")?;
+        }
+        if !self.slots.is_empty() {
+            let slots: Vec<String> = self.slots.iter().map(|s| s.to_string()).collect();
+            writeln!(f, "slots: {}", slots.join(", "))?;
+        }
+        for line in &self.unknown {
+            writeln!(f, "line {line}: argument not known until run")?;
+        }
+        writeln!(f, "save data: {} bytes", self.save_bytes())
+    }
+}
+
+/// Return the arguments of the call whose name is `tokens[at]`, or `None`
+/// if it is not called there.
+fn arguments<'a, 'h>(tokens: &'a [Token<'h>], at: usize) -> Option<Vec<&'a [Token<'h>]>> {
+    let next = tokens.get(at + 1)?;
+    if next.kind == TokenKind::String {
+        return Some(vec![&tokens[at + 1..at + 2]]);
+    }
+    if !next.is("(") {
+        return None;
+    }
+    let mut args = Vec::new();
+    let mut depth = 0;
+    let mut start = at + 2;
+    for (i, token) in tokens.iter().enumerate().skip(at + 2) {
+        if token.is("(") || token.is("[") || token.is("{") {
+            depth += 1;
+        } else if depth > 0 && (token.is(")") || token.is("]") || token.is("}")) {
+            depth -= 1;
+        } else if depth == 0 && (token.is(",") || token.is(")")) {
+            if i > start || token.is(",") {
+                args.push(&tokens[start..i]);
+            }
+            if token.is(")") {
+                return Some(args);
+            }
+            start = i + 1;
+        }
+    }
+    None
+}
+
+/// Return the text of a short string literal.
+fn string_value(arg: &[Token]) -> Option<String> {
+    let [token] = arg else {
+        return None;
+    };
+    let text = token.text;
+    if token.kind != TokenKind::String || text.len() < 2 || text.contains('\\') {
+        return None;
+    }
+    let quote = &text[..1];
+    (quote == "\"" || quote == "'").then(|| text[1..text.len() - 1].to_string())
+}
+
+/// Return the slot that a number literal names.
+fn slot_value(arg: &[Token]) -> Option<usize> {
+    let [token] = arg else {
+        return None;
+    };
+    if token.kind != TokenKind::Number {
+        return None;
+    }
+    let text = token.text.to_ascii_lowercase();
+    let slot = match text.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16).ok()?,
+        None => text.parse().ok()?,
+    };
+    (slot < SLOTS).then_some(slot)
+}
+
+/// Find the carts and save data that `lua` uses.
+///
+/// ```
+/// use pico8_to_lua::data_usage::data_usage;
+/// let usage = data_usage("reload(0, 0, 0x2000, \"level2.p8\")\ncartdata(\"me_game\")\nx = dget(0)");
+/// assert_eq!(usage.reads.iter().collect::<Vec<_>>(), ["level2.p8"]);
+/// assert_eq!(usage.cartdata.iter().collect::<Vec<_>>(), ["me_game"]);
+/// assert_eq!(usage.slots.iter().collect::<Vec<_>>(), [&0]);
+/// ```
+pub fn data_usage(lua: &str) -> DataUsage {
+    let tokens: Vec<Token> = tokenize(lua)
+        .filter(|t| t.kind != TokenKind::Comment)
+        .collect();
+    let mut usage = DataUsage::default();
+    let mut unknown = BTreeSet::new();
+    let mut note = |span: Span| {
+        unknown.insert(span.line_col(lua).0);
+    };
+    for (i, token) in tokens.iter().enumerate() {
+        if token.kind != TokenKind::Name
+            || (i > 0 && (tokens[i - 1].is(".") || tokens[i - 1].is(":")))
+        {
+            continue;
+        }
+        let Some(args) = arguments(&tokens, i) else {
+            continue;
+        };
+        match token.text {
+            "reload" | "cstore" => match args.get(3) {
+                None => usage.own_cart = true,
+                Some(arg) => match string_value(arg) {
+                    Some(cart) if token.text == "reload" => {
+                        usage.reads.insert(cart);
+                    }
+                    Some(cart) => {
+                        usage.writes.insert(cart);
+                    }
+                    None => note(token.span),
+                },
+            },
+            "cartdata" => match args.first().and_then(|arg| string_value(arg)) {
+                Some(id) => {
+                    usage.cartdata.insert(id);
+                }
+                None => note(token.span),
+            },
+            "dget" | "dset" => match args.first().and_then(|arg| slot_value(arg)) {
+                Some(slot) => {
+                    usage.slots.insert(slot);
+                }
+                None => note(token.span),
+            },
+            _ => {}
+        }
+    }
+    usage.unknown = unknown.into_iter().collect();
+    usage
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_data_usage() {
+        let lua = "function save()\n  cstore(0, 0, 0x1000, 'save.p8')\n  dset(1, score)\nend\n\
+                   reload() reload(0x2000, 0x2000, 0x1000, levels[n])\n\
+                   -- cartdata(\"nope\")\nobj.dget(9) x = dget(0x3f)";
+        let usage = data_usage(lua);
+        assert!(usage.reads.is_empty());
+        assert_eq!(usage.writes.iter().collect::<Vec<_>>(), ["save.p8"]);
+        assert!(usage.own_cart);
+        assert!(usage.cartdata.is_empty());
+        assert_eq!(usage.slots.iter().collect::<Vec<_>>(), [&1, &63]);
+        assert_eq!(usage.unknown, [5]);
+        assert_eq!(usage.save_bytes(), 256);
+    }
+}
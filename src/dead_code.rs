@@ -0,0 +1,202 @@
+//! The functions a cart defines and never calls.
+//!
+//! [dead_code] builds a call graph of the functions defined at the top level
+//! of a cart, as [function_tokens](crate::size::function_tokens) finds them,
+//! and walks it from the code outside any function and from Pico-8's
+//! callbacks like `_draw`. A function it never reaches is unused and its
+//! tokens can be reclaimed by deleting it, unless its name is in a string,
+//! as in `_ENV["update_"..state]`, where it may be looked up in a way that
+//! can't be followed without running the cart. Those are reported apart.
+//!
+//! A name counts as a reference wherever it appears, even as a local or a
+//! table key, so a function reported unused is never called by name.
+use std::{collections::BTreeSet, fmt};
+
+use crate::{
+    lexer::{Token, TokenKind, tokenize},
+    size::{tokens, top_level_functions},
+};
+
+/// The functions Pico-8 calls itself.
+pub const CALLBACKS: &[&str] = &["_init", "_update", "_update60", "_draw"];
+
+/// A function that nothing reachable calls.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnusedFunction {
+    /// The name it is defined with, e.g. "f" or "player.draw".
+    pub name: String,
+    /// The one-based line of its definition.
+    pub line: usize,
+    /// The tokens its definition takes, as counted by [tokens].
+    pub tokens: usize,
+}
+
+/// The unused functions of a cart.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct DeadCode {
+    /// The functions never referenced from reachable code, in the order they
+    /// are defined.
+    pub unused: Vec<UnusedFunction>,
+    /// The functions referenced only by a string, which may be looked up by
+    /// name, in the order they are defined.
+    pub maybe_used: Vec<UnusedFunction>,
+}
+
+impl DeadCode {
+    /// The tokens deleting the unused functions would reclaim, leaving the
+    /// ones that may be used.
+    ///
+    /// ```
+    /// use pico8_to_lua::dead_code::dead_code;
+    /// assert_eq!(dead_code("function f() end\nfunction g() end\nh = 'g'").reclaimable(), 3);
+    /// ```
+    pub fn reclaimable(&self) -> usize {
+        self.unused.iter().map(|f| f.tokens).sum()
+    }
+}
+
+impl fmt::Display for DeadCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for function in &self.unused {
+            writeln!(
+                f,
+                "line {}: {} is unused ({} tokens)",
+                function.line, function.name, function.tokens
+            )?;
+        }
+        for function in &self.maybe_used {
+            writeln!(
+                f,
+                "line {}: {} is only named in a string ({} tokens)",
+                function.line, function.name, function.tokens
+            )?;
+        }
+        writeln!(f, "reclaimable: {} tokens", self.reclaimable())
+    }
+}
+
+/// A reference to a name: whether it is a field, as in `t.f` or `t:f`, and
+/// the name.
+type Reference<'h> = (bool, &'h str);
+
+/// Return true if the function `name` is what `reference` refers to: a
+/// plain name by its name, or a field like "t.f" by its last part.
+fn refers_to(name: &str, (field, text): Reference) -> bool {
+    match name.rfind(['.', ':']) {
+        Some(i) => field && name[i + 1..] == *text,
+        None => !field && name == text,
+    }
+}
+
+/// Find the functions defined at the top level of `lua` that nothing
+/// reachable calls.
+///
+/// ```
+/// use pico8_to_lua::dead_code::dead_code;
+/// let lua = "function _update() move() end\nfunction move() end\n\
+///            function old() move() end\nfunction menu() end\nstate = 'menu'";
+/// let dead = dead_code(lua);
+/// assert_eq!(dead.unused[0].name, "old");
+/// assert_eq!(dead.maybe_used[0].name, "menu");
+/// assert_eq!(dead.reclaimable(), 5);
+/// ```
+pub fn dead_code(lua: &str) -> DeadCode {
+    let functions = top_level_functions(lua);
+    let all: Vec<Token> = tokenize(lua)
+        .filter(|t| t.kind != TokenKind::Comment)
+        .collect();
+    let mut words = BTreeSet::new();
+    // The references from the code outside any function, then from each
+    // function.
+    let mut roots: Vec<Reference> = CALLBACKS.iter().map(|name| (false, *name)).collect();
+    let mut calls: Vec<Vec<Reference>> = vec![Vec::new(); functions.len()];
+    for (i, token) in all.iter().enumerate() {
+        if token.kind == TokenKind::String {
+            words.extend(
+                token
+                    .text
+                    .split(|c: char| !(c.is_alphanumeric() || c == '_'))
+                    .filter(|w| !w.is_empty()),
+            );
+            continue;
+        }
+        if token.kind != TokenKind::Name {
+            continue;
+        }
+        let reference = (i > 0 && (all[i - 1].is(".") || all[i - 1].is(":")), token.text);
+        let within = functions
+            .iter()
+            .position(|(_, span)| span.start <= token.span.start && token.span.end <= span.end);
+        match within {
+            None => roots.push(reference),
+            Some(f) => {
+                // The name before the parameters is the definition's own.
+                let named = all[..i]
+                    .iter()
+                    .rev()
+                    .take_while(|t| t.span.start >= functions[f].1.start)
+                    .all(|t| !t.is("("));
+                if !named {
+                    calls[f].push(reference);
+                }
+            }
+        }
+    }
+    let mut reached = vec![false; functions.len()];
+    let mut pending = roots;
+    while let Some(reference) = pending.pop() {
+        for (f, (name, _)) in functions.iter().enumerate() {
+            if !reached[f] && refers_to(name, reference) {
+                reached[f] = true;
+                pending.extend(calls[f].iter().copied());
+            }
+        }
+    }
+    let mut dead = DeadCode::default();
+    for ((name, span), _) in functions.iter().zip(reached).filter(|(_, r)| !r) {
+        let function = UnusedFunction {
+            name: name.clone(),
+            line: span.line_col(lua).0,
+            tokens: tokens(&lua[span.range()]),
+        };
+        let last = &name[name.rfind(['.', ':']).map_or(0, |i| i + 1)..];
+        if words.contains(last) {
+            dead.maybe_used.push(function);
+        } else {
+            dead.unused.push(function);
+        }
+    }
+    dead
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dead_code() {
+        let lua = "function _init() t.reset() end\n\
+                   function t.reset() helper() end\n\
+                   local function helper() return helper end\n\
+                   function t:unused(x) x.helper() end\n\
+                   lonely = function(n) return lonely(n - 1) end\n\
+                   function reset() end\n\
+                   -- dispatch(\"lonely\")\n\
+                   function cb() end\nhooks = {\"cb\"}";
+        let dead = dead_code(lua);
+        let names: Vec<&str> = dead.unused.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, ["t:unused", "lonely", "reset"]);
+        assert_eq!(dead.unused[0].line, 4);
+        assert_eq!(dead.maybe_used[0].name, "cb");
+        assert_eq!(dead.reclaimable(), 8 + 11 + 3);
+    }
+
+    #[test]
+    fn test_shorthand_if() {
+        let lua = "function _update()\n  if (btn(0)) x -= 1\nend\nfunction unused() end";
+        let dead = dead_code(lua);
+        let names: Vec<&str> = dead.unused.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, ["unused"]);
+        assert_eq!(dead.reclaimable(), 3);
+    }
+}
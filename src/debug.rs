@@ -0,0 +1,117 @@
+//! Removing the debugging statements of a cart.
+//!
+//! A port's release build has no use for `printh()` logging, and `assert()`
+//! and `stop()` halt a game that would otherwise carry on. [strip_debug]
+//! deletes the statements that call them, however many lines their
+//! arguments span, and leaves calls whose value is used, as in
+//! `local ok = assert(x)`, alone.
+use std::borrow::Cow;
+
+use crate::{
+    lexer::{Token, TokenKind, tokenize},
+    span::{Span, TextEdit, apply_edits},
+};
+
+/// The functions whose call statements [strip_debug] deletes.
+pub const DEBUG_FUNCTIONS: &[&str] = &["printh", "assert", "stop"];
+
+/// Return whether a statement may start after `prev`, the token before, or
+/// at the start of the code if there is none.
+fn starts_statement(prev: Option<&Token>) -> bool {
+    prev.is_none_or(|t| {
+        t.is(";")
+            || (t.ends_value() && !t.is("..."))
+            || (t.kind == TokenKind::Keyword && matches!(t.text, "do" | "then" | "else" | "repeat"))
+    })
+}
+
+/// Return the index of the token after the arguments of the call whose
+/// first argument token is at `open`, or `None` if they are unclosed.
+fn after_arguments(code: &[Token], open: usize) -> Option<usize> {
+    if code[open].kind == TokenKind::String {
+        return Some(open + 1);
+    }
+    let mut depth = 0;
+    for (i, token) in code.iter().enumerate().skip(open) {
+        if token.is("(") || token.is("{") || token.is("[") {
+            depth += 1;
+        } else if token.is(")") || token.is("}") || token.is("]") {
+            depth -= 1;
+            if depth == 0 {
+                return Some(i + 1);
+            }
+        }
+    }
+    None
+}
+
+/// Delete the statements that call `printh()`, `assert()`, or `stop()`,
+/// leaving strings and comments alone. The lines their arguments spanned are
+/// left blank, so the lines after them keep their numbers.
+///
+/// ```
+/// use pico8_to_lua::debug::strip_debug;
+/// assert_eq!(
+///     strip_debug("x = 1\nprinth(\"x=\"..x,\n  \"log\")\nassert(x > 0) y = 2".into()),
+///     "x = 1\n\n\n y = 2"
+/// );
+/// assert_eq!(strip_debug("local ok = assert(x)".into()), "local ok = assert(x)");
+/// ```
+pub fn strip_debug<'h>(lua: Cow<'h, str>) -> Cow<'h, str> {
+    let code: Vec<Token> = tokenize(&lua)
+        .filter(|t| t.kind != TokenKind::Comment)
+        .collect();
+    let mut edits = Vec::new();
+    let mut i = 0;
+    while i < code.len() {
+        let token = &code[i];
+        let is_call = token.kind == TokenKind::Name
+            && DEBUG_FUNCTIONS.contains(&token.text)
+            && starts_statement(i.checked_sub(1).map(|p| &code[p]))
+            && code
+                .get(i + 1)
+                .is_some_and(|t| t.is("(") || t.is("{") || t.kind == TokenKind::String);
+        let end = is_call.then(|| after_arguments(&code, i + 1)).flatten();
+        // A call whose value is indexed or called again is not a statement.
+        let Some(end) = end.filter(|&end| {
+            code.get(end).is_none_or(|t| {
+                !(t.is(".") || t.is(":") || t.is("[") || t.is("(") || t.is("{"))
+                    && t.kind != TokenKind::String
+            })
+        }) else {
+            i += 1;
+            continue;
+        };
+        let mut span = Span::new(token.span.start, code[end - 1].span.end);
+        let line_start = lua[..span.start].rfind('\n').map_or(0, |at| at + 1);
+        if lua[line_start..span.start].trim().is_empty() {
+            span.start = line_start;
+        }
+        let newlines = lua[span.range()].matches('\n').count();
+        edits.push(TextEdit::new(span, "\n".repeat(newlines)));
+        i = end;
+    }
+    if edits.is_empty() {
+        return lua;
+    }
+    Cow::Owned(apply_edits(&lua, &edits))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strip_debug() {
+        let lua = "function _update()\n  printh(\"a)\" -- )\n    .. b)\n  stop()\n\
+                   \x20 if debug then assert(x, \"no x\") end\n  printh\"hi\"; x = 1\n\
+                   \x20 t.printh(1) printh(2) s = \"printh(3)\" -- printh(4)\n\
+                   \x20 f(assert(y)) printh(z).x = 1 g = stop\nend";
+        assert_eq!(
+            strip_debug(lua.into()),
+            "function _update()\n\n\n\n  if debug then  end\n; x = 1\n\
+             \x20 t.printh(1)  s = \"printh(3)\" -- printh(4)\n\
+             \x20 f(assert(y)) printh(z).x = 1 g = stop\nend"
+        );
+    }
+}
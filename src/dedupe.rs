@@ -0,0 +1,154 @@
+//! Find duplicate carts in a collection.
+//!
+//! A cart's [Fingerprint] hashes its code, after conversion and whitespace
+//! normalization, separately from its data sections. Carts with equal
+//! fingerprints are exact duplicates; carts that only share the code hash are
+//! near duplicates, e.g. the same game with different graphics.
+use std::{
+    collections::HashMap,
+    hash::{DefaultHasher, Hash, Hasher},
+};
+
+use crate::{Cart, patch_lua};
+
+/// Hashes of a cart's code and data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Fingerprint {
+    /// Hash of the normalized, converted `__lua__` section.
+    pub code: u64,
+    /// Hash of every other section.
+    pub data: u64,
+}
+
+/// Convert `lua` and drop the differences in whitespace that do not change
+/// its meaning.
+pub fn normalize_lua(lua: &str) -> String {
+    let mut out = String::with_capacity(lua.len());
+    for line in patch_lua(lua).lines().map(str::trim).filter(|l| !l.is_empty()) {
+        out.push_str(line);
+        out.push('\n');
+    }
+    out
+}
+
+impl Fingerprint {
+    /// Compute the fingerprint of `cart`.
+    pub fn of(cart: &Cart) -> Fingerprint {
+        let mut code = DefaultHasher::new();
+        normalize_lua(cart.lua().unwrap_or("")).hash(&mut code);
+        let mut data = DefaultHasher::new();
+        for section in cart.sections.iter().filter(|s| s.name != "lua") {
+            section.name.hash(&mut data);
+            for line in section.body.lines() {
+                line.trim_end().hash(&mut data);
+            }
+        }
+        Fingerprint {
+            code: code.finish(),
+            data: data.finish(),
+        }
+    }
+}
+
+/// How alike the members of a [DuplicateGroup] are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DuplicateKind {
+    /// Same code and data.
+    Exact,
+    /// Same code but different data.
+    SameCode,
+}
+
+/// A set of carts that are duplicates of each other.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DuplicateGroup<K> {
+    /// How alike the members are.
+    pub kind: DuplicateKind,
+    /// The keys of the carts in the order they were given.
+    pub members: Vec<K>,
+}
+
+/// Group the keyed fingerprints into duplicates.
+///
+/// Each set of carts with identical fingerprints is reported as
+/// [DuplicateKind::Exact]. When carts share code but have more than one
+/// distinct set of data, all of them are also reported together as
+/// [DuplicateKind::SameCode]. Groups are ordered by their first member.
+pub fn find_duplicates<K: Clone>(
+    carts: impl IntoIterator<Item = (K, Fingerprint)>,
+) -> Vec<DuplicateGroup<K>> {
+    // Code hash -> data hash -> keys, remembering first appearance.
+    let mut by_code: Vec<Vec<Vec<K>>> = Vec::new();
+    let mut code_index: HashMap<u64, usize> = HashMap::new();
+    let mut data_index: HashMap<Fingerprint, usize> = HashMap::new();
+    for (key, fingerprint) in carts {
+        let ci = *code_index.entry(fingerprint.code).or_insert_with(|| {
+            by_code.push(Vec::new());
+            by_code.len() - 1
+        });
+        let variants = &mut by_code[ci];
+        let di = *data_index.entry(fingerprint).or_insert_with(|| {
+            variants.push(Vec::new());
+            variants.len() - 1
+        });
+        variants[di].push(key);
+    }
+
+    let mut groups = Vec::new();
+    for variants in by_code {
+        for keys in variants.iter().filter(|keys| keys.len() > 1) {
+            groups.push(DuplicateGroup {
+                kind: DuplicateKind::Exact,
+                members: keys.clone(),
+            });
+        }
+        if variants.len() > 1 {
+            groups.push(DuplicateGroup {
+                kind: DuplicateKind::SameCode,
+                members: variants.into_iter().flatten().collect(),
+            });
+        }
+    }
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cart(lua: &str, gfx: &str) -> Fingerprint {
+        let text = format!("pico-8 cartridge\n__lua__\n{lua}\n__gfx__\n{gfx}\n");
+        Fingerprint::of(&Cart::parse(&text).unwrap())
+    }
+
+    #[test]
+    fn test_normalized_code() {
+        assert_eq!(
+            cart("if a != b then\n  x += 1\nend", "00"),
+            cart("if a ~= b then\nx = x + (1)\n\nend", "00")
+        );
+    }
+
+    #[test]
+    fn test_find_duplicates() {
+        let carts = [
+            ("a", cart("x = 1", "00")),
+            ("b", cart("x = 2", "00")),
+            ("c", cart("x = 1", "00")),
+            ("d", cart("x = 1", "11")),
+        ];
+        assert_eq!(
+            find_duplicates(carts),
+            vec![
+                DuplicateGroup {
+                    kind: DuplicateKind::Exact,
+                    members: vec!["a", "c"]
+                },
+                DuplicateGroup {
+                    kind: DuplicateKind::SameCode,
+                    members: vec!["a", "c", "d"]
+                },
+            ]
+        );
+    }
+}
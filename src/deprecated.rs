@@ -0,0 +1,186 @@
+//! Migrating the API of old carts to its current equivalents.
+//!
+//! Carts from before Pico-8 0.2.0 call `mapdraw` where current carts call
+//! `map`, name a second player's buttons with the numbers 8 and up, and do
+//! bitwise arithmetic with functions like `band` and `shl`, which Pico-8 0.2.0
+//! replaced with operators. [migrate_deprecated] rewrites them as a current
+//! cart would be written, so the operators are translated for a target like
+//! any others and no shims are needed for the old names.
+//!
+//! A `flip()` in a cart's own main loop is left alone, since where the frame
+//! ends depends on the loop of the host that runs the converted code.
+use std::borrow::Cow;
+
+use crate::{
+    api::api_calls,
+    lexer::{Token, TokenKind, tokenize},
+    loops::arguments,
+    span::{Span, TextEdit, apply_edits},
+};
+
+/// The cart format version of the first carts, those of Pico-8 0.2.0, that
+/// [migrate_deprecated] leaves as they are.
+pub const CURRENT_API_VERSION: u32 = 18;
+
+/// The bitwise functions and the operators that replaced them. `~` is the
+/// binary operator that both Pico-8 and Lua 5.3 read as exclusive or.
+const OPERATORS: &[(&str, &str)] = &[
+    ("band", "&"),
+    ("bor", "|"),
+    ("bxor", "~"),
+    ("shl", "<<"),
+    ("shr", ">>"),
+    ("lshr", ">>>"),
+    ("rotl", "<<>"),
+    ("rotr", ">><"),
+];
+
+/// Rewrite the deprecated API calls of code from a cart of format `version`
+/// with their current equivalents, or leave the code as it is if the cart is
+/// [CURRENT_API_VERSION] or newer. Code of an unknown version, like a ".lua"
+/// file's, is migrated.
+///
+/// ```
+/// use pico8_to_lua::deprecated::migrate_deprecated;
+/// assert_eq!(
+///     migrate_deprecated("mapdraw(0, 0, 0, 0, 16, 16) x = band(shl(a, 2), 0xff)", Some(8)),
+///     "map(0, 0, 0, 0, 16, 16) x = ((a << 2) & 0xff)"
+/// );
+/// assert_eq!(migrate_deprecated("if btn(9) then end", None), "if btn(1, 1) then end");
+/// assert_eq!(migrate_deprecated("x = band(a, b)", Some(41)), "x = band(a, b)");
+/// ```
+pub fn migrate_deprecated<'h>(lua: impl Into<Cow<'h, str>>, version: Option<u32>) -> Cow<'h, str> {
+    let lua = lua.into();
+    if version.is_some_and(|version| version >= CURRENT_API_VERSION) {
+        return lua;
+    }
+    let tokens: Vec<Token> = tokenize(&lua)
+        .filter(|t| t.kind != TokenKind::Comment)
+        .collect();
+    let mut edits = Vec::new();
+    for (name, i) in api_calls(&tokens) {
+        let Some((close, commas)) = arguments(&tokens, i + 1) else {
+            continue;
+        };
+        let call = Span::new(tokens[i].span.start, tokens[i + 1].span.end);
+        let operator = OPERATORS.iter().find(|(f, _)| *f == name).map(|(_, op)| *op);
+        match (name, operator, commas.as_slice()) {
+            ("mapdraw", _, _) => edits.push(TextEdit::new(tokens[i].span, "map")),
+            ("btn" | "btnp", _, []) if close == i + 3 => {
+                let Some(n) = tokens[i + 2]
+                    .text
+                    .parse::<u32>()
+                    .ok()
+                    .filter(|n| (8..64).contains(n))
+                else {
+                    continue;
+                };
+                edits.push(TextEdit::new(tokens[i + 2].span, format!("{}, {}", n % 8, n / 8)));
+            }
+            ("bnot", _, []) if close > i + 2 => {
+                let open = if primary(&tokens[i + 2..close]) { "(~" } else { "(~(" };
+                edits.push(TextEdit::new(call, open));
+                if open == "(~(" {
+                    edits.push(TextEdit::new(tokens[close].span, "))"));
+                }
+            }
+            (_, Some(operator), &[comma]) if comma > i + 2 && close > comma + 1 => {
+                let (a, b) = (primary(&tokens[i + 2..comma]), primary(&tokens[comma + 1..close]));
+                edits.push(TextEdit::new(call, if a { "(" } else { "((" }));
+                let (close_a, open_b) = (if a { "" } else { ")" }, if b { "" } else { "(" });
+                edits.push(TextEdit::new(
+                    Span::new(tokens[comma].span.start, tokens[comma + 1].span.start),
+                    format!("{close_a} {operator} {open_b}"),
+                ));
+                edits.push(TextEdit::new(tokens[close].span, if b { ")" } else { "))" }));
+            }
+            _ => {}
+        }
+    }
+    if edits.is_empty() {
+        return lua;
+    }
+    Cow::Owned(apply_edits(&lua, &edits))
+}
+
+/// Return whether `tokens` are a primary expression, like a name, a literal,
+/// a call, or a parenthesized expression, which an operator applies to whole.
+/// A call to a bitwise function is one, since it is parenthesized when it is
+/// rewritten.
+fn primary(tokens: &[Token]) -> bool {
+    let mut i = match tokens.first() {
+        Some(t) if matches!(t.kind, TokenKind::Name | TokenKind::Number | TokenKind::String) => 1,
+        Some(t) if t.is("nil") || t.is("true") || t.is("false") => 1,
+        Some(t) if t.is("(") => match closing(tokens, 0) {
+            Some(close) => close + 1,
+            None => return false,
+        },
+        _ => return false,
+    };
+    while i < tokens.len() {
+        let token = &tokens[i];
+        i = if (token.is(".") || token.is(":"))
+            && tokens.get(i + 1).is_some_and(|t| t.kind == TokenKind::Name)
+        {
+            i + 2
+        } else if token.kind == TokenKind::String {
+            i + 1
+        } else if token.is("(") || token.is("[") || token.is("{") {
+            match closing(tokens, i) {
+                Some(close) => close + 1,
+                None => return false,
+            }
+        } else {
+            return false;
+        };
+    }
+    true
+}
+
+/// Return the index of the bracket that closes `tokens[open]`.
+fn closing(tokens: &[Token], open: usize) -> Option<usize> {
+    let mut depth = 0usize;
+    for (i, token) in tokens.iter().enumerate().skip(open) {
+        if token.kind != TokenKind::Punct {
+            continue;
+        }
+        match token.text {
+            "(" | "[" | "{" => depth += 1,
+            ")" | "]" | "}" => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_migrate_deprecated() {
+        assert_eq!(
+            migrate_deprecated("x = bor(a + 1, t.b[2]) y = bxor(f(x), -1) z = bnot(a.b)", None),
+            "x = ((a + 1) | t.b[2]) y = (f(x) ~ (-1)) z = (~a.b)"
+        );
+        assert_eq!(
+            migrate_deprecated("x = rotr(band(a, 7), lshr(b, 1)) y = bnot(a or b)", Some(16)),
+            "x = ((a & 7) >>< (b >>> 1)) y = (~(a or b))"
+        );
+        assert_eq!(
+            migrate_deprecated("btn(5) btnp(15, 0) btn(n) btn(8)\n-- mapdraw()", None),
+            "btn(5) btnp(15, 0) btn(n) btn(0, 1)\n-- mapdraw()"
+        );
+    }
+
+    #[test]
+    fn test_defined() {
+        let lua = "function shl(a, n) return a * 2 ^ n end\nx = shl(1, 2) t:band(a, b)";
+        assert_eq!(migrate_deprecated(lua, None), lua);
+    }
+}
@@ -0,0 +1,288 @@
+//! Telling the kinds of cart files apart.
+//!
+//! Pico-8 saves a cart as ".p8" text, a ".p8.png" image, or a ".rom" memory
+//! image, and exports its code alone as ".p8l" or ".lua". [detect] tells them
+//! apart by their contents, [Format::from_path] by their names, and
+//! [read_lua] reads the code of any of them, so that a file that is not what
+//! its name says is an error rather than empty output.
+use std::{error::Error, fmt, fs, io, path::Path};
+
+use crate::{
+    cart::{CART_HEADER, Cart, CartError},
+    charset,
+    compress::{self, DecompressError},
+};
+
+/// The size of a ".rom" file, Pico-8's 32 KiB of cart memory.
+pub const ROM_SIZE: usize = 0x8000;
+/// Where the code starts in a ".rom" file.
+pub const ROM_CODE_START: usize = 0x4300;
+
+const PNG_SIGNATURE: &[u8; 8] = b"\x89PNG\r\n\x1a\n";
+
+/// The kind of a cart file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Format {
+    /// A ".p8" text cart.
+    P8,
+    /// Plain code, as in a ".lua" or ".p8l" file.
+    Lua,
+    /// A ".p8.png" image cart.
+    P8Png,
+    /// A ".rom" memory image.
+    Rom,
+}
+
+impl Format {
+    /// Return the format the name of `path` says its file has, or `None` if
+    /// its extension is not one Pico-8 uses.
+    ///
+    /// ```
+    /// use pico8_to_lua::detect::Format;
+    /// assert_eq!(Format::from_path("game.p8.png"), Some(Format::P8Png));
+    /// assert_eq!(Format::from_path("game.p8l"), Some(Format::Lua));
+    /// assert_eq!(Format::from_path("game.txt"), None);
+    /// ```
+    pub fn from_path(path: impl AsRef<Path>) -> Option<Format> {
+        let name = path.as_ref().file_name()?.to_str()?.to_ascii_lowercase();
+        if name.ends_with(".p8.png") || name.ends_with(".png") {
+            return Some(Format::P8Png);
+        }
+        match name.rsplit_once('.')?.1 {
+            "p8" => Some(Format::P8),
+            "p8l" | "lua" => Some(Format::Lua),
+            "rom" => Some(Format::Rom),
+            _ => None,
+        }
+    }
+
+    /// The extension of a file in this format, e.g. "p8.png".
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::P8 => "p8",
+            Format::Lua => "lua",
+            Format::P8Png => "p8.png",
+            Format::Rom => "rom",
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, ".{}", self.extension())
+    }
+}
+
+/// Return the format of a cart file from its contents, `bytes`, or `None` if
+/// they are binary but neither an image nor a ROM.
+///
+/// A ".p8" cart starts with "pico-8 cartridge" after a byte order mark if it
+/// has one, an image with PNG's signature, and a ROM is [ROM_SIZE] bytes with
+/// a zero among them. Any other text is code.
+///
+/// ```
+/// use pico8_to_lua::detect::{Format, detect};
+/// assert_eq!(detect(b"pico-8 cartridge // http://www.pico-8.com\n"), Some(Format::P8));
+/// assert_eq!(detect(b"x += 1"), Some(Format::Lua));
+/// assert_eq!(detect(b"\0\x01"), None);
+/// ```
+pub fn detect(bytes: &[u8]) -> Option<Format> {
+    let text = bytes.strip_prefix("\u{feff}".as_bytes()).unwrap_or(bytes);
+    if text.starts_with(CART_HEADER.as_bytes()) {
+        Some(Format::P8)
+    } else if bytes.starts_with(PNG_SIGNATURE) {
+        Some(Format::P8Png)
+    } else if bytes.len() == ROM_SIZE && bytes.contains(&0) {
+        Some(Format::Rom)
+    } else if !bytes.contains(&0) && std::str::from_utf8(bytes).is_ok() {
+        Some(Format::Lua)
+    } else {
+        None
+    }
+}
+
+/// Return the format of the file at `path` from its contents, or from its
+/// name if they are binary and unrecognized.
+pub fn detect_path(path: impl AsRef<Path>) -> Result<Format, ReadError> {
+    let path = path.as_ref();
+    let bytes = fs::read(path)?;
+    format_of(path, &bytes)
+}
+
+/// Return the format of `bytes`, read from `path`, rejecting a ".p8" file
+/// that is not a cart.
+fn format_of(path: &Path, bytes: &[u8]) -> Result<Format, ReadError> {
+    let named = Format::from_path(path);
+    match (detect(bytes), named) {
+        (Some(Format::Lua), Some(Format::P8)) => Err(ReadError::Mismatch {
+            expected: Format::P8,
+            found: Format::Lua,
+        }),
+        (Some(format), _) => Ok(format),
+        (None, Some(format)) => Ok(format),
+        (None, None) => Err(ReadError::Unknown),
+    }
+}
+
+/// An error encountered while reading the code of a cart file.
+#[derive(Debug)]
+pub enum ReadError {
+    /// The file could not be read.
+    Io(io::Error),
+    /// The ".p8" cart could not be parsed.
+    Cart(CartError),
+    /// The code of a ".rom" could not be decompressed.
+    Decompress(DecompressError),
+    /// The ".p8.png" cart could not be read.
+    #[cfg(feature = "png")]
+    P8Png(crate::p8png::P8PngError),
+    /// The contents are not what the file's name says.
+    Mismatch {
+        /// The format the name says.
+        expected: Format,
+        /// The format the contents are.
+        found: Format,
+    },
+    /// The contents are binary and in no format Pico-8 uses.
+    Unknown,
+    /// The format needs a feature that is not enabled.
+    Unsupported(Format),
+}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReadError::Io(e) => e.fmt(f),
+            ReadError::Cart(e) => e.fmt(f),
+            ReadError::Decompress(e) => e.fmt(f),
+            #[cfg(feature = "png")]
+            ReadError::P8Png(e) => e.fmt(f),
+            ReadError::Mismatch { expected, found } => {
+                write!(f, "expected a {expected} cart but found {found} contents")
+            }
+            ReadError::Unknown => write!(f, "not a pico-8 cart or lua file"),
+            ReadError::Unsupported(format) => {
+                write!(f, "reading {format} carts requires the \"png\" feature")
+            }
+        }
+    }
+}
+
+impl Error for ReadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ReadError::Io(e) => Some(e),
+            ReadError::Cart(e) => Some(e),
+            ReadError::Decompress(e) => Some(e),
+            #[cfg(feature = "png")]
+            ReadError::P8Png(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ReadError {
+    fn from(e: io::Error) -> Self {
+        ReadError::Io(e)
+    }
+}
+
+impl From<CartError> for ReadError {
+    fn from(e: CartError) -> Self {
+        ReadError::Cart(e)
+    }
+}
+
+impl From<DecompressError> for ReadError {
+    fn from(e: DecompressError) -> Self {
+        ReadError::Decompress(e)
+    }
+}
+
+#[cfg(feature = "png")]
+impl From<crate::p8png::P8PngError> for ReadError {
+    fn from(e: crate::p8png::P8PngError) -> Self {
+        ReadError::P8Png(e)
+    }
+}
+
+/// Read the code of the cart or code file at `path`, whatever its format.
+/// A cart without a `__lua__` section has no code.
+pub fn read_lua(path: impl AsRef<Path>) -> Result<String, ReadError> {
+    let path = path.as_ref();
+    let bytes = fs::read(path)?;
+    lua_from_bytes(format_of(path, &bytes)?, bytes)
+}
+
+/// Return the code of the file at `path` like [read_lua] and the number of
+/// lines before it in the file, which is more than zero only for a ".p8"
+/// cart, so a line of the code can be found in the file.
+pub fn read_lua_with_offset(path: impl AsRef<Path>) -> Result<(String, usize), ReadError> {
+    let path = path.as_ref();
+    let bytes = fs::read(path)?;
+    match format_of(path, &bytes)? {
+        Format::P8 => {
+            let cart = Cart::from_bytes(&bytes)?;
+            let offset = cart.lua_line().unwrap_or(0);
+            Ok((cart.lua().unwrap_or("").to_string(), offset))
+        }
+        format => Ok((lua_from_bytes(format, bytes)?, 0)),
+    }
+}
+
+/// Return the code of `bytes` read as `format`, decoding a ".p8" cart as
+/// P8SCII if it is not UTF-8.
+pub fn lua_from_bytes(format: Format, bytes: Vec<u8>) -> Result<String, ReadError> {
+    match format {
+        Format::P8 => Ok(Cart::from_bytes(&bytes)?.lua().unwrap_or("").to_string()),
+        Format::Lua => String::from_utf8(bytes)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e).into()),
+        Format::Rom => {
+            let code = bytes.get(ROM_CODE_START..).ok_or(DecompressError::Truncated)?;
+            Ok(charset::to_utf8(&compress::decompress(code)?))
+        }
+        #[cfg(feature = "png")]
+        Format::P8Png => Ok(charset::to_utf8(&crate::p8png::read_code(bytes.as_slice())?)),
+        #[cfg(not(feature = "png"))]
+        Format::P8Png => Err(ReadError::Unsupported(Format::P8Png)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(detect("\u{feff}pico-8 cartridge\n".as_bytes()), Some(Format::P8));
+        assert_eq!(detect(b"-- pico-8 cartridge\n"), Some(Format::Lua));
+        assert_eq!(detect(b"\x89PNG\r\n\x1a\n\0\0"), Some(Format::P8Png));
+        let mut rom = vec![0; ROM_SIZE];
+        rom[ROM_CODE_START..ROM_CODE_START + 6].copy_from_slice(b"x=1\n?x");
+        assert_eq!(detect(&rom), Some(Format::Rom));
+        assert_eq!(lua_from_bytes(Format::Rom, rom).unwrap(), "x=1\n?x");
+
+        let p8 = Path::new("game.p8");
+        assert_eq!(format_of(p8, b"pico-8 cartridge\n").unwrap(), Format::P8);
+        assert!(matches!(
+            format_of(p8, b"x = 1"),
+            Err(ReadError::Mismatch { expected: Format::P8, found: Format::Lua })
+        ));
+        assert_eq!(format_of(Path::new("game.p8l"), b"x = 1").unwrap(), Format::Lua);
+        assert_eq!(format_of(Path::new("game.rom"), b"\0").unwrap(), Format::Rom);
+        assert!(matches!(format_of(Path::new("x"), b"\0"), Err(ReadError::Unknown)));
+    }
+
+    #[test]
+    fn test_read_lua_with_offset() {
+        let dir = std::env::temp_dir().join(format!("pico8-to-lua-detect-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let cart = dir.join("game.p8");
+        fs::write(&cart, "pico-8 cartridge\nversion 42\n__lua__\nx = 1\ny += 2\n__gfx__\n00\n").unwrap();
+        assert_eq!(read_lua_with_offset(&cart).unwrap(), ("x = 1\ny += 2\n".to_string(), 3));
+        let lua = dir.join("game.lua");
+        fs::write(&lua, "x = 1\n").unwrap();
+        assert_eq!(read_lua_with_offset(&lua).unwrap(), ("x = 1\n".to_string(), 0));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
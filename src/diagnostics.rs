@@ -0,0 +1,494 @@
+//! Warnings about code the converter leaves alone.
+//!
+//! The rewrites skip what they can't read, like a shorthand if whose
+//! parenthesis never closes, and leave it for Lua to reject later. [diagnose]
+//! finds those places with their spans so they can be fixed by hand, and
+//! [diagnose_strict] adds the places that convert but may not convert as
+//! meant, for [try_patch_lua](crate::try_patch_lua). [diagnose_numbers] finds
+//! the numbers that Pico-8's 16.16 fixed point and Lua's floats disagree on.
+//! With the `miette` feature each [Diagnostic] is a `miette::Diagnostic` too.
+use std::{error::Error, fmt};
+
+use find_matching_bracket::find_matching_paren;
+use lazy_regex::regex;
+
+use crate::{
+    glyph,
+    includes::include_regex,
+    lexer::{Token, TokenKind, tokenize},
+    span::Span,
+};
+
+/// What the converter could not handle.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "kebab-case")
+)]
+pub enum DiagnosticKind {
+    /// The condition of a shorthand `if` or `while`, named here, has no
+    /// closing parenthesis.
+    UnmatchedParen(
+        #[cfg_attr(feature = "serde", serde(deserialize_with = "deserialize_statement"))]
+        StaticStr,
+    ),
+    /// A line starts like an "#include" statement but its path can't be read,
+    /// e.g. `#include "a.lua`.
+    MalformedInclude,
+    /// An operator followed by `=` that is no compound assignment Pico-8 has,
+    /// e.g. `x @= 1`.
+    UnknownOperator(String),
+    /// A button glyph used outside the first argument of `btn()` or `btnp()`,
+    /// e.g. `b = ⬅️`.
+    StrayGlyph(String),
+    /// A number literal or a constant expression like `30000 * 2` outside
+    /// Pico-8's range, which wraps around there but not in Lua.
+    NumberOutOfRange(String),
+    /// A hex or binary literal from `0x8000.0001` to `0xffff.ffff`, which is
+    /// negative in Pico-8 but positive in Lua, e.g. `0xffff`.
+    NegativeHex(String),
+    /// Pico-8's largest or smallest number, like `0x7fff.ffff` or `0x8000`,
+    /// often a sentinel, which Lua's numbers go past.
+    FixedPointLimit(String),
+}
+
+/// The largest number Pico-8 can hold, 0x7fff.ffff.
+const FIXED_MAX: f64 = 32767.0 + 65535.0 / 65536.0;
+/// The smallest number Pico-8 can hold, 0x8000 or -32768.
+const FIXED_MIN: f64 = -32768.0;
+
+/// A `&'static str` that serde does not take for a string to borrow from
+/// the input, since it is read into one of a few names instead.
+type StaticStr = &'static str;
+
+/// Read the statement of [DiagnosticKind::UnmatchedParen].
+#[cfg(feature = "serde")]
+fn deserialize_statement<'de, D: serde::Deserializer<'de>>(d: D) -> Result<&'static str, D::Error> {
+    match <String as serde::Deserialize>::deserialize(d)?.as_str() {
+        "if" => Ok("if"),
+        "while" => Ok("while"),
+        other => Err(serde::de::Error::custom(format!("unknown statement {other:?}"))),
+    }
+}
+
+impl DiagnosticKind {
+    /// A stable name for the kind, e.g. "unmatched-paren".
+    pub fn code(&self) -> &'static str {
+        match self {
+            DiagnosticKind::UnmatchedParen(_) => "unmatched-paren",
+            DiagnosticKind::MalformedInclude => "malformed-include",
+            DiagnosticKind::UnknownOperator(_) => "unknown-operator",
+            DiagnosticKind::StrayGlyph(_) => "stray-glyph",
+            DiagnosticKind::NumberOutOfRange(_) => "number-out-of-range",
+            DiagnosticKind::NegativeHex(_) => "negative-hex",
+            DiagnosticKind::FixedPointLimit(_) => "fixed-point-limit",
+        }
+    }
+}
+
+impl fmt::Display for DiagnosticKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DiagnosticKind::UnmatchedParen(statement) => {
+                write!(f, "no closing parenthesis for this {statement} condition")
+            }
+            DiagnosticKind::MalformedInclude => f.write_str("malformed #include statement"),
+            DiagnosticKind::UnknownOperator(op) => {
+                write!(f, "unknown compound assignment operator {op:?}")
+            }
+            DiagnosticKind::StrayGlyph(glyph) => {
+                write!(f, "button glyph {glyph} outside btn() or btnp()")
+            }
+            DiagnosticKind::NumberOutOfRange(number) => {
+                write!(f, "{number} is outside Pico-8's numbers and wraps around there but not in Lua")
+            }
+            DiagnosticKind::NegativeHex(number) => {
+                write!(f, "{number} is negative in Pico-8 but positive in Lua")
+            }
+            DiagnosticKind::FixedPointLimit(number) => {
+                write!(f, "{number} is a limit of Pico-8's numbers but not of Lua's")
+            }
+        }
+    }
+}
+
+/// A place the converter left alone that needs attention.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Diagnostic {
+    /// What is wrong.
+    pub kind: DiagnosticKind,
+    /// Where it is.
+    pub span: Span,
+    /// The one-based line of the span's start.
+    pub line: usize,
+    /// The one-based column in bytes of the span's start.
+    pub column: usize,
+}
+
+impl Diagnostic {
+    fn new(kind: DiagnosticKind, span: Span, lua: &str) -> Self {
+        let (line, column) = span.line_col(lua);
+        Diagnostic {
+            kind,
+            span,
+            line,
+            column,
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: warning: {}", self.line, self.column, self.kind)
+    }
+}
+
+impl Error for Diagnostic {}
+
+#[cfg(feature = "miette")]
+impl miette::Diagnostic for Diagnostic {
+    fn code<'a>(&'a self) -> Option<Box<dyn fmt::Display + 'a>> {
+        Some(Box::new(format!("pico8_to_lua::{}", self.kind.code())))
+    }
+
+    fn severity(&self) -> Option<miette::Severity> {
+        Some(miette::Severity::Warning)
+    }
+
+    fn labels(&self) -> Option<Box<dyn Iterator<Item = miette::LabeledSpan> + '_>> {
+        Some(Box::new(std::iter::once(miette::LabeledSpan::new(
+            Some(self.kind.to_string()),
+            self.span.start,
+            self.span.len(),
+        ))))
+    }
+}
+
+/// Return `lua` with its strings and comments blanked out by spaces, keeping
+/// every newline and byte offset.
+fn blank_strings_and_comments(lua: &str) -> String {
+    let mut out = String::with_capacity(lua.len());
+    let mut last = 0;
+    for token in tokenize(lua).filter(|t| matches!(t.kind, TokenKind::String | TokenKind::Comment))
+    {
+        out.push_str(&lua[last..token.span.start]);
+        for c in token.text.chars() {
+            if c == '\n' {
+                out.push('\n');
+            } else {
+                out.extend(std::iter::repeat_n(' ', c.len_utf8()));
+            }
+        }
+        last = token.span.end;
+    }
+    out.push_str(&lua[last..]);
+    out
+}
+
+/// Find the places in Pico-8 `lua` that the converter leaves alone but that
+/// are not plain Lua either, in order.
+///
+/// ```
+/// use pico8_to_lua::diagnostics::{DiagnosticKind, diagnose};
+/// let found = diagnose("x = 1\nif (a and b x += 1\n");
+/// assert_eq!(found[0].kind, DiagnosticKind::UnmatchedParen("if"));
+/// assert_eq!(found[0].to_string(), "2:4: warning: no closing parenthesis for this if condition");
+/// ```
+pub fn diagnose(lua: &str) -> Vec<Diagnostic> {
+    let code = blank_strings_and_comments(lua);
+    let mut found = Vec::new();
+    for caps in regex!(r"(?m)^[ \t]*(if|while)\s*(\()").captures_iter(&code) {
+        let open = caps.get(2).expect("paren").start();
+        if find_matching_paren(&code, open).is_none() {
+            let statement = if &caps[1] == "if" { "if" } else { "while" };
+            found.push(Diagnostic::new(
+                DiagnosticKind::UnmatchedParen(statement),
+                Span::new(open, open + 1),
+                lua,
+            ));
+        }
+    }
+    for m in regex!(r"(?m)^[ \t]*#include\b[^\n]*").find_iter(&code) {
+        // The path of a well-formed include may be quoted, so check the text.
+        let line = &lua[m.start()..m.end()];
+        if !include_regex().is_match(line) {
+            let start = m.start() + line.find('#').unwrap_or(0);
+            found.push(Diagnostic::new(
+                DiagnosticKind::MalformedInclude,
+                Span::new(start, m.end()),
+                lua,
+            ));
+        }
+    }
+    found.sort_by_key(|d| d.span);
+    found
+}
+
+/// Find what [diagnose] finds and the places that convert but may not convert
+/// as meant: unknown compound assignment operators and button glyphs outside
+/// `btn()` and `btnp()`, in order.
+///
+/// ```
+/// use pico8_to_lua::diagnostics::{DiagnosticKind, diagnose_strict};
+/// let found = diagnose_strict("if btn(⬅️) then x @= 1 end");
+/// assert_eq!(found[0].kind, DiagnosticKind::UnknownOperator("@=".into()));
+/// assert_eq!(found.len(), 1);
+/// ```
+pub fn diagnose_strict(lua: &str) -> Vec<Diagnostic> {
+    let mut found = diagnose(lua);
+    let tokens: Vec<Token> = tokenize(lua)
+        .filter(|t| t.kind != TokenKind::Comment)
+        .collect();
+    for (i, token) in tokens.iter().enumerate() {
+        let before = |n: usize| i.checked_sub(n).map(|j| &tokens[j]);
+        if token.kind == TokenKind::Name && glyph::button(token.text).is_some() {
+            let in_button = before(1).is_some_and(|t| t.is("("))
+                && before(2).is_some_and(|t| matches!(t.text, "btn" | "btnp"));
+            if !in_button {
+                found.push(Diagnostic::new(
+                    DiagnosticKind::StrayGlyph(token.text.to_string()),
+                    token.span,
+                    lua,
+                ));
+            }
+            continue;
+        }
+        // Every compound assignment Pico-8 has is one token, like "+=", so an
+        // operator right before a separate "=" is one it lacks.
+        let Some(next) = tokens.get(i + 1) else {
+            continue;
+        };
+        if matches!(token.kind, TokenKind::Punct | TokenKind::Unknown)
+            && !matches!(token.text, ")" | "]" | "}")
+            && next.is("=")
+            && next.span.start == token.span.end
+            && before(1).is_some_and(Token::ends_value)
+        {
+            found.push(Diagnostic::new(
+                DiagnosticKind::UnknownOperator(format!("{}=", token.text)),
+                Span::new(token.span.start, next.span.end),
+                lua,
+            ));
+        }
+    }
+    found.sort_by_key(|d| d.span);
+    found
+}
+
+/// Find the numbers in Pico-8 `lua` that behave differently as Lua's floats
+/// than in Pico-8's 16.16 fixed point, in order: literals and constant
+/// expressions of two literals outside Pico-8's range, hex literals Pico-8
+/// reads as negative, and Pico-8's largest and smallest numbers, which carts
+/// use as sentinels or to overflow on purpose.
+///
+/// ```
+/// use pico8_to_lua::diagnostics::{DiagnosticKind, diagnose_numbers};
+/// let found = diagnose_numbers("best = 0x7fff.ffff\nmask = 0xffff\nx = 300 * 200");
+/// assert_eq!(found[0].kind, DiagnosticKind::FixedPointLimit("0x7fff.ffff".into()));
+/// assert_eq!(found[1].kind, DiagnosticKind::NegativeHex("0xffff".into()));
+/// assert_eq!(found[2].kind, DiagnosticKind::NumberOutOfRange("300 * 200".into()));
+/// ```
+pub fn diagnose_numbers(lua: &str) -> Vec<Diagnostic> {
+    let tokens: Vec<Token> = tokenize(lua)
+        .filter(|t| t.kind != TokenKind::Comment)
+        .collect();
+    let mut found = Vec::new();
+    let mut i = 0;
+    while i < tokens.len() {
+        let Some((start, value)) = signed_number(&tokens, i) else {
+            i += 1;
+            continue;
+        };
+        let literal = Span::new(tokens[start].span.start, tokens[i].span.end);
+        if let Some(kind) = number_kind(tokens[i].text, value, &lua[literal.range()]) {
+            found.push(Diagnostic::new(kind, literal, lua));
+        } else if let Some(end) = overflowing_expression(&tokens, start, i, value) {
+            let span = Span::new(literal.start, tokens[end].span.end);
+            let kind = DiagnosticKind::NumberOutOfRange(lua[span.range()].to_string());
+            found.push(Diagnostic::new(kind, span, lua));
+            i = end;
+        }
+        i += 1;
+    }
+    found
+}
+
+/// If `tokens[i]` is a number literal, return the index of its first token,
+/// a unary minus if it has one, and its value.
+fn signed_number(tokens: &[Token], i: usize) -> Option<(usize, f64)> {
+    if tokens[i].kind != TokenKind::Number {
+        return None;
+    }
+    let value = number_value(tokens[i].text)?;
+    let negated = i > 0
+        && tokens[i - 1].is("-")
+        && (i < 2 || !tokens[i - 2].ends_value());
+    Some(if negated { (i - 1, -value) } else { (i, value) })
+}
+
+/// Return what is wrong with the literal `text` whose value with its sign is
+/// `value`, shown as `shown`, if anything.
+fn number_kind(text: &str, value: f64, shown: &str) -> Option<DiagnosticKind> {
+    let lower = text.to_ascii_lowercase();
+    let radix = lower.starts_with("0x") || lower.starts_with("0b");
+    if radix && value > FIXED_MAX && value < 65536.0 {
+        Some(if value == -FIXED_MIN {
+            DiagnosticKind::FixedPointLimit(shown.to_string())
+        } else {
+            DiagnosticKind::NegativeHex(shown.to_string())
+        })
+    } else if !(FIXED_MIN..=FIXED_MAX).contains(&value) {
+        Some(DiagnosticKind::NumberOutOfRange(shown.to_string()))
+    } else if value == FIXED_MIN || FIXED_MAX - value < 1.0 / 65536.0 {
+        Some(DiagnosticKind::FixedPointLimit(shown.to_string()))
+    } else {
+        None
+    }
+}
+
+/// If the literal from `tokens[start]` to `tokens[i]`, whose value is
+/// `value`, is added to, subtracted from, or multiplied by another literal
+/// with no other arithmetic around them, and the result is outside Pico-8's
+/// range, return the index of the last token of the other literal.
+fn overflowing_expression(tokens: &[Token], start: usize, i: usize, value: f64) -> Option<usize> {
+    const ARITHMETIC: &[&str] = &["+", "-", "*", "/", "\\", "%", "^", ".."];
+    let arithmetic = |t: &Token| ARITHMETIC.iter().any(|op| t.is(op));
+    let op = tokens.get(i + 1)?;
+    let j = if tokens.get(i + 2)?.is("-") { i + 3 } else { i + 2 };
+    tokens.get(j)?;
+    let (right_start, right) = signed_number(tokens, j)?;
+    if right_start != i + 2
+        || !(FIXED_MIN..=FIXED_MAX).contains(&right)
+        || start.checked_sub(1).is_some_and(|k| arithmetic(&tokens[k]))
+        || tokens.get(j + 1).is_some_and(arithmetic)
+    {
+        return None;
+    }
+    let result = match op.text {
+        "+" if op.kind == TokenKind::Punct => value + right,
+        "-" if op.kind == TokenKind::Punct => value - right,
+        "*" if op.kind == TokenKind::Punct => value * right,
+        _ => return None,
+    };
+    (!(FIXED_MIN..=FIXED_MAX).contains(&result)).then_some(j)
+}
+
+/// Return the value of a number literal, if it is one this can read.
+pub(crate) fn number_value(text: &str) -> Option<f64> {
+    let lower = text.to_ascii_lowercase();
+    let (radix, digits) = if let Some(hex) = lower.strip_prefix("0x") {
+        (16, hex)
+    } else if let Some(bin) = lower.strip_prefix("0b") {
+        (2, bin)
+    } else {
+        return lower.parse().ok();
+    };
+    let (int, frac) = digits.split_once('.').unwrap_or((digits, ""));
+    let mut value = if int.is_empty() {
+        0.0
+    } else {
+        u64::from_str_radix(int, radix).ok()? as f64
+    };
+    let mut scale = 1.0 / radix as f64;
+    for c in frac.chars() {
+        value += c.to_digit(radix)? as f64 * scale;
+        scale /= radix as f64;
+    }
+    Some(value)
+}
+
+/// Pico-8 Lua that [try_patch_lua](crate::try_patch_lua) would not convert.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StrictError {
+    /// What was found in order. There is at least one.
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl fmt::Display for StrictError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut diagnostics = self.diagnostics.iter();
+        if let Some(first) = diagnostics.next() {
+            write!(f, "{}:{}: {}", first.line, first.column, first.kind)?;
+        }
+        match diagnostics.len() {
+            0 => Ok(()),
+            1 => write!(f, " and 1 more problem"),
+            n => write!(f, " and {n} more problems"),
+        }
+    }
+}
+
+impl Error for StrictError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diagnose() {
+        assert!(
+            diagnose("if (a) b()\nwhile (c and\n d) e()\ns = \"if (\"\n-- while (\n").is_empty()
+        );
+        let found = diagnose("while (a b()\n#include \"x.lua\n#include y.lua\n");
+        let kinds: Vec<_> = found.iter().map(|d| (&d.kind, d.line, d.column)).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                (&DiagnosticKind::UnmatchedParen("while"), 1, 7),
+                (&DiagnosticKind::MalformedInclude, 2, 1),
+            ]
+        );
+        assert_eq!(found[1].span, Span::new(13, 28));
+    }
+
+    #[test]
+    fn test_diagnose_strict() {
+        let lua = "b = ⬅️ -- ⬆️\nif btnp( ➡️, 1) then t[1] = x end\nx #= 1\ny := 2\nz = a != b";
+        let found: Vec<_> = diagnose_strict(lua)
+            .into_iter()
+            .map(|d| (d.kind, d.line))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (DiagnosticKind::StrayGlyph("⬅️".into()), 1),
+                (DiagnosticKind::UnknownOperator("#=".into()), 3),
+                (DiagnosticKind::UnknownOperator(":=".into()), 4),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_number_value() {
+        assert_eq!(number_value("0x7fff.8"), Some(32767.5));
+        assert_eq!(number_value("0b10.1"), Some(2.5));
+        assert_eq!(number_value("1e3"), Some(1000.0));
+    }
+
+    #[test]
+    fn test_diagnose_numbers() {
+        let lua = "a = -32768 b = 0x8000 c = -40000 d = 0b1000000000000001\n\
+                   e = 32767 + 1 f = x * 30000 + 30000 g = 2 - -40000\n\
+                   h = -0x7fff.ffff i = 0x10000 -- 99999";
+        let found: Vec<_> = diagnose_numbers(lua)
+            .into_iter()
+            .map(|d| (d.kind.code(), lua[d.span.range()].to_string(), d.line))
+            .collect();
+        let expected = [
+            ("fixed-point-limit", "-32768", 1),
+            ("fixed-point-limit", "0x8000", 1),
+            ("number-out-of-range", "-40000", 1),
+            ("negative-hex", "0b1000000000000001", 1),
+            ("number-out-of-range", "32767 + 1", 2),
+            ("number-out-of-range", "-40000", 2),
+            ("number-out-of-range", "0x10000", 3),
+        ];
+        let expected: Vec<_> = expected
+            .iter()
+            .map(|(code, text, line)| (*code, text.to_string(), *line))
+            .collect();
+        assert_eq!(found, expected);
+    }
+}
@@ -0,0 +1,78 @@
+//! Translation of Pico-8's integer division operator.
+//!
+//! Pico-8's `a \ b` divides and rounds down. [patch_integer_division] rewrites
+//! it and its compound assignment `\=` to `math.floor(a / b)`, or to Lua 5.3's
+//! floor division `a // b` when that is the [LuaTarget].
+//!
+//! Run [patch_lua](crate::patch_lua) first; this pass only handles the integer
+//! division operator.
+use std::borrow::Cow;
+
+use crate::{
+    bitwise::LuaTarget,
+    operators::{Operators, Translation, rewrite_operators},
+    symbols::{DEFAULT_PREFIX, Namer},
+};
+
+struct IntegerDivision(LuaTarget);
+
+impl Operators for IntegerDivision {
+    fn translate(&self, op: &str) -> Option<Translation> {
+        match (op, self.0) {
+            ("\\", LuaTarget::Lua53 | LuaTarget::Lua54) => Some(Translation::Native("//")),
+            ("\\", _) => Some(Translation::Wrap("math.floor", "/")),
+            _ => None,
+        }
+    }
+}
+
+/// Translate Pico-8's integer division operator for `target`.
+///
+/// ```
+/// use pico8_to_lua::{bitwise::LuaTarget, division::patch_integer_division};
+/// assert_eq!(patch_integer_division("x = a \\ 2", LuaTarget::Lua51), "x = math.floor(a / 2)");
+/// assert_eq!(patch_integer_division("x = a \\ 2", LuaTarget::Lua53), "x = a // 2");
+/// ```
+pub fn patch_integer_division<'h>(lua: impl Into<Cow<'h, str>>, target: LuaTarget) -> Cow<'h, str> {
+    let lua = lua.into();
+    let mut namer = Namer::new(DEFAULT_PREFIX, &lua);
+    rewrite_operators(lua, &IntegerDivision(target), &mut namer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_integer_division() {
+        use LuaTarget::*;
+        assert_eq!(
+            patch_integer_division("x = a * b \\ c + d", Lua52),
+            "x = math.floor(a * b / c) + d"
+        );
+        assert_eq!(
+            patch_integer_division("x = a \\ b \\ 2", Lua51),
+            "x = math.floor(math.floor(a / b) / 2)"
+        );
+        assert_eq!(
+            patch_integer_division("x = f(a) \\ -t.n -- a \\ b", Lua53),
+            "x = f(a) // -t.n -- a \\ b"
+        );
+        assert!(matches!(
+            patch_integer_division("s = \"a \\\\ b\"", Lua51),
+            Cow::Borrowed(_)
+        ));
+    }
+
+    #[test]
+    fn test_compound() {
+        assert_eq!(
+            patch_integer_division("x \\= a + 1", LuaTarget::Lua51),
+            "x = math.floor(x / (a + 1))"
+        );
+        assert_eq!(
+            patch_integer_division("t[i] \\= 2", LuaTarget::Lua53),
+            "t[i] = t[i] // (2)"
+        );
+    }
+}
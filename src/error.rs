@@ -0,0 +1,109 @@
+//! One error type for the whole crate.
+//!
+//! [Error] has a variant for each error of the conversion APIs and a `From`
+//! impl for each, so a tool that reads carts, resolves includes, and converts
+//! strictly can use `?` throughout.
+use std::{error, fmt, io};
+
+#[cfg(feature = "png")]
+use crate::p8png::P8PngError;
+use crate::{CartError, IncludeError, diagnostics::StrictError};
+
+/// An error encountered while converting.
+#[derive(Debug)]
+pub enum Error {
+    /// A file could not be read or written.
+    Io(io::Error),
+    /// A cart could not be parsed.
+    Cart(CartError),
+    /// A file the code includes could not be read.
+    Include(IncludeError),
+    /// The code has constructs that may not convert as meant. See
+    /// [try_patch_lua](crate::try_patch_lua).
+    Strict(StrictError),
+    /// A ".p8.png" cart could not be read.
+    #[cfg(feature = "png")]
+    Png(P8PngError),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{e}"),
+            Error::Cart(e) => write!(f, "{e}"),
+            Error::Include(e) => write!(f, "{e}"),
+            Error::Strict(e) => write!(f, "{e}"),
+            #[cfg(feature = "png")]
+            Error::Png(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::Cart(e) => Some(e),
+            Error::Include(e) => Some(e),
+            Error::Strict(e) => Some(e),
+            #[cfg(feature = "png")]
+            Error::Png(e) => Some(e),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<CartError> for Error {
+    fn from(e: CartError) -> Self {
+        Error::Cart(e)
+    }
+}
+
+impl From<IncludeError> for Error {
+    fn from(e: IncludeError) -> Self {
+        Error::Include(e)
+    }
+}
+
+impl From<StrictError> for Error {
+    fn from(e: StrictError) -> Self {
+        Error::Strict(e)
+    }
+}
+
+#[cfg(feature = "png")]
+impl From<P8PngError> for Error {
+    fn from(e: P8PngError) -> Self {
+        Error::Png(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Cart, try_patch_lua};
+
+    #[test]
+    fn test_question_mark() {
+        let convert = |text: &str| -> Result<String, Error> {
+            let cart = Cart::parse(text)?;
+            Ok(try_patch_lua(cart.lua().unwrap_or(""))?.into_owned())
+        };
+        assert_eq!(
+            convert("pico-8 cartridge\n__lua__\nx += 1\n").unwrap(),
+            "x = x + (1)\n"
+        );
+        assert!(matches!(convert("x = 1"), Err(Error::Cart(_))));
+        let error = convert("pico-8 cartridge\n__lua__\nb = ⬅️\n").unwrap_err();
+        assert!(matches!(error, Error::Strict(_)));
+        assert_eq!(
+            error.to_string(),
+            "1:5: button glyph ⬅️ outside btn() or btnp()"
+        );
+    }
+}
@@ -0,0 +1,159 @@
+//! A C interface, with the `cdylib` feature.
+//!
+//! The functions are declared in "include/pico8_to_lua.h". Each takes the
+//! input as a pointer and a length, so it need not end with a NUL, and
+//! returns a NUL-terminated string that the caller frees with
+//! [pico8_to_lua_free], or NULL on error, after which
+//! [pico8_to_lua_last_error] describes it.
+//!
+//! Build the shared library with `cargo rustc --lib --release --features
+//! cdylib --crate-type cdylib`.
+use std::{
+    cell::RefCell,
+    ffi::{CString, c_char, c_int},
+    ptr, slice,
+};
+
+use crate::{Cart, Options, batch::Converter};
+
+thread_local! {
+    /// The error of the last call on this thread that failed.
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Remember `message` as the last error and return NULL.
+fn fail(message: impl Into<Vec<u8>>) -> *mut c_char {
+    let message = CString::new(message).unwrap_or_else(|_| c"error message has a NUL".into());
+    LAST_ERROR.with(|e| *e.borrow_mut() = Some(message));
+    ptr::null_mut()
+}
+
+/// Return `text` as a string for C, storing its length in `out_len` if it is
+/// not NULL.
+///
+/// # Safety
+///
+/// `out_len` must be NULL or valid for writes.
+unsafe fn output(text: String, out_len: *mut usize) -> *mut c_char {
+    let len = text.len();
+    match CString::new(text) {
+        Ok(s) => {
+            if !out_len.is_null() {
+                // SAFETY: The caller promises `out_len` is valid for writes.
+                unsafe { *out_len = len };
+            }
+            s.into_raw()
+        }
+        Err(_) => fail("output has a NUL byte"),
+    }
+}
+
+/// Return the `len` bytes at `input`.
+///
+/// # Safety
+///
+/// `input` must be valid for reads of `len` bytes, or NULL if `len` is 0.
+unsafe fn input<'a>(input: *const c_char, len: usize) -> &'a [u8] {
+    if len == 0 {
+        return &[];
+    }
+    // SAFETY: The caller promises `input` is valid for `len` bytes.
+    unsafe { slice::from_raw_parts(input.cast(), len) }
+}
+
+/// Convert `len` bytes of Pico-8 Lua at `code` to plain Lua.
+///
+/// # Safety
+///
+/// `code` must be valid for reads of `len` bytes and `out_len` must be NULL
+/// or valid for writes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pico8_to_lua_patch(
+    code: *const c_char,
+    len: usize,
+    out_len: *mut usize,
+) -> *mut c_char {
+    // SAFETY: Upheld by the caller.
+    let bytes = unsafe { input(code, len) };
+    let Ok(code) = std::str::from_utf8(bytes) else {
+        return fail("code is not UTF-8");
+    };
+    // SAFETY: Upheld by the caller.
+    unsafe { output(crate::patch_lua(code).into_owned(), out_len) }
+}
+
+/// Convert `len` bytes of a ".p8" cart at `cart`, returning the converted
+/// cart, or only its Lua if `lua_only` is not 0.
+///
+/// # Safety
+///
+/// `cart` must be valid for reads of `len` bytes and `out_len` must be NULL
+/// or valid for writes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pico8_to_lua_convert_cart(
+    cart: *const c_char,
+    len: usize,
+    lua_only: c_int,
+    out_len: *mut usize,
+) -> *mut c_char {
+    // SAFETY: Upheld by the caller.
+    let bytes = unsafe { input(cart, len) };
+    let cart = match Cart::from_bytes(bytes) {
+        Ok(cart) => cart,
+        Err(e) => return fail(e.to_string()),
+    };
+    let options = Options {
+        lua_only: lua_only != 0,
+        ..Options::default()
+    };
+    match Converter::new(options).convert(&cart.to_string()) {
+        // SAFETY: Upheld by the caller.
+        Ok(out) => unsafe { output(out, out_len) },
+        Err(e) => fail(e.to_string()),
+    }
+}
+
+/// Return the error of the last call on this thread that returned NULL, or
+/// NULL if there was none. The string is valid until the next call.
+#[unsafe(no_mangle)]
+pub extern "C" fn pico8_to_lua_last_error() -> *const c_char {
+    LAST_ERROR.with(|e| e.borrow().as_ref().map_or(ptr::null(), |s| s.as_ptr()))
+}
+
+/// Free a string returned by this library. Does nothing for NULL.
+///
+/// # Safety
+///
+/// `s` must be NULL or a string returned by this library that has not been
+/// freed.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pico8_to_lua_free(s: *mut c_char) {
+    if !s.is_null() {
+        // SAFETY: The caller promises `s` came from `CString::into_raw`.
+        drop(unsafe { CString::from_raw(s) });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CStr;
+
+    #[test]
+    fn test_ffi() {
+        let code = "x += 1";
+        let mut len = 0;
+        unsafe {
+            let out = pico8_to_lua_patch(code.as_ptr().cast(), code.len(), &mut len);
+            assert_eq!(CStr::from_ptr(out).to_str(), Ok("x = x + (1)"));
+            assert_eq!(len, 11);
+            pico8_to_lua_free(out);
+
+            let out =
+                pico8_to_lua_convert_cart(code.as_ptr().cast(), code.len(), 1, ptr::null_mut());
+            assert!(out.is_null());
+            let error = CStr::from_ptr(pico8_to_lua_last_error());
+            assert_eq!(error.to_str(), Ok("missing \"pico-8 cartridge\" header"));
+        }
+    }
+}
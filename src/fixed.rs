@@ -0,0 +1,201 @@
+//! Emulation of Pico-8's 16.16 fixed point numbers.
+//!
+//! Pico-8's numbers have 16 bits of integer and 16 bits of fraction and wrap
+//! around past 32767.99998, so `32767 + 1` is `-32768` and `0.1` is really
+//! `0.100006103515625`. Plain Lua's doubles don't, which can change the
+//! logic of a cart that counts on either. [patch_fixed_point] rewrites the
+//! arithmetic operators into calls to helpers that round and wrap their
+//! results like Pico-8 does, and each number literal into the value Pico-8
+//! reads it as.
+//!
+//! Run [patch_lua](crate::patch_lua) first, and this pass before
+//! [patch_integer_division](crate::division::patch_integer_division), which
+//! it makes unnecessary.
+use std::borrow::Cow;
+
+use crate::{
+    lexer::{TokenKind, tokenize},
+    operators::{Operators, Translation, rewrite_operators},
+    symbols::{DEFAULT_PREFIX, Namer},
+};
+
+/// The largest number, `0x7fff.ffff`, which is also what dividing a positive
+/// number by zero gives.
+pub const MAX: f64 = 32767.0 + 65535.0 / 65536.0;
+
+/// The smallest number, `-0x8000`.
+pub const MIN: f64 = -32768.0;
+
+struct FixedPoint;
+
+impl Operators for FixedPoint {
+    fn translate(&self, op: &str) -> Option<Translation> {
+        Some(Translation::Helper(match op {
+            "+" => "add",
+            "-" => "sub",
+            "*" => "mul",
+            "/" => "div",
+            "\\" => "idiv",
+            "%" => "mod",
+            "^" => "pow",
+            _ => return None,
+        }))
+    }
+
+    fn unary(&self, op: &str) -> Option<Translation> {
+        (op == "-").then_some(Translation::Helper("neg"))
+    }
+
+    fn helper_definition(&self, helper: &str, name: &str) -> String {
+        let (guard, expr) = match helper {
+            "add" => ("", "a + b"),
+            "sub" => ("", "a - b"),
+            "mul" => ("", "a * b"),
+            "div" => (
+                "if b == 0 then return a < 0 and -32767.9999847412109375 or 32767.9999847412109375 end ",
+                "a / b",
+            ),
+            "idiv" => (
+                "if b == 0 then return a < 0 and -32768 or 32767 end ",
+                "math.floor(a / b)",
+            ),
+            "mod" => ("if b == 0 then return a end ", "a % b"),
+            "pow" => ("", "a ^ b"),
+            _ => ("", "-a"),
+        };
+        let params = if helper == "neg" { "a" } else { "a, b" };
+        format!(
+            "local function {name}({params}) {guard}local r = math.floor(({expr}) * 65536) % 4294967296 \
+             if r >= 2147483648 then r = r - 4294967296 end return r / 65536 end\n"
+        )
+    }
+}
+
+/// Return `value` rounded to the nearest 16.16 fixed point number and
+/// wrapped into its range like Pico-8 reads a literal.
+///
+/// ```
+/// use pico8_to_lua::fixed::to_fixed;
+/// assert_eq!(to_fixed(0.1), 0.100006103515625);
+/// assert_eq!(to_fixed(32768.0), -32768.0);
+/// assert_eq!(to_fixed(65535.0), -1.0);
+/// ```
+pub fn to_fixed(value: f64) -> f64 {
+    let bits = (value * 65536.0).round().rem_euclid(4294967296.0);
+    let bits = if bits >= 2147483648.0 {
+        bits - 4294967296.0
+    } else {
+        bits
+    };
+    bits / 65536.0
+}
+
+/// Return the value of a Lua number literal, e.g. `0x1.8` or `1e3`.
+fn literal_value(text: &str) -> Option<f64> {
+    let lower = text.to_ascii_lowercase();
+    let Some(hex) = lower.strip_prefix("0x") else {
+        return lower.parse().ok();
+    };
+    let (int, frac) = hex.split_once('.').unwrap_or((hex, ""));
+    let mut value = if int.is_empty() {
+        0.0
+    } else {
+        u64::from_str_radix(int, 16).ok()? as f64
+    };
+    let mut scale = 1.0;
+    for c in frac.chars() {
+        scale /= 16.0;
+        value += c.to_digit(16)? as f64 * scale;
+    }
+    Some(value)
+}
+
+/// Rewrite each number literal whose value Pico-8 can't hold as the value it
+/// holds instead.
+fn patch_literals(lua: Cow<'_, str>) -> Cow<'_, str> {
+    let mut out = String::new();
+    let mut last = 0;
+    for token in tokenize(&lua).filter(|t| t.kind == TokenKind::Number) {
+        let Some(value) = literal_value(token.text) else {
+            continue;
+        };
+        let fixed = to_fixed(value);
+        if fixed != value {
+            let before = &lua[last..token.span.start];
+            out.push_str(before);
+            // A minus sign right after another would start a comment.
+            let after_minus = lua[..token.span.start].trim_end().ends_with('-');
+            if fixed < 0.0 && after_minus {
+                out.push_str(&format!("({fixed})"));
+            } else {
+                out.push_str(&fixed.to_string());
+            }
+            last = token.span.end;
+        }
+    }
+    if last == 0 {
+        return lua;
+    }
+    out.push_str(&lua[last..]);
+    Cow::Owned(out)
+}
+
+/// Rewrite the arithmetic of converted `lua` to behave like Pico-8's 16.16
+/// fixed point numbers, naming the helpers with [DEFAULT_PREFIX].
+///
+/// Products are rounded from doubles, so those of numbers with many bits of
+/// both integer and fraction may be off by 1/65536.
+///
+/// ```
+/// use pico8_to_lua::fixed::patch_fixed_point;
+/// let lua = patch_fixed_point("x = y * 0.1");
+/// assert!(lua.ends_with("x = __p8_mul(y, 0.100006103515625)"));
+/// ```
+pub fn patch_fixed_point<'h>(lua: impl Into<Cow<'h, str>>) -> Cow<'h, str> {
+    let lua = lua.into();
+    let mut namer = Namer::new(DEFAULT_PREFIX, &lua);
+    patch_fixed_point_with(lua, &mut namer)
+}
+
+/// Rewrite the arithmetic of converted `lua` like [patch_fixed_point], naming
+/// the helpers with `namer`.
+pub fn patch_fixed_point_with<'h>(lua: impl Into<Cow<'h, str>>, namer: &mut Namer) -> Cow<'h, str> {
+    // The literals go first so those of the helpers are left alone.
+    rewrite_operators(patch_literals(lua.into()), &FixedPoint, namer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_patch_fixed_point() {
+        let lua = patch_fixed_point("-- game\nx = -a + b \\ 2 .. \"-1\"\n");
+        let helpers = FixedPoint.helper_definition("neg", "__p8_neg")
+            + &FixedPoint.helper_definition("add", "__p8_add")
+            + &FixedPoint.helper_definition("idiv", "__p8_idiv");
+        assert_eq!(
+            lua,
+            format!("-- game\n{helpers}x = __p8_add(__p8_neg(a), __p8_idiv(b, 2)) .. \"-1\"\n")
+        );
+        assert!(matches!(
+            patch_fixed_point("s = \"a\" .. b"),
+            Cow::Borrowed(_)
+        ));
+    }
+
+    #[test]
+    fn test_literals() {
+        assert_eq!(
+            patch_literals("x = {1, 0x1.8, 40000, 0xffff, 1e-6}".into()),
+            "x = {1, 0x1.8, -25536, -1, 0}"
+        );
+        // Pico-8 reads 0.1 as 0x0000.199a and 0.3 as 0x0000.4ccd.
+        assert_eq!(
+            patch_literals("x = 0.1 + 0.3".into()),
+            "x = 0.100006103515625 + 0.3000030517578125"
+        );
+        assert_eq!(patch_literals("x = -0xffff".into()), "x = -(-1)");
+        assert_eq!(to_fixed(MAX + 1.0 / 65536.0), MIN);
+    }
+}
@@ -0,0 +1,160 @@
+//! Formatting converted Lua.
+//!
+//! [format_lua] indents each line by the blocks, tables, and parentheses it
+//! is in and collapses the runs of spaces between tokens, so the shorthand
+//! ifs and compound assignments the converter expands read as if written by
+//! hand. Strings and comments, and the lines inside long ones, are left as
+//! they are, and no line is added or removed, so line numbers still match.
+use std::{error::Error, fmt, str::FromStr};
+
+use crate::lexer::{Token, TokenKind, tokenize_lua};
+
+/// What a line is indented with for each level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Indent {
+    /// This many spaces.
+    Spaces(usize),
+    /// One tab.
+    Tab,
+}
+
+/// A text that names no [Indent].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownIndent(pub String);
+
+impl fmt::Display for UnknownIndent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "unknown indent {:?}; expected a number of spaces or tab",
+            self.0
+        )
+    }
+}
+
+impl Error for UnknownIndent {}
+
+impl FromStr for Indent {
+    type Err = UnknownIndent;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("tab") {
+            return Ok(Indent::Tab);
+        }
+        s.parse()
+            .map(Indent::Spaces)
+            .map_err(|_| UnknownIndent(s.to_string()))
+    }
+}
+
+/// How [format_lua] lays out the code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FormatOptions {
+    /// What each level of indentation is.
+    pub indent: Indent,
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        FormatOptions {
+            indent: Indent::Spaces(2),
+        }
+    }
+}
+
+/// Return how `token` changes the depth of the lines after it.
+fn depth_change(token: &Token<'_>) -> isize {
+    match token.kind {
+        TokenKind::Keyword => match token.text {
+            "function" | "do" | "then" | "repeat" => 1,
+            "end" | "until" | "elseif" => -1,
+            _ => 0,
+        },
+        TokenKind::Punct => match token.text {
+            "(" | "[" | "{" => 1,
+            ")" | "]" | "}" => -1,
+            _ => 0,
+        },
+        _ => 0,
+    }
+}
+
+/// Return true if `token` at the start of a line puts the line a level out,
+/// like `end` or `else`.
+fn closes(token: &Token<'_>) -> bool {
+    depth_change(token) < 0 || token.is("else")
+}
+
+/// Indent and space `lua` as [FormatOptions] say.
+///
+/// ```
+/// use pico8_to_lua::format::{FormatOptions, format_lua};
+/// let lua = "function f()\nif a then  b()  else c() end\n    x = {\n1,\n   }\nend\n";
+/// assert_eq!(format_lua(lua, &FormatOptions::default()),
+///            "function f()\n  if a then b() else c() end\n  x = {\n    1,\n  }\nend\n");
+/// ```
+pub fn format_lua(lua: &str, options: &FormatOptions) -> String {
+    let indent = match options.indent {
+        Indent::Spaces(n) => " ".repeat(n),
+        Indent::Tab => "\t".to_string(),
+    };
+    let tokens: Vec<Token> = tokenize_lua(lua).collect();
+    let mut out = String::with_capacity(lua.len());
+    let mut depth = 0isize;
+    let mut next = 0;
+    let mut start = 0;
+    for line in lua.split_inclusive('\n') {
+        let end = start + line.len();
+        let body_end = start + line.trim_end_matches(['\r', '\n']).len();
+        // A long string or comment from an earlier line runs into this one.
+        let inside = next > 0 && tokens[next - 1].span.end > start;
+        let first = next;
+        while next < tokens.len() && tokens[next].span.start < end {
+            next += 1;
+        }
+        let line_tokens = &tokens[first..next];
+        if inside {
+            out.push_str(line);
+        } else if !line_tokens.is_empty() {
+            let outdent = line_tokens.iter().take_while(|t| closes(t)).count() as isize;
+            for _ in 0..(depth - outdent).max(0) {
+                out.push_str(&indent);
+            }
+            let mut last = None;
+            for token in line_tokens {
+                if last.is_some_and(|last| last < token.span.start) {
+                    out.push(' ');
+                }
+                out.push_str(&lua[token.span.start..token.span.end.min(body_end)]);
+                last = Some(token.span.end);
+            }
+            out.push_str(&lua[body_end..end]);
+        } else {
+            out.push_str(&lua[body_end..end]);
+        }
+        depth = (depth + line_tokens.iter().map(depth_change).sum::<isize>()).max(0);
+        start = end;
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_lua() {
+        let lua = "if a then\n\tx = [[\n  keep  ]]   y = 1\nelseif b then\n  -- c   d\nend  \r\n\n";
+        assert_eq!(
+            format_lua(
+                lua,
+                &FormatOptions {
+                    indent: Indent::Tab
+                }
+            ),
+            "if a then\n\tx = [[\n  keep  ]]   y = 1\nelseif b then\n\t-- c   d\nend\r\n\n"
+        );
+        assert_eq!("4".parse(), Ok(Indent::Spaces(4)));
+        assert!("wide".parse::<Indent>().is_err());
+    }
+}
@@ -0,0 +1,211 @@
+//! The spellings of Pico-8's glyphs.
+//!
+//! Pico-8 saves some glyphs, like the button glyphs `⬅️` and `🅾️`, with the
+//! emoji variation selector U+FE0F after them, but an editor may drop it or
+//! put the text variation selector U+FE0E in its place. Every pass that reads
+//! a glyph goes through this module, so the three spellings are the same
+//! glyph wherever one can appear.
+//!
+//! A [ButtonMap] replaces the built-in button glyphs in `btn()` and `btnp()`
+//! calls with a mapping of your own, e.g. for placeholders like `(x)` that a
+//! toolchain wrote in place of the glyphs, or for the extra buttons of a
+//! custom port.
+use std::borrow::Cow;
+
+use crate::{
+    lexer::{TokenKind, tokenize},
+    span::{Span, TextEdit, apply_edits},
+};
+
+/// The variation selector that asks for a glyph's emoji style.
+pub const EMOJI_SELECTOR: char = '\u{fe0f}';
+
+/// The variation selector that asks for a glyph's text style.
+pub const TEXT_SELECTOR: char = '\u{fe0e}';
+
+/// The button glyphs, the names generated for them, and their values.
+pub const BUTTON_GLYPHS: &[(&str, &str, u8)] = &[
+    ("⬅", "left", 0),
+    ("➡", "right", 1),
+    ("⬆", "up", 2),
+    ("⬇", "down", 3),
+    ("🅾", "o", 4),
+    ("❎", "x", 5),
+];
+
+/// Return whether `c` is a variation selector that may follow a glyph.
+pub fn is_selector(c: char) -> bool {
+    c == EMOJI_SELECTOR || c == TEXT_SELECTOR
+}
+
+/// Return `text` without the variation selectors at its end.
+///
+/// ```
+/// use pico8_to_lua::glyph::strip_selectors;
+/// assert_eq!(strip_selectors("⬅\u{fe0f}"), "⬅");
+/// assert_eq!(strip_selectors("⬅\u{fe0e}"), "⬅");
+/// ```
+pub fn strip_selectors(text: &str) -> &str {
+    text.trim_end_matches(is_selector)
+}
+
+/// Return the name and value of the button glyph `text` in any of its
+/// spellings, or `None` if it is not one.
+///
+/// ```
+/// use pico8_to_lua::glyph::button;
+/// assert_eq!(button("🅾️"), Some(("o", 4)));
+/// assert_eq!(button("🅾"), Some(("o", 4)));
+/// assert_eq!(button("♥"), None);
+/// ```
+pub fn button(text: &str) -> Option<(&'static str, u8)> {
+    let glyph = strip_selectors(text);
+    BUTTON_GLYPHS
+        .iter()
+        .find(|(g, _, _)| *g == glyph)
+        .map(|&(_, name, value)| (name, value))
+}
+
+/// The text that stands for each button in `btn()` and `btnp()` calls and
+/// the button's value.
+///
+/// The default map has the button glyphs of [BUTTON_GLYPHS], each in any of
+/// its spellings.
+///
+/// ```
+/// use pico8_to_lua::glyph::ButtonMap;
+/// let buttons = ButtonMap::default().with("(x)", 5).with("🅲", 6);
+/// assert_eq!(buttons.button("❎"), Some(5));
+/// assert_eq!(buttons.button("(x)"), Some(5));
+/// assert_eq!(ButtonMap::empty().with("(x)", 5).button("❎"), None);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ButtonMap {
+    buttons: Vec<(String, u8)>,
+}
+
+impl Default for ButtonMap {
+    fn default() -> Self {
+        ButtonMap {
+            buttons: BUTTON_GLYPHS
+                .iter()
+                .map(|&(glyph, _, value)| (glyph.to_string(), value))
+                .collect(),
+        }
+    }
+}
+
+impl ButtonMap {
+    /// Create a map without any buttons.
+    pub fn empty() -> Self {
+        ButtonMap {
+            buttons: Vec::new(),
+        }
+    }
+
+    /// Map `text` to `value`, in place of what it was mapped to before.
+    pub fn insert(&mut self, text: impl Into<String>, value: u8) {
+        let text = text.into();
+        let text = strip_selectors(&text).to_string();
+        self.buttons.retain(|(t, _)| *t != text);
+        self.buttons.push((text, value));
+    }
+
+    /// Return this map with `text` mapped to `value`. See [insert](Self::insert).
+    pub fn with(mut self, text: impl Into<String>, value: u8) -> Self {
+        self.insert(text, value);
+        self
+    }
+
+    /// Return the value of the button `text` stands for, in any spelling of
+    /// a glyph, or `None` if it stands for none.
+    pub fn button(&self, text: &str) -> Option<u8> {
+        let text = strip_selectors(text);
+        self.buttons
+            .iter()
+            .find(|(t, _)| t == text)
+            .map(|&(_, value)| value)
+    }
+
+    /// Return the value and the length of the longest button `text` starts
+    /// with, with the selectors after it, that ends the first argument.
+    fn prefix(&self, text: &str) -> Option<(u8, usize)> {
+        self.buttons
+            .iter()
+            .filter(|(t, _)| !t.is_empty() && text.starts_with(t.as_str()))
+            .map(|(t, value)| {
+                let rest = text[t.len()..].trim_start_matches(is_selector);
+                (*value, text.len() - rest.len(), rest)
+            })
+            .filter(|(_, _, rest)| rest.trim_start().starts_with([',', ')']))
+            .max_by_key(|&(_, len, _)| len)
+            .map(|(value, len, _)| (value, len))
+    }
+}
+
+/// Replace the first argument of each `btn()` and `btnp()` call that a button
+/// of `buttons` stands for with its value, leaving strings and comments
+/// alone. The argument may be text the lexer splits, like `(x)`.
+///
+/// ```
+/// use pico8_to_lua::glyph::{ButtonMap, patch_buttons};
+/// let buttons = ButtonMap::default().with("(x)", 5);
+/// assert_eq!(patch_buttons("if btnp((x), 1) then end".into(), &buttons), "if btnp(5, 1) then end");
+/// ```
+pub fn patch_buttons<'h>(lua: Cow<'h, str>, buttons: &ButtonMap) -> Cow<'h, str> {
+    let tokens: Vec<_> = tokenize(&lua)
+        .filter(|t| t.kind != TokenKind::Comment)
+        .collect();
+    let edits: Vec<TextEdit> = tokens
+        .windows(2)
+        .filter(|pair| {
+            pair[0].kind == TokenKind::Name
+                && matches!(pair[0].text, "btn" | "btnp")
+                && pair[1].is("(")
+        })
+        .filter_map(|pair| {
+            let after = pair[1].span.end;
+            let start = after + (lua[after..].len() - lua[after..].trim_start().len());
+            let (value, len) = buttons.prefix(&lua[start..])?;
+            Some(TextEdit::new(Span::new(start, start + len), value.to_string()))
+        })
+        .collect();
+    if edits.is_empty() {
+        return lua;
+    }
+    Cow::Owned(apply_edits(&lua, &edits))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{patch_lua, symbols::number_glyphs};
+
+    #[test]
+    fn test_spellings() {
+        for glyph in ["⬅", "⬅\u{fe0f}", "⬅\u{fe0e}"] {
+            assert_eq!(
+                patch_lua(format!("if btn({glyph}) then end")),
+                "if btn(0) then end"
+            );
+            assert_eq!(number_glyphs(format!("b = {glyph}").into()), "b = 0");
+        }
+    }
+
+    #[test]
+    fn test_patch_buttons() {
+        let buttons = ButtonMap::default().with("(x)", 5).with("(o)", 4).with("🅲", 6);
+        assert_eq!(
+            patch_buttons(
+                "if btn(🅲️) or btnp( (o) ,p) or btn(⬅) then end\n\
+                 s = \"btn((x))\" -- btn((x))\nb = btn((x)+1)"
+                    .into(),
+                &buttons
+            ),
+            "if btn(6) or btnp( 4 ,p) or btn(0) then end\n\
+             s = \"btn((x))\" -- btn((x))\nb = btn((x)+1)"
+        );
+        let buttons = ButtonMap::empty().with("(x)", 5);
+        assert_eq!(patch_buttons("btn(⬅)".into(), &buttons), "btn(⬅)");
+    }
+}
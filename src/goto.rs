@@ -0,0 +1,206 @@
+//! Rewriting `goto continue` for Lua 5.1.
+//!
+//! Pico-8 and Lua 5.2 and later have `goto` and labels, which Lua 5.1 lacks.
+//! Most carts use them only to skip to the next iteration of a loop:
+//!
+//! ```lua
+//! for i = 1, 3 do
+//!   if i == 2 then goto continue end
+//!   print(i)
+//!   ::continue::
+//! end
+//! ```
+//!
+//! [patch_goto_continue] wraps the body of such a loop in `repeat ... until
+//! true` so that `break` skips to its end. It leaves every other `goto` as it
+//! is for [check_version](crate::version::check_version) to report.
+use std::borrow::Cow;
+
+use crate::{
+    lexer::{Token, TokenKind, tokenize},
+    span::{Span, TextEdit, apply_edits},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Block {
+    /// The body of a `for` or `while` loop.
+    Loop,
+    Repeat,
+    Function,
+    /// An `if` or `do` block.
+    Other,
+}
+
+/// A `goto` and whether only blocks other than loops and functions are
+/// between it and the block it was found in.
+struct Goto<'a> {
+    label: &'a str,
+    span: Span,
+    direct: bool,
+}
+
+struct Frame<'a> {
+    block: Block,
+    /// The token that opens the block.
+    open: Span,
+    gotos: Vec<Goto<'a>>,
+    /// Whether a `break` would leave this block.
+    breaks: bool,
+    /// The label that is the last statement so far and its span.
+    label: Option<(&'a str, Span)>,
+}
+
+/// Rewrite each loop of converted `lua` that ends with a label that is only
+/// the target of `goto`s in its body, so it runs on Lua 5.1. Lines are kept.
+///
+/// A loop is left alone if a `goto` to its label is inside an inner loop or
+/// if its body has a `break` of its own, which the rewrite would change.
+///
+/// ```
+/// use pico8_to_lua::goto::patch_goto_continue;
+/// assert_eq!(
+///     patch_goto_continue("while f() do if a then goto skip end g() ::skip:: end"),
+///     "while f() do repeat if a then break end g() until true end"
+/// );
+/// ```
+pub fn patch_goto_continue<'h>(lua: impl Into<Cow<'h, str>>) -> Cow<'h, str> {
+    let lua = lua.into();
+    let tokens: Vec<Token> = tokenize(&lua)
+        .filter(|t| t.kind != TokenKind::Comment)
+        .collect();
+    let mut edits = Vec::new();
+    let mut stack: Vec<Frame> = Vec::new();
+    // The depth of the stack at a `for` or `while` whose `do` is to come.
+    let mut loop_at = None;
+    let mut i = 0;
+    while i < tokens.len() {
+        let token = &tokens[i];
+        let mut label = None;
+        let mut open = None;
+        match token.text {
+            _ if !matches!(token.kind, TokenKind::Keyword | TokenKind::Punct) => {}
+            "for" | "while" => loop_at = Some(stack.len()),
+            "do" if loop_at == Some(stack.len()) => {
+                loop_at = None;
+                open = Some(Block::Loop);
+            }
+            "do" | "if" => open = Some(Block::Other),
+            "repeat" => open = Some(Block::Repeat),
+            "function" => open = Some(Block::Function),
+            "break" => {
+                if let Some(frame) = stack.last_mut() {
+                    frame.breaks = true;
+                }
+            }
+            "goto" => {
+                if let (Some(name), Some(frame)) = (tokens.get(i + 1), stack.last_mut()) {
+                    frame.gotos.push(Goto {
+                        label: name.text,
+                        span: Span::new(token.span.start, name.span.end),
+                        direct: true,
+                    });
+                    i += 1;
+                }
+            }
+            "::" => {
+                if let (Some(name), Some(close)) = (tokens.get(i + 1), tokens.get(i + 2))
+                    && close.is("::")
+                {
+                    label = Some((name.text, Span::new(token.span.start, close.span.end)));
+                    i += 2;
+                }
+            }
+            ";" => label = stack.last().and_then(|f| f.label),
+            "end" | "until" => {
+                if let Some(frame) = stack.pop() {
+                    close(frame, stack.last_mut(), &mut edits);
+                }
+            }
+            _ => {}
+        }
+        if let Some(frame) = stack.last_mut() {
+            frame.label = label;
+        }
+        if let Some(block) = open {
+            stack.push(Frame {
+                block,
+                open: token.span,
+                gotos: Vec::new(),
+                breaks: false,
+                label: None,
+            });
+        }
+        i += 1;
+    }
+    if edits.is_empty() {
+        return lua;
+    }
+    Cow::Owned(apply_edits(&lua, &edits))
+}
+
+/// Close `frame`, rewriting it if it is a loop that ends with the target of
+/// its `goto`s, and pass what is left to the enclosing `parent`.
+fn close<'a>(frame: Frame<'a>, parent: Option<&mut Frame<'a>>, edits: &mut Vec<TextEdit>) {
+    let mut gotos = frame.gotos;
+    if let (Block::Loop, Some((name, span))) = (frame.block, frame.label) {
+        let mut targets = gotos.iter().filter(|g| g.label == name).peekable();
+        if !frame.breaks && targets.peek().is_some() && targets.all(|g| g.direct) {
+            edits.push(TextEdit::new(frame.open, "do repeat"));
+            edits.push(TextEdit::new(span, "until true"));
+            for goto in gotos.iter().filter(|g| g.label == name) {
+                edits.push(TextEdit::new(goto.span, "break"));
+            }
+            gotos.retain(|g| g.label != name);
+        }
+    }
+    let Some(parent) = parent else {
+        return;
+    };
+    match frame.block {
+        Block::Other => {
+            parent.breaks |= frame.breaks;
+            parent.gotos.extend(gotos);
+        }
+        Block::Loop | Block::Repeat => parent
+            .gotos
+            .extend(gotos.into_iter().map(|g| Goto { direct: false, ..g })),
+        // A goto can't leave a function.
+        Block::Function => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_patch_goto_continue() {
+        let lua = "for i = 1, 3 do\n  if i == 2 then goto continue end\n  print(i)\n  ::continue::\nend\n";
+        assert_eq!(
+            patch_goto_continue(lua),
+            "for i = 1, 3 do repeat\n  if i == 2 then break end\n  print(i)\n  until true\nend\n"
+        );
+        let nested = "for i = 1, 3 do\n  for j = 1, 3 do\n    if j == i then goto continue end\n  \
+                      end\n  ::continue::\nend\n";
+        assert!(matches!(patch_goto_continue(nested), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn test_left_alone() {
+        for lua in [
+            "while a do if b then break end goto continue ::continue:: end",
+            "while a do goto continue ::continue:: f() end",
+            "::top:: goto top",
+            "repeat goto continue ::continue:: until a",
+        ] {
+            assert!(
+                matches!(patch_goto_continue(lua), Cow::Borrowed(_)),
+                "{lua}"
+            );
+        }
+        assert_eq!(
+            patch_goto_continue("while a do goto c ::c:: ; end"),
+            "while a do repeat break until true ; end"
+        );
+    }
+}
@@ -0,0 +1,1048 @@
+//! Flattening of "#include" statements with a record of where each included
+//! file landed in the output.
+//!
+//! [patch_includes](crate::patch_includes) is the right tool for a one-shot
+//! conversion. When the same project is converted over and over, e.g. in a
+//! watch mode or an editor, it is wasteful to re-read and re-flatten every
+//! include because a single file changed. A [Flattened] output remembers the
+//! region each include occupies so that one file's contents can be spliced
+//! back in with [Flattened::splice_include].
+//!
+//! [FsResolver] reads included files from the file system.
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    error::Error,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use lazy_regex::{regex, regex::{Captures, Regex}};
+
+use crate::{
+    cart::{is_cart, lua_span, tabs},
+    lexer::{TokenKind, tokenize},
+    limits::{LimitError, Limits},
+    love::lua_string,
+    p8scii::decode,
+    span::{Span, TextEdit},
+};
+
+/// Match an "#include" statement: the path, which may be in double or single
+/// quotes, and an optional trailing comment.
+pub(crate) fn include_regex() -> &'static Regex {
+    regex!(
+        r#"(?m)^[ \t]*#include\s+(?:"([^"\n]*)"|'([^'\n]*)'|([^\s"']\S*?))[ \t]*((?:--|//)[^\r\n]*)?\r?$"#
+    )
+}
+
+/// Return the path of an [include_regex] match without quotes.
+pub(crate) fn include_path<'h>(caps: &Captures<'h>) -> &'h str {
+    caps.get(1)
+        .or_else(|| caps.get(2))
+        .or_else(|| caps.get(3))
+        .map_or("", |m| m.as_str())
+}
+
+/// Split the tab suffix from the path of an "#include", e.g. "lib.p8:2" is
+/// tab 2 of "lib.p8". Pico-8 numbers the tabs of its code editor from 0.
+///
+/// ```
+/// use pico8_to_lua::split_tab;
+/// assert_eq!(split_tab("lib.p8:2"), ("lib.p8", Some(2)));
+/// assert_eq!(split_tab("lib.lua"), ("lib.lua", None));
+/// ```
+pub fn split_tab(path: &str) -> (&str, Option<usize>) {
+    match path.rsplit_once(':') {
+        Some((file, tab))
+            if !file.is_empty() && !tab.is_empty() && tab.bytes().all(|b| b.is_ascii_digit()) =>
+        {
+            match tab.parse() {
+                Ok(tab) => (file, Some(tab)),
+                Err(_) => (path, None),
+            }
+        }
+        _ => (path, None),
+    }
+}
+
+/// Return the [include_regex] matches of `lua` that are not inside a block
+/// comment or long string.
+pub(crate) fn include_matches(lua: &str) -> impl Iterator<Item = Captures<'_>> {
+    let long: Vec<Span> = tokenize(lua)
+        .filter(|t| matches!(t.kind, TokenKind::String | TokenKind::Comment) && t.text.contains('\n'))
+        .map(|t| t.span)
+        .collect();
+    include_regex().captures_iter(lua).filter(move |caps| {
+        let whole = caps.get(0).unwrap();
+        let hash = whole.start() + whole.as_str().find('#').unwrap_or(0);
+        !long.iter().any(|span| span.contains(hash))
+    })
+}
+
+/// Replace each "#include" statement of `lua` that is not inside a block
+/// comment or long string with `resolve` of its path.
+pub(crate) fn replace_includes<'h>(
+    lua: Cow<'h, str>,
+    mut resolve: impl FnMut(&str) -> String,
+) -> Cow<'h, str> {
+    let mut out = String::new();
+    let mut last = 0;
+    for caps in include_matches(&lua) {
+        let whole = caps.get(0).unwrap();
+        out.push_str(&lua[last..whole.start()]);
+        out.push_str(&resolve(include_path(&caps)));
+        last = whole.end();
+    }
+    if last == 0 {
+        return lua;
+    }
+    out.push_str(&lua[last..]);
+    Cow::Owned(out)
+}
+
+/// What an "#include" statement resolves to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Included {
+    /// Code, inlined in place of the statement.
+    Code(String),
+    /// Data such as level strings, assigned to a name as a long string so the
+    /// code conversions leave it alone.
+    Data {
+        /// The name the data is assigned to, e.g. "levels".
+        name: String,
+        /// The data.
+        text: String,
+    },
+}
+
+impl Included {
+    /// Return the text that replaces the "#include" statement.
+    ///
+    /// ```
+    /// use pico8_to_lua::Included;
+    /// let data = Included::Data { name: "levels".into(), text: "a]]b".into() };
+    /// assert_eq!(data.to_lua(), "levels = [=[\na]]b]=]");
+    /// ```
+    pub fn to_lua(&self) -> String {
+        match self {
+            Included::Code(code) => code.clone(),
+            Included::Data { name, text } => {
+                let mut level = String::new();
+                while text.contains(&format!("]{level}]")) {
+                    level.push('=');
+                }
+                // Lua drops a newline right after the opening bracket, so
+                // one is added to keep any newline the data starts with.
+                format!("{name} = [{level}[\n{text}]{level}]")
+            }
+        }
+    }
+}
+
+/// An included file converted as a module of its own. See
+/// [FsResolver::modules].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Module {
+    /// The name that `require` loads it by. See [module_name].
+    pub name: String,
+    /// The path as written in the "#include" statement, or as a wildcard or
+    /// directory expanded to.
+    pub path: String,
+    /// Its Lua with its own includes rewritten as `require` calls.
+    pub lua: String,
+}
+
+impl Module {
+    /// The path that `require` looks for the module at, relative to the
+    /// directory of the code that requires it, e.g. "lib/util.lua" for
+    /// "lib.util".
+    pub fn file(&self) -> PathBuf {
+        PathBuf::from(format!("{}.lua", self.name.replace('.', "/")))
+    }
+}
+
+/// Return the name that `require` loads the included file `path` by: the
+/// path without a ".lua" or ".p8" extension and with dots between its
+/// directories, and the tab, if any, after an underscore.
+///
+/// ```
+/// use pico8_to_lua::module_name;
+/// assert_eq!(module_name("lib/util.lua"), "lib.util");
+/// assert_eq!(module_name("./enemies.p8:2"), "enemies_2");
+/// ```
+pub fn module_name(path: &str) -> String {
+    let (file, tab) = split_tab(path);
+    let file = file.trim_start_matches("./");
+    let stem = file
+        .strip_suffix(".lua")
+        .or_else(|| file.strip_suffix(".p8"))
+        .unwrap_or(file);
+    let name = stem.replace(['/', '\\'], ".");
+    match tab {
+        Some(tab) => format!("{name}_{tab}"),
+        None => name,
+    }
+}
+
+/// Return the statement that requires the module `name`.
+pub(crate) fn require_call(name: &str) -> String {
+    format!("require({})", lua_string(name))
+}
+
+/// An included file that could not be read.
+#[derive(Debug)]
+pub struct IncludeError {
+    /// The path as written in the "#include" statement.
+    pub path: String,
+    /// The paths of the includes that led to this one, outermost first, or
+    /// none if the code being converted includes it directly.
+    pub chain: Vec<String>,
+    /// Why it could not be read.
+    pub error: io::Error,
+}
+
+impl fmt::Display for IncludeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "failed to include {:?}", self.path)?;
+        for (i, path) in self.chain.iter().enumerate() {
+            write!(f, "{} {:?}", if i == 0 { " via" } else { " >" }, path)?;
+        }
+        write!(f, ": {}", self.error)
+    }
+}
+
+impl Error for IncludeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// Return true if the file name `name` matches `pattern`, where `*` matches
+/// any characters and `?` matches one.
+fn wildcard(pattern: &str, name: &str) -> bool {
+    let mut chars = pattern.chars();
+    match chars.next() {
+        None => name.is_empty(),
+        Some('*') => (0..=name.len())
+            .filter(|&i| name.is_char_boundary(i))
+            .any(|i| wildcard(chars.as_str(), &name[i..])),
+        Some('?') => {
+            let mut rest = name.chars();
+            rest.next().is_some() && wildcard(chars.as_str(), rest.as_str())
+        }
+        Some(c) => name
+            .strip_prefix(c)
+            .is_some_and(|rest| wildcard(chars.as_str(), rest)),
+    }
+}
+
+/// Return whether the include `path` is downloaded rather than read: an
+/// "http://" or "https://" URL or a "bbs:" cart id.
+pub(crate) fn is_remote(path: &str) -> bool {
+    ["http://", "https://", "bbs:"].iter().any(|scheme| path.starts_with(scheme))
+}
+
+/// Return the Lua of an included file's `bytes`: only the Lua of a cart, and
+/// text that is not UTF-8 read as P8SCII.
+fn included_lua(bytes: Vec<u8>) -> String {
+    let text = String::from_utf8(bytes).unwrap_or_else(|e| decode(e.as_bytes()));
+    if is_cart(&text) {
+        lua_span(&text).map_or(String::new(), |span| text[span.range()].to_string())
+    } else {
+        text
+    }
+}
+
+/// Surround `lua`, included from `path`, with comments naming it, so that an
+/// error in the flattened code can be traced to the file it came from.
+///
+/// ```
+/// use pico8_to_lua::{mark_include, patch_includes};
+/// let lua = patch_includes("#include util.lua\nf()", |path| mark_include(path, "function f() end"));
+/// assert_eq!(lua, "-- >>> include util.lua\nfunction f() end\n-- <<< include util.lua\nf()");
+/// ```
+pub fn mark_include(path: &str, lua: &str) -> String {
+    let newline = if lua.is_empty() || lua.ends_with('\n') { "" } else { "\n" };
+    format!("-- >>> include {path}\n{lua}{newline}-- <<< include {path}")
+}
+
+/// The error of an include past a limit.
+fn over(limit: LimitError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, limit)
+}
+
+/// Reads included files from the file system.
+///
+/// A path is looked for in the cart's directory, if there is one, then in
+/// each search path in order. Files that are not UTF-8 are read as P8SCII,
+/// only the Lua of an included ".p8" cart is used, or only one tab of it for
+/// a path like "lib.p8:2", and each file is read once. A path with a wildcard
+/// in its file name, like "lib/*.lua", or that names a directory includes
+/// each of its files in order by name. See [expand](FsResolver::expand).
+///
+/// With the `net` feature, an "http://" or "https://" URL or a BBS cart, as
+/// "bbs:celeste-0", is downloaded once, up to
+/// [max_include_bytes](Limits::max_include_bytes) or else
+/// [MAX_INCLUDE_DOWNLOAD](crate::bbs::MAX_INCLUDE_DOWNLOAD), and kept in
+/// [download_dir](FsResolver::download_dir) if there is one.
+///
+/// ```no_run
+/// use pico8_to_lua::{FsResolver, patch_includes, try_patch_includes};
+/// let mut resolver = FsResolver::for_cart("game/main.p8", ["lib"]);
+/// let lua = try_patch_includes("#include util.lua", |path| resolver.resolve(path)).unwrap();
+/// let lua = patch_includes("#include util.lua", resolver.as_fn());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct FsResolver {
+    /// The directory of the cart being converted, searched first.
+    pub cart_dir: Option<PathBuf>,
+    /// The directories searched after the cart's in order.
+    pub search_paths: Vec<PathBuf>,
+    /// Refuse includes past these limits, or resolve any if `None`. See
+    /// [Limits].
+    pub limits: Option<Limits>,
+    /// Keep each downloaded include in this directory and read it from there
+    /// instead of downloading it again, or download on every run if `None`.
+    #[cfg(feature = "net")]
+    pub download_dir: Option<PathBuf>,
+    /// Surround the Lua of each file included with comments naming it, as
+    /// [mark_include] does.
+    pub markers: bool,
+    /// Include each file only the first time this resolver includes it, like
+    /// a header guard, and leave a comment in place of each later include.
+    pub once: bool,
+    cache: HashMap<PathBuf, String>,
+    /// The files and tabs included so far, for [once](FsResolver::once).
+    seen: HashSet<(String, Option<usize>)>,
+    /// The bytes of every include resolved so far.
+    included: usize,
+}
+
+impl FsResolver {
+    /// Look for included files in `search_paths`.
+    pub fn new(search_paths: impl IntoIterator<Item = impl Into<PathBuf>>) -> Self {
+        FsResolver {
+            cart_dir: None,
+            search_paths: search_paths.into_iter().map(Into::into).collect(),
+            limits: None,
+            #[cfg(feature = "net")]
+            download_dir: None,
+            markers: false,
+            once: false,
+            cache: HashMap::new(),
+            seen: HashSet::new(),
+            included: 0,
+        }
+    }
+
+    /// Look for included files next to `cart` first, then in `search_paths`.
+    pub fn for_cart(
+        cart: impl AsRef<Path>,
+        search_paths: impl IntoIterator<Item = impl Into<PathBuf>>,
+    ) -> Self {
+        FsResolver {
+            cart_dir: Some(cart.as_ref().parent().unwrap_or(Path::new("")).to_path_buf()),
+            ..FsResolver::new(search_paths)
+        }
+    }
+
+    /// Return the file that `path` names, if there is one.
+    pub fn find(&self, path: &str) -> Option<PathBuf> {
+        let path = Path::new(path);
+        if path.is_absolute() {
+            return path.is_file().then(|| path.to_path_buf());
+        }
+        self.cart_dir
+            .iter()
+            .chain(&self.search_paths)
+            .map(|dir| dir.join(path))
+            .find(|p| p.is_file())
+    }
+
+    /// Return the directory that `path` names, if there is one.
+    fn find_dir(&self, path: &str) -> Option<PathBuf> {
+        let path = Path::new(path);
+        if path.is_absolute() {
+            return path.is_dir().then(|| path.to_path_buf());
+        }
+        self.cart_dir
+            .iter()
+            .chain(&self.search_paths)
+            .map(|dir| dir.join(path))
+            .find(|p| p.is_dir())
+    }
+
+    /// Return the paths of the files that `path` includes, in order by name,
+    /// if it has a `*` or `?` wildcard in its file name or names a directory,
+    /// or `None` if it names one file. A directory includes its ".lua" and
+    /// ".p8" files, and neither includes hidden files or those of
+    /// subdirectories. The directory of a wildcard is looked for like a file.
+    ///
+    /// ```no_run
+    /// use pico8_to_lua::FsResolver;
+    /// let resolver = FsResolver::for_cart("game/main.p8", ["lib"]);
+    /// assert_eq!(resolver.expand("util/*.lua").unwrap(), ["util/draw.lua", "util/math.lua"]);
+    /// assert_eq!(resolver.expand("util.lua"), None);
+    /// ```
+    pub fn expand(&self, path: &str) -> Option<Vec<String>> {
+        if is_remote(path) {
+            return None;
+        }
+        let (dir, pattern) = path.rsplit_once('/').unwrap_or(("", path));
+        let wild = |s: &str| s.contains(['*', '?']);
+        let (dir, pattern) = if wild(pattern) && !wild(dir) {
+            (dir, Some(pattern))
+        } else {
+            (path.trim_end_matches('/'), None)
+        };
+        let found = match self.find_dir(dir) {
+            Some(found) => found,
+            None if pattern.is_some() => return Some(Vec::new()),
+            None => return None,
+        };
+        let mut names: Vec<String> = fs::read_dir(found)
+            .ok()?
+            .filter_map(|entry| {
+                let entry = entry.ok()?;
+                let name = entry.file_name().into_string().ok()?;
+                let included = match pattern {
+                    Some(pattern) => wildcard(pattern, &name),
+                    None => name.ends_with(".lua") || name.ends_with(".p8"),
+                };
+                (included && !name.starts_with('.') && entry.path().is_file()).then_some(name)
+            })
+            .collect();
+        names.sort();
+        Some(
+            names
+                .into_iter()
+                .map(|name| {
+                    if dir.is_empty() {
+                        name
+                    } else {
+                        format!("{dir}/{name}")
+                    }
+                })
+                .collect(),
+        )
+    }
+
+    /// Resolve each of `paths`, which `path` expands to, with `resolve` and
+    /// join their Lua.
+    fn resolve_each(
+        &mut self,
+        path: &str,
+        paths: Vec<String>,
+        chain: Vec<String>,
+        mut resolve: impl FnMut(&mut Self, &str) -> Result<String, IncludeError>,
+    ) -> Result<String, IncludeError> {
+        if paths.is_empty() {
+            return Err(IncludeError {
+                path: path.to_string(),
+                chain,
+                error: io::Error::new(io::ErrorKind::NotFound, "no files match"),
+            });
+        }
+        let mut lua = Vec::with_capacity(paths.len());
+        for path in &paths {
+            lua.push(resolve(self, path)?);
+        }
+        Ok(lua.join("\n"))
+    }
+
+    /// Return the Lua that `path` includes, which may have a tab suffix.
+    pub fn resolve(&mut self, path: &str) -> Result<String, IncludeError> {
+        if let Some(paths) = self.expand(path) {
+            return self.resolve_each(path, paths, Vec::new(), Self::resolve);
+        }
+        crate::trace::include(path, || {
+            let error = |error| IncludeError {
+                path: path.to_string(),
+                chain: Vec::new(),
+                error,
+            };
+            let (file, tab) = split_tab(path);
+            let key = self.once.then(|| self.seen_key(file));
+            // The tabs of the file included so far, all of them if it was
+            // included whole.
+            let seen: Vec<Option<usize>> = self
+                .seen
+                .iter()
+                .filter(|(k, _)| key.as_ref() == Some(k))
+                .map(|(_, tab)| *tab)
+                .collect();
+            let seen = |tab| seen.contains(&None) || seen.contains(&tab);
+            if seen(tab) {
+                return Ok(format!("-- {path} is included above"));
+            }
+            let lua = self.read(file).map_err(error)?;
+            let lua = match tab {
+                None if (0..tabs(&lua).len()).any(|tab| seen(Some(tab))) => tabs(&lua)
+                    .iter()
+                    .enumerate()
+                    .map(|(tab, code)| {
+                        if seen(Some(tab)) {
+                            format!("-- {file}:{tab} is included above\n")
+                        } else {
+                            code.to_string()
+                        }
+                    })
+                    .collect::<Vec<_>>()
+                    .join("-->8\n"),
+                None => lua,
+                Some(tab) => tabs(&lua)
+                    .get(tab)
+                    .map(|code| code.to_string())
+                    .ok_or_else(|| error(io::Error::new(io::ErrorKind::NotFound, "no such tab")))?,
+            };
+            self.included = self.included.saturating_add(lua.len());
+            let included = self.included;
+            if let Some(limits) = self.limits.filter(|l| included > l.max_include_bytes) {
+                return Err(error(over(LimitError::IncludeBytes {
+                    limit: limits.max_include_bytes,
+                })));
+            }
+            if let Some(key) = key {
+                self.seen.insert((key, tab));
+            }
+            Ok(if self.markers { mark_include(path, &lua) } else { lua })
+        })
+    }
+
+    /// Return what [once](FsResolver::once) knows the file `path` by: the
+    /// canonical path of the file it names, so that two paths to one file are
+    /// the same, or `path` itself if there is none.
+    fn seen_key(&self, path: &str) -> String {
+        self.find(path)
+            .and_then(|found| found.canonicalize().ok())
+            .map_or_else(|| path.to_string(), |found| found.display().to_string())
+    }
+
+    /// Return the Lua that `path` includes like [resolve](Self::resolve)
+    /// with the includes in it resolved too, however deep. An error names
+    /// the chain of includes that led to it, and a file that includes itself
+    /// is an error rather than endless.
+    pub fn resolve_nested(&mut self, path: &str) -> Result<String, IncludeError> {
+        self.resolve_chain(path, &mut Vec::new())
+    }
+
+    fn resolve_chain(
+        &mut self,
+        path: &str,
+        chain: &mut Vec<String>,
+    ) -> Result<String, IncludeError> {
+        if chain.iter().any(|p| p == path) {
+            return Err(IncludeError {
+                path: path.to_string(),
+                chain: chain.clone(),
+                error: io::Error::new(io::ErrorKind::InvalidData, "it includes itself"),
+            });
+        }
+        if let Some(limits) = self.limits.filter(|l| chain.len() >= l.max_include_depth) {
+            return Err(IncludeError {
+                path: path.to_string(),
+                chain: chain.clone(),
+                error: over(LimitError::IncludeDepth {
+                    limit: limits.max_include_depth,
+                }),
+            });
+        }
+        if let Some(paths) = self.expand(path) {
+            let via = chain.clone();
+            return self.resolve_each(path, paths, via, |resolver, inner| {
+                resolver.resolve_chain(inner, chain)
+            });
+        }
+        let lua = self.resolve(path).map_err(|e| IncludeError {
+            chain: chain.clone(),
+            ..e
+        })?;
+        chain.push(path.to_string());
+        let lua = crate::try_patch_includes(lua, |inner| self.resolve_chain(inner, chain));
+        chain.pop();
+        lua.map(Cow::into_owned)
+    }
+
+    /// Return the Lua of the file that `path` names.
+    fn read(&mut self, path: &str) -> io::Result<String> {
+        #[cfg(feature = "net")]
+        if is_remote(path) {
+            return self.download(path);
+        }
+        let file = self.find(path).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                "not found in the cart's directory or the search paths",
+            )
+        })?;
+        if let Some(lua) = self.cache.get(&file) {
+            return Ok(lua.clone());
+        }
+        let lua = included_lua(fs::read(&file)?);
+        self.cache.insert(file, lua.clone());
+        Ok(lua)
+    }
+
+    /// Download the Lua that the URL or BBS cart `path` names once, or read
+    /// it from [download_dir](Self::download_dir) if it was downloaded there
+    /// before.
+    #[cfg(feature = "net")]
+    fn download(&mut self, path: &str) -> io::Result<String> {
+        let key = PathBuf::from(path);
+        if let Some(lua) = self.cache.get(&key) {
+            return Ok(lua.clone());
+        }
+        let saved = self.download_dir.as_ref().map(|dir| {
+            let name: String = path
+                .chars()
+                .map(|c| if c.is_ascii_alphanumeric() || c == '.' { c } else { '_' })
+                .collect();
+            dir.join(name)
+        });
+        let bytes = match saved.as_ref().and_then(|saved| fs::read(saved).ok()) {
+            Some(bytes) => bytes,
+            None => {
+                let limit = self
+                    .limits
+                    .map_or(crate::bbs::MAX_INCLUDE_DOWNLOAD, |l| l.max_include_bytes as u64);
+                let bytes = crate::bbs::fetch_include(path, limit).map_err(io::Error::other)?;
+                if let Some(saved) = &saved {
+                    fs::create_dir_all(saved.parent().unwrap_or(Path::new("")))?;
+                    fs::write(saved, &bytes)?;
+                }
+                bytes
+            }
+        };
+        let lua = match crate::detect::detect(&bytes) {
+            Some(format @ (crate::detect::Format::P8Png | crate::detect::Format::Rom)) => {
+                crate::detect::lua_from_bytes(format, bytes).map_err(io::Error::other)?
+            }
+            _ => included_lua(bytes),
+        };
+        self.cache.insert(key, lua.clone());
+        Ok(lua)
+    }
+
+    /// Rewrite each "#include" statement of `lua` as a `require` of the
+    /// module `module` names for its path, or of one for each file a
+    /// wildcard or directory expands to, and read each file required,
+    /// however deep, as a [Module] with its own includes rewritten too. Each
+    /// file is read once, in the order they are first required.
+    ///
+    /// ```no_run
+    /// use pico8_to_lua::{FsResolver, module_name};
+    /// let mut resolver = FsResolver::for_cart("game/main.p8", ["lib"]);
+    /// let (lua, modules) = resolver.modules("#include util.lua", module_name).unwrap();
+    /// assert_eq!(lua, "require(\"util\")");
+    /// assert_eq!(modules[0].name, "util");
+    /// ```
+    pub fn modules<'h>(
+        &mut self,
+        lua: &'h str,
+        mut module: impl FnMut(&str) -> String,
+    ) -> Result<(Cow<'h, str>, Vec<Module>), IncludeError> {
+        let mut pending = Vec::new();
+        let main = self.requires(lua.into(), &mut module, &mut pending);
+        let mut modules = Vec::new();
+        while let Some(path) = pending.get(modules.len()).cloned() {
+            let lua = self.resolve(&path)?;
+            let lua = self.requires(lua.into(), &mut module, &mut pending);
+            modules.push(Module {
+                name: module(&path),
+                path,
+                lua: lua.into_owned(),
+            });
+        }
+        Ok((main, modules))
+    }
+
+    /// Rewrite the includes of `lua` as `require` calls, adding the paths
+    /// not yet in `pending` to it.
+    fn requires<'h>(
+        &self,
+        lua: Cow<'h, str>,
+        module: &mut impl FnMut(&str) -> String,
+        pending: &mut Vec<String>,
+    ) -> Cow<'h, str> {
+        replace_includes(lua, |path| {
+            let paths = self.expand(path).unwrap_or_else(|| vec![path.to_string()]);
+            let calls: Vec<String> = paths
+                .into_iter()
+                .map(|path| {
+                    let call = require_call(&module(&path));
+                    if !pending.contains(&path) {
+                        pending.push(path);
+                    }
+                    call
+                })
+                .collect();
+            calls.join("\n")
+        })
+    }
+
+    /// Return a resolver for [patch_includes](crate::patch_includes) that
+    /// replaces an include it can't read with a Lua `error()` call.
+    pub fn as_fn(&mut self) -> impl FnMut(&str) -> String + '_ {
+        move |path| {
+            self.resolve(path)
+                .unwrap_or_else(|e| format!("error({:?})", e.to_string()))
+        }
+    }
+}
+
+/// An "#include" statement.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Include<'a> {
+    /// The path without quotes or tab suffix.
+    pub path: &'a str,
+    /// The tab of the included cart, e.g. 2 for `#include lib.p8:2`, or
+    /// `None` for all of it. See [split_tab].
+    pub tab: Option<usize>,
+    /// Whether the path was quoted, e.g. `#include "my lib.lua"`.
+    pub quoted: bool,
+    /// The span of the whole statement, including any trailing comment.
+    pub span: Span,
+    /// The one-based line of the statement.
+    pub line: usize,
+}
+
+/// Return each "#include" statement of `lua`, skipping any inside a block
+/// comment or long string.
+///
+/// ```
+/// use pico8_to_lua::parse_includes;
+/// let include = parse_includes("x = 1\n#include lib.p8:2\n").next().unwrap();
+/// assert_eq!((include.path, include.tab, include.line), ("lib.p8", Some(2), 2));
+/// assert_eq!(include.span.start, 6);
+/// ```
+pub fn parse_includes(lua: &str) -> impl Iterator<Item = Include<'_>> {
+    let (mut counted, mut line) = (0, 1);
+    include_matches(lua).map(move |caps| {
+        let whole = caps.get(0).unwrap();
+        line += lua[counted..whole.start()].matches('\n').count();
+        counted = whole.start();
+        let (path, tab) = split_tab(include_path(&caps));
+        Include {
+            path,
+            tab,
+            quoted: caps.get(3).is_none(),
+            span: Span::new(whole.start(), whole.end()),
+            line,
+        }
+    })
+}
+
+/// The region of flattened output that was produced by one "#include"
+/// statement.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IncludeRegion {
+    /// The path as written in the "#include path.p8" statement.
+    pub path: String,
+    /// The span of the output occupied by the included contents.
+    pub span: Span,
+}
+
+/// Lua with its "#include" statements resolved, along with the regions each
+/// include occupies.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Flattened {
+    /// The flattened Lua.
+    pub lua: String,
+    /// The include regions in the order they appear in `lua`.
+    pub regions: Vec<IncludeRegion>,
+}
+
+impl Flattened {
+    /// Resolve the Pico-8 "#include path.p8" statements, recording where each
+    /// one was placed.
+    ///
+    /// The output `lua` is identical to what
+    /// [patch_includes](crate::patch_includes) would produce with the same
+    /// resolver.
+    pub fn new(lua: &str, mut resolve: impl FnMut(&str) -> String) -> Self {
+        let mut out = String::with_capacity(lua.len());
+        let mut regions = Vec::new();
+        let mut last = 0;
+        for caps in include_matches(lua) {
+            let whole = caps.get(0).unwrap();
+            out.push_str(&lua[last..whole.start()]);
+            let path = include_path(&caps);
+            let contents = resolve(path);
+            let start = out.len();
+            out.push_str(&contents);
+            regions.push(IncludeRegion {
+                path: path.to_string(),
+                span: Span::new(start, out.len()),
+            });
+            last = whole.end();
+        }
+        out.push_str(&lua[last..]);
+        Flattened { lua: out, regions }
+    }
+
+    /// Return the regions that came from `path`.
+    pub fn regions_for<'a>(&'a self, path: &'a str) -> impl Iterator<Item = &'a IncludeRegion> {
+        self.regions.iter().filter(move |r| r.path == path)
+    }
+
+    /// Replace the contents of every region included from `path` with
+    /// `contents`, shifting the regions that follow.
+    ///
+    /// Returns the edits that were applied, with spans relative to the output
+    /// before the splice, so an editor can apply them to its own buffer. If
+    /// `path` was never included, nothing is changed, no edits are returned,
+    /// and the caller must re-flatten from scratch.
+    pub fn splice_include(&mut self, path: &str, contents: &str) -> Vec<TextEdit> {
+        let mut edits = Vec::new();
+        let mut shift: isize = 0;
+        for region in &mut self.regions {
+            let span = region.span.shift(shift);
+            if region.path == path {
+                self.lua.replace_range(span.range(), contents);
+                let edit = TextEdit::new(region.span, contents);
+                shift += edit.delta();
+                edits.push(edit);
+                region.span = Span::new(span.start, span.start + contents.len());
+            } else {
+                region.span = span;
+            }
+        }
+        edits
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{patch_includes, span::apply_edits};
+
+    fn resolver(path: &str) -> String {
+        format!("-- BEGIN {path}\nx = 1\n-- END {path}")
+    }
+
+    #[test]
+    fn test_matches_patch_includes() {
+        let lua = "a = 1\n#include a.p8\nb = 2\n#include b.p8\n";
+        let flat = Flattened::new(lua, resolver);
+        assert_eq!(flat.lua, patch_includes(lua, resolver));
+        assert_eq!(flat.regions.len(), 2);
+        for region in &flat.regions {
+            assert_eq!(&flat.lua[region.span.range()], resolver(&region.path));
+        }
+    }
+
+    #[test]
+    fn test_splice_include() {
+        let lua = "a = 1\n#include a.p8\nb = 2\n#include b.p8\n#include a.p8\n";
+        let mut flat = Flattened::new(lua, resolver);
+        let before = flat.lua.clone();
+        let edits = flat.splice_include("a.p8", "y = 2");
+        assert_eq!(edits.len(), 2);
+        assert_eq!(apply_edits(&before, &edits), flat.lua);
+        let expected = Flattened::new(lua, |path| {
+            if path == "a.p8" { "y = 2".into() } else { resolver(path) }
+        });
+        assert_eq!(flat, expected);
+    }
+
+    #[test]
+    fn test_parse_includes() {
+        let includes: Vec<_> = parse_includes("#include \"a b.p8\" -- lib\n#include c.lua").collect();
+        assert_eq!(includes[0].path, "a b.p8");
+        assert!(includes[0].quoted);
+        assert_eq!(includes[0].span, Span::new(0, 24));
+        assert_eq!(includes[1].path, "c.lua");
+        assert!(!includes[1].quoted);
+        let include = parse_includes("#include lib.p8:2").next().unwrap();
+        assert_eq!((include.path, include.tab), ("lib.p8", Some(2)));
+        let lines: Vec<_> = parse_includes("--[[\n#include a.p8\n]]\n\n#include b.p8\n#include c.p8")
+            .map(|i| i.line)
+            .collect();
+        assert_eq!(lines, vec![5, 6]);
+    }
+
+    #[test]
+    fn test_splice_missing_include() {
+        let mut flat = Flattened::new("#include a.p8", resolver);
+        let before = flat.clone();
+        assert!(flat.splice_include("c.p8", "y = 2").is_empty());
+        assert_eq!(flat, before);
+    }
+
+    #[test]
+    fn test_data_include() {
+        let lua = "#include lib.lua\n#include levels.txt\nx = 1 // c\n";
+        let flat = crate::patch_includes_with(lua, |path| {
+            if path.ends_with(".txt") {
+                Included::Data {
+                    name: "levels".into(),
+                    text: "\n-- != //".into(),
+                }
+            } else {
+                Included::Code("y += 1".into())
+            }
+        });
+        assert_eq!(flat, "y += 1\nlevels = [[\n\n-- != //]]\nx = 1 // c\n");
+        assert_eq!(
+            crate::patch_lua(flat),
+            "y = y + (1)\nlevels = [[\n\n-- != //]]\nx = 1 -- c\n"
+        );
+    }
+
+    #[test]
+    fn test_skip_block_comments() {
+        let lua = "--[[\n#include a.p8\n]]\n#include b.p8\n";
+        let paths: Vec<_> = parse_includes(lua).map(|i| i.path).collect();
+        assert_eq!(paths, vec!["b.p8"]);
+    }
+
+    #[test]
+    fn test_fs_resolver() {
+        let root =
+            std::env::temp_dir().join(format!("pico8-to-lua-includes-{}", std::process::id()));
+        let (cart, lib) = (root.join("cart"), root.join("lib"));
+        fs::create_dir_all(&cart).unwrap();
+        fs::create_dir_all(&lib).unwrap();
+        fs::write(cart.join("a.lua"), "a = 1").unwrap();
+        fs::write(lib.join("a.lua"), "a = 2").unwrap();
+        fs::write(lib.join("b.lua"), b"s = \"\x87\"").unwrap();
+        fs::write(
+            lib.join("c.p8"),
+            "pico-8 cartridge\n__lua__\nc = 3\n-->8\nd = 4\n__gfx__\n00\n",
+        )
+        .unwrap();
+        let mut resolver = FsResolver::for_cart(cart.join("main.p8"), [&lib]);
+        let lua = crate::patch_includes(
+            "#include a.lua\n#include b.lua\n#include c.p8:1\n#include d.lua",
+            resolver.as_fn(),
+        );
+        assert_eq!(
+            lua,
+            "a = 1\ns = \"♥\"\nd = 4\n\nerror(\"failed to include \\\"d.lua\\\": \
+             not found in the cart's directory or the search paths\")"
+        );
+        // Files are read once.
+        fs::write(lib.join("b.lua"), "b = 2").unwrap();
+        assert_eq!(resolver.resolve("b.lua").unwrap(), "s = \"♥\"");
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[cfg(feature = "net")]
+    #[test]
+    fn test_download_dir() {
+        let root =
+            std::env::temp_dir().join(format!("pico8-to-lua-downloads-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        let url = "https://example.com/lib.lua?v=2";
+        fs::write(root.join("https___example.com_lib.lua_v_2"), "x += 1").unwrap();
+        let mut resolver = FsResolver::new(Vec::<PathBuf>::new());
+        resolver.download_dir = Some(root.clone());
+        assert_eq!(resolver.expand(url), None);
+        assert_eq!(resolver.resolve(url).unwrap(), "x += 1");
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_resolve_nested() {
+        let root =
+            std::env::temp_dir().join(format!("pico8-to-lua-nested-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("a.lua"), "a = 1\n#include b.lua").unwrap();
+        fs::write(root.join("b.lua"), "b = 2\n#include c.lua").unwrap();
+        fs::write(root.join("c.lua"), "#include a.lua").unwrap();
+        let mut resolver = FsResolver::new([&root]);
+        let error = resolver.resolve_nested("a.lua").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "failed to include \"a.lua\" via \"a.lua\" > \"b.lua\" > \"c.lua\": \
+             it includes itself"
+        );
+        fs::write(root.join("c.lua"), "c = 3").unwrap();
+        let mut resolver = FsResolver::new([&root]);
+        fs::create_dir_all(root.join("lib")).unwrap();
+        fs::write(root.join("lib/y.lua"), "y = 2").unwrap();
+        fs::write(root.join("lib/x.lua"), "x = 1\n#include c.lua").unwrap();
+        fs::write(root.join("lib/z.txt"), "z = 3").unwrap();
+        assert_eq!(
+            resolver.expand("lib"),
+            Some(vec!["lib/x.lua".into(), "lib/y.lua".into()])
+        );
+        assert_eq!(resolver.expand("lib/?.txt"), Some(vec!["lib/z.txt".into()]));
+        assert_eq!(resolver.resolve_nested("lib/*.lua").unwrap(), "x = 1\nc = 3\ny = 2");
+        assert!(resolver.resolve("lib/*.p8").is_err());
+        assert_eq!(resolver.resolve_nested("a.lua").unwrap(), "a = 1\nb = 2\nc = 3");
+        resolver.markers = true;
+        assert_eq!(
+            resolver.resolve_nested("b.lua").unwrap(),
+            "-- >>> include b.lua\nb = 2\n-- >>> include c.lua\nc = 3\n-- <<< include c.lua\n\
+             -- <<< include b.lua"
+        );
+        resolver.markers = false;
+        resolver.once = true;
+        fs::write(root.join("d.lua"), "#include c.lua\n#include ./c.lua\n#include b.lua").unwrap();
+        assert_eq!(
+            resolver.resolve_nested("d.lua").unwrap(),
+            "c = 3\n-- ./c.lua is included above\nb = 2\n-- c.lua is included above"
+        );
+        resolver.once = false;
+        resolver.limits = Some(Limits {
+            max_include_depth: 2,
+            ..Limits::default()
+        });
+        let error = resolver.resolve_nested("a.lua").unwrap_err();
+        assert!(error.to_string().ends_with("includes nest deeper than the limit of 2"));
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_include_once() {
+        let root = std::env::temp_dir().join(format!("pico8-to-lua-once-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("c.p8"), "pico-8 cartridge\n__lua__\nc = 3\n-->8\nd = 4\n").unwrap();
+        let mut resolver = FsResolver::new([&root]);
+        resolver.once = true;
+        assert_eq!(resolver.resolve("c.p8").unwrap(), "c = 3\n-->8\nd = 4\n");
+        assert_eq!(resolver.resolve("c.p8:1").unwrap(), "-- c.p8:1 is included above");
+        let mut resolver = FsResolver::new([&root]);
+        resolver.once = true;
+        assert_eq!(resolver.resolve("c.p8:1").unwrap(), "d = 4\n");
+        assert_eq!(
+            resolver.resolve("c.p8").unwrap(),
+            "c = 3\n-->8\n-- c.p8:1 is included above\n"
+        );
+        // An include that fails is tried again.
+        fs::write(root.join("e.lua"), "e = 5").unwrap();
+        resolver.limits = Some(Limits {
+            max_include_bytes: 1,
+            ..Limits::default()
+        });
+        assert!(resolver.resolve("e.lua").is_err());
+        resolver.limits = None;
+        assert_eq!(resolver.resolve("e.lua").unwrap(), "e = 5");
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_modules() {
+        let root =
+            std::env::temp_dir().join(format!("pico8-to-lua-modules-{}", std::process::id()));
+        fs::create_dir_all(root.join("lib")).unwrap();
+        fs::write(root.join("lib/a.lua"), "a = 1\n#include lib/b.lua").unwrap();
+        fs::write(root.join("lib/b.lua"), "#include lib/a.lua\nb = 2").unwrap();
+        let mut resolver = FsResolver::new([&root]);
+        let (lua, modules) = resolver
+            .modules("#include lib/*.lua\nx = 1", module_name)
+            .unwrap();
+        assert_eq!(lua, "require(\"lib.a\")\nrequire(\"lib.b\")\nx = 1");
+        let found: Vec<_> = modules.iter().map(|m| (m.file(), m.lua.as_str())).collect();
+        assert_eq!(
+            found,
+            [
+                (PathBuf::from("lib/a.lua"), "a = 1\nrequire(\"lib.b\")"),
+                (PathBuf::from("lib/b.lua"), "require(\"lib.a\")\nb = 2"),
+            ]
+        );
+        fs::remove_dir_all(root).unwrap();
+    }
+}
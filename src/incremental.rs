@@ -0,0 +1,279 @@
+//! Converting a buffer again as it is edited.
+//!
+//! An editor with a live preview would otherwise run
+//! [patch_lua_with](crate::patch_lua_with) on the whole buffer after every
+//! keystroke. An [IncrementalPatcher] keeps the conversion of each chunk of
+//! lines, where a chunk ends only between statements and outside of brackets,
+//! strings, and comments, so converting the chunks one at a time gives the
+//! same Lua as converting the whole. [IncrementalPatcher::edit] converts only
+//! the chunks an edit touches and returns the edit to make to the output.
+//!
+//! Only the Pico-8 syntax rewrites run; the passes of a
+//! [Patcher](crate::pipeline::Patcher) that look at the whole program, like
+//! naming the glyphs, do not.
+use crate::{
+    PatchOptions, is_converted,
+    lexer::{Token, TokenKind, tokenize},
+    patch_lua_with,
+    span::{Span, TextEdit},
+};
+
+/// A chunk of lines and its conversion.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Chunk {
+    /// The length of its source in bytes.
+    len: usize,
+    /// The converted Lua.
+    output: String,
+}
+
+/// Converts Pico-8 Lua and converts it again as it is edited.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IncrementalPatcher {
+    options: PatchOptions,
+    source: String,
+    chunks: Vec<Chunk>,
+}
+
+impl IncrementalPatcher {
+    /// Convert `lua`, the Lua of a cart, with `options`.
+    pub fn new(lua: impl Into<String>, options: PatchOptions) -> Self {
+        let mut patcher = IncrementalPatcher {
+            options,
+            source: lua.into(),
+            chunks: Vec::new(),
+        };
+        patcher.chunks = patcher.convert(&patcher.source, 0, |_| false);
+        patcher
+    }
+
+    /// The Lua as it is after the edits so far.
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// The converted Lua.
+    pub fn output(&self) -> String {
+        self.chunks.iter().map(|c| c.output.as_str()).collect()
+    }
+
+    /// Apply `edit` to the Lua and return the edits that bring the converted
+    /// Lua up to date, whose spans refer to the output before them.
+    ///
+    /// ```
+    /// use pico8_to_lua::{PatchOptions, incremental::IncrementalPatcher, span::TextEdit};
+    /// let mut patcher = IncrementalPatcher::new("x = 1\ny = 2\n", PatchOptions::default());
+    /// let edits = patcher.edit(&TextEdit::new(8..11, "+= 3"));
+    /// assert_eq!(edits, [TextEdit::new(10..11, "y + (3)")]);
+    /// assert_eq!(patcher.output(), "x = 1\ny = y + (3)\n");
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// If the span of `edit` is not within the Lua or not on character
+    /// boundaries.
+    pub fn edit(&mut self, edit: &TextEdit) -> Vec<TextEdit> {
+        let Span { start, end } = edit.span;
+        assert!(
+            self.source.is_char_boundary(start) && self.source.is_char_boundary(end),
+            "edit {start}..{end} is not within the source"
+        );
+        // Start a chunk early so that a line joined to the one before it is
+        // converted with it.
+        let first = self.chunk_at(start).saturating_sub(1);
+        let from: usize = self.chunks[..first].iter().map(|c| c.len).sum();
+        let old_ends: Vec<usize> = self.chunks[first..]
+            .iter()
+            .scan(from, |offset, chunk| {
+                *offset += chunk.len;
+                Some(*offset)
+            })
+            .collect();
+        let was_converted = is_converted(&self.source);
+        self.source.replace_range(start..end, &edit.replacement);
+        if is_converted(&self.source) != was_converted {
+            let old_output = self.output();
+            self.chunks = self.convert(&self.source, 0, |_| false);
+            return TextEdit::diff(&old_output, &self.output())
+                .into_iter()
+                .collect();
+        }
+        let edited = start + edit.replacement.len();
+        // Convert until a chunk ends where one ended before, past the edit,
+        // since the chunks after it are the same.
+        let mut resync = old_ends
+            .iter()
+            .filter(|old| **old >= end)
+            .map(|old| old - end + edited)
+            .peekable();
+        let chunks = self.convert(&self.source, from, |new_end| {
+            while resync.next_if(|old| *old < new_end).is_some() {}
+            resync.peek() == Some(&new_end)
+        });
+        let old_stop = from + chunks.iter().map(|c| c.len).sum::<usize>() - edited + end;
+        let replaced = first
+            + old_ends
+                .iter()
+                .position(|old| *old == old_stop)
+                .map_or(old_ends.len(), |i| i + 1);
+        let out_start: usize = self.chunks[..first].iter().map(|c| c.output.len()).sum();
+        let old_output: String = self.chunks[first..replaced]
+            .iter()
+            .map(|c| c.output.as_str())
+            .collect();
+        let new_output: String = chunks.iter().map(|c| c.output.as_str()).collect();
+        self.chunks.splice(first..replaced, chunks);
+        TextEdit::diff(&old_output, &new_output)
+            .map(|e| {
+                let span = e.span.start + out_start..e.span.end + out_start;
+                TextEdit::new(span, e.replacement)
+            })
+            .into_iter()
+            .collect()
+    }
+
+    /// Return the index of the chunk that holds byte `offset` of the source,
+    /// or the last chunk if it is the end.
+    fn chunk_at(&self, offset: usize) -> usize {
+        let mut end = 0;
+        for (i, chunk) in self.chunks.iter().enumerate() {
+            end += chunk.len;
+            if offset < end {
+                return i;
+            }
+        }
+        self.chunks.len().saturating_sub(1)
+    }
+
+    /// Split `source` from `from` into chunks and convert them, unless
+    /// `source` [is_converted], stopping after a chunk that ends where `done`
+    /// says.
+    fn convert(
+        &self,
+        source: &str,
+        from: usize,
+        mut done: impl FnMut(usize) -> bool,
+    ) -> Vec<Chunk> {
+        let converted = is_converted(source);
+        let mut chunks = Vec::new();
+        let mut start = from;
+        for end in chunk_ends(source, from) {
+            let lua = &source[start..end];
+            chunks.push(Chunk {
+                len: end - start,
+                output: if converted {
+                    lua.to_string()
+                } else {
+                    patch_lua_with(lua, &self.options).into_owned()
+                },
+            });
+            start = end;
+            if done(end) {
+                break;
+            }
+        }
+        chunks
+    }
+}
+
+/// Return the offsets in `source` from `from`, which starts a chunk, where
+/// chunks end, the last being the end of `source`.
+pub(crate) fn chunk_ends(source: &str, from: usize) -> impl Iterator<Item = usize> + '_ {
+    let mut tokens = tokenize(&source[from..]);
+    let mut depth = 0usize;
+    let mut last: Option<Token> = None;
+    let mut prev_end = 0;
+    let mut done = false;
+    std::iter::from_fn(move || {
+        if done {
+            return None;
+        }
+        for token in tokens.by_ref() {
+            let gap = prev_end..token.span.start;
+            prev_end = token.span.end;
+            let between = depth == 0 && last.as_ref().is_some_and(ends_statement);
+            let boundary = source[from..][gap.clone()]
+                .find('\n')
+                .filter(|_| between && starts_statement(&token))
+                .map(|i| from + gap.start + i + 1);
+            if token.kind != TokenKind::Comment {
+                match token.text {
+                    "(" | "[" | "{" if token.kind == TokenKind::Punct => depth += 1,
+                    ")" | "]" | "}" if token.kind == TokenKind::Punct => {
+                        depth = depth.saturating_sub(1)
+                    }
+                    _ => {}
+                }
+                last = Some(token);
+            }
+            if boundary.is_some() {
+                return boundary;
+            }
+        }
+        done = true;
+        Some(source.len()).filter(|end| *end > from)
+    })
+}
+
+/// Return whether a statement may end with `token`.
+fn ends_statement(token: &Token) -> bool {
+    token.ends_value()
+        || ["do", "then", "else", "repeat", "break", ";"]
+            .iter()
+            .any(|s| token.is(s))
+}
+
+/// Return whether `token` starts a statement and cannot continue the one
+/// before it.
+fn starts_statement(token: &Token) -> bool {
+    match token.kind {
+        TokenKind::Name => true,
+        TokenKind::Keyword => matches!(
+            token.text,
+            "local"
+                | "function"
+                | "if"
+                | "for"
+                | "while"
+                | "return"
+                | "end"
+                | "repeat"
+                | "until"
+                | "break"
+                | "goto"
+                | "do"
+                | "else"
+                | "elseif"
+        ),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::span::apply_edits;
+
+    #[test]
+    fn test_edits() {
+        let lua = "x += 1\nif (a != b) c()\n--[[ long\ncomment ]]\ny = {\n  1,\n}\n";
+        let mut patcher = IncrementalPatcher::new(lua, PatchOptions::default());
+        let mut output = patcher.output();
+        for (old, new) in [
+            ("x += 1", "x -= 1"),
+            ("if (a != b)", "--[[ if (a != b)"),
+            ("--[[ if", "if"),
+            ("y = {\n", "y = 1\nw = {\n"),
+            ("}\n", "}\nq *= 2"),
+            ("1\nw", "1 +\nw"),
+        ] {
+            let start = patcher.source().find(old).unwrap();
+            let edits = patcher.edit(&TextEdit::new(start..start + old.len(), new));
+            output = apply_edits(&output, &edits);
+            let full = patch_lua_with(patcher.source(), &PatchOptions::default());
+            assert_eq!(output, full);
+            assert_eq!(patcher.output(), full);
+        }
+        assert!(patcher.chunks.len() > 2);
+    }
+}
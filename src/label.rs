@@ -0,0 +1,258 @@
+//! The cart label, a 128x128 screenshot stored in the `__label__` section.
+//!
+//! Each pixel is one character: "0" to "f" for the sixteen standard colors and
+//! "g" to "v" for the secret palette, which Pico-8 numbers 128 to 143.
+//! [quantize_to_p8] turns a screenshot into a label and [render_ansi] previews
+//! one in a terminal.
+use crate::cart::{LineError, check_len};
+
+/// The width and height of the label in pixels.
+pub const LABEL_SIZE: usize = 128;
+
+/// The RGB values of colors 0 to 15.
+pub const PALETTE: [[u8; 3]; 16] = [
+    [0x00, 0x00, 0x00],
+    [0x1d, 0x2b, 0x53],
+    [0x7e, 0x25, 0x53],
+    [0x00, 0x87, 0x51],
+    [0xab, 0x52, 0x36],
+    [0x5f, 0x57, 0x4f],
+    [0xc2, 0xc3, 0xc7],
+    [0xff, 0xf1, 0xe8],
+    [0xff, 0x00, 0x4d],
+    [0xff, 0xa3, 0x00],
+    [0xff, 0xec, 0x27],
+    [0x00, 0xe4, 0x36],
+    [0x29, 0xad, 0xff],
+    [0x83, 0x76, 0x9c],
+    [0xff, 0x77, 0xa8],
+    [0xff, 0xcc, 0xaa],
+];
+
+/// The RGB values of the secret colors 128 to 143.
+pub const SECRET_PALETTE: [[u8; 3]; 16] = [
+    [0x29, 0x18, 0x14],
+    [0x11, 0x1d, 0x35],
+    [0x42, 0x21, 0x36],
+    [0x12, 0x53, 0x59],
+    [0x74, 0x2f, 0x29],
+    [0x49, 0x33, 0x3b],
+    [0xa2, 0x88, 0x79],
+    [0xf3, 0xef, 0x7d],
+    [0xbe, 0x12, 0x50],
+    [0xff, 0x6c, 0x24],
+    [0xa8, 0xe7, 0x2e],
+    [0x00, 0xb5, 0x43],
+    [0x06, 0x5a, 0xb5],
+    [0x75, 0x46, 0x65],
+    [0xff, 0x6e, 0x59],
+    [0xff, 0x9d, 0x81],
+];
+
+/// Return the RGB value of a Pico-8 color, 0 to 15 or 128 to 143. Other values
+/// wrap like they do in Pico-8's palette functions.
+pub fn rgb(color: u8) -> [u8; 3] {
+    if color & 0x80 != 0 {
+        SECRET_PALETTE[(color & 0xf) as usize]
+    } else {
+        PALETTE[(color & 0xf) as usize]
+    }
+}
+
+/// A decoded cart label.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Label {
+    /// The colors of each pixel in row-major order.
+    pub pixels: Vec<u8>,
+}
+
+impl Default for Label {
+    fn default() -> Self {
+        Label {
+            pixels: vec![0; LABEL_SIZE * LABEL_SIZE],
+        }
+    }
+}
+
+fn decode_pixel(c: u8) -> Result<u8, LineError> {
+    match c {
+        b'0'..=b'9' => Ok(c - b'0'),
+        b'a'..=b'f' => Ok(c - b'a' + 10),
+        b'g'..=b'v' => Ok(c - b'g' + 128),
+        _ => Err(LineError::InvalidHex),
+    }
+}
+
+fn encode_pixel(c: u8) -> char {
+    let c = if c & 0x80 != 0 {
+        (c & 0xf) + 16
+    } else {
+        c & 0xf
+    };
+    char::from_digit(c as u32, 32).expect("a digit below 32")
+}
+
+impl Label {
+    pub(crate) fn parse_line(&mut self, y: usize, line: &str) -> Result<(), LineError> {
+        check_len(line, LABEL_SIZE)?;
+        for (x, c) in line.bytes().enumerate() {
+            self.pixels[y * LABEL_SIZE + x] = decode_pixel(c)?;
+        }
+        Ok(())
+    }
+
+    /// Return the color at `(x, y)`.
+    pub fn get(&self, x: usize, y: usize) -> u8 {
+        self.pixels[y * LABEL_SIZE + x]
+    }
+
+    /// Return the pixels as 8-bit RGB triples in row-major order.
+    pub fn to_rgb(&self) -> Vec<u8> {
+        self.pixels.iter().flat_map(|&c| rgb(c)).collect()
+    }
+
+    /// Return the label as the lines of a `__label__` section.
+    pub fn to_text(&self) -> String {
+        let mut text = String::with_capacity((LABEL_SIZE + 1) * LABEL_SIZE);
+        for row in self.pixels.chunks(LABEL_SIZE) {
+            text.extend(row.iter().map(|&c| encode_pixel(c)));
+            text.push('\n');
+        }
+        text
+    }
+
+    /// Write the label as a 128x128 RGB PNG.
+    #[cfg(feature = "png")]
+    pub fn write_png(&self, w: impl std::io::Write) -> Result<(), png::EncodingError> {
+        let mut encoder = png::Encoder::new(w, LABEL_SIZE as u32, LABEL_SIZE as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.to_rgb())?;
+        writer.finish()
+    }
+}
+
+/// Return the standard color, 0 to 15, nearest to an RGB value.
+fn nearest_color(rgb: &[u8]) -> u8 {
+    let distance = |color: &[u8; 3]| -> u32 {
+        color
+            .iter()
+            .zip(rgb)
+            .map(|(&a, &b)| (a as i32 - b as i32).pow(2) as u32)
+            .sum()
+    };
+    (0..PALETTE.len())
+        .min_by_key(|&i| distance(&PALETTE[i]))
+        .expect("a color") as u8
+}
+
+/// Scale a screenshot of 8-bit RGB triples in row-major order to 128x128 and
+/// map each pixel to the nearest standard color.
+///
+/// ```
+/// use pico8_to_lua::label::quantize_to_p8;
+/// let label = quantize_to_p8(&[0xff, 0x00, 0x40], 1, 1);
+/// assert_eq!(label.get(127, 127), 8);
+/// ```
+///
+/// # Panics
+///
+/// Panics if `rgb` has fewer than `width * height` pixels or the screenshot is
+/// empty.
+pub fn quantize_to_p8(rgb: &[u8], width: usize, height: usize) -> Label {
+    assert!(width > 0 && height > 0, "empty screenshot");
+    assert!(rgb.len() >= width * height * 3, "too few pixels");
+    let mut label = Label::default();
+    for y in 0..LABEL_SIZE {
+        for x in 0..LABEL_SIZE {
+            let (sx, sy) = (x * width / LABEL_SIZE, y * height / LABEL_SIZE);
+            let at = (sy * width + sx) * 3;
+            label.pixels[y * LABEL_SIZE + x] = nearest_color(&rgb[at..at + 3]);
+        }
+    }
+    label
+}
+
+/// Render the label for a terminal with 24-bit color, two pixels per
+/// character with the upper half block "▀", in 64 lines of 128 characters.
+pub fn render_ansi(label: &Label) -> String {
+    let mut out = String::new();
+    for y in (0..LABEL_SIZE).step_by(2) {
+        for x in 0..LABEL_SIZE {
+            let [r, g, b] = rgb(label.get(x, y));
+            let [br, bg, bb] = rgb(label.get(x, y + 1));
+            out.push_str(&format!(
+                "\x1b[38;2;{r};{g};{b}m\x1b[48;2;{br};{bg};{bb}m\u{2580}"
+            ));
+        }
+        out.push_str("\x1b[0m\n");
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Cart;
+
+    #[test]
+    fn test_label() {
+        let mut text = String::from("pico-8 cartridge\n__label__\n");
+        text.push_str(&format!("07gv{}\n", "0".repeat(124)));
+        for _ in 1..LABEL_SIZE {
+            text.push_str(&"1".repeat(LABEL_SIZE));
+            text.push('\n');
+        }
+        let label = Cart::parse(&text).unwrap().label().unwrap().unwrap();
+        assert_eq!(label.get(1, 0), 7);
+        assert_eq!(label.get(2, 0), 128);
+        assert_eq!(label.get(3, 0), 143);
+        assert_eq!(label.get(127, 127), 1);
+        assert_eq!(&label.to_rgb()[3..6], &[0xff, 0xf1, 0xe8]);
+    }
+
+    #[test]
+    fn test_no_label() {
+        let cart = Cart::parse("pico-8 cartridge\n__lua__\n").unwrap();
+        assert_eq!(cart.label(), Ok(None));
+    }
+
+    #[test]
+    fn test_quantize() {
+        // Two columns, near white and near dark blue.
+        let label = quantize_to_p8(&[0xf0, 0xf0, 0xf0, 0x20, 0x30, 0x50], 2, 1);
+        assert_eq!(label.get(0, 0), 7);
+        assert_eq!(label.get(63, 127), 7);
+        assert_eq!(label.get(64, 0), 1);
+        let text = label.to_text();
+        assert_eq!(text.lines().count(), LABEL_SIZE);
+        assert!(text.starts_with(&format!("{}{}\n", "7".repeat(64), "1".repeat(64))));
+    }
+
+    #[test]
+    fn test_to_text_round_trip() {
+        let mut label = Label::default();
+        label.pixels[0] = 143;
+        label.pixels[1] = 10;
+        let text = format!("pico-8 cartridge\n__label__\n{}", label.to_text());
+        assert!(text.contains("\n__label__\nva0"));
+        assert_eq!(Cart::parse(&text).unwrap().label(), Ok(Some(label)));
+    }
+
+    #[test]
+    fn test_render_ansi() {
+        let ansi = render_ansi(&Label::default());
+        assert_eq!(ansi.lines().count(), LABEL_SIZE / 2);
+        assert!(ansi.starts_with("\x1b[38;2;0;0;0m\x1b[48;2;0;0;0m\u{2580}"));
+        assert!(ansi.ends_with("\x1b[0m\n"));
+    }
+
+    #[cfg(feature = "png")]
+    #[test]
+    fn test_write_png() {
+        let mut png = Vec::new();
+        Label::default().write_png(&mut png).unwrap();
+        assert_eq!(&png[1..4], b"PNG");
+    }
+}
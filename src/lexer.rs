@@ -0,0 +1,313 @@
+//! A lexer for Pico-8's dialect of Lua.
+//!
+//! The lexer never fails. Anything it does not recognize becomes a
+//! [TokenKind::Unknown] token, and unterminated strings and comments run to the
+//! end of their line or the input. Whitespace is skipped but comments are
+//! kept so callers can decide what to do with them.
+//!
+//! [tokenize] reads `//` as a comment, as Pico-8 does. [tokenize_lua] reads
+//! plain Lua, like converted code, in which `//` is integer division.
+use crate::span::Span;
+
+/// What kind of token a [Token] is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TokenKind {
+    /// An identifier, including Pico-8's glyph identifiers like `⬅️`.
+    Name,
+    /// A reserved word like `if` or `end`.
+    Keyword,
+    /// A numeric literal in decimal, hex, or binary.
+    Number,
+    /// A quoted or long bracket string literal.
+    String,
+    /// A `--` or `//` comment, short or long.
+    Comment,
+    /// An operator or punctuation.
+    Punct,
+    /// A character that starts no valid token.
+    Unknown,
+}
+
+/// A token and where it was found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Token<'a> {
+    /// The kind of token.
+    pub kind: TokenKind,
+    /// The source text of the token.
+    pub text: &'a str,
+    /// Where the token is in the source.
+    pub span: Span,
+}
+
+impl Token<'_> {
+    /// Return true if this token is the punctuation or keyword `s`.
+    pub fn is(&self, s: &str) -> bool {
+        matches!(self.kind, TokenKind::Punct | TokenKind::Keyword) && self.text == s
+    }
+
+    /// Return true if this token can end a value, so an operator after it is
+    /// binary rather than unary.
+    pub fn ends_value(&self) -> bool {
+        match self.kind {
+            TokenKind::Name | TokenKind::Number | TokenKind::String => true,
+            TokenKind::Keyword => matches!(self.text, "end" | "nil" | "true" | "false"),
+            TokenKind::Punct => matches!(self.text, ")" | "]" | "}" | "..."),
+            TokenKind::Comment | TokenKind::Unknown => false,
+        }
+    }
+}
+
+/// The reserved words of Lua.
+pub const KEYWORDS: &[&str] = &[
+    "and", "break", "do", "else", "elseif", "end", "false", "for", "function", "goto", "if", "in",
+    "local", "nil", "not", "or", "repeat", "return", "then", "true", "until", "while",
+];
+
+// Longest first so that the first match is the longest.
+const PUNCTS: &[&str] = &[
+    ">>>=", "<<>=", ">><=", "...", "..=", ">>>", "<<>", ">><", "^^=", ">>=", "<<=", "..", "==",
+    "~=", "!=", "<=", ">=", "<<", ">>", "//", "+=", "-=", "*=", "/=", "\\=", "%=", "^=", "|=", "&=",
+    "^^", "::", "+", "-", "*", "/", "\\", "%", "^", "#", "&", "|", "~", "<", ">", "=", "(", ")",
+    "{", "}", "[", "]", ";", ":", ",", ".", "@", "$", "?",
+];
+
+/// An iterator over the tokens of a string.
+#[derive(Debug, Clone)]
+pub struct Lexer<'a> {
+    src: &'a str,
+    pos: usize,
+    /// Whether `//` starts a comment.
+    slash_comments: bool,
+}
+
+/// Return the tokens of `src`.
+pub fn tokenize(src: &str) -> Lexer<'_> {
+    Lexer {
+        src,
+        pos: 0,
+        slash_comments: true,
+    }
+}
+
+/// Return the tokens of `src` read as plain Lua, in which `//` is the integer
+/// division operator rather than a comment.
+///
+/// ```
+/// use pico8_to_lua::lexer::{TokenKind, tokenize, tokenize_lua};
+/// assert_eq!(tokenize_lua("a // 2").nth(1).unwrap().kind, TokenKind::Punct);
+/// assert_eq!(tokenize("a // 2").nth(1).unwrap().kind, TokenKind::Comment);
+/// ```
+pub fn tokenize_lua(src: &str) -> Lexer<'_> {
+    Lexer {
+        src,
+        pos: 0,
+        slash_comments: false,
+    }
+}
+
+fn is_name_byte(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'_' || !b.is_ascii()
+}
+
+/// If a long bracket like `[==[` starts at `pos`, return its level and length.
+fn long_bracket(bytes: &[u8], pos: usize) -> Option<(usize, usize)> {
+    if bytes.get(pos) != Some(&b'[') {
+        return None;
+    }
+    let level = bytes[pos + 1..].iter().take_while(|&&b| b == b'=').count();
+    (bytes.get(pos + 1 + level) == Some(&b'[')).then_some((level, level + 2))
+}
+
+/// Return the position after the long bracket close of `level` at or after
+/// `pos`, or the end of `src`.
+fn long_bracket_end(src: &str, pos: usize, level: usize) -> usize {
+    let close = format!("]{}]", "=".repeat(level));
+    src[pos..].find(&close).map_or(src.len(), |i| pos + i + close.len())
+}
+
+impl Lexer<'_> {
+    fn scan(&mut self) -> Option<TokenKind> {
+        let bytes = self.src.as_bytes();
+        let start = self.pos;
+        let b = *bytes.get(start)?;
+        let rest = &self.src[start..];
+        let kind = if rest.starts_with("--") || (self.slash_comments && rest.starts_with("//")) {
+            self.pos = match long_bracket(bytes, start + 2) {
+                Some((level, len)) => long_bracket_end(self.src, start + 2 + len, level),
+                None => rest.find('\n').map_or(self.src.len(), |i| start + i),
+            };
+            TokenKind::Comment
+        } else if let Some((level, len)) = long_bracket(bytes, start) {
+            self.pos = long_bracket_end(self.src, start + len, level);
+            TokenKind::String
+        } else if b == b'"' || b == b'\'' {
+            let mut i = start + 1;
+            while let Some(&c) = bytes.get(i) {
+                i += 1;
+                if c == b'\\' {
+                    i += 1;
+                } else if c == b || c == b'\n' {
+                    if c == b'\n' {
+                        i -= 1;
+                    }
+                    break;
+                }
+            }
+            self.pos = i.min(bytes.len());
+            TokenKind::String
+        } else if b.is_ascii_digit()
+            || (b == b'.' && bytes.get(start + 1).is_some_and(u8::is_ascii_digit))
+        {
+            self.pos = number_end(bytes, start);
+            TokenKind::Number
+        } else if is_name_byte(b) {
+            let len = bytes[start..].iter().take_while(|&&c| is_name_byte(c)).count();
+            self.pos = start + len;
+            if KEYWORDS.contains(&&self.src[start..self.pos]) {
+                TokenKind::Keyword
+            } else {
+                TokenKind::Name
+            }
+        } else if let Some(p) = PUNCTS.iter().find(|p| rest.starts_with(*p)) {
+            self.pos = start + p.len();
+            TokenKind::Punct
+        } else {
+            self.pos = start + rest.chars().next().map_or(1, char::len_utf8);
+            TokenKind::Unknown
+        };
+        Some(kind)
+    }
+}
+
+fn number_end(bytes: &[u8], start: usize) -> usize {
+    let radix_digits = |i: usize, valid: fn(&u8) -> bool| {
+        let mut i = i;
+        while let Some(c) = bytes.get(i) {
+            if valid(c) || (*c == b'.' && bytes.get(i + 1) != Some(&b'.')) {
+                i += 1;
+            } else {
+                break;
+            }
+        }
+        i
+    };
+    match bytes.get(start..start + 2) {
+        Some(b"0x" | b"0X") => radix_digits(start + 2, u8::is_ascii_hexdigit),
+        Some(b"0b" | b"0B") => radix_digits(start + 2, |c| *c == b'0' || *c == b'1'),
+        _ => {
+            let mut i = radix_digits(start, u8::is_ascii_digit);
+            if matches!(bytes.get(i), Some(b'e' | b'E')) {
+                i += 1;
+                if matches!(bytes.get(i), Some(b'+' | b'-')) {
+                    i += 1;
+                }
+                while bytes.get(i).is_some_and(u8::is_ascii_digit) {
+                    i += 1;
+                }
+            }
+            i
+        }
+    }
+}
+
+impl<'a> Iterator for Lexer<'a> {
+    type Item = Token<'a>;
+
+    fn next(&mut self) -> Option<Token<'a>> {
+        let skip = self.src[self.pos..]
+            .bytes()
+            .take_while(u8::is_ascii_whitespace)
+            .count();
+        self.pos += skip;
+        let start = self.pos;
+        let kind = self.scan()?;
+        Some(Token {
+            kind,
+            text: &self.src[start..self.pos],
+            span: Span::new(start, self.pos),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(src: &str) -> Vec<(TokenKind, &str)> {
+        tokenize(src).map(|t| (t.kind, t.text)).collect()
+    }
+
+    #[test]
+    fn test_tokenize() {
+        use TokenKind::*;
+        assert_eq!(
+            kinds("if (a != 0x1.8) x += 'b\\'c' // hi\nend"),
+            vec![
+                (Keyword, "if"),
+                (Punct, "("),
+                (Name, "a"),
+                (Punct, "!="),
+                (Number, "0x1.8"),
+                (Punct, ")"),
+                (Name, "x"),
+                (Punct, "+="),
+                (String, "'b\\'c'"),
+                (Comment, "// hi"),
+                (Keyword, "end"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_lua() {
+        use TokenKind::*;
+        let kinds: Vec<_> = tokenize_lua("x = a // 2 -- a // 2\ny = b / 2")
+            .map(|t| (t.kind, t.text))
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                (Name, "x"),
+                (Punct, "="),
+                (Name, "a"),
+                (Punct, "//"),
+                (Number, "2"),
+                (Comment, "-- a // 2"),
+                (Name, "y"),
+                (Punct, "="),
+                (Name, "b"),
+                (Punct, "/"),
+                (Number, "2"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_long_brackets() {
+        use TokenKind::*;
+        assert_eq!(
+            kinds("--[[ a\nb ]] x = [==[ ]] ]==] .. 1..2"),
+            vec![
+                (Comment, "--[[ a\nb ]]"),
+                (Name, "x"),
+                (Punct, "="),
+                (String, "[==[ ]] ]==]"),
+                (Punct, ".."),
+                (Number, "1"),
+                (Punct, ".."),
+                (Number, "2"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_glyph_names() {
+        assert_eq!(kinds("btn(⬅️)")[2], (TokenKind::Name, "⬅️"));
+    }
+
+    #[test]
+    fn test_unterminated() {
+        assert_eq!(kinds("'abc\nx").len(), 2);
+        assert_eq!(kinds("--[[ abc").len(), 1);
+    }
+}
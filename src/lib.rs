@@ -11,53 +11,224 @@
 /// [here](https://github.com/benwiley4000/pico8-to-lua/blob/master/pico8-to-lua.lua).
 ///
 /// Licensed under the Zlib license.
-use regex::{Regex, Replacer};
-use std::{borrow::Cow, error::Error};
+use regex::Regex;
+use std::borrow::Cow;
 use find_matching_bracket::find_matching_paren;
 use lazy_regex::regex;
 
-// https://stackoverflow.com/a/79268946/6454690
-fn replace_all_in_place<R: Replacer>(regex: &Regex, s: &mut Cow<'_, str>, replacer: R) {
-    let new = regex.replace_all(s, replacer);
-    if let Cow::Owned(o) = new {
-        *s = Cow::Owned(o);
-    } // Otherwise, no change was made.
+pub mod api;
+pub mod assemble;
+#[cfg(feature = "ast")]
+pub mod ast;
+pub mod batch;
+#[cfg(feature = "net")]
+pub mod bbs;
+#[cfg(feature = "bevy")]
+pub mod bevy;
+pub mod bitwise;
+pub mod builtins;
+pub mod build_support;
+pub mod cart;
+pub mod cart_diff;
+pub mod charset;
+#[cfg(feature = "mlua")]
+pub mod compile;
+pub mod compress;
+pub mod convert;
+pub mod coroutines;
+pub mod data_usage;
+pub mod dead_code;
+pub mod debug;
+pub mod dedupe;
+pub mod deprecated;
+pub mod detect;
+pub mod error;
+#[cfg(feature = "cdylib")]
+pub mod ffi;
+pub mod diagnostics;
+pub mod division;
+pub mod fixed;
+pub mod format;
+pub mod glyph;
+pub mod goto;
+mod includes;
+pub mod incremental;
+mod json;
+pub mod label;
+pub mod lexer;
+pub mod license;
+pub mod limits;
+#[cfg(feature = "mlua")]
+pub mod loader;
+pub mod loops;
+pub mod love;
+#[cfg(feature = "lsp")]
+pub mod lsp;
+pub mod marker;
+pub mod minify;
+mod newline;
+#[cfg(feature = "node")]
+// The bindings are only called from Node, so the tests leave them unused.
+#[cfg_attr(test, allow(dead_code))]
+mod node;
+mod operators;
+pub mod p8scii;
+pub mod partial;
+pub mod peek;
+pub mod pipeline;
+mod prefilter;
+#[cfg(feature = "python")]
+mod python;
+#[cfg(feature = "png")]
+pub mod p8png;
+#[cfg(feature = "serve")]
+pub mod serve;
+pub mod recover;
+pub mod report;
+pub mod shim;
+pub mod sink;
+pub mod size;
+pub mod sourcemap;
+pub mod span;
+pub mod split;
+pub mod stats;
+pub mod stream;
+pub mod symbols;
+pub mod tables;
+pub mod tic80;
+mod trace;
+pub mod trig;
+pub mod unpatch;
+pub mod verify;
+pub mod version;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(feature = "ast")]
+pub use ast::patch_lua_ast;
+pub use batch::{
+    Input, Options, Output, convert_cart_file, convert_file, convert_many, patch_lua_file,
+};
+pub use cart::{Cart, CartError, CartHeader, CartMeta};
+pub use convert::{Converted, convert};
+pub use error::Error;
+use includes::{include_matches, include_path, replace_includes, require_call};
+pub use includes::{
+    Flattened, FsResolver, Include, IncludeError, IncludeRegion, Included, Module, mark_include,
+    module_name, parse_includes, split_tab,
+};
+pub use marker::is_converted;
+pub use partial::{Partial, Residue, ResidueKind, convert_partial};
+pub use pipeline::{PatchOutcome, PatchReport};
+pub use recover::{Issue, IssueKind, Recovered};
+pub use size::{SizeReport, TOKEN_LIMIT, size_report, tokens};
+pub use span::{Span, TextEdit};
+pub use stream::patch_stream;
+pub use unpatch::unpatch_lua;
+
+/// Return an edit for each match of `regex` in `lua` that `replace` changes.
+fn regex_edits(
+    regex: &Regex,
+    lua: &str,
+    mut replace: impl FnMut(&regex::Captures) -> String,
+) -> Vec<TextEdit> {
+    regex
+        .captures_iter(lua)
+        .filter_map(|caps| {
+            let found = caps.get(0).expect("whole match");
+            let replacement = replace(&caps);
+            (replacement != found.as_str()).then(|| TextEdit::new(found.range(), replacement))
+        })
+        .collect()
+}
+
+/// Expand a replacement `template` like "$1 = $1" for `caps`.
+fn expand(caps: &regex::Captures, template: &str) -> String {
+    let mut out = String::new();
+    caps.expand(template, &mut out);
+    out
+}
+
+/// Apply the `edits` of a pass to `lua` in one splice. Without any, `lua` is
+/// left as it is, borrowed if it was.
+fn splice(lua: &mut Cow<'_, str>, edits: &[TextEdit]) {
+    if !edits.is_empty() {
+        *lua = Cow::Owned(span::apply_edits(lua, edits));
+    }
 }
 
 /// Resolve the Pico-8 "#include path.p8" statements with possible errors.
 ///
+/// The path may be quoted, e.g. `#include "my lib.lua"`, and a comment may
+/// follow it on the same line; the comment is dropped along with the
+/// statement.
+///
 /// If there are substitution errors, the first error will be returned.
-pub fn try_patch_includes<'h, E: Error>(
+pub fn try_patch_includes<'h, E: std::error::Error>(
     lua: impl Into<Cow<'h, str>>,
     mut resolve: impl FnMut(&str) -> Result<String, E>,
 ) -> Result<Cow<'h, str>, E> {
-    let mut lua = lua.into();
     let mut error = None;
-
-    replace_all_in_place(
-        regex!(r"(?m)^\s*#include\s+(\S+)"),
-        &mut lua,
-        |caps: &regex::Captures| {
-            match resolve(&caps[1]) {
-                Ok(s) => s,
-                Err(e) => {
-                    // This is kind of pointless since the user will never get
-                    // access to the string. I'm leaving here incase the results
-                    // change to make it relevant later.
-                    let result = format!("error(\"failed to include {:?}: {}\")", &caps[1], &e);
-                    if error.is_none() {
-                        error = Some(Err(e))
-                    }
-                    result
-                }
+    let mut count = 0;
+    let lua = replace_includes(lua.into(), |path| match resolve(path) {
+        Ok(s) => {
+            count += 1;
+            s
+        }
+        Err(e) => {
+            // This is kind of pointless since the user will never get
+            // access to the string. I'm leaving here incase the results
+            // change to make it relevant later.
+            let result = format!("error(\"failed to include {:?}: {}\")", path, &e);
+            if error.is_none() {
+                error = Some(Err(e))
             }
-        },
-    );
+            result
+        }
+    });
+    trace::includes(count);
     error.unwrap_or(Ok(lua))
 }
 
+/// Resolve the Pico-8 "#include path.p8" statements like
+/// [try_patch_includes] with a `resolve` that returns a future, e.g. an
+/// `async fn` that fetches each path over the network, so that no thread is
+/// blocked while it waits. Any runtime can drive it.
+///
+/// The includes are resolved one at a time in order, and the first error is
+/// returned without resolving the rest.
+///
+/// ```
+/// use std::{convert::Infallible, pin::pin, task::{Context, Poll, Waker}};
+/// use pico8_to_lua::try_patch_includes_async;
+/// async fn fetch(path: String) -> Result<String, Infallible> {
+///     Ok(format!("-- {path}"))
+/// }
+/// let mut lua = pin!(try_patch_includes_async("#include a.lua\nx = 1", fetch));
+/// let Poll::Ready(lua) = lua.as_mut().poll(&mut Context::from_waker(Waker::noop())) else {
+///     unreachable!()
+/// };
+/// assert_eq!(lua.unwrap(), "-- a.lua\nx = 1");
+/// ```
+pub async fn try_patch_includes_async<'h, E, F>(
+    lua: impl Into<Cow<'h, str>>,
+    mut resolve: impl FnMut(String) -> F,
+) -> Result<Cow<'h, str>, E>
+where
+    F: std::future::Future<Output = Result<String, E>>,
+{
+    let lua = lua.into();
+    let mut resolved = Vec::new();
+    for path in find_includes(&lua) {
+        resolved.push(resolve(path.to_string()).await?);
+    }
+    trace::includes(resolved.len());
+    let mut resolved = resolved.into_iter();
+    Ok(replace_includes(lua, |_| resolved.next().unwrap_or_default()))
+}
+
 /// Returns true if the patch_output was patched by testing whether it is
-/// `Cow::Owned`; a `Cow::Borrowed` implies it was not patched.
+/// `Cow::Owned`; a `Cow::Borrowed` implies it was not patched. See
+/// [patch_lua_with_outcome] for what was patched.
 #[allow(clippy::ptr_arg)]
 pub fn was_patched(patch_output: &Cow<'_, str>) -> bool {
     match patch_output {
@@ -67,17 +238,53 @@ pub fn was_patched(patch_output: &Cow<'_, str>) -> bool {
 }
 
 /// Resolve the Pico-8 "#include path.p8" statements without possible error.
+///
+/// Paths may be quoted as with [try_patch_includes].
 pub fn patch_includes<'h>(
     lua: impl Into<Cow<'h, str>>,
-    mut resolve: impl FnMut(&str) -> String,
+    resolve: impl FnMut(&str) -> String,
 ) -> Cow<'h, str> {
-    let mut lua = lua.into();
-    replace_all_in_place(
-        regex!(r"(?m)^\s*#include\s+(\S+)"),
-        &mut lua,
-        |caps: &regex::Captures| resolve(&caps[1]),
-    );
-    lua
+    replace_includes(lua.into(), resolve)
+}
+
+/// Resolve the Pico-8 "#include path.p8" statements as code or as data.
+///
+/// Data, e.g. a text file of level strings, is assigned to a name as a long
+/// string, which [patch_lua] does not rewrite. See [Included].
+pub fn patch_includes_with<'h>(
+    lua: impl Into<Cow<'h, str>>,
+    mut resolve: impl FnMut(&str) -> Included,
+) -> Cow<'h, str> {
+    replace_includes(lua.into(), |path| resolve(path).to_lua())
+}
+
+/// Rewrite the Pico-8 "#include path.p8" statements as `require` calls of
+/// the module that `module` names for each path, e.g. [module_name], to keep
+/// each included file a module of its own. See [FsResolver::modules] to read
+/// the modules too.
+///
+/// ```
+/// use pico8_to_lua::{module_name, require_includes};
+/// assert_eq!(require_includes("#include lib/util.lua", module_name), "require(\"lib.util\")");
+/// ```
+pub fn require_includes<'h>(
+    lua: impl Into<Cow<'h, str>>,
+    mut module: impl FnMut(&str) -> String,
+) -> Cow<'h, str> {
+    replace_includes(lua.into(), |path| require_call(&module(path)))
+}
+
+/// Resolve the Pico-8 "#include path.p8" statements with the path and the tab
+/// to include, if any, e.g. "lib.p8" and `Some(2)` for `#include lib.p8:2`.
+/// See [includes::split_tab].
+pub fn patch_includes_with_tabs<'h>(
+    lua: impl Into<Cow<'h, str>>,
+    mut resolve: impl FnMut(&str, Option<usize>) -> String,
+) -> Cow<'h, str> {
+    replace_includes(lua.into(), |path| {
+        let (path, tab) = includes::split_tab(path);
+        resolve(path, tab)
+    })
 }
 
 /// Return each path from the the Pico-8 "#include path.p8" statements.
@@ -86,11 +293,13 @@ pub fn patch_includes<'h>(
 /// synchronously using [patch_includes] or [try_patch_includes]. However, in an
 /// asynchronous IO context, it is often necessary to read in the contents
 /// before patching the includes.
+///
+/// Each path is borrowed from `lua`. Use [parse_includes] for where each
+/// statement is, e.g. to go to the included file from an editor.
 pub fn find_includes(
     lua: &str,
-) -> impl Iterator<Item = String> {
-    regex!(r"(?m)^\s*#include\s+(\S+)").captures_iter(lua)
-        .map(|caps: regex::Captures| caps[1].to_string())
+) -> impl Iterator<Item = &str> {
+    include_matches(lua).map(|caps: regex::Captures| include_path(&caps))
 }
 
 /// Given a string with the Pico-8 dialect of Lua, it will convert that code to
@@ -100,108 +309,748 @@ pub fn find_includes(
 /// recommended to use [patch_includes] before this function since if those
 /// inclusions may use the Pico-8 dialect.
 ///
+/// Strings and comments are never rewritten, except that `//` comments become
+/// `--` comments.
+///
 /// NOTE: This is not a full language parser, but a series of regular
 /// expressions, so it is not guaranteed to work with every valid Pico-8
 /// expression. But if it does not work, please file an issue with the failing
 /// expression.
+///
+/// The comment lines at the very top of the code, which Pico-8 shows as the
+/// cart's title and author, are left exactly as they are. See
+/// [cart::comment_header].
+///
+/// If `lua` is the whole text of a ".p8" cart, only its `__lua__` section is
+/// converted and the header and data sections are left exactly as they are.
+/// See [cart::lua_span].
 pub fn patch_lua<'h>(lua: impl Into<Cow<'h, str>>) -> Cow<'h, str> {
+    patch_lua_with(lua, &PatchOptions::default())
+}
+
+/// Convert Pico-8 Lua like [patch_lua] or fail with every construct it may
+/// not convert as meant: a shorthand condition whose parenthesis never
+/// closes, a malformed include, an unknown compound assignment operator, or
+/// a button glyph outside `btn()` and `btnp()`. See
+/// [diagnostics::diagnose_strict].
+///
+/// ```
+/// use pico8_to_lua::try_patch_lua;
+/// assert_eq!(try_patch_lua("x += 1").unwrap(), "x = x + (1)");
+/// let error = try_patch_lua("x += 1\nif (a b()").unwrap_err();
+/// assert_eq!(error.to_string(), "2:4: no closing parenthesis for this if condition");
+/// ```
+pub fn try_patch_lua<'h>(
+    lua: impl Into<Cow<'h, str>>,
+) -> Result<Cow<'h, str>, diagnostics::StrictError> {
+    let lua = lua.into();
+    let found = diagnostics::diagnose_strict(&lua);
+    if !found.is_empty() {
+        return Err(diagnostics::StrictError { diagnostics: found });
+    }
+    Ok(patch_lua(lua))
+}
+
+/// Which rewrites [patch_lua_with] does. Each field is named after its pass in
+/// [pipeline::PASSES].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PatchOptions {
+    /// Rewrite `// comment` to `-- comment`.
+    pub slash_comments: bool,
+    /// Remove comments, except the leading title and author comments.
+    /// Comments alone on a line leave a blank line so the lines keep their
+    /// numbers.
+    pub strip_comments: bool,
+    /// Rewrite `!=` to `~=`.
+    pub not_equal: bool,
+    /// Rewrite button glyphs in `btn()` and `btnp()` calls to numbers.
+    pub button_arguments: bool,
+    /// Rewrite `if (cond) stmt` to `if cond then stmt end`.
+    pub shorthand_if: bool,
+    /// Rewrite `while (cond) stmt` to `while cond do stmt end`.
+    pub shorthand_while: bool,
+    /// Rewrite `x += 1` to `x = x + (1)`.
+    pub compound_assignment: bool,
+    /// Rewrite `?x` to `print(x)`.
+    pub print_shorthand: bool,
+    /// Rewrite binary literals like `0b1010.1` to hex literals like `0xa.8`.
+    pub binary_literals: bool,
+    /// Rewrite `@a`, `%a`, and `$a` to `peek(a)`, `peek2(a)`, and `peek4(a)`.
+    pub peek_shorthand: bool,
+}
+
+impl Default for PatchOptions {
+    fn default() -> Self {
+        PatchOptions {
+            slash_comments: true,
+            strip_comments: false,
+            not_equal: true,
+            button_arguments: true,
+            shorthand_if: true,
+            shorthand_while: true,
+            compound_assignment: true,
+            print_shorthand: true,
+            binary_literals: true,
+            peek_shorthand: true,
+        }
+    }
+}
+
+/// Convert Pico-8 Lua to plain Lua like [patch_lua] with the rewrites in
+/// `options`. Lua that [is_converted] is returned as it is.
+///
+/// ```
+/// use pico8_to_lua::{PatchOptions, patch_lua_with};
+/// let options = PatchOptions { slash_comments: false, ..PatchOptions::default() };
+/// assert_eq!(patch_lua_with("x += 1 // see http://x", &options), "x = x + (1) // see http://x");
+/// ```
+pub fn patch_lua_with<'h>(lua: impl Into<Cow<'h, str>>, options: &PatchOptions) -> Cow<'h, str> {
+    let lua = lua.into();
+    trace::patch_passes(&lua, options);
+    patch_stage(lua, options)
+}
+
+// Convert a cart or Lua like [patch_lua_with] without tracing the passes, for
+// running them one more at a time.
+fn patch_stage<'h>(lua: Cow<'h, str>, options: &PatchOptions) -> Cow<'h, str> {
+    if let Some((lua, newlines)) = newline::Newlines::normalize(&lua) {
+        return Cow::Owned(newlines.restore(&patch_stage(Cow::Owned(lua), options)));
+    }
+    if is_converted(&lua) {
+        return lua;
+    }
+    if !cart::is_cart(&lua) {
+        return patch_section(lua, options);
+    }
+    let Some(span) = cart::lua_span(&lua) else {
+        return lua;
+    };
+    let patched = match patch_section(&lua[span.start..span.end], options) {
+        Cow::Owned(o) => Some(o),
+        Cow::Borrowed(_) => None,
+    };
+    match patched {
+        Some(code) => Cow::Owned(format!(
+            "{}{}{}",
+            &lua[..span.start],
+            code,
+            &lua[span.end..]
+        )),
+        None => lua,
+    }
+}
+
+impl PatchOptions {
+    /// Return options with every rewrite off.
+    fn none() -> Self {
+        PatchOptions {
+            slash_comments: false,
+            strip_comments: false,
+            not_equal: false,
+            button_arguments: false,
+            shorthand_if: false,
+            shorthand_while: false,
+            compound_assignment: false,
+            print_shorthand: false,
+            binary_literals: false,
+            peek_shorthand: false,
+        }
+    }
+
+    /// Return the field of the pass called `name` in [pipeline::PASSES], e.g.
+    /// "compound-assignment", if it is one of these options.
+    fn pass_mut(&mut self, name: &str) -> Option<&mut bool> {
+        Some(match name {
+            "slash-comments" => &mut self.slash_comments,
+            "strip-comments" => &mut self.strip_comments,
+            "not-equal" => &mut self.not_equal,
+            "button-arguments" => &mut self.button_arguments,
+            "shorthand-if" => &mut self.shorthand_if,
+            "shorthand-while" => &mut self.shorthand_while,
+            "compound-assignment" => &mut self.compound_assignment,
+            "print-shorthand" => &mut self.print_shorthand,
+            "binary-literals" => &mut self.binary_literals,
+            "peek-shorthand" => &mut self.peek_shorthand,
+            _ => return None,
+        })
+    }
+}
+
+/// Convert Pico-8 Lua like [patch_lua_with] and report what each pass
+/// changed.
+///
+/// Each pass is run after the ones before it, and each line it changed is
+/// reported where the change starts in the Lua the pass was given.
+///
+/// ```
+/// use pico8_to_lua::{PatchOptions, patch_lua_with_report};
+/// let (lua, report) = patch_lua_with_report("x += 1\nif (x != 2) y += 1", &PatchOptions::default());
+/// assert_eq!(lua, "x = x + (1)\nif x ~= 2 then y = y + (1) end");
+/// assert_eq!(report.count("compound-assignment"), 2);
+/// assert_eq!(report.to_string(), "not-equal: 1 change at 2:7\n\
+///     shorthand-if: 1 change at 2:4\n\
+///     compound-assignment: 2 changes at 1:3, 2:18\n");
+/// ```
+pub fn patch_lua_with_report<'h>(
+    lua: impl Into<Cow<'h, str>>,
+    options: &PatchOptions,
+) -> (Cow<'h, str>, pipeline::PatchReport) {
+    let lua = lua.into();
+    let mut report = pipeline::PatchReport::default();
+    let mut stage = PatchOptions::none();
+    let mut before = lua.to_string();
+    for pass in pipeline::PASSES {
+        let mut wanted = *options;
+        if wanted.pass_mut(pass.name).is_none_or(|on| !*on) {
+            continue;
+        }
+        *stage.pass_mut(pass.name).expect("a patch pass") = true;
+        let after = patch_stage(Cow::Borrowed(lua.as_ref()), &stage).into_owned();
+        let changes = pipeline::line_changes(&before, &after);
+        if !changes.is_empty() {
+            report.passes.push(pipeline::PassChanges {
+                name: pass.name,
+                changes,
+            });
+        }
+        before = after;
+    }
+    (patch_lua_with(lua, options), report)
+}
+
+/// Convert Pico-8 Lua like [patch_lua_with] and describe what changed: the
+/// categories of the passes that changed it, what each pass changed, and the
+/// warnings about the Lua, or about the Lua of a cart.
+///
+/// ```
+/// use pico8_to_lua::{PatchOptions, patch_lua_with_outcome, pipeline::PassCategory};
+/// let (lua, outcome) = patch_lua_with_outcome("if (a != b) c()", &PatchOptions::default());
+/// assert_eq!(lua, "if a ~= b then c() end");
+/// assert_eq!(outcome.categories, [PassCategory::Operator, PassCategory::Statement]);
+/// assert!(outcome.is_changed() && outcome.diagnostics.is_empty());
+/// ```
+pub fn patch_lua_with_outcome<'h>(
+    lua: impl Into<Cow<'h, str>>,
+    options: &PatchOptions,
+) -> (Cow<'h, str>, PatchOutcome) {
+    let lua = lua.into();
+    let diagnostics = match cart::lua_span(&lua) {
+        Some(span) => diagnostics::diagnose(&lua[span.range()]),
+        None if cart::is_cart(&lua) => Vec::new(),
+        None => diagnostics::diagnose(&lua),
+    };
+    let (lua, report) = patch_lua_with_report(lua, options);
+    (lua, PatchOutcome::new(report, diagnostics))
+}
+
+// Convert the code of a `__lua__` section.
+fn patch_section<'h>(lua: impl Into<Cow<'h, str>>, options: &PatchOptions) -> Cow<'h, str> {
+    let lua = lua.into();
+    let header_len = cart::comment_header(&lua).len();
+    let lua = if header_len == 0 {
+        patch_code(lua, options)
+    } else {
+        let patched = match patch_code(&lua[header_len..], options) {
+            Cow::Owned(o) => Some(o),
+            Cow::Borrowed(_) => None,
+        };
+        match patched {
+            Some(code) => Cow::Owned(format!("{}{}", &lua[..header_len], code)),
+            None => lua,
+        }
+    };
+    let lua = if options.peek_shorthand {
+        peek::patch_peek(lua)
+    } else {
+        lua
+    };
+    if options.strip_comments {
+        strip_comments(lua, header_len)
+    } else {
+        lua
+    }
+}
+
+// Convert code that continues a `__lua__` section, so has no comment header.
+fn patch_continued<'h>(lua: impl Into<Cow<'h, str>>, options: &PatchOptions) -> Cow<'h, str> {
+    let lua = patch_code(lua, options);
+    let lua = if options.peek_shorthand {
+        peek::patch_peek(lua)
+    } else {
+        lua
+    };
+    if options.strip_comments {
+        strip_comments(lua, 0)
+    } else {
+        lua
+    }
+}
+
+// Remove the comments after byte `from` with the spaces before them. A
+// comment inside a line with no space before it becomes a space so the tokens
+// around it stay apart. The line breaks in block comments are kept.
+fn strip_comments(mut lua: Cow<'_, str>, from: usize) -> Cow<'_, str> {
+    let edits: Vec<TextEdit> = lexer::tokenize(&lua[from..])
+        .filter(|t| t.kind == lexer::TokenKind::Comment)
+        .map(|t| {
+            let (start, end) = (from + t.span.start, from + t.span.end);
+            let breaks = "\n".repeat(t.text.matches('\n').count());
+            let rest = &lua[end..];
+            let tail = rest.find('\n').map_or(rest, |i| &rest[..i]);
+            let tail = tail.strip_suffix('\r').unwrap_or(tail);
+            let before = lua[..start].trim_end_matches([' ', '\t']).len();
+            if tail.trim().is_empty() {
+                TextEdit::new(before..end + tail.len(), breaks)
+            } else if breaks.is_empty() && before == start {
+                TextEdit::new(start..end, " ")
+            } else {
+                TextEdit::new(before..end, breaks)
+            }
+        })
+        .collect();
+    splice(&mut lua, &edits);
+    lua
+}
+
+// Strings and comments are swapped for placeholders like "\u{E000}7\u{E001}"
+// while the regular expressions run so that they only rewrite code. Comments
+// keep a "--" in front of their placeholder so the rewrites still see where a
+// line's comment begins.
+const MASK_OPEN: char = '\u{E000}';
+const MASK_CLOSE: char = '\u{E001}';
+
+fn patch_code<'h>(lua: impl Into<Cow<'h, str>>, options: &PatchOptions) -> Cow<'h, str> {
+    let lua = lua.into();
+    let regions: Vec<lexer::Token> = lexer::tokenize(&lua)
+        .filter(|t| matches!(t.kind, lexer::TokenKind::String | lexer::TokenKind::Comment))
+        .collect();
+    if regions.is_empty() {
+        return patch_masked(lua, options);
+    }
+    let mut masked = String::with_capacity(lua.len());
+    let mut last = 0;
+    for (i, region) in regions.iter().enumerate() {
+        masked.push_str(&lua[last..region.span.start]);
+        if region.kind == lexer::TokenKind::Comment {
+            masked.push_str("--");
+        }
+        masked.push(MASK_OPEN);
+        masked.push_str(&i.to_string());
+        masked.push(MASK_CLOSE);
+        last = region.span.end;
+    }
+    masked.push_str(&lua[last..]);
+
+    let patched = patch_masked(masked.as_str(), options);
+    // Comments starting with "//" become "--" comments when they are restored.
+    let slash_comments =
+        options.slash_comments && regions.iter().any(|r| r.text.starts_with("//"));
+    if !was_patched(&patched) && !slash_comments {
+        return lua;
+    }
+    let placeholder = regex!("(--)?\u{E000}([0-9]+)\u{E001}");
+    let restored = placeholder.replace_all(&patched, |caps: &regex::Captures| {
+        let region = &regions[caps[2].parse::<usize>().expect("placeholder index")];
+        let prefix = caps.get(1).map_or("", |m| m.as_str());
+        match region.kind {
+            lexer::TokenKind::Comment if options.slash_comments || prefix.is_empty() => {
+                format!("{prefix}{}", &region.text[2..])
+            }
+            lexer::TokenKind::Comment => region.text.to_string(),
+            _ => format!("{prefix}{}", region.text),
+        }
+    });
+    Cow::Owned(restored.into_owned())
+}
+
+fn patch_masked<'h>(lua: impl Into<Cow<'h, str>>, options: &PatchOptions) -> Cow<'h, str> {
     let mut lua = lua.into();
+    let found = prefilter::Triggers::scan(&lua);
     // Replace != with ~=.
-    replace_all_in_place(regex!(r"!="), &mut lua, "~=");
-
-    // Replace // with --.
-    replace_all_in_place(regex!(r"//"), &mut lua, "--");
+    if options.not_equal && found.not_equal {
+        let edits = regex_edits(regex!(r"!="), &lua, |_| "~=".into());
+        splice(&mut lua, &edits);
+    }
 
     // Replace unicode symbols for buttons.
-    replace_all_in_place(
-        regex!(r"(btnp?)\(\s*(\S+)\s*\)"),
-        &mut lua,
-        |caps: &regex::Captures| {
-            let func = &caps[1];
-            let symbol = caps[2].trim_end_matches("\u{fe0f}");
-            let sub = match symbol {
-                "⬅" => "0",
-                "➡" => "1",
-                "⬆" => "2",
-                "⬇" => "3",
-                "🅾" => "4",
-                "❎" => "5",
-                x => x,
-            };
-            format!("{func}({sub})")
-        },
-    );
+    // The player argument, if any, is kept as it is.
+    if options.button_arguments && found.buttons {
+        let edits = regex_edits(
+            regex!(r"\b(btnp?)\s*\(\s*([^,\s)]+)\s*(,[^)]*?)?\s*\)"),
+            &lua,
+            |caps| {
+                let func = &caps[1];
+                let player = caps.get(3).map_or("", |m| m.as_str());
+                match glyph::button(&caps[2]) {
+                    Some((_, value)) => format!("{func}({value}{player})"),
+                    None => format!("{func}({}{player})", glyph::strip_selectors(&caps[2])),
+                }
+            },
+        );
+        splice(&mut lua, &edits);
+    }
 
     // Rewrite shorthand if statements.
     //
     // This is why using regex is not a great tool for parsing but because we
     // only need to match one line, we find the matching parenthesis and move on.
-    replace_all_in_place(
-        regex!(r"(?m)^(\s*)if\s*(\([^\n]*)$"),
-        &mut lua,
-        |caps: &regex::Captures| {
-            let prefix = &caps[1];
-            let line = &caps[2];
-
-            if regex!(r"\bthen\b").is_match(line) {
-                return caps[0].to_string();
-            }
-            if let Some(index) = find_matching_paren(line, 0) {
+    if options.shorthand_if && found.shorthand_if {
+        let edits = regex_edits(
+            regex!(r"(?m)^(\s*)if\s*(\([^\n]*)$"),
+            &lua,
+            |caps| {
+                let prefix = &caps[1];
+                let line = &caps[2];
+
+                if regex!(r"\bthen\b").is_match(line) {
+                    return caps[0].to_string();
+                }
+                if let Some(index) = find_matching_paren(line, 0) {
+                    let cond = &line[1..index];
+                    let body = &line[index + 1..].trim_start();
+                    if continues_condition(body) {
+                        return caps[0].to_string();
+                    }
+                    let comment_start = body.find("--");
+                    if let Some(cs) = comment_start {
+                        let (code, comment) = body.split_at(cs);
+                        format!(
+                            "{}if {} then {} end {}",
+                            prefix,
+                            cond,
+                            shorthand_body(code.trim_end()),
+                            comment
+                        )
+                    } else {
+                        format!("{}if {} then {} end", prefix, cond, shorthand_body(body))
+                    }
+                } else {
+                    caps[0].to_string()
+                }
+            },
+        );
+        splice(&mut lua, &edits);
+    }
+
+    // Rewrite shorthand if statements whose condition spans lines.
+    if options.shorthand_if && found.shorthand_if {
+        let edits = multiline_shorthand_if_edits(&lua);
+        splice(&mut lua, &edits);
+    }
+
+    // Rewrite shorthand while statements the same way.
+    if options.shorthand_while && found.shorthand_while {
+        let edits = regex_edits(
+            regex!(r"(?m)^(\s*)while\s*(\([^\n]*)$"),
+            &lua,
+            |caps| {
+                let prefix = &caps[1];
+                let line = &caps[2];
+
+                if regex!(r"\bdo\b").is_match(line) {
+                    return caps[0].to_string();
+                }
+                let Some(index) = find_matching_paren(line, 0) else {
+                    return caps[0].to_string();
+                };
                 let cond = &line[1..index];
                 let body = &line[index + 1..].trim_start();
-                let comment_start = body.find("--");
-                if let Some(cs) = comment_start {
-                    let (code, comment) = body.split_at(cs);
+                let (code, comment) = body.split_at(body.find("--").unwrap_or(body.len()));
+                if code.trim().is_empty() {
+                    return caps[0].to_string();
+                }
+                if comment.is_empty() {
+                    format!("{}while {} do {} end", prefix, cond, shorthand_body(body))
+                } else {
                     format!(
-                        "{}if {} then {} end {}",
+                        "{}while {} do {} end {}",
                         prefix,
                         cond,
-                        code.trim_end(),
+                        shorthand_body(code.trim_end()),
                         comment
                     )
-                } else {
-                    format!("{}if {} then {} end", prefix, cond, body)
                 }
-            } else {
-                caps[0].to_string()
-            }
-        },
-    );
+            },
+        );
+        splice(&mut lua, &edits);
+    }
 
-    // Rewrite assignment operators (+=, -=, etc.).
-    replace_all_in_place(regex!(r"(?m)([^-\s]\S*)\s*([+\-*/%])=\s*([^\n\r]+?)(\s*(\breturn|\bend|\belse|;|--|$))"), &mut lua, "$1 = $1 $2 ($3)$4");
+    // Rewrite assignment operators (+=, -=, ..=, >>>=, etc.).
+    if options.compound_assignment && found.compound_assignment {
+        let edits = compound_assignment_edits(&lua);
+        splice(&mut lua, &edits);
+    }
 
     // Replace "?expr" with "print(expr)".
-    replace_all_in_place(regex!(r"(?m)^(\s*)\?([^\n\r]+)"), &mut lua, "${1}print($2)");
+    if options.print_shorthand && found.print_shorthand {
+        let edits = print_shorthand_edits(&lua);
+        splice(&mut lua, &edits);
+    }
 
     // Convert binary literals to hex literals.
-    replace_all_in_place(
-        regex!(r"([^[:alnum:]_])0[bB]([01.]+)"),
-        &mut lua,
-        |caps: &regex::Captures| {
-            let prefix = &caps[1];
-            let bin = &caps[2];
-            let mut parts = bin.split('.');
-
-            let p1 = parts.next().unwrap_or("");
-            let p2 = parts.next().unwrap_or("");
-
-            let int_val = u64::from_str_radix(p1, 2).ok();
-            let frac_val = if !p2.is_empty() {
-                let padded = format!("{:0<4}", p2);
-                u64::from_str_radix(&padded, 2).ok()
-            } else {
-                None
-            };
+    if options.binary_literals && found.binary_literals {
+        let edits = regex_edits(
+            regex!(r"(?m)(^|[^[:alnum:]_])0[bB]([01]+(?:\.[01]+)?)"),
+            &lua,
+            |caps| match binary_to_hex(&caps[2]) {
+                Some(hex) => format!("{}{}", &caps[1], hex),
+                None => caps[0].to_string(),
+            },
+        );
+        splice(&mut lua, &edits);
+    }
+    lua
+}
+
+/// Return the edits that rewrite the compound assignments of `lua`, e.g.
+/// "x += 1 y -= 2", each of which ends where its right hand side does. The
+/// left hand side is matched lazily so that an operator like ">>>" is not
+/// split into a left hand side ending in ">" and the operator ">>".
+///
+/// A right hand side left open at the end of its line, e.g. "x += f(\n1)" or
+/// "x += a\n+ b", runs on over the lines after, whose line breaks are kept.
+fn compound_assignment_edits(lua: &str) -> Vec<TextEdit> {
+    let mut edits = Vec::new();
+    let mut last = 0;
+    for caps in regex!(r"(?m)([^-\s]\S*?)\s*(\.\.|\^\^|>>>|<<>|>><|<<|>>|[+\-*/%\\^&|])=\s*([^\n\r]+?)(\s*(\breturn|\bend|\belse|;|--|$))").captures_iter(lua) {
+        let whole = caps.get(0).expect("whole match");
+        let rhs = caps.get(3).expect("right hand side");
+        if whole.start() < last {
+            continue;
+        }
+        let end = rhs.start() + expression_end(&lua[rhs.start()..]);
+        let (span, replacement) = if end > rhs.end() {
+            let expr = lua[rhs.start()..end].trim_end();
+            let replacement = format!("{} = {} {} ({})", &caps[1], &caps[1], &caps[2], expr);
+            (whole.start()..rhs.start() + expr.len(), replacement)
+        } else {
+            (whole.range(), compound_assignment(&caps))
+        };
+        last = span.end;
+        if replacement != lua[span.clone()] {
+            edits.push(TextEdit::new(span, replacement));
+        }
+    }
+    edits
+}
+
+/// Rewrite a compound assignment whose right hand side ends on its line.
+fn compound_assignment(caps: &regex::Captures) -> String {
+    let rhs = &caps[3];
+    let end = expression_end(rhs);
+    if end == 0 || end == rhs.len() {
+        return expand(caps, "$1 = $1 $2 ($3)$4");
+    }
+    // Another statement follows on the line without a separator.
+    let rest = format!("{}{}", &rhs[end..], &caps[4]);
+    let mut rest = Cow::Borrowed(rest.as_str());
+    let edits = compound_assignment_edits(&rest);
+    splice(&mut rest, &edits);
+    format!("{} = {} {} ({}){}", &caps[1], &caps[1], &caps[2], rhs[..end].trim_end(), rest)
+}
+
+/// Return the edits that rewrite each "?expr" to "print(expr)" where a
+/// statement starts: at the start of a line or after `;`, `do`, `then`,
+/// `else`, or `repeat`, e.g. "for i=1,3 do ?i end".
+fn print_shorthand_edits(lua: &str) -> Vec<TextEdit> {
+    let mut edits = Vec::new();
+    for caps in regex!(r"(?m)(^|;|\bdo\b|\bthen\b|\belse\b|\brepeat\b)[ \t]*\?").captures_iter(lua) {
+        let start = caps.get(0).expect("whole match").end();
+        let line = &lua[start..];
+        let line = &line[..line.find(['\n', '\r']).unwrap_or(line.len())];
+        // Arguments with a bracket that never closes take the rest of the line.
+        let end = match expression_end(line) {
+            0 => line.len(),
+            end => end,
+        };
+        if line[..end].trim().is_empty() {
+            continue;
+        }
+        edits.push(TextEdit::new(start - 1..start + end, format!("print({})", &line[..end])));
+    }
+    edits
+}
 
-            match (int_val, frac_val) {
-                (Some(i), Some(f)) => format!("{}0x{:x}.{:x}", prefix, i, f),
-                (Some(i), None) => format!("{}0x{:x}", prefix, i),
-                _ => caps[0].to_string(),
+/// Return where the expression that starts `code` ends, or 0 if a bracket in
+/// it never closes. It ends where a value is followed by the start of another
+/// statement, as in "1 y += 1", by a keyword like `end`, or by a line break,
+/// unless a bracket is open or an operator joins the lines.
+fn expression_end(code: &str) -> usize {
+    const BINARY: &[&str] = &[
+        "+", "-", "*", "/", "\\", "%", "^", "..", "==", "~=", "!=", "<", ">", "<=", ">=", "&",
+        "|", "^^", "<<", ">>", ">>>", "<<>", ">><", "and", "or",
+    ];
+    let mut depth = 0isize;
+    let mut value = false;
+    let mut binary = false;
+    let mut prev_end = 0;
+    for token in lexer::tokenize(code) {
+        if token.kind == lexer::TokenKind::Comment {
+            continue;
+        }
+        let starts_statement = token.kind == lexer::TokenKind::Name
+            || token.is("?")
+            || ["local", "if", "for", "while", "repeat", "do", "goto", "break"]
+                .iter()
+                .any(|k| token.is(k));
+        let ends_statement = ["return", "end", "else", "elseif", "then", "until", ";"]
+            .iter()
+            .any(|k| token.is(k));
+        let is_binary = BINARY.iter().any(|op| token.is(op));
+        let new_line = code[prev_end..token.span.start].contains('\n');
+        if depth == 0
+            && (value && starts_statement
+                || ends_statement
+                || new_line && !binary && !is_binary)
+        {
+            return prev_end;
+        }
+        if token.is("(") || token.is("[") || token.is("{") {
+            depth += 1;
+        } else if token.is(")") || token.is("]") || token.is("}") {
+            if depth == 0 {
+                return prev_end;
             }
-        },
-    );
-    lua
+            depth -= 1;
+        }
+        // A string hidden from the rewrites ends with MASK_CLOSE.
+        value = token.ends_value() || token.text.ends_with(MASK_CLOSE);
+        binary = is_binary;
+        prev_end = token.span.end;
+    }
+    // A bracket that never closes is left to the line's own rewrite.
+    if depth > 0 { 0 } else { prev_end }
+}
+
+/// Return the edits that rewrite the shorthand ifs whose condition spans
+/// lines, e.g. "if (a and\n b) c()", keeping the line breaks of the condition
+/// so line numbers still match.
+fn multiline_shorthand_if_edits(lua: &str) -> Vec<TextEdit> {
+    let mut edits = Vec::new();
+    let mut last = 0;
+    for caps in regex!(r"(?m)^[ \t]*(if\s*\()").captures_iter(lua) {
+        let start = caps.get(1).expect("if").start();
+        let open = caps.get(1).expect("if").end() - 1;
+        if start < last {
+            continue;
+        }
+        let line_end = lua[open..].find('\n').map_or(lua.len(), |i| open + i);
+        let Some(close) = find_matching_paren(lua, open) else {
+            continue;
+        };
+        // Conditions on one line are rewritten with the other shorthand ifs.
+        if close < line_end {
+            continue;
+        }
+        let end = lua[close..].find('\n').map_or(lua.len(), |i| close + i);
+        let rest = &lua[close + 1..end];
+        let (code, comment) = rest.split_at(rest.find("--").unwrap_or(rest.len()));
+        let code = code.trim();
+        if code.is_empty() || regex!(r"\bthen\b").is_match(code) || continues_condition(code) {
+            continue;
+        }
+        let mut replacement = format!(
+            "if {} then {} end",
+            &lua[open + 1..close],
+            shorthand_body(code)
+        );
+        if !comment.is_empty() {
+            replacement.push(' ');
+            replacement.push_str(comment);
+        }
+        edits.push(TextEdit::new(start..end, replacement));
+        last = end;
+    }
+    edits
+}
+
+/// Whether `code` after a parenthesized expression continues the condition of
+/// a regular if, e.g. "and (b or" in "if (a) and (b or\n c) then".
+fn continues_condition(code: &str) -> bool {
+    regex!(r"^(and\b|or\b|[=~<>+*/%^.&|]|-([^-]|$))").is_match(code)
+}
+
+/// Rewrite the body of a shorthand if, e.g. "b() else c()", whose branches
+/// may be shorthand ifs or prints themselves. An `else` belongs to the
+/// nearest if, as in Pico-8.
+fn shorthand_body(body: &str) -> String {
+    if let Some(nested) = nested_shorthand_if(body) {
+        return nested;
+    }
+    let mut depth = 0;
+    let mut at = None;
+    for (i, c) in body.char_indices() {
+        match c {
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' => depth -= 1,
+            'e' if depth == 0 && regex!(r"^\belse\b").is_match(&body[i..]) => {
+                let word_start = body[..i]
+                    .chars()
+                    .next_back()
+                    .is_none_or(|p| !(p.is_alphanumeric() || p == '_'));
+                if word_start {
+                    at = Some(i);
+                    break;
+                }
+            }
+            _ => {}
+        }
+    }
+    let Some(at) = at else {
+        return print_shorthand(body);
+    };
+    let (then, other) = (&body[..at], body[at + "else".len()..].trim_start());
+    let other = nested_shorthand_if(other).unwrap_or_else(|| print_shorthand(other));
+    format!("{} else {}", print_shorthand(then.trim_end()), other)
+}
+
+/// Rewrite `code` if it is a print shorthand, e.g. "?x".
+fn print_shorthand(code: &str) -> String {
+    match code.strip_prefix('?') {
+        Some(args) => format!("print({args})"),
+        None => code.to_string(),
+    }
+}
+
+/// Rewrite `code` if it is a shorthand if or while, e.g. "if (a) b()" or
+/// "while (a) b()", whose body may be one too.
+fn nested_shorthand_if(code: &str) -> Option<String> {
+    let (keyword, then, then_regex, rest) = match code.strip_prefix("if") {
+        Some(rest) => ("if", "then", regex!(r"\bthen\b"), rest),
+        None => ("while", "do", regex!(r"\bdo\b"), code.strip_prefix("while")?),
+    };
+    let rest = rest.trim_start();
+    if !rest.starts_with('(') || then_regex.is_match(rest) {
+        return None;
+    }
+    let index = find_matching_paren(rest, 0)?;
+    let cond = &rest[1..index];
+    Some(format!(
+        "{} {} {} {} end",
+        keyword,
+        cond,
+        then,
+        shorthand_body(rest[index + 1..].trim_start())
+    ))
+}
+
+/// Convert the digits of a binary literal like `0b1010.1`, without the "0b",
+/// to a hex literal like `0xa.8`.
+///
+/// The fraction is exact up to the 16 bits Pico-8 keeps and any further bits
+/// are dropped, as Pico-8 does.
+pub(crate) fn binary_to_hex(bin: &str) -> Option<String> {
+    let (int, frac) = bin.split_once('.').unwrap_or((bin, ""));
+    let int_val = u64::from_str_radix(int, 2).ok()?;
+    let frac = &frac[..frac.len().min(16)];
+    if frac.is_empty() {
+        return Some(format!("0x{:x}", int_val));
+    }
+    // Each hex digit is four bits, so pad the fraction to a multiple of four.
+    let digits = frac.len().div_ceil(4);
+    let padded = format!("{:0<width$}", frac, width = digits * 4);
+    let frac_val = u64::from_str_radix(&padded, 2).ok()?;
+    Some(format!("0x{:x}.{:0width$x}", int_val, frac_val, width = digits))
 }
 
 #[cfg(test)]
@@ -222,6 +1071,23 @@ mod tests {
         assert!(patched.contains("-- this is a comment"));
     }
 
+    #[test]
+    fn test_strings_untouched() {
+        assert_patch(
+            "print(\"http://a.b/?x!=1\") x += 1 // done",
+            "print(\"http://a.b/?x!=1\") x = x + (1) -- done",
+        );
+        assert_patch("s = 'a != b' .. [[\n?0b10\n]]", "s = 'a != b' .. [[\n?0b10\n]]");
+        assert_patch("if (a) print(\"a -- b\") -- c", "if a then print(\"a -- b\") end -- c");
+    }
+
+    #[test]
+    fn test_comments_untouched() {
+        assert_patch("x = 1 -- a != b, c += 1", "x = 1 -- a != b, c += 1");
+        assert_patch("--[[\nif (a) b\n]]\n", "--[[\nif (a) b\n]]\n");
+        assert_patch("// a // b != c\n", "-- a // b != c\n");
+    }
+
     #[test]
     fn test_shorthand_if_rewrite() {
         let lua = "if (not b) i = 1\n";
@@ -267,6 +1133,15 @@ mod tests {
         assert_eq!(patched.trim(), "print(x + y)");
     }
 
+    #[test]
+    fn test_print_shorthand_after_statement() {
+        assert_patch("init(); ?\"ready\"", "init(); print(\"ready\")");
+        assert_patch("for i=1,3 do ?i end", "for i=1,3 do print(i) end");
+        assert_patch("if a then ?\"a\",1,2 else ?b end", "if a then print(\"a\",1,2) else print(b) end");
+        assert_patch("?x -- show x", "print(x) -- show x");
+        assert_patch("repeat ?t[i] i+=1 until i>3", "repeat print(t[i]) i = i + (1) until i>3");
+    }
+
     #[test]
     fn test_binary_literal_conversion_integer() {
         let lua = "a = 0b1010";
@@ -281,6 +1156,15 @@ mod tests {
         assert_eq!(patched.trim(), "a = 0xa.8");
     }
 
+    #[test]
+    fn test_binary_literal_precision() {
+        assert_patch("a = 0b0.000001", "a = 0x0.04");
+        assert_patch("a = 0b1.0000000000000001", "a = 0x1.0001");
+        assert_patch("a = 0b0.00000000000000011", "a = 0x0.0001");
+        assert_patch("0b11 .. 0b1..s", "0x3 .. 0x1..s");
+        assert_patch("a = b0b1", "a = b0b1");
+    }
+
     #[test]
     fn test_mixed_transforms() {
         let lua = r#"
@@ -329,6 +1213,60 @@ mod tests {
         assert!(patched.contains("-- INCLUDE blah.p8"), "{}", &patched);
     }
 
+    #[test]
+    fn test_try_patch_includes_async() {
+        use std::{
+            pin::pin,
+            task::{Context, Poll, Waker},
+        };
+        let mut fetched = Vec::new();
+        let lua = "#include a.lua\n#include b.lua\n#include c.lua\n";
+        let resolve = |path: String| {
+            fetched.push(path.clone());
+            async move {
+                match path.as_str() {
+                    "b.lua" => Err(std::io::Error::other("offline")),
+                    _ => Ok(format!("-- {path}")),
+                }
+            }
+        };
+        let result = {
+            let mut patched = pin!(try_patch_includes_async(lua, resolve));
+            match patched.as_mut().poll(&mut Context::from_waker(Waker::noop())) {
+                Poll::Ready(result) => result,
+                Poll::Pending => panic!("nothing to wait for"),
+            }
+        };
+        assert_eq!(result.unwrap_err().to_string(), "offline");
+        assert_eq!(fetched, ["a.lua", "b.lua"]);
+    }
+
+    #[test]
+    fn test_long_brackets_untouched() {
+        let lua = "--[==[\nif (a) b += 1\n?0b1\n]==]\ns = [[\n#include a.p8\nx != y\n]]\n";
+        assert_patch(lua, lua);
+        assert!(!was_patched(&patch_includes(lua, |_| String::new())));
+        assert_eq!(find_includes(lua).count(), 0);
+        assert_patch("--[[ a ]] x += 1", "--[[ a ]] x = x + (1)");
+    }
+
+    #[test]
+    fn test_includes_in_comments_and_strings() {
+        let lua = "-- #include old.p8\n// #include old.lua\n--[[\n#include a.p8\n]]\n\
+                   s = [==[\n  #include b.lua\n]==]\n#include c.lua\n";
+        assert_eq!(find_includes(lua).collect::<Vec<_>>(), ["c.lua"]);
+        let patched = try_patch_includes(lua, |path| match path {
+            "c.lua" => Ok("c = 3".to_string()),
+            _ => Err(std::io::Error::other(path.to_string())),
+        })
+        .unwrap();
+        assert_eq!(
+            patched,
+            "-- #include old.p8\n// #include old.lua\n--[[\n#include a.p8\n]]\n\
+             s = [==[\n  #include b.lua\n]==]\nc = 3\n"
+        );
+    }
+
     #[test]
     fn test_bad_comment() {
         let lua = "--==configurations==--";
@@ -378,6 +1316,27 @@ mod tests {
         assert_eq!(patched.trim(), "if btnp(4) then");
     }
 
+    #[test]
+    fn test_button_player() {
+        assert_patch("if btn(❎, 1) then", "if btn(5, 1) then");
+        assert_patch("if btnp( ⬅️ ,0 ) then", "if btnp(0,0) then");
+        assert_patch("b = btn (🅾️, p)", "b = btn(4, p)");
+        assert_patch("b = mybtn(⬅️)", "b = mybtn(⬅️)");
+    }
+
+    #[test]
+    fn test_cart_sections_untouched() {
+        // Data that looks like code in every section but `__lua__`.
+        let data = "__gfx__\n?00 // x != 1\nif (a) b += 1\n__label__\n0b11 x+=1\n";
+        let cart = format!("pico-8 cartridge // x\nversion 41\n__lua__\n?x != 1\n{data}");
+        assert_eq!(
+            patch_lua(cart.as_str()),
+            format!("pico-8 cartridge // x\nversion 41\n__lua__\nprint(x ~= 1)\n{data}")
+        );
+        let no_lua = format!("pico-8 cartridge\n{data}");
+        assert!(matches!(patch_lua(no_lua.as_str()), Cow::Borrowed(_)));
+    }
+
     fn assert_patch(unpatched: &str, expected_patched: &str) {
         let patched = patch_lua(unpatched);
         assert_eq!(patched, expected_patched);
@@ -455,6 +1414,176 @@ if ((abs(x) < (a.w+a2.w)) and
                      "       i = i + (1)");
     }
 
+    #[test]
+    fn test_all_assignment_operators() {
+        assert_patch("x \\= 2", "x = x \\ (2)");
+        assert_patch("x ^= 2", "x = x ^ (2)");
+        assert_patch("s..=\"!\"", "s = s .. (\"!\")");
+        assert_patch("t.a &= b | c", "t.a = t.a & (b | c)");
+        assert_patch("x|=1", "x = x | (1)");
+        assert_patch("x^^=1", "x = x ^^ (1)");
+        assert_patch("x<<=1", "x = x << (1)");
+        assert_patch("x >>= 1", "x = x >> (1)");
+        assert_patch("x>>>=1", "x = x >>> (1)");
+        assert_patch("x<<>=1 -- rotate", "x = x <<> (1) -- rotate");
+        assert_patch("x >><= 1", "x = x >>< (1)");
+        assert_patch("if a <= b and c >= d then end", "if a <= b and c >= d then end");
+    }
+
+    #[test]
+    fn test_compound_assignments_on_one_line() {
+        assert_patch("x+=1 y+=1", "x = x + (1) y = y + (1)");
+        assert_patch("x += f(a, b) y -= t[1] z *= 2 -- c", "x = x + (f(a, b)) y = y - (t[1]) z = z * (2) -- c");
+        assert_patch("if (a) x+=1 print(x)", "if a then x = x + (1) print(x) end");
+        assert_patch("s ..= \"a\" .. b", "s = s .. (\"a\" .. b)");
+        assert_patch("s ..= \"a\" y += 1", "s = s .. (\"a\") y = y + (1)");
+    }
+
+    #[test]
+    fn test_compound_assignment_over_lines() {
+        assert_patch("x += a\n + b\ny = 1", "x = x + (a\n + b)\ny = 1");
+        assert_patch("t.v += f(\n  1,2) -- c\n", "t.v = t.v + (f(\n  1,2)) -- c\n");
+        assert_patch("x -= a + -- c\n b y += 1", "x = x - (a + -- c\n b) y = y + (1)");
+        assert_patch("x += 1\n-- c\n(f)()", "x = x + (1)\n-- c\n(f)()");
+    }
+
+    #[test]
+    fn test_peek_shorthand() {
+        assert_patch("x = @0x5f00 + %a * 2 % 3", "x = peek(0x5f00) + peek2(a) * 2 % 3");
+        assert_patch("?$(a+4)", "print(peek4((a+4)))");
+        assert_patch("s = \"%d@%s\"", "s = \"%d@%s\"");
+    }
+
+    #[test]
+    fn test_shorthand_if_else() {
+        assert_patch("if (x>0) y=1 else y=-1", "if x>0 then y=1 else y=-1 end");
+        assert_patch(
+            "if (x>0) y+=1 else y-=1 -- step",
+            "if x>0 then y = y + (1) else y = y - (1) end -- step",
+        );
+        assert_patch(
+            "if (a) b() else if (c) d() else e()",
+            "if a then b() else if c then d() else e() end end",
+        );
+        assert_patch("if (a) if (b) c() else d()", "if a then if b then c() else d() end end");
+        assert_patch("if (a) if (b) c=1", "if a then if b then c=1 end end");
+        assert_patch("if (a) if(b) if (c) x+=1 -- all", "if a then if b then if c then x = x + (1) end end end -- all");
+        assert_patch("if (a) while (b()) c()", "if a then while b() do c() end end");
+    }
+
+    #[test]
+    fn test_shorthand_paren_in_string() {
+        assert_patch("if (s == \")\") x=1", "if s == \")\" then x=1 end");
+        assert_patch("while (s != \"(\") s=f()", "while s ~= \"(\" do s=f() end");
+        assert_patch("if (a) if (c == ')') ?\"(\"", "if a then if c == ')' then print(\"(\") end end");
+        assert_patch("if (s == \")\" and\n t) x=1", "if s == \")\" and\n t then x=1 end");
+        assert!(diagnostics::diagnose("if (s == \")\") x=1").is_empty());
+        assert_patch("if (a) f(elsewhere) else g(x)", "if a then f(elsewhere) else g(x) end");
+    }
+
+    #[test]
+    fn test_shorthand_if_print() {
+        assert_patch("if (debug) ?x", "if debug then print(x) end");
+        assert_patch(
+            "  if (a) ?\"yes\" else ?\"no\" // answer",
+            "  if a then print(\"yes\") else print(\"no\") end -- answer",
+        );
+        assert_patch("while (a) ?b", "while a do print(b) end");
+    }
+
+    #[test]
+    fn test_shorthand_if_multiline() {
+        assert_patch("if (a and\n b) x=1", "if a and\n b then x=1 end");
+        assert_patch(
+            "  if (f(a,\n   b) != 0) y+=1 // hit\nz()",
+            "  if f(a,\n   b) ~= 0 then y = y + (1) end -- hit\nz()",
+        );
+        assert_patch("if (a or\n b) then x=1 end", "if (a or\n b) then x=1 end");
+        assert_patch("if (a or\n b)\nthen x=1 end", "if (a or\n b)\nthen x=1 end");
+        assert_patch(
+            "if (a) and (b or\n c) then x=1 end",
+            "if (a) and (b or\n c) then x=1 end",
+        );
+    }
+
+    #[test]
+    fn test_try_patch_lua() {
+        assert!(try_patch_lua("if btn(⬅️) x += 1").is_ok());
+        let error = try_patch_lua("b = ⬅️\nx @= 1").unwrap_err();
+        assert_eq!(error.diagnostics.len(), 2);
+        assert_eq!(
+            error.to_string(),
+            "1:5: button glyph ⬅️ outside btn() or btnp() and 1 more problem"
+        );
+    }
+
+    #[test]
+    fn test_patch_options() {
+        let lua = "x += 0b1 // see http://x\ny = \"//\"";
+        let options = PatchOptions {
+            slash_comments: false,
+            binary_literals: false,
+            ..PatchOptions::default()
+        };
+        assert_eq!(
+            patch_lua_with(lua, &options),
+            "x = x + (0b1) // see http://x\ny = \"//\""
+        );
+        let options = PatchOptions {
+            compound_assignment: false,
+            ..PatchOptions::default()
+        };
+        assert_eq!(
+            patch_lua_with(lua, &options),
+            "x += 0x1 -- see http://x\ny = \"//\""
+        );
+    }
+
+    #[test]
+    fn test_idempotent() {
+        let sources = [
+            "x += 1 // one != two",
+            "if (a) x+=1 print(x) // again",
+            "while (i < 3) i += 1",
+            "?$(a+4)",
+            "if btn(⬅️) then x = 0b10 end",
+            "if (x>0) y+=1 else y-=1 -- step",
+            "s = \"// not a comment\"",
+        ];
+        for source in sources {
+            let once = patch_lua(source).into_owned();
+            assert_eq!(patch_lua(once.as_str()), once, "{source}");
+        }
+        let marked = "-- converted by pico8-to-lua\nx += 1";
+        assert_eq!(patch_lua(marked), marked);
+    }
+
+    #[test]
+    fn test_strip_comments() {
+        let lua = "-- game\n-- by me\nx += 1 // one\n  -- note\n\
+                   y = \"-- no\" --[[a\nb]] z()\nw=1--[[c]]v=2 --[[d]] u=3\r\n";
+        let options = PatchOptions {
+            strip_comments: true,
+            ..PatchOptions::default()
+        };
+        assert_eq!(
+            patch_lua_with(lua, &options),
+            "-- game\n-- by me\nx = x + (1)\n\ny = \"-- no\"\n z()\nw=1 v=2 u=3\r\n"
+        );
+    }
+
+    #[test]
+    fn test_shorthand_while() {
+        assert_patch("while (i < 3) i += 1", "while i < 3 do i = i + (1) end");
+        assert_patch(
+            "  while (not done()) step() // again",
+            "  while not done() do step() end -- again",
+        );
+        assert_patch("while (a) if (b) c()", "while a do if b then c() end end");
+        assert_patch("while (a) do b() end", "while (a) do b() end");
+        assert_patch("while (a and\n  b) do end", "while (a and\n  b) do end");
+    }
+
     #[test]
     fn test_plist0() {
         let lua = r#"
@@ -475,6 +1604,41 @@ local key = keys[i]
         assert_eq!(find_includes(lua).collect::<Vec<_>>(), vec!["a.p8", "b.lua"]);
     }
 
+    #[test]
+    fn test_quoted_includes() {
+        let lua = "#include \"my lib.lua\"\n#include 'b.p8' -- utils\n#include c.p8 // more\nx = 1\n";
+        assert_eq!(
+            find_includes(lua).collect::<Vec<_>>(),
+            vec!["my lib.lua", "b.p8", "c.p8"]
+        );
+        let patched = patch_includes(lua, |path| format!("-- INCLUDE {}", path));
+        assert_eq!(
+            patched,
+            "-- INCLUDE my lib.lua\n-- INCLUDE b.p8\n-- INCLUDE c.p8\nx = 1\n"
+        );
+        let lua = "\t#include\t\"my file.p8\"\t-- level data\r\n  #include  'a b.lua'  // c\r\n";
+        assert_eq!(
+            find_includes(lua).collect::<Vec<_>>(),
+            vec!["my file.p8", "a b.lua"]
+        );
+        assert_eq!(patch_includes(lua, |path| path.len().to_string()), "10\n7\n");
+    }
+
+
+    #[test]
+    fn test_comment_header_untouched() {
+        assert_patch("-- my game // v2\n-- by me != you\nx += 1 // inc\n",
+                     "-- my game // v2\n-- by me != you\nx = x + (1) -- inc\n");
+        let lua = "-- title\nx = 1";
+        assert!(!was_patched(&patch_lua(lua)));
+    }
+
+    #[test]
+    fn test_unchanged_matches_not_patched() {
+        // Each of these matches a rewrite that leaves it as it is.
+        let lua = "if (a) then b() end\nwhile (a) do b() end\nbtn(0)";
+        assert!(!was_patched(&patch_lua(lua)));
+    }
 
     #[test]
     #[ignore = "need a real parser to fix this; see 'antlr' branch"]
@@ -0,0 +1,196 @@
+//! Find the license of a cart so converted copies keep their attribution.
+//!
+//! Licenses are looked for in the comments that open a cart's code and in
+//! the `__meta:*__` sections newer versions of Pico-8 write. Common license
+//! names are mapped to their [SPDX](https://spdx.org/licenses/) identifiers.
+use std::borrow::Cow;
+
+use lazy_regex::regex;
+
+use crate::{
+    cart::comment_header,
+    lexer::{TokenKind, tokenize},
+};
+
+/// Where a [License] was found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LicenseSource {
+    /// A comment at the start of the code.
+    Comment,
+    /// A `__meta:*__` section of the cart.
+    Meta,
+}
+
+/// A statement of license or copyright.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct License {
+    /// The line that states the license, without comment markers.
+    pub text: String,
+    /// The SPDX identifier of the license, if it could be recognized.
+    pub spdx: Option<String>,
+    /// Where the statement was found.
+    pub source: LicenseSource,
+}
+
+/// Return true if `line` looks like a statement of license or copyright.
+pub fn is_license_line(line: &str) -> bool {
+    regex!(r"(?i)\b(?:licen[cs]e[ds]?|copyright|spdx-license-identifier|cc[- ]?by|cc0|unlicense|public domain)\b|\(c\)|©")
+        .is_match(line)
+}
+
+/// Return the SPDX identifier of the license named in `text`, if any.
+///
+/// ```
+/// use pico8_to_lua::license::spdx_id;
+/// assert_eq!(spdx_id("CC BY-NC-SA 4.0").as_deref(), Some("CC-BY-NC-SA-4.0"));
+/// assert_eq!(spdx_id("MIT License").as_deref(), Some("MIT"));
+/// ```
+pub fn spdx_id(text: &str) -> Option<String> {
+    if let Some(caps) = regex!(r"(?i)SPDX-License-Identifier:\s*(\S+)").captures(text) {
+        return Some(caps[1].to_string());
+    }
+    if let Some(caps) =
+        regex!(r"(?i)\bcc[- ]?(by(?:[- ](?:nc|sa|nd))*)\b(?:[- ]*(\d\.\d))?").captures(text)
+    {
+        let parts = caps[1].to_uppercase().replace(' ', "-");
+        let version = caps.get(2).map_or("4.0", |m| m.as_str());
+        return Some(format!("CC-{parts}-{version}"));
+    }
+    let known = [
+        (regex!(r"(?i)\bcc0\b"), "CC0-1.0"),
+        (regex!(r"(?i)\bunlicense\b"), "Unlicense"),
+        (
+            regex!(r"(?i)\bapache(?: license)?,?(?: version)? 2(?:\.0)?\b"),
+            "Apache-2.0",
+        ),
+        (regex!(r"(?i)\bmit\b"), "MIT"),
+    ];
+    known
+        .iter()
+        .find(|(re, _)| re.is_match(text))
+        .map(|(_, id)| id.to_string())
+}
+
+/// Return the text of a comment without its markers or long brackets.
+fn comment_body(comment: &str) -> &str {
+    let body = &comment[2..];
+    let level = body
+        .strip_prefix('[')
+        .map(|rest| rest.bytes().take_while(|&b| b == b'=').count());
+    match level {
+        Some(level) if body.as_bytes().get(level + 1) == Some(&b'[') => {
+            let close = format!("]{}]", "=".repeat(level));
+            let body = &body[level + 2..];
+            body.strip_suffix(close.as_str()).unwrap_or(body)
+        }
+        _ => body,
+    }
+}
+
+fn license(line: &str, source: LicenseSource) -> Option<License> {
+    let text = line.trim().trim_start_matches('-').trim();
+    is_license_line(text).then(|| License {
+        text: text.to_string(),
+        spdx: spdx_id(text),
+        source,
+    })
+}
+
+/// Find the licenses stated in the comments that open `lua`.
+pub fn lua_licenses(lua: &str) -> Vec<License> {
+    tokenize(lua)
+        .take_while(|t| t.kind == TokenKind::Comment)
+        .flat_map(|t| comment_body(t.text).lines())
+        .filter_map(|line| license(line, LicenseSource::Comment))
+        .collect()
+}
+
+/// Find the licenses stated in the body of a `__meta:*__` section.
+pub fn meta_licenses(body: &str) -> Vec<License> {
+    body.lines()
+        .filter_map(|line| license(line, LicenseSource::Meta))
+        .collect()
+}
+
+/// Add comments stating `licenses` to `lua` unless it already does.
+///
+/// Licenses found only in the cart's metadata are copied as comments, and an
+/// `SPDX-License-Identifier` tag is added for each recognized license that
+/// has none. The comments go after the cart's title and author so Pico-8
+/// still finds those on the first two lines.
+pub fn with_license_header<'h>(lua: Cow<'h, str>, licenses: &[License]) -> Cow<'h, str> {
+    let mut header = String::new();
+    for license in licenses {
+        let line = format!("-- {}\n", license.text);
+        if license.source == LicenseSource::Meta
+            && !lua.contains(&license.text)
+            && !header.contains(&line)
+        {
+            header.push_str(&line);
+        }
+    }
+    for id in licenses.iter().filter_map(|l| l.spdx.as_deref()) {
+        let tag = format!("-- SPDX-License-Identifier: {id}\n");
+        let tagged = regex!(r"(?i)SPDX-License-Identifier:\s*(\S+)")
+            .captures_iter(&lua)
+            .any(|caps| &caps[1] == id);
+        if !tagged && !header.contains(&tag) {
+            header.push_str(&tag);
+        }
+    }
+    if header.is_empty() {
+        return lua;
+    }
+    let at = comment_header(&lua).len();
+    let mut out = String::with_capacity(lua.len() + header.len());
+    out.push_str(&lua[..at]);
+    if at > 0 && !out.ends_with('\n') {
+        out.push('\n');
+    }
+    out.push_str(&header);
+    out.push_str(&lua[at..]);
+    Cow::Owned(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lua_licenses() {
+        let lua = "-- my game\n-- by me\n--[[\ncopyright 2024 me\nlicensed under cc by-sa 3.0\n]]\nx = 1 -- mit license\n";
+        let licenses = lua_licenses(lua);
+        assert_eq!(licenses.len(), 2);
+        assert_eq!(licenses[0].text, "copyright 2024 me");
+        assert_eq!(licenses[0].spdx, None);
+        assert_eq!(licenses[1].spdx.as_deref(), Some("CC-BY-SA-3.0"));
+    }
+
+    #[test]
+    fn test_spdx_id() {
+        assert_eq!(
+            spdx_id("SPDX-License-Identifier: GPL-3.0-or-later").as_deref(),
+            Some("GPL-3.0-or-later")
+        );
+        assert_eq!(spdx_id("released as CC0").as_deref(), Some("CC0-1.0"));
+        assert_eq!(
+            spdx_id("Apache License, Version 2.0").as_deref(),
+            Some("Apache-2.0")
+        );
+        assert_eq!(spdx_id("(c) me, all rights reserved"), None);
+    }
+
+    #[test]
+    fn test_with_license_header() {
+        let licenses = meta_licenses("license: CC BY-NC-SA 4.0\n");
+        assert_eq!(
+            with_license_header("-- my game\n-- by me\nx = 1\n".into(), &licenses),
+            "-- my game\n-- by me\n-- license: CC BY-NC-SA 4.0\n-- SPDX-License-Identifier: CC-BY-NC-SA-4.0\nx = 1\n"
+        );
+        let lua = "-- SPDX-License-Identifier: MIT\nx = 1\n";
+        assert!(matches!(
+            with_license_header(lua.into(), &lua_licenses(lua)),
+            Cow::Borrowed(_)
+        ));
+    }
+}
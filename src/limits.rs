@@ -0,0 +1,138 @@
+//! Limits on what a conversion may take, for converting untrusted carts.
+//!
+//! A service that converts the carts its users submit can bound the size of
+//! the input, the code that includes add however deep they nest, and the
+//! changes the conversion makes. Going over a limit is a [LimitError] rather
+//! than unbounded time and memory spent on a degenerate cart or on a chain of
+//! includes that multiplies itself. Set [Options::limits](crate::Options) for
+//! the conversion and [FsResolver::limits](crate::FsResolver) for the
+//! includes.
+use std::{error::Error, fmt};
+
+use crate::pipeline::line_changes;
+
+/// How much a conversion may take.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Limits {
+    /// The most bytes of input.
+    pub max_input: usize,
+    /// The most bytes the includes may add in all, counting a file each time
+    /// it is included.
+    pub max_include_bytes: usize,
+    /// The deepest includes may nest. 1 allows the code being converted to
+    /// include files but not the files it includes.
+    pub max_include_depth: usize,
+    /// The most changes the conversion may make, counting each line it
+    /// changes once.
+    pub max_changes: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_input: 1 << 20,
+            max_include_bytes: 4 << 20,
+            max_include_depth: 8,
+            max_changes: 100_000,
+        }
+    }
+}
+
+/// A limit that a conversion went over.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LimitError {
+    /// The input is larger than [Limits::max_input].
+    Input {
+        /// The bytes of input.
+        size: usize,
+        /// The limit.
+        limit: usize,
+    },
+    /// The includes add more than [Limits::max_include_bytes].
+    IncludeBytes {
+        /// The limit.
+        limit: usize,
+    },
+    /// The includes nest deeper than [Limits::max_include_depth].
+    IncludeDepth {
+        /// The limit.
+        limit: usize,
+    },
+    /// The conversion changes more lines than [Limits::max_changes].
+    Changes {
+        /// The lines changed.
+        count: usize,
+        /// The limit.
+        limit: usize,
+    },
+}
+
+impl fmt::Display for LimitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LimitError::Input { size, limit } => {
+                write!(f, "input of {size} bytes is over the limit of {limit}")
+            }
+            LimitError::IncludeBytes { limit } => {
+                write!(f, "includes add more than the limit of {limit} bytes")
+            }
+            LimitError::IncludeDepth { limit } => {
+                write!(f, "includes nest deeper than the limit of {limit}")
+            }
+            LimitError::Changes { count, limit } => {
+                write!(f, "{count} changed lines are over the limit of {limit}")
+            }
+        }
+    }
+}
+
+impl Error for LimitError {}
+
+impl Limits {
+    /// Check the size of `input`.
+    ///
+    /// ```
+    /// use pico8_to_lua::limits::{LimitError, Limits};
+    /// let limits = Limits { max_input: 4, ..Limits::default() };
+    /// assert_eq!(limits.check_input("x = 1"), Err(LimitError::Input { size: 5, limit: 4 }));
+    /// ```
+    pub fn check_input(&self, input: &str) -> Result<(), LimitError> {
+        if input.len() > self.max_input {
+            return Err(LimitError::Input {
+                size: input.len(),
+                limit: self.max_input,
+            });
+        }
+        Ok(())
+    }
+
+    /// Check the lines a conversion changed to turn `before` into `after`.
+    pub fn check_changes(&self, before: &str, after: &str) -> Result<(), LimitError> {
+        let count = line_changes(before, after).len();
+        if count > self.max_changes {
+            return Err(LimitError::Changes {
+                count,
+                limit: self.max_changes,
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_changes() {
+        let limits = Limits {
+            max_changes: 1,
+            ..Limits::default()
+        };
+        assert_eq!(limits.check_changes("a\nb", "a\nc"), Ok(()));
+        assert_eq!(
+            limits.check_changes("a\nb", "c\nd"),
+            Err(LimitError::Changes { count: 2, limit: 1 })
+        );
+    }
+}
@@ -0,0 +1,150 @@
+//! Loading carts into an embedded Lua, with the `mlua` feature.
+//!
+//! [load_cart] does what an engine that runs carts needs in one step: it
+//! reads a ".p8" cart or Pico-8 Lua file, resolves its includes, converts its
+//! code for Lua 5.4, and compiles it into a function named after the file.
+//! Errors in the function point at the lines of the file.
+use std::{error::Error, fmt, fs, io, path::Path};
+
+use crate::{
+    FsResolver, IncludeError, Options,
+    batch::Converter,
+    bitwise::LuaTarget,
+    cart::{Cart, CartError, is_cart},
+    try_patch_includes,
+};
+
+/// An error encountered while loading a cart.
+#[derive(Debug)]
+pub enum LoadError {
+    /// The file could not be read.
+    Io(io::Error),
+    /// The cart could not be parsed.
+    Cart(CartError),
+    /// An included file could not be read.
+    Include(IncludeError),
+    /// The converted code did not compile.
+    Lua(mlua::Error),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Io(e) => write!(f, "cannot read cart: {e}"),
+            LoadError::Cart(e) => write!(f, "cannot parse cart: {e}"),
+            LoadError::Include(e) => write!(f, "{e}"),
+            LoadError::Lua(e) => write!(f, "cannot compile cart: {e}"),
+        }
+    }
+}
+
+impl Error for LoadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            LoadError::Io(e) => Some(e),
+            LoadError::Cart(e) => Some(e),
+            LoadError::Include(e) => Some(e),
+            LoadError::Lua(e) => Some(e),
+        }
+    }
+}
+
+impl From<io::Error> for LoadError {
+    fn from(e: io::Error) -> Self {
+        LoadError::Io(e)
+    }
+}
+
+impl From<CartError> for LoadError {
+    fn from(e: CartError) -> Self {
+        LoadError::Cart(e)
+    }
+}
+
+impl From<IncludeError> for LoadError {
+    fn from(e: IncludeError) -> Self {
+        LoadError::Include(e)
+    }
+}
+
+impl From<mlua::Error> for LoadError {
+    fn from(e: mlua::Error) -> Self {
+        LoadError::Lua(e)
+    }
+}
+
+/// Load the cart or Pico-8 Lua file at `path` into `lua` as a function that
+/// runs its code, resolving includes next to it.
+///
+/// ```no_run
+/// let lua = mlua::Lua::new();
+/// pico8_to_lua::loader::load_cart(&lua, "game.p8").unwrap().call::<()>(()).unwrap();
+/// ```
+pub fn load_cart(lua: &mlua::Lua, path: impl AsRef<Path>) -> Result<mlua::Function, LoadError> {
+    let path = path.as_ref();
+    let options = Options {
+        target: Some(LuaTarget::Lua54),
+        preserve_lines: true,
+        ..Options::default()
+    };
+    load_cart_with(
+        lua,
+        path,
+        &options,
+        &mut FsResolver::for_cart(path, Vec::<&Path>::new()),
+    )
+}
+
+/// Load the cart or Pico-8 Lua file at `path` into `lua` like [load_cart],
+/// converting with `options` and resolving includes with `resolver`.
+pub fn load_cart_with(
+    lua: &mlua::Lua,
+    path: impl AsRef<Path>,
+    options: &Options,
+    resolver: &mut FsResolver,
+) -> Result<mlua::Function, LoadError> {
+    let path = path.as_ref();
+    let text = fs::read_to_string(path)?;
+    let (code, offset) = if is_cart(&text) {
+        let cart = Cart::parse(&text)?;
+        // The code starts on the line after "__lua__".
+        let offset = text
+            .find("__lua__\n")
+            .map_or(0, |at| text[..at].matches('\n').count() + 1);
+        (cart.lua().unwrap_or("").to_string(), offset)
+    } else {
+        (text, 0)
+    };
+    let code = try_patch_includes(code.as_str(), |include| resolver.resolve(include))?;
+    let converted = Converter::new(options.clone()).convert(&code)?;
+    let chunk = format!("{}{converted}", "\n".repeat(offset));
+    Ok(lua
+        .load(chunk)
+        .set_name(format!("@{}", path.display()))
+        .into_function()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_cart() {
+        let root = std::env::temp_dir().join(format!("pico8-to-lua-loader-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("lib.lua"), "function inc(n) return n + 1 end\n").unwrap();
+        let cart = root.join("game.p8");
+        fs::write(
+            &cart,
+            "pico-8 cartridge\nversion 41\n__lua__\n#include lib.lua\nx = 1\nx += inc(1)\nif (x != 3) error(\"bad\")\n",
+        )
+        .unwrap();
+        let lua = mlua::Lua::new();
+        load_cart(&lua, &cart).unwrap().call::<()>(()).unwrap();
+        assert_eq!(lua.globals().get::<i64>("x").unwrap(), 3);
+
+        fs::write(&cart, "pico-8 cartridge\n__lua__\nx = 1\ny = = 2\n").unwrap();
+        let error = load_cart(&lua, &cart).unwrap_err().to_string();
+        assert!(error.contains("game.p8:4:"), "{error}");
+    }
+}
@@ -0,0 +1,155 @@
+//! Rewriting Pico-8's `all` and `foreach` loops as plain Lua loops.
+//!
+//! `for v in all(t) do` and `foreach(t, f)` need shims for `all` and
+//! `foreach` in plain Lua, and a shim's closure per loop is slower than
+//! `ipairs`, especially under LuaJIT. [patch_all_loops] rewrites them as
+//! `for _, v in ipairs(t) do` and an explicit loop that calls `f`.
+//!
+//! Pico-8's `all` and `foreach` keep going when the loop deletes the item it
+//! is on, where `ipairs` skips the item after it, so the rewrite is only for
+//! carts that do not delete while they iterate.
+use std::borrow::Cow;
+
+use crate::{
+    api::api_calls,
+    lexer::{Token, TokenKind, tokenize},
+    span::{Span, TextEdit, apply_edits},
+    symbols::{DEFAULT_PREFIX, Namer},
+};
+
+/// Rewrite `all` loops and `foreach` statements as `ipairs` loops, naming
+/// the locals they need with [DEFAULT_PREFIX].
+///
+/// ```
+/// use pico8_to_lua::loops::patch_all_loops;
+/// assert_eq!(
+///     patch_all_loops("for e in all(enemies) do e:update() end"),
+///     "for _, e in ipairs(enemies) do e:update() end"
+/// );
+/// assert_eq!(
+///     patch_all_loops("foreach(enemies, draw)"),
+///     "for _, __p8_v in ipairs(enemies) do draw(__p8_v) end"
+/// );
+/// ```
+pub fn patch_all_loops<'h>(lua: impl Into<Cow<'h, str>>) -> Cow<'h, str> {
+    let lua = lua.into();
+    let mut namer = Namer::new(DEFAULT_PREFIX, &lua);
+    patch_all_loops_with(lua, &mut namer)
+}
+
+/// Rewrite `all` loops and `foreach` statements like [patch_all_loops],
+/// naming the locals with `namer`.
+pub fn patch_all_loops_with<'h>(lua: impl Into<Cow<'h, str>>, namer: &mut Namer) -> Cow<'h, str> {
+    let lua = lua.into();
+    let tokens: Vec<Token> = tokenize(&lua)
+        .filter(|t| t.kind != TokenKind::Comment)
+        .collect();
+    let mut edits = Vec::new();
+    for (name, i) in api_calls(&tokens) {
+        let Some((close, commas)) = arguments(&tokens, i + 1) else {
+            continue;
+        };
+        let call = Span::new(tokens[i].span.start, tokens[i + 1].span.end);
+        match (name, commas.as_slice()) {
+            ("all", []) if close > i + 2 && for_in(&tokens, i, close) => {
+                let var = &tokens[i - 2];
+                edits.push(TextEdit::new(var.span, format!("_, {}", var.text)));
+                edits.push(TextEdit::new(tokens[i].span, "ipairs"));
+            }
+            ("foreach", &[comma])
+                if comma > i + 2 && close > comma + 1 && statement(&tokens, i) =>
+            {
+                let v = namer.name("v");
+                if close == comma + 2 && tokens[comma + 1].kind == TokenKind::Name {
+                    let f = tokens[comma + 1].text;
+                    edits.push(TextEdit::new(call, format!("for _, {v} in ipairs(")));
+                    edits.push(TextEdit::new(
+                        Span::new(tokens[comma].span.start, tokens[close].span.end),
+                        format!(") do {f}({v}) end"),
+                    ));
+                } else {
+                    let (t, f) = (namer.name("t"), namer.name("f"));
+                    edits.push(TextEdit::new(call, format!("do local {t}, {f} = ")));
+                    edits.push(TextEdit::new(
+                        tokens[close].span,
+                        format!(" for _, {v} in ipairs({t}) do {f}({v}) end end"),
+                    ));
+                }
+            }
+            _ => {}
+        }
+    }
+    if edits.is_empty() {
+        return lua;
+    }
+    Cow::Owned(apply_edits(&lua, &edits))
+}
+
+/// Return whether the call whose name is `tokens[i]` and whose arguments
+/// close at `tokens[close]` is all of `for name in ... do`.
+fn for_in(tokens: &[Token], i: usize, close: usize) -> bool {
+    i >= 3
+        && tokens[i - 1].is("in")
+        && tokens[i - 2].kind == TokenKind::Name
+        && tokens[i - 3].is("for")
+        && tokens.get(close + 1).is_some_and(|t| t.is("do"))
+}
+
+/// Return whether the call whose name is `tokens[i]` starts a statement, so
+/// its value is not used.
+pub(crate) fn statement(tokens: &[Token], i: usize) -> bool {
+    match i.checked_sub(1).map(|i| &tokens[i]) {
+        None => true,
+        Some(prev) => {
+            prev.ends_value()
+                || ["do", "then", "else", "repeat", ";"]
+                    .iter()
+                    .any(|s| prev.is(s))
+        }
+    }
+}
+
+/// If `tokens[open]` opens the arguments of a call, return the index of the
+/// parenthesis that closes them and the indices of the commas between them.
+pub(crate) fn arguments(tokens: &[Token], open: usize) -> Option<(usize, Vec<usize>)> {
+    if !tokens.get(open)?.is("(") {
+        return None;
+    }
+    let mut depth = 0;
+    let mut commas = Vec::new();
+    for (i, token) in tokens.iter().enumerate().skip(open + 1) {
+        if token.kind != TokenKind::Punct {
+            continue;
+        }
+        match token.text {
+            "(" | "[" | "{" => depth += 1,
+            ")" if depth == 0 => return Some((i, commas)),
+            ")" | "]" | "}" => depth -= 1,
+            "," if depth == 0 => commas.push(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_patch_all_loops() {
+        assert_eq!(
+            patch_all_loops("for v in all(f(a, b)) do\nfor w in all(v) do end\nend"),
+            "for _, v in ipairs(f(a, b)) do\nfor _, w in ipairs(v) do end\nend"
+        );
+        assert_eq!(
+            patch_all_loops("if x then foreach(t, function(e)\ne.x += 1\nend) end"),
+            "if x then do local __p8_t, __p8_f = t, function(e)\ne.x += 1\nend for _, __p8_v in \
+             ipairs(__p8_t) do __p8_f(__p8_v) end end end"
+        );
+        assert!(matches!(
+            patch_all_loops("x = foreach(t, f)\nfor k, v in all(t) do end\n-- foreach(t, f)"),
+            Cow::Borrowed(_)
+        ));
+    }
+}
@@ -0,0 +1,202 @@
+//! Exporting a cart as a LÖVE project.
+//!
+//! [export] writes everything `love` needs to run a cart:
+//!
+//! - "main.lua", the converted code;
+//! - "conf.lua", the window;
+//! - "data.lua", the palette, spritesheet, map, and sprite flags;
+//! - "pico8.lua", a runtime adapter that implements Pico-8's API with LÖVE's
+//!   and calls the cart's `_init`, `_update`, and `_draw`.
+//!
+//! With the `png` feature the spritesheet is written as "spritesheet.png"
+//! too. The adapter prefers it to "data.lua", so the sprites can be edited
+//! with any image editor.
+use std::{error::Error, fmt, io};
+
+use crate::{
+    Options,
+    batch::Converter,
+    bitwise::LuaTarget,
+    cart::{Cart, CartError, MAP_HEIGHT, MAP_WIDTH, SHEET_SIZE, SpriteFlags, Spritesheet, TileMap},
+    label::PALETTE,
+    shim::{self, SHIMS},
+    sink::OutputSink,
+};
+
+/// The runtime adapter without the shims.
+const RUNTIME: &str = include_str!("love/pico8.lua");
+
+/// An error encountered while exporting a cart.
+#[derive(Debug)]
+pub enum ExportError {
+    /// A section of the cart could not be read.
+    Cart(CartError),
+    /// A file could not be written.
+    Io(io::Error),
+}
+
+impl fmt::Display for ExportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExportError::Cart(e) => write!(f, "cannot read cart: {e}"),
+            ExportError::Io(e) => write!(f, "cannot write project: {e}"),
+        }
+    }
+}
+
+impl Error for ExportError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ExportError::Cart(e) => Some(e),
+            ExportError::Io(e) => Some(e),
+        }
+    }
+}
+
+impl From<CartError> for ExportError {
+    fn from(e: CartError) -> Self {
+        ExportError::Cart(e)
+    }
+}
+
+impl From<io::Error> for ExportError {
+    fn from(e: io::Error) -> Self {
+        ExportError::Io(e)
+    }
+}
+
+/// Quote `s` as a Lua string.
+pub(crate) fn lua_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\{}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Return "main.lua": the converted code of `cart` for LÖVE's LuaJIT, which
+/// loads the runtime adapter after its comment header.
+pub fn main_lua(cart: &Cart) -> Result<String, CartError> {
+    let options = Options {
+        lua_only: true,
+        target: Some(LuaTarget::LuaJit),
+        ..Options::default()
+    };
+    let lua = Converter::new(options).convert(cart.lua().unwrap_or(""))?;
+    let at = crate::cart::comment_header(&lua).len();
+    Ok(format!("{}require(\"pico8\")\n{}", &lua[..at], &lua[at..]))
+}
+
+/// Return "conf.lua", which opens a resizable window titled after `cart`.
+pub fn conf_lua(cart: &Cart) -> String {
+    format!(
+        "function love.conf(t)\n  t.window.title = {}\n  t.window.width = 512\n  \
+         t.window.height = 512\n  t.window.resizable = true\nend\n",
+        lua_string(cart.title().unwrap_or("Pico-8"))
+    )
+}
+
+/// Return "data.lua", which returns the palette, the rows of the spritesheet
+/// and the map in hex, and the sprite flags in hex.
+pub fn data_lua(sheet: &Spritesheet, map: &TileMap, flags: &SpriteFlags) -> String {
+    let mut out = String::from("return {\n  palette = {");
+    let colors: Vec<String> = PALETTE
+        .iter()
+        .map(|[r, g, b]| format!("{{{r}, {g}, {b}}}"))
+        .collect();
+    out.push_str(&colors.join(", "));
+    out.push_str("},\n  gfx = {\n");
+    for row in sheet.pixels.chunks(SHEET_SIZE) {
+        let hex: String = row.iter().map(|c| format!("{c:x}")).collect();
+        out.push_str(&format!("    \"{hex}\",\n"));
+    }
+    out.push_str("  },\n  map = {\n");
+    for row in map.tiles.chunks(MAP_WIDTH).take(MAP_HEIGHT) {
+        let hex: String = row.iter().map(|t| format!("{t:02x}")).collect();
+        out.push_str(&format!("    \"{hex}\",\n"));
+    }
+    let hex: String = flags.0.iter().map(|f| format!("{f:02x}")).collect();
+    out.push_str(&format!("  }},\n  flags = \"{hex}\",\n}}\n"));
+    out
+}
+
+/// Return "pico8.lua", the runtime adapter, with every shim except those it
+/// implements with LÖVE.
+pub fn runtime() -> String {
+    let shims: Vec<_> = SHIMS
+        .iter()
+        .filter(|s| !matches!(s.name, "print" | "time" | "t"))
+        .collect();
+    format!(
+        "{RUNTIME}\n-- Pico-8's functions that plain Lua can provide.\n{}",
+        shim::module(&shims)
+    )
+}
+
+/// Write a LÖVE project that runs `cart` to `sink` and finish the sink.
+///
+/// ```
+/// use pico8_to_lua::{Cart, love::export, sink::Memory};
+/// let cart = Cart::parse("pico-8 cartridge\n__lua__\n-- game\nx += 1\n").unwrap();
+/// let mut memory = Memory::default();
+/// export(&cart, &mut memory).unwrap();
+/// assert_eq!(memory.files["main.lua"], b"-- game\nrequire(\"pico8\")\nx = x + (1)\n");
+/// ```
+pub fn export(cart: &Cart, sink: &mut impl OutputSink) -> Result<(), ExportError> {
+    let sheet = cart.gfx()?;
+    let data = data_lua(&sheet, &cart.map()?, &cart.sprite_flags()?);
+    sink.write("main.lua", main_lua(cart)?.as_bytes())?;
+    sink.write("conf.lua", conf_lua(cart).as_bytes())?;
+    sink.write("data.lua", data.as_bytes())?;
+    sink.write("pico8.lua", runtime().as_bytes())?;
+    #[cfg(feature = "png")]
+    {
+        let mut png = Vec::new();
+        sheet.write_png(&mut png).map_err(io::Error::other)?;
+        sink.write("spritesheet.png", &png)?;
+    }
+    sink.finish()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::Memory;
+
+    #[test]
+    fn test_export() {
+        let text = "pico-8 cartridge\n__lua__\n-- \"big\" game\nx = 1 & 3\n__gfx__\n";
+        let cart = Cart::parse(&format!("{text}0a{}\n", "0".repeat(126))).unwrap();
+        let mut memory = Memory::default();
+        export(&cart, &mut memory).unwrap();
+        let file = |name: &str| String::from_utf8(memory.files[name].clone()).unwrap();
+        assert!(file("main.lua").ends_with("x = __p8_band(1, 3)\n"));
+        assert!(file("conf.lua").contains("t.window.title = \"\\\"big\\\" game\"\n"));
+        let data = file("data.lua");
+        assert!(data.contains("  gfx = {\n    \"0a00"));
+        assert_eq!(data.matches("\",\n").count(), SHEET_SIZE + MAP_HEIGHT + 1);
+        assert!(data.ends_with(&format!("  flags = \"{}\",\n}}\n", "0".repeat(512))));
+        let runtime = file("pico8.lua");
+        assert!(runtime.contains("function flr(x)"));
+        assert!(!runtime.contains("function print(s)"));
+    }
+
+    #[test]
+    fn test_bad_gfx() {
+        let cart = Cart::parse("pico-8 cartridge\n__gfx__\nxy\n").unwrap();
+        let error = export(&cart, &mut Memory::default()).unwrap_err();
+        assert!(matches!(
+            error,
+            ExportError::Cart(CartError::BadLength { .. })
+        ));
+    }
+}
@@ -0,0 +1,246 @@
+-- Runs a converted Pico-8 cart in LÖVE.
+--
+-- Pico-8's drawing functions draw to a 128x128 canvas that is scaled to fit
+-- the window, and its callbacks are called at 30 or 60 frames per second.
+-- Sound, the palette functions, and fill patterns do nothing yet.
+local data = require("data")
+local palette = data.palette
+
+local canvas, sheet, font
+local draw_color = 6
+local camera_x, camera_y = 0, 0
+local cursor_x, cursor_y = 0, 0
+local tiles, flags = {}, {}
+local down, was_down = {}, {}
+local start = 0
+local elapsed = 0
+
+local function set_color(c)
+  if c then draw_color = math.floor(c) % 16 end
+  local rgb = palette[draw_color + 1]
+  love.graphics.setColor(rgb[1] / 255, rgb[2] / 255, rgb[3] / 255)
+end
+
+local function load_sheet()
+  local image
+  if love.filesystem.getInfo("spritesheet.png") then
+    image = love.image.newImageData("spritesheet.png")
+  else
+    image = love.image.newImageData(128, 128)
+    for y, row in ipairs(data.gfx) do
+      for x = 1, #row do
+        local rgb = palette[tonumber(row:sub(x, x), 16) + 1]
+        image:setPixel(x - 1, y - 1, rgb[1] / 255, rgb[2] / 255, rgb[3] / 255, 1)
+      end
+    end
+  end
+  -- Color 0 is transparent in sprites, as it is by default in Pico-8.
+  image:mapPixel(function(_, _, r, g, b, a)
+    if r == 0 and g == 0 and b == 0 then return 0, 0, 0, 0 end
+    return r, g, b, a
+  end)
+  return love.graphics.newImage(image)
+end
+
+local function load_map()
+  for y, row in ipairs(data.map) do
+    for x = 0, 127 do
+      tiles[(y - 1) * 128 + x] = tonumber(row:sub(2 * x + 1, 2 * x + 2), 16)
+    end
+  end
+  for i = 0, 255 do
+    flags[i] = tonumber(data.flags:sub(2 * i + 1, 2 * i + 2), 16) or 0
+  end
+end
+
+function cls(c)
+  local rgb = palette[math.floor(c or 0) % 16 + 1]
+  love.graphics.clear(rgb[1] / 255, rgb[2] / 255, rgb[3] / 255)
+  cursor_x, cursor_y = 0, 0
+end
+
+function color(c) draw_color = math.floor(c or 6) % 16 end
+
+function camera(x, y)
+  local old_x, old_y = camera_x, camera_y
+  camera_x, camera_y = math.floor(x or 0), math.floor(y or 0)
+  return old_x, old_y
+end
+
+function clip(x, y, w, h)
+  if x then love.graphics.setScissor(x, y, w, h) else love.graphics.setScissor() end
+end
+
+function pset(x, y, c)
+  set_color(c)
+  love.graphics.points(math.floor(x) - camera_x + 0.5, math.floor(y) - camera_y + 0.5)
+end
+
+-- The canvas can't be read while it is drawn to.
+function pget(x, y) return 0 end
+
+function line(x0, y0, x1, y1, c)
+  set_color(c)
+  love.graphics.line(x0 - camera_x + 0.5, y0 - camera_y + 0.5, x1 - camera_x + 0.5, y1 - camera_y + 0.5)
+end
+
+function rect(x0, y0, x1, y1, c)
+  set_color(c)
+  x0, x1 = math.min(x0, x1), math.max(x0, x1)
+  y0, y1 = math.min(y0, y1), math.max(y0, y1)
+  love.graphics.rectangle("line", x0 - camera_x + 0.5, y0 - camera_y + 0.5, x1 - x0, y1 - y0)
+end
+
+function rectfill(x0, y0, x1, y1, c)
+  set_color(c)
+  x0, x1 = math.min(x0, x1), math.max(x0, x1)
+  y0, y1 = math.min(y0, y1), math.max(y0, y1)
+  love.graphics.rectangle("fill", x0 - camera_x, y0 - camera_y, x1 - x0 + 1, y1 - y0 + 1)
+end
+
+function circ(x, y, r, c)
+  set_color(c)
+  love.graphics.circle("line", x - camera_x + 0.5, y - camera_y + 0.5, r or 4)
+end
+
+function circfill(x, y, r, c)
+  set_color(c)
+  love.graphics.circle("fill", x - camera_x + 0.5, y - camera_y + 0.5, r or 4)
+end
+
+function sspr(sx, sy, sw, sh, dx, dy, dw, dh, flip_x, flip_y)
+  dw, dh = dw or sw, dh or sh
+  local quad = love.graphics.newQuad(sx, sy, sw, sh, 128, 128)
+  love.graphics.setColor(1, 1, 1)
+  love.graphics.draw(sheet, quad,
+    math.floor(dx) - camera_x + (flip_x and dw or 0),
+    math.floor(dy) - camera_y + (flip_y and dh or 0),
+    0, (flip_x and -1 or 1) * dw / sw, (flip_y and -1 or 1) * dh / sh)
+end
+
+function spr(n, x, y, w, h, flip_x, flip_y)
+  n = math.floor(n or 0)
+  w, h = w or 1, h or 1
+  sspr(n % 16 * 8, math.floor(n / 16) * 8, w * 8, h * 8, x or 0, y or 0, w * 8, h * 8, flip_x, flip_y)
+end
+
+function mget(x, y) return tiles[math.floor(y) * 128 + math.floor(x)] or 0 end
+
+function mset(x, y, n) tiles[math.floor(y) * 128 + math.floor(x)] = n end
+
+function fget(n, f)
+  local bits = flags[math.floor(n or 0)] or 0
+  if f == nil then return bits end
+  return math.floor(bits / 2 ^ f) % 2 == 1
+end
+
+function fset(n, f, v)
+  if v == nil then flags[n] = f return end
+  local mask = 2 ^ f
+  local set = math.floor(flags[n] / mask) % 2 == 1
+  if v and not set then flags[n] = flags[n] + mask end
+  if not v and set then flags[n] = flags[n] - mask end
+end
+
+function map(cx, cy, sx, sy, cw, ch, layers)
+  cx, cy, sx, sy = cx or 0, cy or 0, sx or 0, sy or 0
+  for y = 0, (ch or 64) - 1 do
+    for x = 0, (cw or 128) - 1 do
+      local n = mget(cx + x, cy + y)
+      if n ~= 0 and (not layers or bit.band(fget(n), layers) == layers) then
+        spr(n, sx + x * 8, sy + y * 8)
+      end
+    end
+  end
+end
+
+function print(s, x, y, c)
+  if type(x) == "number" then cursor_x, cursor_y = x, y else c = x end
+  set_color(c)
+  love.graphics.print(tostr(s), cursor_x - camera_x, cursor_y - camera_y)
+  cursor_y = cursor_y + 6
+end
+
+function cursor(x, y, c)
+  cursor_x, cursor_y = x or 0, y or 0
+  if c then color(c) end
+end
+
+local keys = {
+  [0] = {"left"}, {"right"}, {"up"}, {"down"}, {"z", "c", "n"}, {"x", "v", "m"},
+}
+
+local function held(i)
+  for _, key in ipairs(keys[i] or {}) do
+    if love.keyboard.isDown(key) then return true end
+  end
+  return false
+end
+
+function btn(i, p)
+  if p and p ~= 0 then return false end
+  if i == nil then
+    local bits = 0
+    for b = 0, 5 do if down[b] then bits = bits + 2 ^ b end end
+    return bits
+  end
+  return down[i] or false
+end
+
+function btnp(i, p)
+  if p and p ~= 0 then return false end
+  if i == nil then
+    local bits = 0
+    for b = 0, 5 do if down[b] and not was_down[b] then bits = bits + 2 ^ b end end
+    return bits
+  end
+  return down[i] and not was_down[i] or false
+end
+
+function time() return love.timer.getTime() - start end
+t = time
+
+function sfx() end
+function music() end
+function pal() end
+function palt() end
+function fillp() end
+function flip() end
+function stat() return 0 end
+
+function love.load()
+  love.graphics.setDefaultFilter("nearest", "nearest")
+  love.graphics.setLineStyle("rough")
+  canvas = love.graphics.newCanvas(128, 128)
+  font = love.graphics.newFont(6)
+  sheet = load_sheet()
+  load_map()
+  start = love.timer.getTime()
+  love.graphics.setCanvas(canvas)
+  love.graphics.setFont(font)
+  if _init then _init() end
+  love.graphics.setCanvas()
+end
+
+function love.update(dt)
+  local step = 1 / (_update60 and 60 or 30)
+  elapsed = math.min(elapsed + dt, 4 * step)
+  while elapsed >= step do
+    elapsed = elapsed - step
+    for i = 0, 5 do was_down[i], down[i] = down[i], held(i) end
+    love.graphics.setCanvas(canvas)
+    if _update60 then _update60() elseif _update then _update() end
+    love.graphics.setCanvas()
+  end
+end
+
+function love.draw()
+  love.graphics.setCanvas(canvas)
+  love.graphics.setFont(font)
+  if _draw then _draw() end
+  love.graphics.setCanvas()
+  local w, h = love.graphics.getDimensions()
+  local scale = math.max(1, math.floor(math.min(w, h) / 128))
+  love.graphics.setColor(1, 1, 1)
+  love.graphics.draw(canvas, math.floor((w - 128 * scale) / 2), math.floor((h - 128 * scale) / 2), 0, scale)
+end
@@ -0,0 +1,503 @@
+//! A language server that publishes what the converter knows, with the `lsp`
+//! feature.
+//!
+//! `pico8-to-lua lsp` speaks the Language Server Protocol on stdin and
+//! stdout. For each ".p8" cart or Lua file an editor opens or changes, it
+//! publishes the constructs the converter will not convert, the button
+//! glyphs that need names defined at runtime, the "#include" statements
+//! whose files can't be found, and code over Pico-8's token, character, or
+//! compressed size limits. Like [serve](crate::serve) it uses only the
+//! standard library.
+use std::{
+    fmt::Write as _,
+    io::{self, BufRead, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    FsResolver,
+    cart::{is_cart, lua_span},
+    diagnostics::{DiagnosticKind, diagnose_strict},
+    json::json_string,
+    parse_includes,
+    size::{CHAR_LIMIT, COMPRESSED_LIMIT, TOKEN_LIMIT, size_report},
+    span::Span,
+};
+
+/// How serious a [Problem] is, numbered as the protocol numbers them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Severity {
+    /// The cart won't run or convert as it is.
+    Error = 1,
+    /// The converter leaves it alone and it may not work.
+    Warning = 2,
+    /// The converter handles it at a cost worth knowing.
+    Information = 3,
+}
+
+/// Something to show in the editor.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Problem {
+    /// Where it is in the text of the file.
+    pub span: Span,
+    /// How serious it is.
+    pub severity: Severity,
+    /// A stable name for the kind, e.g. "unmatched-paren".
+    pub code: &'static str,
+    /// What it is.
+    pub message: String,
+}
+
+/// Find the problems in the text of a ".p8" cart or Lua file. Includes are
+/// looked for next to `path`, or not at all if there is none.
+///
+/// ```
+/// use pico8_to_lua::lsp::{Severity, problems};
+/// let found = problems("pico-8 cartridge\n__lua__\nx @= 1\n", None);
+/// assert_eq!(found[0].code, "unknown-operator");
+/// assert_eq!(found[0].severity, Severity::Warning);
+/// assert_eq!(found[0].span.start, 27);
+/// ```
+pub fn problems(text: &str, path: Option<&Path>) -> Vec<Problem> {
+    let (lua, offset) = match lua_span(text) {
+        Some(span) => (&text[span.range()], span.start),
+        None if is_cart(text) => return Vec::new(),
+        None => (text, 0),
+    };
+    let shift = |span: Span| Span::new(span.start + offset, span.end + offset);
+    let mut found: Vec<Problem> = diagnose_strict(lua)
+        .into_iter()
+        .map(|d| match d.kind {
+            DiagnosticKind::StrayGlyph(_) => Problem {
+                span: shift(d.span),
+                severity: Severity::Information,
+                code: d.kind.code(),
+                message: format!("{}; it needs a name defined at runtime", d.kind),
+            },
+            _ => Problem {
+                span: shift(d.span),
+                severity: Severity::Warning,
+                code: d.kind.code(),
+                message: d.kind.to_string(),
+            },
+        })
+        .collect();
+    if let Some(path) = path {
+        let resolver = FsResolver::for_cart(path, Vec::<PathBuf>::new());
+        for include in parse_includes(lua) {
+            let found_files = resolver
+                .expand(include.path)
+                .map_or(resolver.find(include.path).is_some(), |files| {
+                    !files.is_empty()
+                });
+            if !found_files {
+                found.push(Problem {
+                    span: shift(include.span),
+                    severity: Severity::Error,
+                    code: "unresolved-include",
+                    message: format!("can't find {:?} to include", include.path),
+                });
+            }
+        }
+    }
+    let first_line = shift(Span::new(0, lua.find('\n').unwrap_or(lua.len())));
+    let size = size_report(lua);
+    for (code, what, count, limit) in [
+        ("token-limit", "tokens", size.tokens, TOKEN_LIMIT),
+        ("char-limit", "characters", size.chars, CHAR_LIMIT),
+        (
+            "compressed-limit",
+            "compressed bytes",
+            size.compressed,
+            COMPRESSED_LIMIT,
+        ),
+    ] {
+        if count > limit {
+            found.push(Problem {
+                span: first_line,
+                severity: Severity::Error,
+                code,
+                message: format!("{count} {what} are over Pico-8's limit of {limit}"),
+            });
+        }
+    }
+    found.sort_by_key(|p| p.span.start);
+    found
+}
+
+/// Serve the editor on stdin and stdout until it says to exit.
+pub fn serve_stdio() -> io::Result<()> {
+    run(io::stdin().lock(), io::stdout().lock())
+}
+
+/// Serve an editor that sends its messages to `input` and reads the replies
+/// from `output`, until it says to exit or closes `input`.
+pub fn run(mut input: impl BufRead, mut output: impl Write) -> io::Result<()> {
+    while let Some(message) = read_message(&mut input)? {
+        let Some(message) = Json::parse(&message) else {
+            continue;
+        };
+        let id = message.get("id");
+        let params = message.get("params");
+        let document = params.and_then(|p| p.get("textDocument"));
+        let uri = document
+            .and_then(|d| d.get("uri"))
+            .and_then(Json::as_str)
+            .unwrap_or("")
+            .to_string();
+        match message.get("method").and_then(Json::as_str).unwrap_or("") {
+            "initialize" => respond(
+                &mut output,
+                id,
+                "{\"capabilities\":{\"textDocumentSync\":1},\
+                 \"serverInfo\":{\"name\":\"pico8-to-lua\"}}",
+            )?,
+            "shutdown" => respond(&mut output, id, "null")?,
+            "exit" => return Ok(()),
+            "textDocument/didOpen" => {
+                let text = document.and_then(|d| d.get("text")).and_then(Json::as_str);
+                publish(&mut output, &uri, text.unwrap_or(""))?;
+            }
+            "textDocument/didChange" => {
+                let changes = params.and_then(|p| p.get("contentChanges"));
+                let text = match changes {
+                    Some(Json::Array(changes)) => changes.last().and_then(|c| c.get("text")),
+                    _ => None,
+                };
+                if let Some(text) = text.and_then(Json::as_str) {
+                    publish(&mut output, &uri, text)?;
+                }
+            }
+            "textDocument/didClose" => publish(&mut output, &uri, "")?,
+            _ if id.is_some() => {
+                let error = "{\"code\":-32601,\"message\":\"method not found\"}";
+                let id = id.map_or("null".into(), Json::to_json);
+                let body = format!("{{\"jsonrpc\":\"2.0\",\"id\":{id},\"error\":{error}}}");
+                write_message(&mut output, &body)?;
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Read the body of the next message, or `None` at the end of `input`.
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<String>> {
+    let mut length = None;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            if length.is_some() {
+                break;
+            }
+            continue;
+        }
+        let content_length = header
+            .split_once(':')
+            .filter(|(name, _)| name.eq_ignore_ascii_case("content-length"));
+        if let Some((_, value)) = content_length {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+    let mut body = vec![0; length.unwrap_or(0)];
+    input.read_exact(&mut body)?;
+    Ok(Some(String::from_utf8_lossy(&body).into_owned()))
+}
+
+fn write_message(output: &mut impl Write, body: &str) -> io::Result<()> {
+    write!(output, "Content-Length: {}\r\n\r\n{body}", body.len())?;
+    output.flush()
+}
+
+fn respond(output: &mut impl Write, id: Option<&Json>, result: &str) -> io::Result<()> {
+    let id = id.map_or("null".into(), Json::to_json);
+    write_message(
+        output,
+        &format!("{{\"jsonrpc\":\"2.0\",\"id\":{id},\"result\":{result}}}"),
+    )
+}
+
+/// Publish the problems in `text`, the document at `uri`.
+fn publish(output: &mut impl Write, uri: &str, text: &str) -> io::Result<()> {
+    let path = uri_path(uri);
+    let mut diagnostics = String::new();
+    for (i, problem) in problems(text, path.as_deref()).iter().enumerate() {
+        let (line, character) = position(text, problem.span.start);
+        let (end_line, end_character) = position(text, problem.span.end);
+        if i > 0 {
+            diagnostics.push(',');
+        }
+        write!(
+            diagnostics,
+            "{{\"range\":{{\"start\":{{\"line\":{line},\"character\":{character}}},\
+             \"end\":{{\"line\":{end_line},\"character\":{end_character}}}}},\
+             \"severity\":{},\"code\":{},\"source\":\"pico8-to-lua\",\"message\":{}}}",
+            problem.severity as u8,
+            json_string(problem.code),
+            json_string(&problem.message)
+        )
+        .expect("writing to a String cannot fail");
+    }
+    let body = format!(
+        "{{\"jsonrpc\":\"2.0\",\"method\":\"textDocument/publishDiagnostics\",\
+         \"params\":{{\"uri\":{},\"diagnostics\":[{diagnostics}]}}}}",
+        json_string(uri)
+    );
+    write_message(output, &body)
+}
+
+/// Return the zero-based line and UTF-16 column of `offset` in `text`, as
+/// the protocol counts them.
+fn position(text: &str, offset: usize) -> (usize, usize) {
+    let before = &text[..offset];
+    let start = before.rfind('\n').map_or(0, |i| i + 1);
+    (
+        before.matches('\n').count(),
+        before[start..].encode_utf16().count(),
+    )
+}
+
+/// Return the path of a "file:" URI.
+fn uri_path(uri: &str) -> Option<PathBuf> {
+    let path = uri.strip_prefix("file://")?;
+    let mut bytes = Vec::with_capacity(path.len());
+    let mut rest = path.as_bytes();
+    while let Some((&b, tail)) = rest.split_first() {
+        let escaped = tail
+            .get(..2)
+            .filter(|_| b == b'%')
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match escaped {
+            Some(byte) => {
+                bytes.push(byte);
+                rest = &tail[2..];
+            }
+            None => {
+                bytes.push(b);
+                rest = tail;
+            }
+        }
+    }
+    Some(PathBuf::from(String::from_utf8_lossy(&bytes).into_owned()))
+}
+
+/// A JSON value of a message.
+#[derive(Debug, Clone, PartialEq)]
+enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    fn parse(text: &str) -> Option<Json> {
+        let mut parser = Parser { text, pos: 0 };
+        let value = parser.value()?;
+        parser.skip_space();
+        (parser.pos == text.len()).then_some(value)
+    }
+
+    fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(fields) => fields.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// Write a request id back: a number, a string, or null.
+    fn to_json(&self) -> String {
+        match self {
+            Json::Number(n) => n.to_string(),
+            Json::String(s) => json_string(s),
+            _ => "null".into(),
+        }
+    }
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn skip_space(&mut self) {
+        let rest = &self.text[self.pos..];
+        self.pos += rest.len() - rest.trim_start_matches([' ', '\t', '\r', '\n']).len();
+    }
+
+    fn eat(&mut self, s: &str) -> bool {
+        let found = self.text[self.pos..].starts_with(s);
+        if found {
+            self.pos += s.len();
+        }
+        found
+    }
+
+    fn value(&mut self) -> Option<Json> {
+        self.skip_space();
+        let rest = &self.text[self.pos..];
+        match rest.as_bytes().first()? {
+            b'{' => {
+                self.pos += 1;
+                let mut fields = Vec::new();
+                self.skip_space();
+                if self.eat("}") {
+                    return Some(Json::Object(fields));
+                }
+                loop {
+                    self.skip_space();
+                    let key = self.string()?;
+                    self.skip_space();
+                    if !self.eat(":") {
+                        return None;
+                    }
+                    fields.push((key, self.value()?));
+                    self.skip_space();
+                    if self.eat("}") {
+                        return Some(Json::Object(fields));
+                    }
+                    if !self.eat(",") {
+                        return None;
+                    }
+                }
+            }
+            b'[' => {
+                self.pos += 1;
+                let mut items = Vec::new();
+                self.skip_space();
+                if self.eat("]") {
+                    return Some(Json::Array(items));
+                }
+                loop {
+                    items.push(self.value()?);
+                    self.skip_space();
+                    if self.eat("]") {
+                        return Some(Json::Array(items));
+                    }
+                    if !self.eat(",") {
+                        return None;
+                    }
+                }
+            }
+            b'"' => self.string().map(Json::String),
+            _ if self.eat("true") => Some(Json::Bool(true)),
+            _ if self.eat("false") => Some(Json::Bool(false)),
+            _ if self.eat("null") => Some(Json::Null),
+            _ => {
+                let len = rest
+                    .find(|c: char| !matches!(c, '0'..='9' | '-' | '+' | '.' | 'e' | 'E'))
+                    .unwrap_or(rest.len());
+                let n = rest[..len].parse().ok()?;
+                self.pos += len;
+                Some(Json::Number(n))
+            }
+        }
+    }
+
+    fn string(&mut self) -> Option<String> {
+        if !self.eat("\"") {
+            return None;
+        }
+        let mut out = String::new();
+        let mut chars = self.text[self.pos..].char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.pos += i + 1;
+                    return Some(out);
+                }
+                '\\' => match chars.next()?.1 {
+                    'n' => out.push('\n'),
+                    'r' => out.push('\r'),
+                    't' => out.push('\t'),
+                    'b' => out.push('\u{8}'),
+                    'f' => out.push('\u{c}'),
+                    'u' => {
+                        let high = utf16_unit(&mut chars)?;
+                        let code = if (0xd800..0xdc00).contains(&high) {
+                            if chars.next()?.1 != '\\' || chars.next()?.1 != 'u' {
+                                return None;
+                            }
+                            let low = utf16_unit(&mut chars)?;
+                            0x10000 + ((high - 0xd800) << 10) + (low.checked_sub(0xdc00)?)
+                        } else {
+                            high
+                        };
+                        out.push(char::from_u32(code).unwrap_or('\u{fffd}'));
+                    }
+                    c => out.push(c),
+                },
+                c => out.push(c),
+            }
+        }
+        None
+    }
+}
+
+/// Read the four hex digits of a "\\u" escape.
+fn utf16_unit(chars: &mut impl Iterator<Item = (usize, char)>) -> Option<u32> {
+    let hex: String = chars.take(4).map(|(_, c)| c).collect();
+    if hex.len() != 4 {
+        return None;
+    }
+    u32::from_str_radix(&hex, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json() {
+        let json = Json::parse(r#"{"id": 3, "a": [true, null, "x\né😀"], "b": {}}"#).unwrap();
+        assert_eq!(json.get("id").unwrap().to_json(), "3");
+        assert_eq!(
+            json.get("a"),
+            Some(&Json::Array(vec![
+                Json::Bool(true),
+                Json::Null,
+                Json::String("x\né😀".into())
+            ]))
+        );
+        assert_eq!(Json::parse("{\"a\": }"), None);
+    }
+
+    #[test]
+    fn test_run() {
+        let messages = [
+            r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{}}"#,
+            r#"{"jsonrpc":"2.0","method":"textDocument/didOpen","params":{"textDocument":{"uri":"file:///nowhere/a%20b.lua","text":"b = ⬅️\n#include lib.lua\n"}}}"#,
+            r#"{"jsonrpc":"2.0","id":2,"method":"shutdown"}"#,
+            r#"{"jsonrpc":"2.0","method":"exit"}"#,
+        ];
+        let input: String = messages
+            .iter()
+            .map(|m| format!("Content-Length: {}\r\n\r\n{m}", m.len()))
+            .collect();
+        let mut output = Vec::new();
+        run(input.as_bytes(), &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("\"id\":1,\"result\":{\"capabilities\""));
+        assert!(output.contains(
+            "\"range\":{\"start\":{\"line\":0,\"character\":4},\"end\":{\"line\":0,\"character\":6}},\
+             \"severity\":3,\"code\":\"stray-glyph\""
+        ));
+        assert!(output.contains("\"severity\":1,\"code\":\"unresolved-include\""));
+        assert!(output.ends_with("{\"jsonrpc\":\"2.0\",\"id\":2,\"result\":null}"));
+    }
+}
@@ -0,0 +1,81 @@
+//! Marking Lua as converted.
+//!
+//! The passes rewrite Pico-8 syntax, but some of them, like the fixed point
+//! and bitwise passes, also rewrite plain Lua, including the helpers they
+//! add, so converting their output again would change it. [with_marker] adds
+//! a [MARKER] comment to the comment header, and the converter leaves Lua for
+//! which [is_converted] is true as it is, so converting twice is the same as
+//! converting once.
+use std::borrow::Cow;
+
+use crate::cart::{comment_header, is_cart, lua_span};
+
+/// The comment that marks Lua as converted.
+pub const MARKER: &str = "-- converted by pico8-to-lua";
+
+/// Return true if `lua`, or the Lua of a cart, has the [MARKER] in its
+/// comment header, also when it was folded into a block comment on the line
+/// after it to keep the line numbers.
+///
+/// ```
+/// use pico8_to_lua::is_converted;
+/// assert!(is_converted("-- game\n-- converted by pico8-to-lua\nx = 1"));
+/// assert!(is_converted("--[[ converted by pico8-to-lua ]] x = 1"));
+/// assert!(!is_converted("x = 1 -- converted by pico8-to-lua"));
+/// ```
+pub fn is_converted(lua: &str) -> bool {
+    let lua = if is_cart(lua) {
+        match lua_span(lua) {
+            Some(span) => &lua[span.range()],
+            None => return false,
+        }
+    } else {
+        lua
+    };
+    let header = comment_header(lua);
+    let folded = lua[header.len()..].lines().next().unwrap_or("");
+    header.lines().chain([folded]).any(|line| {
+        line.trim_end() == MARKER
+            || line
+                .strip_prefix("--[[")
+                .is_some_and(|rest| rest.starts_with(&MARKER[2..]))
+    })
+}
+
+/// Add the [MARKER] to the end of the comment header of `lua` unless it is
+/// there.
+///
+/// ```
+/// use pico8_to_lua::marker::with_marker;
+/// assert_eq!(with_marker("-- game\nx = 1".into()), "-- game\n-- converted by pico8-to-lua\nx = 1");
+/// ```
+pub fn with_marker(lua: Cow<'_, str>) -> Cow<'_, str> {
+    if is_converted(&lua) {
+        return lua;
+    }
+    let at = comment_header(&lua).len();
+    let mut out = String::with_capacity(lua.len() + MARKER.len() + 2);
+    out.push_str(&lua[..at]);
+    if at > 0 && !out.ends_with('\n') {
+        out.push('\n');
+    }
+    out.push_str(MARKER);
+    out.push('\n');
+    out.push_str(&lua[at..]);
+    Cow::Owned(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cart::CART_HEADER;
+
+    #[test]
+    fn test_with_marker() {
+        let marked = with_marker("x = 1\n".into());
+        assert_eq!(marked, "-- converted by pico8-to-lua\nx = 1\n");
+        assert_eq!(with_marker(marked.clone()), marked);
+        let cart = format!("{CART_HEADER}\n__lua__\n{marked}__gfx__\n");
+        assert!(is_converted(&cart));
+    }
+}
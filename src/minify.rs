@@ -0,0 +1,327 @@
+//! Shrink Pico-8 Lua toward Pico-8's character and compression limits.
+//!
+//! [minify] strips comments, collapses whitespace, and renames local
+//! identifiers to short names. Line breaks are kept because Pico-8's
+//! shorthand `if` and `?` end at the end of a line, and "#include" lines are
+//! left as they are.
+use std::collections::{HashMap, HashSet};
+
+use lazy_regex::regex;
+
+use crate::{
+    api,
+    lexer::{KEYWORDS, Token, TokenKind, tokenize},
+    span::Span,
+};
+
+/// Which parts of minification to do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MinifyOptions {
+    /// Remove comments, except a cart's leading title and author comments.
+    pub strip_comments: bool,
+    /// Remove indentation, blank lines, and spaces that are not needed.
+    pub collapse_whitespace: bool,
+    /// Give local variables, parameters, and loop variables shorter names.
+    pub rename_locals: bool,
+}
+
+impl Default for MinifyOptions {
+    fn default() -> Self {
+        MinifyOptions {
+            strip_comments: true,
+            collapse_whitespace: true,
+            rename_locals: true,
+        }
+    }
+}
+
+/// The output of [minify].
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Minified {
+    /// The minified Lua.
+    pub lua: String,
+    /// Each renamed identifier and its new name.
+    pub renames: Vec<(String, String)>,
+}
+
+/// Minify `lua`.
+pub fn minify(lua: &str, options: &MinifyOptions) -> Minified {
+    let tokens: Vec<Token> = tokenize(lua).collect();
+    let verbatim: Vec<Span> = regex!(r"(?m)^[ \t]*#include[^\n]*")
+        .find_iter(lua)
+        .map(|m| Span::new(m.start(), m.end()))
+        .collect();
+    let is_verbatim: Vec<bool> = tokens
+        .iter()
+        .map(|t| verbatim.iter().any(|s| s.contains(t.span.start)))
+        .collect();
+    let header_end = crate::cart::comment_header(lua).len();
+
+    let code: Vec<usize> = (0..tokens.len())
+        .filter(|&i| tokens[i].kind != TokenKind::Comment && !is_verbatim[i])
+        .collect();
+    let renames = if options.rename_locals {
+        plan_renames(&tokens, &code)
+    } else {
+        HashMap::new()
+    };
+    let renamable: HashSet<usize> = (0..code.len())
+        .filter(|&ci| is_renamable(&tokens, &code, ci))
+        .map(|ci| code[ci])
+        .collect();
+
+    let mut out = String::with_capacity(lua.len());
+    let mut pending = String::new();
+    let mut last_end = 0;
+    let mut prev_text: Option<&str> = None;
+    let mut emitted_verbatim = HashSet::new();
+    for (i, token) in tokens.iter().enumerate() {
+        if let Some(span) = verbatim.iter().find(|s| s.contains(token.span.start)) {
+            if emitted_verbatim.insert(span.start) {
+                let ws = &lua[last_end..token.span.start];
+                separate(&mut out, &pending, ws, prev_text, "\n", options);
+                out.push_str(&lua[span.range()]);
+                prev_text = Some("\n");
+            }
+            last_end = last_end.max(span.end);
+            pending.clear();
+            continue;
+        }
+        let ws = &lua[last_end..token.span.start];
+        last_end = token.span.end;
+        if token.kind == TokenKind::Comment
+            && options.strip_comments
+            && token.span.end > header_end
+        {
+            if ws.contains('\n') {
+                pending.push_str(ws);
+            }
+            continue;
+        }
+        let text = if renamable.contains(&i) {
+            renames.get(token.text).map_or(token.text, String::as_str)
+        } else {
+            token.text
+        };
+        separate(&mut out, &pending, ws, prev_text, text, options);
+        pending.clear();
+        out.push_str(text);
+        prev_text = Some(text);
+    }
+    if !options.collapse_whitespace {
+        out.push_str(&pending);
+        out.push_str(&lua[last_end.min(lua.len())..]);
+    } else if lua.ends_with('\n') && !out.is_empty() {
+        out.push('\n');
+    }
+
+    let mut renames: Vec<(String, String)> = renames.into_iter().collect();
+    renames.sort();
+    Minified { lua: out, renames }
+}
+
+/// Write what should separate the previous token from `next`.
+fn separate(
+    out: &mut String,
+    pending: &str,
+    ws: &str,
+    prev: Option<&str>,
+    next: &str,
+    options: &MinifyOptions,
+) {
+    if !options.collapse_whitespace {
+        out.push_str(pending);
+        out.push_str(ws);
+    } else if let Some(prev) = prev {
+        if pending.contains('\n') || ws.contains('\n') {
+            out.push('\n');
+        } else if needs_space(prev, next) {
+            out.push(' ');
+        }
+    }
+}
+
+/// Return true if `a` and `b` would not lex as the same two tokens without a
+/// space between them.
+fn needs_space(a: &str, b: &str) -> bool {
+    let word = |c: char| c.is_alphanumeric() || c == '_' || !c.is_ascii();
+    if a.ends_with(word) && b.starts_with(word) {
+        return true;
+    }
+    // Lua reads a number up to the next character that cannot be in one.
+    if a.starts_with(|c: char| c.is_ascii_digit()) && b.starts_with('.') {
+        return true;
+    }
+    let joined = format!("{a}{b}");
+    let mut lexed = tokenize(&joined);
+    !(lexed.next().is_some_and(|t| t.text == a)
+        && lexed.next().is_some_and(|t| t.text == b)
+        && lexed.next().is_none())
+}
+
+/// Return true if `code[ci]` is a variable rather than a field name.
+fn is_renamable(tokens: &[Token], code: &[usize], ci: usize) -> bool {
+    let token = &tokens[code[ci]];
+    if token.kind != TokenKind::Name {
+        return false;
+    }
+    let prev = ci.checked_sub(1).map(|p| &tokens[code[p]]);
+    let next = code.get(ci + 1).map(|&n| &tokens[n]);
+    if prev.is_some_and(|p| p.is(".") || p.is(":")) {
+        return false;
+    }
+    // A key in a table constructor like `{x = 1}`.
+    let is_key = prev.is_some_and(|p| p.is("{") || p.is(",") || p.is(";"))
+        && next.is_some_and(|n| n.is("="))
+        && innermost_bracket(tokens, &code[..ci]) == Some("{");
+    !is_key
+}
+
+fn innermost_bracket<'a>(tokens: &[Token<'a>], code: &[usize]) -> Option<&'a str> {
+    let mut depth = 0;
+    for &i in code.iter().rev() {
+        match tokens[i].text {
+            ")" | "]" | "}" if tokens[i].kind == TokenKind::Punct => depth += 1,
+            "(" | "[" | "{" if tokens[i].kind == TokenKind::Punct => {
+                if depth == 0 {
+                    return Some(tokens[i].text);
+                }
+                depth -= 1;
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Find the names declared as locals, parameters, or loop variables.
+fn declared_locals<'a>(code: &[&Token<'a>]) -> HashSet<&'a str> {
+    let mut names = HashSet::new();
+    for (i, t) in code.iter().enumerate() {
+        if t.is("local") || t.is("for") {
+            let mut j = i + 1;
+            if code.get(j).is_some_and(|t| t.is("function")) {
+                j += 1;
+            }
+            while let Some(name) = code.get(j).filter(|t| t.kind == TokenKind::Name) {
+                names.insert(name.text);
+                if !code.get(j + 1).is_some_and(|t| t.is(",")) {
+                    break;
+                }
+                j += 2;
+            }
+        } else if t.is("function") {
+            let params = code[i..]
+                .iter()
+                .skip_while(|t| !t.is("("))
+                .skip(1)
+                .take_while(|t| !t.is(")"));
+            names.extend(params.filter(|p| p.kind == TokenKind::Name).map(|p| p.text));
+        }
+    }
+    names
+}
+
+/// Return the `n`th short name: "a" to "z", then "aa", "ab", and so on.
+fn short_name(mut n: usize) -> String {
+    let mut name = Vec::new();
+    loop {
+        name.push(b'a' + (n % 26) as u8);
+        if n < 26 {
+            break;
+        }
+        n = n / 26 - 1;
+    }
+    name.reverse();
+    String::from_utf8(name).unwrap()
+}
+
+fn plan_renames(tokens: &[Token], code: &[usize]) -> HashMap<String, String> {
+    let code: Vec<&Token> = code.iter().map(|&i| &tokens[i]).collect();
+    let used: HashSet<&str> = tokens
+        .iter()
+        .filter(|t| t.kind == TokenKind::Name)
+        .map(|t| t.text)
+        .collect();
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for t in code.iter().filter(|t| t.kind == TokenKind::Name) {
+        *counts.entry(t.text).or_default() += 1;
+    }
+    let mut candidates: Vec<&str> = declared_locals(&code)
+        .into_iter()
+        .filter(|n| n.is_ascii() && !api::is_api(n) && !matches!(*n, "self" | "_ENV"))
+        .collect();
+    candidates.sort_by_key(|n| (std::cmp::Reverse(counts.get(n).copied().unwrap_or(0)), *n));
+
+    let mut renames = HashMap::new();
+    let mut next = 0;
+    for old in candidates {
+        let new = loop {
+            let name = short_name(next);
+            next += 1;
+            if !used.contains(name.as_str())
+                && !KEYWORDS.contains(&name.as_str())
+                && !api::is_api(&name)
+            {
+                break name;
+            }
+        };
+        if new.len() < old.len() {
+            renames.insert(old.to_string(), new);
+        } else {
+            next -= 1;
+        }
+    }
+    renames
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_minify() {
+        let lua = "-- my game\n-- by me\n\nfunction _update()\n  local speed = 2 -- fast\n  player.x += speed\n  t = {speed = speed}\nend\n";
+        let minified = minify(lua, &MinifyOptions::default());
+        assert_eq!(
+            minified.lua,
+            "-- my game\n-- by me\nfunction _update()\nlocal a=2\nplayer.x+=a\nt={speed=a}\nend\n"
+        );
+        assert_eq!(minified.renames, vec![("speed".into(), "a".into())]);
+    }
+
+    #[test]
+    fn test_needs_space() {
+        assert!(needs_space("a", "b"));
+        assert!(needs_space("1", ".."));
+        assert!(needs_space("-", "-1"));
+        assert!(!needs_space("x", "="));
+        assert!(!needs_space(")", "and"));
+    }
+
+    #[test]
+    fn test_keep_includes() {
+        let lua = "#include my_lib.p8\nlocal my_lib = 1\n";
+        let minified = minify(lua, &MinifyOptions::default());
+        assert_eq!(minified.lua, "#include my_lib.p8\nlocal a=1\n");
+    }
+
+    #[test]
+    fn test_no_collapse() {
+        let options = MinifyOptions {
+            collapse_whitespace: false,
+            rename_locals: false,
+            ..MinifyOptions::default()
+        };
+        let minified = minify("x = 1 -- one\n  y = 2\n", &options);
+        assert_eq!(minified.lua, "x = 1\n  y = 2\n");
+    }
+
+    #[test]
+    fn test_short_name() {
+        assert_eq!(short_name(0), "a");
+        assert_eq!(short_name(25), "z");
+        assert_eq!(short_name(26), "aa");
+        assert_eq!(short_name(27), "ab");
+    }
+}
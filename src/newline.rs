@@ -0,0 +1,91 @@
+//! Line endings and byte order marks.
+//!
+//! Carts edited on Windows end their lines with "\r\n" and may start with a
+//! UTF-8 byte order mark, which the line-anchored rewrites would take for
+//! part of the code. [Newlines::normalize] removes both before the passes run
+//! and [Newlines::restore] puts them back after, so the passes only ever see
+//! "\n" and the output keeps the line endings of the input.
+/// The UTF-8 byte order mark as a character.
+pub(crate) const BOM: char = '\u{feff}';
+
+/// The line endings and byte order mark of the text before it was
+/// normalized.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Newlines {
+    /// Whether the text started with a byte order mark.
+    bom: bool,
+    /// Whether each line break was "\r\n".
+    crlf: Vec<bool>,
+}
+
+impl Newlines {
+    /// Remove the byte order mark and the "\r" of each "\r\n" from `text`,
+    /// returning the text and what to restore, or `None` if there was
+    /// neither.
+    pub(crate) fn normalize(text: &str) -> Option<(String, Newlines)> {
+        let body = text.strip_prefix(BOM);
+        if body.is_none() && !text.contains("\r\n") {
+            return None;
+        }
+        let crlf = body
+            .unwrap_or(text)
+            .split_inclusive('\n')
+            .filter(|line| line.ends_with('\n'))
+            .map(|line| line.ends_with("\r\n"))
+            .collect();
+        let newlines = Newlines {
+            bom: body.is_some(),
+            crlf,
+        };
+        Some((body.unwrap_or(text).replace("\r\n", "\n"), newlines))
+    }
+
+    /// Put the line endings and byte order mark back into `text`, the
+    /// normalized text after the passes. If the passes kept the number of
+    /// lines, each line break is restored as it was, or else every one is
+    /// "\r\n" if most of them were.
+    pub(crate) fn restore(&self, text: &str) -> String {
+        let kept = text.matches('\n').count() == self.crlf.len();
+        let most = self.crlf.iter().filter(|crlf| **crlf).count() * 2 > self.crlf.len();
+        let mut out = String::with_capacity(text.len() + self.crlf.len() + BOM.len_utf8());
+        if self.bom {
+            out.push(BOM);
+        }
+        for (i, line) in text.split_inclusive('\n').enumerate() {
+            let crlf = if kept { self.crlf[i] } else { most };
+            match line.strip_suffix('\n') {
+                Some(line) if crlf => {
+                    out.push_str(line);
+                    out.push_str("\r\n");
+                }
+                _ => out.push_str(line),
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::patch_lua;
+
+    #[test]
+    fn test_crlf_and_bom() {
+        assert_eq!(Newlines::normalize("a\nb"), None);
+        let (text, newlines) = Newlines::normalize("\u{feff}a\r\nb\n").unwrap();
+        assert_eq!(text, "a\nb\n");
+        assert_eq!(newlines.restore("c\nd\n"), "\u{feff}c\r\nd\n");
+        assert_eq!(newlines.restore("c\nd\ne\nf"), "\u{feff}c\nd\ne\nf");
+        let lua = "\u{feff}#include a.lua\r\nx += 1\r\nif (x != 2) y = 1\r\nz = 3\n";
+        assert_eq!(
+            patch_lua(lua),
+            "\u{feff}#include a.lua\r\nx = x + (1)\r\nif x ~= 2 then y = 1 end\r\nz = 3\n"
+        );
+        let cart = "\u{feff}pico-8 cartridge\r\n__lua__\r\nx -= 1\r\n__gfx__\r\n00\r\n";
+        assert_eq!(
+            patch_lua(cart),
+            "\u{feff}pico-8 cartridge\r\n__lua__\r\nx = x - (1)\r\n__gfx__\r\n00\r\n"
+        );
+    }
+}
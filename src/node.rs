@@ -0,0 +1,93 @@
+//! Node.js bindings, with the `node` feature.
+//!
+//! Build with `npm run build`, which runs `cargo rustc --lib --release
+//! --features node --crate-type cdylib`, to get a native module that Node and
+//! Electron tools can call synchronously with the `Buffer` of a cart,
+//! ".p8.png" and ".rom" included:
+//!
+//! ```js
+//! const { patchLua, convert, convertMany, detectFormat } = require("pico8-to-lua");
+//! patchLua("x += 1"); // "x = x + (1)"
+//! const { lua, cart } = convert(fs.readFileSync("game.p8.png"));
+//! const outputs = convertMany(paths.map((path) => fs.readFileSync(path)));
+//! detectFormat(fs.readFileSync("game.rom")); // "rom"
+//! ```
+use napi::{Error, Result, bindgen_prelude::Buffer};
+use napi_derive::napi;
+
+use crate::{Options, detect};
+
+fn error(e: impl std::fmt::Display) -> Error {
+    Error::from_reason(e.to_string())
+}
+
+/// How to convert; every field is optional.
+#[napi(object)]
+#[derive(Default)]
+pub struct ConvertOptions {
+    /// Keep each line of code on its line.
+    pub preserve_lines: Option<bool>,
+    /// Mark the output as converted.
+    pub marker: Option<bool>,
+}
+
+impl From<ConvertOptions> for Options {
+    fn from(options: ConvertOptions) -> Self {
+        Options {
+            preserve_lines: options.preserve_lines.unwrap_or(false),
+            marker: options.marker.unwrap_or(false),
+            ..Options::default()
+        }
+    }
+}
+
+/// The result of a conversion.
+#[napi(object)]
+pub struct Converted {
+    /// The converted Lua.
+    pub lua: String,
+    /// The converted cart if the input was a ".p8" cart.
+    pub cart: Option<String>,
+}
+
+/// Convert Pico-8 Lua to plain Lua.
+#[napi]
+pub fn patch_lua(code: String) -> String {
+    crate::patch_lua(code.as_str()).into_owned()
+}
+
+/// Convert a ".p8" cart, a ".p8.png" cart, a ".rom", or Pico-8 Lua,
+/// resolving its includes from the current directory.
+#[napi]
+pub fn convert(input: Buffer, options: Option<ConvertOptions>) -> Result<Converted> {
+    convert_with(&input, &options.unwrap_or_default().into())
+}
+
+/// Convert each input like `convert`, throwing the error of the first that
+/// fails with its index.
+#[napi]
+pub fn convert_many(inputs: Vec<Buffer>, options: Option<ConvertOptions>) -> Result<Vec<Converted>> {
+    let options = options.unwrap_or_default().into();
+    inputs
+        .iter()
+        .enumerate()
+        .map(|(i, input)| {
+            convert_with(input, &options).map_err(|e| error(format!("input {i}: {}", e.reason)))
+        })
+        .collect()
+}
+
+fn convert_with(input: &[u8], options: &Options) -> Result<Converted> {
+    let converted = crate::convert(input, options).map_err(error)?;
+    Ok(Converted {
+        lua: converted.lua,
+        cart: converted.cart.map(|cart| cart.to_string()),
+    })
+}
+
+/// Return the format of a cart file from its contents: "p8", "lua",
+/// "p8.png", or "rom", or `null` if it is in none of them.
+#[napi]
+pub fn detect_format(input: Buffer) -> Option<String> {
+    detect::detect(&input).map(|format| format.extension().to_string())
+}
@@ -0,0 +1,385 @@
+//! Rewriting of Pico-8 operators into plain Lua.
+//!
+//! Passes like [patch_bitwise](crate::bitwise::patch_bitwise) find the
+//! operands of an operator by its priority, the way a parser would, and then
+//! write the operation as a call, a call to a generated helper, or another
+//! operator. An [Operators] says how each operator is written.
+use std::borrow::Cow;
+
+use crate::{
+    cart::comment_header,
+    lexer::{Token, TokenKind, tokenize},
+    span::{Span, TextEdit, apply_edits},
+    symbols::Namer,
+};
+
+/// How an operator is written in plain Lua.
+pub(crate) enum Translation {
+    /// Call a function with the operands, e.g. `peek(a)`.
+    Call(&'static str),
+    /// Call a generated helper, e.g. `__p8_rotl(a, b)`.
+    Helper(&'static str),
+    /// Call a function with the operation written with another operator, e.g.
+    /// `math.floor(a / b)`.
+    Wrap(&'static str, &'static str),
+    /// Use another operator, e.g. `a ~ b`.
+    Native(&'static str),
+}
+
+/// The operators a pass rewrites.
+pub(crate) trait Operators {
+    /// How to write the binary operator `op`, or `None` to leave it.
+    fn translate(&self, op: &str) -> Option<Translation>;
+
+    /// How to write the unary operator `op` as a [Translation::Call] or
+    /// [Translation::Helper], or `None` to leave it.
+    fn unary(&self, _op: &str) -> Option<Translation> {
+        None
+    }
+
+    /// The definition of a [Translation::Helper] given its generated name.
+    fn helper_definition(&self, _helper: &str, _name: &str) -> String {
+        String::new()
+    }
+}
+
+pub(crate) fn binary_priority(token: &Token) -> Option<(u8, u8)> {
+    if !matches!(token.kind, TokenKind::Punct | TokenKind::Keyword) {
+        return None;
+    }
+    Some(match token.text {
+        "or" => (1, 1),
+        "and" => (2, 2),
+        "<" | ">" | "<=" | ">=" | "~=" | "!=" | "==" => (3, 3),
+        "|" => (4, 4),
+        "~" | "^^" => (5, 5),
+        "&" => (6, 6),
+        "<<" | ">>" | ">>>" | "<<>" | ">><" => (7, 7),
+        ".." => (9, 8),
+        "+" | "-" => (10, 10),
+        "*" | "/" | "\\" | "%" => (11, 11),
+        "^" => (14, 13),
+        _ => return None,
+    })
+}
+
+pub(crate) const UNARY_PRIORITY: u8 = 12;
+
+const COMPOUND_OPS: &[&str] = &[
+    "+=", "-=", "*=", "/=", "\\=", "%=", "^=", "..=", "|=", "&=", "^^=", "<<=", ">>=", ">>>=",
+    "<<>=", ">><=",
+];
+
+struct Scanner<'t, 'a> {
+    tokens: &'t [Token<'a>],
+}
+
+impl Scanner<'_, '_> {
+    /// Return true if the operator at `i` is binary rather than unary.
+    fn is_binary(&self, i: usize) -> bool {
+        i > 0 && self.tokens[i - 1].ends_value() && binary_priority(&self.tokens[i]).is_some()
+    }
+
+    fn is_unary(&self, i: usize) -> bool {
+        !self.is_binary(i)
+            && self.tokens.get(i).is_some_and(|t| {
+                matches!(t.kind, TokenKind::Punct | TokenKind::Keyword)
+                    && matches!(t.text, "not" | "-" | "#" | "~" | "@" | "%" | "$")
+            })
+    }
+
+    /// Return the index after the bracket that closes the one at `open`.
+    fn after_close(&self, open: usize) -> Option<usize> {
+        let mut depth = 0;
+        for (i, t) in self.tokens.iter().enumerate().skip(open) {
+            if t.kind != TokenKind::Punct {
+                continue;
+            }
+            match t.text {
+                "(" | "[" | "{" => depth += 1,
+                ")" | "]" | "}" => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Some(i + 1);
+                    }
+                }
+                _ => {}
+            }
+        }
+        None
+    }
+
+    /// Return the index of the bracket that opens the one that closes at
+    /// `close`.
+    fn open_of(&self, close: usize) -> Option<usize> {
+        let mut depth = 0;
+        for i in (0..=close).rev() {
+            let t = &self.tokens[i];
+            if t.kind != TokenKind::Punct {
+                continue;
+            }
+            match t.text {
+                ")" | "]" | "}" => depth += 1,
+                "(" | "[" | "{" => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Some(i);
+                    }
+                }
+                _ => {}
+            }
+        }
+        None
+    }
+
+    /// Return the index after a variable, call, or simple value at `i`.
+    fn suffixed_end(&self, i: usize) -> Option<usize> {
+        let t = self.tokens.get(i)?;
+        let mut j = match t.kind {
+            TokenKind::Name => i + 1,
+            TokenKind::Number | TokenKind::String => return Some(i + 1),
+            TokenKind::Keyword if matches!(t.text, "nil" | "true" | "false") => return Some(i + 1),
+            TokenKind::Punct if t.is("...") => return Some(i + 1),
+            TokenKind::Punct if t.is("{") => return self.after_close(i),
+            TokenKind::Punct if t.is("(") => self.after_close(i)?,
+            _ => return None,
+        };
+        loop {
+            match self.tokens.get(j) {
+                Some(t) if t.is(".") => j += 2,
+                Some(t) if t.is(":") => j += 2,
+                Some(t) if t.is("(") || t.is("[") || t.is("{") => j = self.after_close(j)?,
+                Some(t) if t.kind == TokenKind::String => j += 1,
+                _ => return Some(j.min(self.tokens.len())),
+            }
+        }
+    }
+
+    /// Return the index after the expression at `i` whose binary operators
+    /// all have a left priority greater than `limit`.
+    fn expr_end(&self, i: usize, limit: u8) -> Option<usize> {
+        let mut j = if self.is_unary(i) {
+            self.expr_end(i + 1, UNARY_PRIORITY)?
+        } else {
+            self.suffixed_end(i)?
+        };
+        while j < self.tokens.len() && self.is_binary(j) {
+            let (left, right) = binary_priority(&self.tokens[j])?;
+            if left <= limit {
+                break;
+            }
+            j = self.expr_end(j + 1, right)?;
+        }
+        Some(j)
+    }
+
+    /// Return the index of the first token of the left operand of the binary
+    /// operator at `op`.
+    fn operand_start(&self, op: usize) -> usize {
+        let (left, _) = binary_priority(&self.tokens[op]).unwrap_or((0, 0));
+        let mut j = op;
+        while j > 0 {
+            let t = &self.tokens[j - 1];
+            if t.kind == TokenKind::Punct && matches!(t.text, ")" | "]" | "}") {
+                match self.open_of(j - 1) {
+                    Some(open) => j = open,
+                    None => break,
+                }
+                continue;
+            }
+            if self.is_binary(j - 1) {
+                match binary_priority(t) {
+                    Some((_, right)) if left <= right => {
+                        j -= 1;
+                        continue;
+                    }
+                    _ => break,
+                }
+            }
+            if self.is_unary(j - 1) || t.is(".") || t.is(":") {
+                j -= 1;
+                continue;
+            }
+            let is_value = t.ends_value() && !t.is("end");
+            // Two values in a row are two statements, e.g. `a = 1 b = c & d`.
+            let next_starts_statement = j < op
+                && matches!(
+                    self.tokens[j].kind,
+                    TokenKind::Name | TokenKind::Number | TokenKind::Keyword
+                );
+            if !is_value || next_starts_statement {
+                break;
+            }
+            j -= 1;
+        }
+        j
+    }
+}
+
+/// Rewrite the operators of `lua` and their compound assignments, naming any
+/// helpers with `namer`.
+pub(crate) fn rewrite_operators<'h>(
+    lua: Cow<'h, str>,
+    operators: &impl Operators,
+    namer: &mut Namer,
+) -> Cow<'h, str> {
+    let tokens: Vec<Token> = tokenize(&lua)
+        .filter(|t| t.kind != TokenKind::Comment)
+        .collect();
+    let scanner = Scanner { tokens: &tokens };
+    // Insertions are (offset, nesting, text). Opening text for an outer
+    // expression must come before an inner one at the same offset and
+    // closing text after.
+    let mut inserts: Vec<(usize, isize, String)> = Vec::new();
+    let mut edits: Vec<TextEdit> = Vec::new();
+    let mut helpers: Vec<(&'static str, String)> = Vec::new();
+    let mut helper_name = |helper: &'static str, helpers: &mut Vec<(&'static str, String)>| {
+        let name = namer.name(helper);
+        if !helpers.iter().any(|(h, _)| *h == helper) {
+            helpers.push((helper, name.clone()));
+        }
+        name
+    };
+
+    for i in 0..tokens.len() {
+        let token = &tokens[i];
+        if scanner.is_unary(i) {
+            let function = match operators.unary(token.text) {
+                Some(Translation::Call(function)) => function.to_string(),
+                Some(Translation::Helper(helper)) => helper_name(helper, &mut helpers),
+                _ => continue,
+            };
+            let Some(end) = scanner.expr_end(i + 1, UNARY_PRIORITY) else {
+                continue;
+            };
+            let end = tokens[end - 1].span.end;
+            let size = (end - token.span.start) as isize;
+            edits.push(TextEdit::new(token.span, ""));
+            inserts.push((token.span.start, -size, format!("{function}(")));
+            inserts.push((end, size, ")".into()));
+            continue;
+        }
+        if !scanner.is_binary(i) {
+            continue;
+        }
+        let Some(translation) = operators.translate(token.text) else {
+            continue;
+        };
+        let (_, right) = binary_priority(token).expect("a binary operator");
+        let Some(end) = scanner.expr_end(i + 1, right) else {
+            continue;
+        };
+        let start = scanner.operand_start(i);
+        if start == i {
+            continue;
+        }
+        let (start, end) = (tokens[start].span.start, tokens[end - 1].span.end);
+        let (function, separator) = match translation {
+            Translation::Native(native) => {
+                if native != token.text {
+                    edits.push(TextEdit::new(token.span, native));
+                }
+                continue;
+            }
+            Translation::Call(function) => (function.to_string(), ", ".to_string()),
+            Translation::Helper(helper) => (helper_name(helper, &mut helpers), ", ".to_string()),
+            Translation::Wrap(function, op) => (function.to_string(), format!(" {op} ")),
+        };
+        let size = (end - start) as isize;
+        let left_end = tokens[i - 1].span.end;
+        let right_start = tokens[i + 1].span.start;
+        edits.push(TextEdit::new(Span::new(left_end, right_start), separator));
+        inserts.push((start, -size, format!("{function}(")));
+        inserts.push((end, size, ")".into()));
+    }
+
+    // Compound assignments, after the operators so their left hand sides can
+    // be copied with those edits applied.
+    let mut compound = Vec::new();
+    for (i, token) in tokens.iter().enumerate() {
+        if !(token.kind == TokenKind::Punct && COMPOUND_OPS.contains(&token.text)) {
+            continue;
+        }
+        let op = &token.text[..token.text.len() - 1];
+        let Some(translation) = operators.translate(op) else {
+            continue;
+        };
+        let start = scanner.operand_start(i);
+        let Some(end) = scanner.expr_end(i + 1, 0) else {
+            continue;
+        };
+        if start == i || end == i + 1 {
+            continue;
+        }
+        let target_span = Span::new(tokens[start].span.start, tokens[i - 1].span.end);
+        let value_start = tokens[i + 1].span.start;
+        let value_end = tokens[end - 1].span.end;
+        let lhs = rendered(&lua, target_span, &edits, &inserts);
+        let (open, close) = match translation {
+            Translation::Native(native) => (format!("= {lhs} {native} ("), ")".to_string()),
+            Translation::Call(function) => (format!("= {function}({lhs}, "), ")".to_string()),
+            Translation::Helper(helper) => {
+                let name = helper_name(helper, &mut helpers);
+                (format!("= {name}({lhs}, "), ")".to_string())
+            }
+            Translation::Wrap(function, op) => {
+                (format!("= {function}({lhs} {op} ("), "))".to_string())
+            }
+        };
+        compound.push(TextEdit::new(
+            Span::new(token.span.start, value_start),
+            open,
+        ));
+        inserts.push((value_end, isize::MAX, close));
+    }
+    edits.extend(compound);
+
+    if edits.is_empty() && inserts.is_empty() {
+        return lua;
+    }
+    if !helpers.is_empty() {
+        let at = comment_header(&lua).len();
+        let definitions: String = helpers
+            .iter()
+            .map(|(helper, name)| operators.helper_definition(helper, name))
+            .collect();
+        inserts.push((at, isize::MIN, definitions));
+    }
+    inserts.sort_by_key(|(offset, nesting, _)| (*offset, *nesting));
+    let mut all: Vec<TextEdit> = inserts
+        .into_iter()
+        .map(|(offset, _, text)| TextEdit::new(Span::new(offset, offset), text))
+        .collect();
+    all.extend(edits);
+    Cow::Owned(apply_edits(&lua, &all))
+}
+
+/// Return the text of `span` with the edits inside it applied.
+fn rendered(
+    lua: &str,
+    span: Span,
+    edits: &[TextEdit],
+    inserts: &[(usize, isize, String)],
+) -> String {
+    let mut inside: Vec<(usize, isize, TextEdit)> = inserts
+        .iter()
+        .filter(|(offset, _, _)| span.start < *offset && *offset < span.end)
+        .map(|(offset, nesting, text)| {
+            let at = offset - span.start;
+            (
+                *offset,
+                *nesting,
+                TextEdit::new(Span::new(at, at), text.clone()),
+            )
+        })
+        .collect();
+    inside.sort_by_key(|(offset, nesting, _)| (*offset, *nesting));
+    let mut relative: Vec<TextEdit> = inside.into_iter().map(|(_, _, e)| e).collect();
+    relative.extend(
+        edits
+            .iter()
+            .filter(|e| e.span.start >= span.start && e.span.end <= span.end)
+            .map(|e| TextEdit::new(e.span.shift(-(span.start as isize)), e.replacement.clone())),
+    );
+    apply_edits(&lua[span.range()], &relative)
+}
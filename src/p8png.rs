@@ -0,0 +1,82 @@
+//! Reading ".p8.png" carts.
+//!
+//! A ".p8.png" cart hides its 32 KiB ROM in the two low bits of each channel
+//! of the cart image's pixels. The code region of the ROM may be compressed;
+//! see [compress](crate::compress).
+use std::{error::Error, fmt, io::Read};
+
+use crate::compress::{self, DecompressError};
+
+/// The size of the ROM stored in the image.
+pub const ROM_SIZE: usize = 0x8000;
+/// Where the code starts in the ROM.
+pub const CODE_START: usize = 0x4300;
+
+/// An error encountered while reading a ".p8.png" cart.
+#[derive(Debug)]
+pub enum P8PngError {
+    /// The image could not be decoded.
+    Png(png::DecodingError),
+    /// The image is too small or not 8-bit RGBA.
+    BadImage,
+    /// The code could not be decompressed.
+    Decompress(DecompressError),
+}
+
+impl fmt::Display for P8PngError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            P8PngError::Png(e) => write!(f, "invalid png: {e}"),
+            P8PngError::BadImage => write!(f, "image is not a pico-8 cart"),
+            P8PngError::Decompress(e) => e.fmt(f),
+        }
+    }
+}
+
+impl Error for P8PngError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            P8PngError::Png(e) => Some(e),
+            P8PngError::BadImage => None,
+            P8PngError::Decompress(e) => Some(e),
+        }
+    }
+}
+
+impl From<png::DecodingError> for P8PngError {
+    fn from(e: png::DecodingError) -> Self {
+        P8PngError::Png(e)
+    }
+}
+
+impl From<DecompressError> for P8PngError {
+    fn from(e: DecompressError) -> Self {
+        P8PngError::Decompress(e)
+    }
+}
+
+/// Extract the ROM from the pixels of a ".p8.png" image.
+pub fn read_rom(png: impl Read) -> Result<Vec<u8>, P8PngError> {
+    let decoder = png::Decoder::new(png);
+    let mut reader = decoder.read_info()?;
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf)?;
+    if info.color_type != png::ColorType::Rgba || info.bit_depth != png::BitDepth::Eight {
+        return Err(P8PngError::BadImage);
+    }
+    let pixels = &buf[..info.buffer_size()];
+    if pixels.len() < ROM_SIZE * 4 {
+        return Err(P8PngError::BadImage);
+    }
+    Ok(pixels
+        .chunks_exact(4)
+        .take(ROM_SIZE)
+        .map(|p| (p[3] & 3) << 6 | (p[0] & 3) << 4 | (p[1] & 3) << 2 | (p[2] & 3))
+        .collect())
+}
+
+/// Read the code of a ".p8.png" cart as bytes in Pico-8's charset.
+pub fn read_code(png: impl Read) -> Result<Vec<u8>, P8PngError> {
+    let rom = read_rom(png)?;
+    Ok(compress::decompress(&rom[CODE_START..])?)
+}
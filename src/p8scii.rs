@@ -0,0 +1,343 @@
+//! Pico-8's charset, P8SCII, in string literals.
+//!
+//! Pico-8 saves the glyphs of its charset, like `█` or `♥`, as Unicode in a
+//! ".p8" cart but they are single bytes when the cart runs. A runtime with a
+//! Pico-8 font expects those bytes, so [patch_string_glyphs] rewrites the
+//! glyphs in string literals as decimal escapes, `chr()` calls, or text of the
+//! caller's choosing.
+//!
+//! Strings may also hold P8SCII control codes like `\^w` or `\#1` that change
+//! how `print` draws. Most are not valid escapes in plain Lua, so
+//! [patch_control_codes] strips them, keeps them as bytes, or hands the
+//! string to a print wrapper of the caller's choosing.
+use std::{borrow::Cow, collections::BTreeMap};
+
+use crate::{
+    charset::{CHARSET, byte_of},
+    glyph::is_selector,
+    lexer::{TokenKind, tokenize},
+    span::{TextEdit, apply_edits},
+};
+
+/// Decode P8SCII bytes, e.g. a file Pico-8 wrote that is not UTF-8, keeping
+/// ASCII and turning bytes 128 to 255 into their glyphs.
+///
+/// ```
+/// use pico8_to_lua::p8scii::decode;
+/// assert_eq!(decode(b"s = \"\x87\""), "s = \"♥\"");
+/// ```
+pub fn decode(bytes: &[u8]) -> String {
+    let mut text = String::with_capacity(bytes.len());
+    for &b in bytes {
+        match b {
+            0..=127 => text.push(b as char),
+            _ => text.push_str(CHARSET[b as usize]),
+        }
+    }
+    text
+}
+
+/// Return the P8SCII byte of a glyph, e.g. 135 for `♥`, or `None` if `c` is
+/// not a glyph. ASCII characters are not glyphs.
+pub fn glyph_byte(c: char) -> Option<u8> {
+    byte_of(c).filter(|b| matches!(b, 16..=31 | 128..=255))
+}
+
+/// How glyphs in string literals are rewritten.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GlyphStyle {
+    /// A decimal escape like `\135`. Long bracket strings, which have no
+    /// escapes, are left alone.
+    Escape,
+    /// A call like `chr(135)` joined to the rest of the string with `..`.
+    Chr,
+    /// The text given for each glyph, inserted as it is. Glyphs without text
+    /// are left alone.
+    Map(BTreeMap<char, String>),
+}
+
+/// A piece of a string literal's contents.
+enum Piece<'a> {
+    Text(&'a str),
+    Glyph(char, u8),
+}
+
+// Split `contents` at its glyphs, dropping the variation selectors that
+// follow some of them.
+fn pieces(contents: &str) -> Vec<Piece<'_>> {
+    let mut pieces = Vec::new();
+    let mut start = 0;
+    let mut chars = contents.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let Some(byte) = glyph_byte(c) else {
+            continue;
+        };
+        if start < i {
+            pieces.push(Piece::Text(&contents[start..i]));
+        }
+        pieces.push(Piece::Glyph(c, byte));
+        start = i + c.len_utf8();
+        while let Some(&(j, selector)) = chars.peek().filter(|(_, c)| is_selector(*c)) {
+            chars.next();
+            start = j + selector.len_utf8();
+        }
+    }
+    if start < contents.len() {
+        pieces.push(Piece::Text(&contents[start..]));
+    }
+    pieces
+}
+
+// Rewrite one string literal, or return `None` if it stays as it is.
+fn rewrite_string(text: &str, style: &GlyphStyle) -> Option<String> {
+    let (open, close) = if text.starts_with('"') || text.starts_with('\'') {
+        text.split_at(1)
+    } else {
+        let level = text[1..].find('[')? + 2;
+        text.split_at(level)
+    };
+    let is_long = open.len() > 1;
+    let (contents, close) = close.split_at(close.len().saturating_sub(open.len()));
+    let pieces = pieces(contents);
+    if !pieces.iter().any(|p| matches!(p, Piece::Glyph(..))) {
+        return None;
+    }
+    if *style == GlyphStyle::Chr {
+        let parts: Vec<String> = pieces
+            .iter()
+            .enumerate()
+            .map(|(i, piece)| match piece {
+                // Lua skips a newline right after a long bracket.
+                Piece::Text(text) if is_long && i > 0 && text.starts_with('\n') => {
+                    format!("{open}\n{text}{close}")
+                }
+                Piece::Text(text) => format!("{open}{text}{close}"),
+                Piece::Glyph(_, byte) => format!("chr({byte})"),
+            })
+            .collect();
+        return Some(format!("({})", parts.join(" .. ")));
+    }
+    if is_long && *style == GlyphStyle::Escape {
+        return None;
+    }
+    let mut out = open.to_string();
+    for piece in &pieces {
+        match (piece, style) {
+            (Piece::Text(text), _) => out.push_str(text),
+            (Piece::Glyph(c, _), GlyphStyle::Map(map)) => match map.get(c) {
+                Some(replacement) => out.push_str(replacement),
+                None => out.push(*c),
+            },
+            (Piece::Glyph(_, byte), _) => out.push_str(&format!("\\{byte:03}")),
+        }
+    }
+    out.push_str(close);
+    Some(out)
+}
+
+/// What to do with the P8SCII control codes in string literals.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ControlCodes {
+    /// Remove each control code and its parameters, e.g. `"\#1hi"` becomes
+    /// `"hi"`.
+    Strip,
+    /// Keep each control code as the byte Pico-8 reads, e.g. `"\^w"` becomes
+    /// `"\006w"`.
+    Bytes,
+    /// Keep the control codes as bytes and pass the string to the named
+    /// function, e.g. `p8text("\006w")`, for a runtime that draws them.
+    Wrap(String),
+}
+
+// The byte of the control code that `\c` writes, if it is not a valid escape
+// in plain Lua.
+fn control_byte(c: char) -> Option<u8> {
+    Some(match c {
+        '*' => 1,
+        '#' => 2,
+        '-' => 3,
+        '|' => 4,
+        '+' => 5,
+        '^' => 6,
+        _ => return None,
+    })
+}
+
+// The number of characters of parameters after the control code `\c` that
+// `rest` starts after.
+fn control_params(c: char, rest: &str) -> usize {
+    match c {
+        '*' | '#' | '-' | '|' | 'f' => 1,
+        '+' | 'v' => 2,
+        // The sfx to play.
+        'a' => rest.chars().take_while(char::is_ascii_digit).count(),
+        '^' => {
+            let params = match rest.chars().next() {
+                Some('-' | 'c' | 's' | 'x' | 'y' | 'r' | 'd') => 1,
+                Some('j') => 2,
+                Some('o') => 3,
+                Some('.') => 8,
+                Some(':') => 16,
+                // The rest of the string is poked into memory.
+                Some('!') => rest.chars().count(),
+                _ => 0,
+            };
+            (1 + params).min(rest.chars().count())
+        }
+        _ => 0,
+    }
+}
+
+// Rewrite the control codes of one string literal, or return `None` if it
+// has none. Long bracket strings have no escapes.
+fn rewrite_controls(text: &str, mode: &ControlCodes) -> Option<String> {
+    let quote = &text[..text.len().min(1)];
+    if !(quote == "\"" || quote == "'") || text.len() < 2 || !text.ends_with(quote) {
+        return None;
+    }
+    let contents = &text[1..text.len() - 1];
+    let mut out = String::from(quote);
+    let mut found = false;
+    let mut chars = contents.char_indices();
+    while let Some((_, c)) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        let Some((i, code)) = chars.next() else {
+            out.push(c);
+            break;
+        };
+        let strip = *mode == ControlCodes::Strip
+            && (control_byte(code).is_some() || matches!(code, 'f' | 'v' | 'a'));
+        if strip {
+            found = true;
+            let rest = &contents[i + code.len_utf8()..];
+            for _ in 0..control_params(code, rest) {
+                chars.next();
+            }
+        } else if let Some(byte) = control_byte(code) {
+            found = true;
+            out.push_str(&format!("\\{byte:03}"));
+        } else {
+            out.push(c);
+            out.push(code);
+        }
+    }
+    if !found {
+        return None;
+    }
+    out.push_str(quote);
+    Some(match mode {
+        ControlCodes::Wrap(function) => format!("{function}({out})"),
+        _ => out,
+    })
+}
+
+/// Rewrite the P8SCII control codes in the string literals of `lua` as `mode`
+/// says.
+///
+/// ```
+/// use pico8_to_lua::p8scii::{ControlCodes, patch_control_codes};
+/// let lua = "print(\"\\^w\\#1hi\")";
+/// assert_eq!(patch_control_codes(lua.into(), &ControlCodes::Strip), "print(\"hi\")");
+/// assert_eq!(patch_control_codes(lua.into(), &ControlCodes::Bytes), "print(\"\\006w\\0021hi\")");
+/// ```
+pub fn patch_control_codes<'h>(lua: Cow<'h, str>, mode: &ControlCodes) -> Cow<'h, str> {
+    let edits: Vec<TextEdit> = tokenize(&lua)
+        .filter(|t| t.kind == TokenKind::String)
+        .filter_map(|t| Some(TextEdit::new(t.span, rewrite_controls(t.text, mode)?)))
+        .collect();
+    if edits.is_empty() {
+        return lua;
+    }
+    Cow::Owned(apply_edits(&lua, &edits))
+}
+
+/// Rewrite the P8SCII glyphs in the string literals of `lua` in `style`.
+/// Code and comments are left alone.
+///
+/// ```
+/// use pico8_to_lua::p8scii::{GlyphStyle, patch_string_glyphs};
+/// assert_eq!(patch_string_glyphs("s = \"♥1\"".into(), &GlyphStyle::Escape), "s = \"\\1351\"");
+/// assert_eq!(patch_string_glyphs("s = \"a♥\"".into(), &GlyphStyle::Chr), "s = (\"a\" .. chr(135))");
+/// ```
+pub fn patch_string_glyphs<'h>(lua: Cow<'h, str>, style: &GlyphStyle) -> Cow<'h, str> {
+    let edits: Vec<TextEdit> = tokenize(&lua)
+        .filter(|t| t.kind == TokenKind::String)
+        .filter_map(|t| Some(TextEdit::new(t.span, rewrite_string(t.text, style)?)))
+        .collect();
+    if edits.is_empty() {
+        return lua;
+    }
+    Cow::Owned(apply_edits(&lua, &edits))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_charset() {
+        assert_eq!(glyph_byte('█'), Some(128));
+        assert_eq!(glyph_byte('❎'), Some(151));
+        assert_eq!(glyph_byte('あ'), Some(154));
+        assert_eq!(glyph_byte('ア'), Some(204));
+        assert_eq!(glyph_byte('◝'), Some(255));
+        assert_eq!(glyph_byte('▮'), Some(16));
+        assert_eq!(glyph_byte('a'), None);
+    }
+
+    #[test]
+    fn test_escape() {
+        let lua = "print(\"⬅️ to move\", ⬅️) -- ♥\ns = [[♥]]";
+        assert_eq!(
+            patch_string_glyphs(lua.into(), &GlyphStyle::Escape),
+            "print(\"\\139 to move\", ⬅️) -- ♥\ns = [[♥]]"
+        );
+        assert!(matches!(
+            patch_string_glyphs("s = 'abc'".into(), &GlyphStyle::Escape),
+            Cow::Borrowed(_)
+        ));
+    }
+
+    #[test]
+    fn test_chr() {
+        assert_eq!(
+            patch_string_glyphs("s = '█x█' .. [[\nab♪\nc]]".into(), &GlyphStyle::Chr),
+            "s = (chr(128) .. 'x' .. chr(128)) .. ([[\nab]] .. chr(141) .. [[\n\nc]])"
+        );
+    }
+
+    #[test]
+    fn test_strip_controls() {
+        fn strip(lua: &str) -> Cow<'_, str> {
+            patch_control_codes(lua.into(), &ControlCodes::Strip)
+        }
+        assert_eq!(
+            strip(r#"s = "\^-w\^c0\-3a\+12b\f7c\a12d""#),
+            r#"s = "abcd""#
+        );
+        assert_eq!(strip(r#"s = '\^.12345678x\*3y'"#), "s = 'xy'");
+        assert_eq!(strip(r#"s = "\\#\"\n""#), r#"s = "\\#\"\n""#);
+        assert!(matches!(strip("s = [[\\^w]]"), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn test_wrap_controls() {
+        let mode = ControlCodes::Wrap("p8text".into());
+        assert_eq!(
+            patch_control_codes(r#"print("\^i\|2hi", 1) print("ok")"#.into(), &mode),
+            r#"print(p8text("\006i\0042hi"), 1) print("ok")"#
+        );
+    }
+
+    #[test]
+    fn test_map() {
+        let map = BTreeMap::from([('♥', "\\u{e087}".to_string())]);
+        assert_eq!(
+            patch_string_glyphs("s = \"♥●\"".into(), &GlyphStyle::Map(map)),
+            "s = \"\\u{e087}●\""
+        );
+    }
+}
@@ -0,0 +1,257 @@
+//! Conversion that reports what it left behind.
+//!
+//! [convert_partial] converts what it can with [patch_lua](crate::patch_lua)
+//! and then lists every Pico-8 construct still present in the output, so a
+//! person porting a cart by hand knows exactly what is left to do.
+use std::fmt;
+
+use crate::{
+    lexer::{Token, TokenKind, tokenize},
+    patch_lua,
+    span::Span,
+};
+
+/// The kind of a Pico-8 construct that plain Lua does not have.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ResidueKind {
+    /// A compound assignment like `x |= 1` or `s ..= "a"`.
+    CompoundAssignment,
+    /// A shorthand `if (cond) stmt`.
+    ShorthandIf,
+    /// A shorthand `while (cond) stmt`.
+    ShorthandWhile,
+    /// A `?expr` print statement.
+    PrintShorthand,
+    /// The `!=` operator.
+    NotEqual,
+    /// A bitwise operator like `&`, `|`, `^^`, `~`, `<<`, or `>>>`.
+    BitwiseOperator,
+    /// The `\` integer division operator.
+    IntegerDivision,
+    /// A peek operator: `@`, `%`, or `$`.
+    PeekOperator,
+    /// A binary literal like `0b101`.
+    BinaryLiteral,
+    /// A glyph identifier like `⬅️`.
+    Glyph,
+    /// An unresolved `#include` statement.
+    Include,
+}
+
+impl fmt::Display for ResidueKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ResidueKind::CompoundAssignment => "compound assignment",
+            ResidueKind::ShorthandIf => "shorthand if",
+            ResidueKind::ShorthandWhile => "shorthand while",
+            ResidueKind::PrintShorthand => "print shorthand",
+            ResidueKind::NotEqual => "!= operator",
+            ResidueKind::BitwiseOperator => "bitwise operator",
+            ResidueKind::IntegerDivision => "integer division",
+            ResidueKind::PeekOperator => "peek operator",
+            ResidueKind::BinaryLiteral => "binary literal",
+            ResidueKind::Glyph => "glyph identifier",
+            ResidueKind::Include => "#include",
+        })
+    }
+}
+
+/// A Pico-8 construct that remains in converted Lua.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Residue {
+    /// What kind of construct it is.
+    pub kind: ResidueKind,
+    /// Where it is in the converted Lua.
+    pub span: Span,
+    /// Its text.
+    pub text: String,
+}
+
+/// The output of [convert_partial].
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Partial {
+    /// The converted Lua.
+    pub lua: String,
+    /// The Pico-8 constructs left in `lua`, in order.
+    pub residue: Vec<Residue>,
+}
+
+impl Partial {
+    /// Return true if nothing was left to convert.
+    pub fn is_complete(&self) -> bool {
+        self.residue.is_empty()
+    }
+}
+
+/// Convert what can be converted and list what remains.
+///
+/// ```
+/// use pico8_to_lua::{convert_partial, partial::ResidueKind};
+/// let partial = convert_partial("x += 1\ny = a & b\n");
+/// assert_eq!(partial.lua, "x = x + (1)\ny = a & b\n");
+/// assert_eq!(partial.residue[0].kind, ResidueKind::BitwiseOperator);
+/// ```
+pub fn convert_partial(lua: &str) -> Partial {
+    let lua = patch_lua(lua).into_owned();
+    let residue = find_residue(&lua);
+    Partial { lua, residue }
+}
+
+fn at_line_start(lua: &str, token: &Token) -> bool {
+    lua[..token.span.start]
+        .rsplit('\n')
+        .next()
+        .is_some_and(|before| before.trim().is_empty())
+}
+
+fn same_line(lua: &str, a: &Token, b: &Token) -> bool {
+    !lua[a.span.end..b.span.start].contains('\n')
+}
+
+/// Return the index of the `)` that closes the `(` at `open`.
+fn closing_paren(code: &[Token], open: usize) -> Option<usize> {
+    let mut depth = 0;
+    for (i, t) in code.iter().enumerate().skip(open) {
+        if t.is("(") {
+            depth += 1;
+        } else if t.is(")") {
+            depth -= 1;
+            if depth == 0 {
+                return Some(i);
+            }
+        }
+    }
+    None
+}
+
+/// Return true if `if (cond)` or `while (cond)` at `i` is followed on the
+/// same line by a statement rather than by `then`, `do`, or more of the
+/// condition.
+pub(crate) fn is_shorthand(lua: &str, code: &[Token], i: usize) -> bool {
+    if !code.get(i + 1).is_some_and(|t| t.is("(")) {
+        return false;
+    }
+    let Some(close) = closing_paren(code, i + 1) else {
+        return false;
+    };
+    let Some(next) = code.get(close + 1) else {
+        return false;
+    };
+    same_line(lua, &code[close], next)
+        && match next.kind {
+            TokenKind::Name => true,
+            TokenKind::Keyword => matches!(
+                next.text,
+                "return" | "break" | "local" | "goto" | "if" | "for" | "while" | "repeat" | "do"
+            ) && !(code[i].is("while") && next.is("do")),
+            TokenKind::Punct => next.is("?"),
+            _ => false,
+        }
+}
+
+/// List the Pico-8 constructs in `lua` that plain Lua does not have.
+///
+/// Strings and comments are not searched.
+pub fn find_residue(lua: &str) -> Vec<Residue> {
+    let code: Vec<Token> = tokenize(lua)
+        .filter(|t| !matches!(t.kind, TokenKind::Comment | TokenKind::String))
+        .collect();
+    let mut residue: Vec<Residue> = Vec::new();
+    for (i, token) in code.iter().enumerate() {
+        // The path of an "#include" is not code.
+        if residue
+            .last()
+            .is_some_and(|r| r.kind == ResidueKind::Include && r.span.contains(token.span.start))
+        {
+            continue;
+        }
+        let prev = i.checked_sub(1).map(|p| &code[p]);
+        let binary = prev.is_some_and(|p| p.ends_value() && same_line(lua, p, token));
+        let kind = match token.kind {
+            TokenKind::Number if token.text.starts_with("0b") || token.text.starts_with("0B") => {
+                Some(ResidueKind::BinaryLiteral)
+            }
+            TokenKind::Name if !token.text.is_ascii() => Some(ResidueKind::Glyph),
+            TokenKind::Keyword if token.is("if") && is_shorthand(lua, &code, i) => {
+                Some(ResidueKind::ShorthandIf)
+            }
+            TokenKind::Keyword if token.is("while") && is_shorthand(lua, &code, i) => {
+                Some(ResidueKind::ShorthandWhile)
+            }
+            TokenKind::Punct => match token.text {
+                "!=" => Some(ResidueKind::NotEqual),
+                "==" | "~=" | "<=" | ">=" => None,
+                t if t.len() > 1 && t.ends_with('=') => Some(ResidueKind::CompoundAssignment),
+                "&" | "|" | "^^" | "~" | "<<" | ">>" | ">>>" | "<<>" | ">><" => {
+                    Some(ResidueKind::BitwiseOperator)
+                }
+                "\\" => Some(ResidueKind::IntegerDivision),
+                "@" | "$" => Some(ResidueKind::PeekOperator),
+                "%" if !binary => Some(ResidueKind::PeekOperator),
+                "?" => Some(ResidueKind::PrintShorthand),
+                "#" if at_line_start(lua, token)
+                    && code.get(i + 1).is_some_and(|n| n.text == "include") =>
+                {
+                    Some(ResidueKind::Include)
+                }
+                _ => None,
+            },
+            _ => None,
+        };
+        if let Some(kind) = kind {
+            let span = if kind == ResidueKind::Include {
+                let end = lua[token.span.start..]
+                    .find('\n')
+                    .map_or(lua.len(), |n| token.span.start + n);
+                Span::new(token.span.start, lua[..end].trim_end().len())
+            } else {
+                token.span
+            };
+            residue.push(Residue {
+                kind,
+                span,
+                text: lua[span.range()].to_string(),
+            });
+        }
+    }
+    residue
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(lua: &str) -> Vec<ResidueKind> {
+        find_residue(lua).into_iter().map(|r| r.kind).collect()
+    }
+
+    #[test]
+    fn test_find_residue() {
+        use ResidueKind::*;
+        assert_eq!(
+            kinds("x |= 1\ny = a \\ 2 + @0x6000 + %b\nz = -x % 2\n"),
+            vec![CompoundAssignment, IntegerDivision, PeekOperator, PeekOperator]
+        );
+        assert_eq!(kinds("if (a) b()\nwhile (c) d()\nif (a) then end"), vec![ShorthandIf, ShorthandWhile]);
+        assert_eq!(kinds("print(\"a != b\") -- c &= d"), vec![]);
+        assert_eq!(kinds("x = ⬅️ ~ 0b1"), vec![Glyph, BitwiseOperator, BinaryLiteral]);
+    }
+
+    #[test]
+    fn test_include_span() {
+        let residue = find_residue("a = 1\n#include 0b-lib.p8 \nb = 2\n");
+        assert_eq!(residue.len(), 1);
+        assert_eq!(residue[0].kind, ResidueKind::Include);
+        assert_eq!(residue[0].text, "#include 0b-lib.p8");
+    }
+
+    #[test]
+    fn test_convert_partial() {
+        let partial = convert_partial("if (a != b) x &= 1\n");
+        assert_eq!(partial.lua, "if a ~= b then x = x & (1) end\n");
+        assert_eq!(partial.residue.len(), 1);
+        assert_eq!(partial.residue[0].kind, ResidueKind::BitwiseOperator);
+        assert_eq!(&partial.lua[partial.residue[0].span.range()], "&");
+        assert!(convert_partial("x ..= \"!\"").is_complete());
+    }
+}
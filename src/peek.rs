@@ -0,0 +1,58 @@
+//! Translation of Pico-8's memory access shorthands.
+//!
+//! Pico-8 reads memory with `@addr`, `%addr`, and `$addr`, which are short
+//! for `peek(addr)`, `peek2(addr)`, and `peek4(addr)`. [patch_peek] rewrites
+//! them as calls. Only the prefix operators are rewritten, so `a % b` stays a
+//! modulo and strings like `"%d"` are left alone.
+use std::borrow::Cow;
+
+use crate::{
+    operators::{Operators, Translation, rewrite_operators},
+    symbols::{DEFAULT_PREFIX, Namer},
+};
+
+struct Peek;
+
+impl Operators for Peek {
+    fn translate(&self, _op: &str) -> Option<Translation> {
+        None
+    }
+
+    fn unary(&self, op: &str) -> Option<Translation> {
+        match op {
+            "@" => Some(Translation::Call("peek")),
+            "%" => Some(Translation::Call("peek2")),
+            "$" => Some(Translation::Call("peek4")),
+            _ => None,
+        }
+    }
+}
+
+/// Rewrite the memory access shorthands as calls to `peek`, `peek2`, and
+/// `peek4`.
+///
+/// ```
+/// use pico8_to_lua::peek::patch_peek;
+/// assert_eq!(patch_peek("x = @a + %(a + 1) % 4"), "x = peek(a) + peek2((a + 1)) % 4");
+/// ```
+pub fn patch_peek<'h>(lua: impl Into<Cow<'h, str>>) -> Cow<'h, str> {
+    let lua = lua.into();
+    let mut namer = Namer::new(DEFAULT_PREFIX, &lua);
+    rewrite_operators(lua, &Peek, &mut namer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_patch_peek() {
+        assert_eq!(
+            patch_peek("if @t.addr == 0 then end"),
+            "if peek(t.addr) == 0 then end"
+        );
+        assert_eq!(patch_peek("x = -$a ^ 2"), "x = -peek4(a ^ 2)");
+        assert_eq!(patch_peek("f(%a, @%b)"), "f(peek2(a), peek(peek2(b)))");
+        assert!(matches!(patch_peek("x = a % b -- @c"), Cow::Borrowed(_)));
+    }
+}
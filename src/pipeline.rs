@@ -0,0 +1,935 @@
+//! The passes of a conversion.
+//!
+//! A [Patcher] converts Lua with a set of [Options]. [Patcher::describe] lists
+//! the passes it runs in order, so tools can show what a conversion will do
+//! and tests can check how the pipeline is put together. [PASSES] is the same
+//! list as a constant with every optional pass off.
+//!
+//! A [Pipeline] runs a [Patcher] between [Transform]s of your own, e.g. to
+//! expand a project's macros before the built-in passes see the code.
+use std::{
+    borrow::Cow,
+    fmt::{self, Write as _},
+};
+
+use crate::{
+    Options,
+    api::namespace_calls,
+    bitwise::{LuaTarget, patch_bitwise_with},
+    builtins::inline_math,
+    cart::comment_header,
+    coroutines::patch_coroutines,
+    debug::strip_debug,
+    deprecated::migrate_deprecated,
+    diagnostics::Diagnostic,
+    division::patch_integer_division,
+    fixed::patch_fixed_point_with,
+    format::format_lua,
+    glyph::patch_buttons,
+    goto::patch_goto_continue,
+    is_converted,
+    limits::LimitError,
+    loops::patch_all_loops_with,
+    newline::Newlines,
+    p8scii::{patch_control_codes, patch_string_glyphs},
+    patch_lua_with,
+    span::{Span, TextEdit},
+    symbols::{Namer, number_glyphs, rename_glyphs},
+    tables::patch_table_builtins_with,
+    trace,
+    trig::patch_trig_with,
+    version::{Feature, patch_hex_floats},
+};
+
+/// What a pass converts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum PassCategory {
+    /// Comment syntax.
+    Comment,
+    /// Operators.
+    Operator,
+    /// Statements.
+    Statement,
+    /// Literals.
+    Literal,
+    /// Glyph identifiers.
+    Glyph,
+    /// Layout of the converted code.
+    Layout,
+    /// License comments.
+    License,
+}
+
+impl fmt::Display for PassCategory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            PassCategory::Comment => "comment",
+            PassCategory::Operator => "operator",
+            PassCategory::Statement => "statement",
+            PassCategory::Literal => "literal",
+            PassCategory::Glyph => "glyph",
+            PassCategory::Layout => "layout",
+            PassCategory::License => "license",
+        })
+    }
+}
+
+/// A pass of a conversion.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PassInfo {
+    /// The name of the pass, e.g. "compound-assignment".
+    pub name: &'static str,
+    /// What the pass converts.
+    pub category: PassCategory,
+    /// Whether the pass runs.
+    pub enabled: bool,
+    /// The zero-based position of the pass in the pipeline.
+    pub order: usize,
+}
+
+impl PassInfo {
+    const fn new(order: usize, name: &'static str, category: PassCategory, enabled: bool) -> Self {
+        PassInfo {
+            name,
+            category,
+            enabled,
+            order,
+        }
+    }
+}
+
+/// Every pass in order with the optional passes off, as for
+/// [Options::default] except for "license-header".
+pub const PASSES: &[PassInfo] = &[
+    PassInfo::new(0, "slash-comments", PassCategory::Comment, true),
+    PassInfo::new(1, "not-equal", PassCategory::Operator, true),
+    PassInfo::new(2, "button-arguments", PassCategory::Glyph, true),
+    PassInfo::new(3, "shorthand-if", PassCategory::Statement, true),
+    PassInfo::new(4, "shorthand-while", PassCategory::Statement, true),
+    PassInfo::new(5, "compound-assignment", PassCategory::Statement, true),
+    PassInfo::new(6, "print-shorthand", PassCategory::Statement, true),
+    PassInfo::new(7, "binary-literals", PassCategory::Literal, true),
+    PassInfo::new(8, "peek-shorthand", PassCategory::Operator, true),
+    PassInfo::new(9, "strip-comments", PassCategory::Comment, false),
+    PassInfo::new(10, "glyph-names", PassCategory::Glyph, true),
+    PassInfo::new(11, "deprecated-api", PassCategory::Statement, false),
+    PassInfo::new(12, "fixed-point", PassCategory::Operator, false),
+    PassInfo::new(13, "bitwise", PassCategory::Operator, false),
+    PassInfo::new(14, "inline-math", PassCategory::Operator, false),
+    PassInfo::new(15, "trigonometry", PassCategory::Operator, false),
+    PassInfo::new(16, "all-loops", PassCategory::Statement, false),
+    PassInfo::new(17, "table-builtins", PassCategory::Statement, false),
+    PassInfo::new(18, "coroutines", PassCategory::Statement, false),
+    PassInfo::new(19, "strip-debug", PassCategory::Statement, false),
+    PassInfo::new(20, "api-namespace", PassCategory::Statement, false),
+    PassInfo::new(21, "goto-continue", PassCategory::Statement, false),
+    PassInfo::new(22, "hex-floats", PassCategory::Literal, false),
+    PassInfo::new(23, "string-glyphs", PassCategory::Literal, false),
+    PassInfo::new(24, "control-codes", PassCategory::Literal, false),
+    PassInfo::new(25, "integer-division", PassCategory::Operator, false),
+    PassInfo::new(26, "format", PassCategory::Layout, false),
+    PassInfo::new(27, "license-header", PassCategory::License, false),
+];
+
+/// Converts Lua with a set of [Options].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Patcher {
+    options: Options,
+    cart_version: Option<u32>,
+}
+
+impl Patcher {
+    /// Create a patcher.
+    pub fn new(options: Options) -> Self {
+        Patcher {
+            options,
+            cart_version: None,
+        }
+    }
+
+    /// Set the cart format version of the code to patch, from its cart's
+    /// "version N" line, or `None` if it is unknown. The
+    /// [migrate_deprecated] pass leaves the code of a current cart as it is.
+    pub fn set_cart_version(&mut self, version: Option<u32>) {
+        self.cart_version = version;
+    }
+
+    /// The options of the patcher.
+    pub fn options(&self) -> &Options {
+        &self.options
+    }
+
+    /// List the passes in the order they run and whether each is enabled by
+    /// the options.
+    ///
+    /// ```
+    /// use pico8_to_lua::{Options, pipeline::Patcher};
+    /// let patcher = Patcher::new(Options::default());
+    /// let passes = patcher.describe();
+    /// assert_eq!(passes[5].name, "compound-assignment");
+    /// assert!(!passes.iter().find(|p| p.name == "bitwise").unwrap().enabled);
+    /// ```
+    pub fn describe(&self) -> Vec<PassInfo> {
+        PASSES
+            .iter()
+            .map(|pass| PassInfo {
+                enabled: match pass.name {
+                    "deprecated-api" => self.options.deprecated_api,
+                    "fixed-point" => self.options.fixed_point,
+                    "bitwise" => self.bitwise().is_some(),
+                    "integer-division" => self.integer_division().is_some(),
+                    "inline-math" => self.options.inline_math,
+                    "trigonometry" => self.options.trigonometry,
+                    "all-loops" => self.options.all_loops,
+                    "table-builtins" => self.options.table_builtins,
+                    "coroutines" => self.options.coroutines,
+                    "strip-debug" => self.options.strip_debug,
+                    "api-namespace" => self.options.namespace.is_some(),
+                    "goto-continue" => self.lacks(Feature::Goto),
+                    "hex-floats" => self.lacks(Feature::HexFloat),
+                    "string-glyphs" => self.options.string_glyphs.is_some(),
+                    "control-codes" => self.options.control_codes.is_some(),
+                    "format" => self.options.format.is_some(),
+                    "license-header" => self.options.license_header,
+                    "slash-comments" => self.options.patch.slash_comments,
+                    "strip-comments" => self.options.patch.strip_comments,
+                    "not-equal" => self.options.patch.not_equal,
+                    "button-arguments" => self.options.patch.button_arguments,
+                    "shorthand-if" => self.options.patch.shorthand_if,
+                    "shorthand-while" => self.options.patch.shorthand_while,
+                    "compound-assignment" => self.options.patch.compound_assignment,
+                    "print-shorthand" => self.options.patch.print_shorthand,
+                    "binary-literals" => self.options.patch.binary_literals,
+                    "peek-shorthand" => self.options.patch.peek_shorthand,
+                    _ => pass.enabled,
+                },
+                ..*pass
+            })
+            .collect()
+    }
+
+    /// Convert `lua` with every enabled pass except "license-header", which
+    /// needs the cart the Lua came from.
+    ///
+    /// With [Options::preserve_lines] the Lua keeps its number of lines. Lua
+    /// that [is_converted] is returned as it is. The passes see "\n" line
+    /// endings and no byte order mark, and the Lua returned has those of
+    /// `lua`.
+    pub fn patch<'h>(&self, lua: &'h str) -> Cow<'h, str> {
+        if let Some((lua, newlines)) = Newlines::normalize(lua) {
+            return Cow::Owned(newlines.restore(&self.patch(&lua)));
+        }
+        if is_converted(lua) {
+            return Cow::Borrowed(lua);
+        }
+        if let Cow::Owned(lua) = self.buttons(lua) {
+            return Cow::Owned(self.patch(&lua).into_owned());
+        }
+        self.finish(lua, patch_lua_with(lua, &self.options.patch))
+    }
+
+    /// Rewrite the buttons of [Options::buttons], if there are any.
+    fn buttons<'h>(&self, lua: &'h str) -> Cow<'h, str> {
+        match &self.options.buttons {
+            Some(buttons) if self.options.patch.button_arguments => {
+                patch_buttons(Cow::Borrowed(lua), buttons)
+            }
+            _ => Cow::Borrowed(lua),
+        }
+    }
+
+    /// Convert `lua` like [patch](Self::patch) within [Options::limits], if
+    /// there are any. The changes counted are those of the Pico-8 syntax
+    /// rewrites.
+    ///
+    /// ```
+    /// use pico8_to_lua::{Options, limits::{LimitError, Limits}, pipeline::Patcher};
+    /// let limits = Limits { max_changes: 1, ..Limits::default() };
+    /// let patcher = Patcher::new(Options { limits: Some(limits), ..Options::default() });
+    /// assert_eq!(patcher.try_patch("x += 1").unwrap(), "x = x + (1)");
+    /// assert_eq!(
+    ///     patcher.try_patch("x += 1\ny -= 1"),
+    ///     Err(LimitError::Changes { count: 2, limit: 1 })
+    /// );
+    /// ```
+    pub fn try_patch<'h>(&self, lua: &'h str) -> Result<Cow<'h, str>, LimitError> {
+        if let Some((lua, newlines)) = Newlines::normalize(lua) {
+            return Ok(Cow::Owned(newlines.restore(&self.try_patch(&lua)?)));
+        }
+        if is_converted(lua) {
+            return Ok(Cow::Borrowed(lua));
+        }
+        if let Cow::Owned(lua) = self.buttons(lua) {
+            return Ok(Cow::Owned(self.try_patch(&lua)?.into_owned()));
+        }
+        let Some(limits) = &self.options.limits else {
+            return Ok(self.patch(lua));
+        };
+        limits.check_input(lua)?;
+        let patched = patch_lua_with(lua, &self.options.patch);
+        limits.check_changes(lua, &patched)?;
+        Ok(self.finish(lua, patched))
+    }
+
+    /// Run the passes after the Pico-8 syntax rewrites, which turned
+    /// `source` into `patched`.
+    fn finish<'h>(&self, source: &'h str, patched: Cow<'h, str>) -> Cow<'h, str> {
+        let mut namer = Namer::new(&self.options.prefix, source);
+        let lua = trace::pass("glyph-names", patched, |lua| {
+            if self.options.glyph_numbers {
+                number_glyphs(lua)
+            } else {
+                let before = lua.to_string();
+                self.fold(&before, rename_glyphs(lua, &mut namer))
+            }
+        });
+        let lua = if self.options.deprecated_api {
+            trace::pass("deprecated-api", lua, |lua| migrate_deprecated(lua, self.cart_version))
+        } else {
+            lua
+        };
+        let lua = if self.options.fixed_point {
+            trace::pass("fixed-point", lua, |lua| {
+                let before = lua.to_string();
+                self.fold(&before, patch_fixed_point_with(lua, &mut namer))
+            })
+        } else {
+            lua
+        };
+        let lua = match self.bitwise() {
+            Some(target) => trace::pass("bitwise", lua, |lua| {
+                let before = lua.to_string();
+                self.fold(&before, patch_bitwise_with(lua, target, &mut namer))
+            }),
+            None => lua,
+        };
+        let lua = if self.options.inline_math {
+            trace::pass("inline-math", lua, inline_math)
+        } else {
+            lua
+        };
+        let lua = if self.options.trigonometry {
+            trace::pass("trigonometry", lua, |lua| {
+                let before = lua.to_string();
+                self.fold(&before, patch_trig_with(lua, &mut namer))
+            })
+        } else {
+            lua
+        };
+        let lua = if self.options.all_loops {
+            trace::pass("all-loops", lua, |lua| patch_all_loops_with(lua, &mut namer))
+        } else {
+            lua
+        };
+        let lua = if self.options.table_builtins {
+            trace::pass("table-builtins", lua, |lua| {
+                let before = lua.to_string();
+                self.fold(&before, patch_table_builtins_with(lua, &mut namer))
+            })
+        } else {
+            lua
+        };
+        let lua = if self.options.coroutines {
+            trace::pass("coroutines", lua, patch_coroutines)
+        } else {
+            lua
+        };
+        let lua = if self.options.strip_debug {
+            trace::pass("strip-debug", lua, strip_debug)
+        } else {
+            lua
+        };
+        let lua = match &self.options.namespace {
+            Some(namespace) => {
+                trace::pass("api-namespace", lua, |lua| namespace_calls(lua, namespace))
+            }
+            None => lua,
+        };
+        let lua = if self.lacks(Feature::Goto) {
+            trace::pass("goto-continue", lua, patch_goto_continue)
+        } else {
+            lua
+        };
+        let lua = if self.lacks(Feature::HexFloat) {
+            trace::pass("hex-floats", lua, patch_hex_floats)
+        } else {
+            lua
+        };
+        let lua = match &self.options.string_glyphs {
+            Some(style) => trace::pass("string-glyphs", lua, |lua| patch_string_glyphs(lua, style)),
+            None => lua,
+        };
+        let lua = match &self.options.control_codes {
+            Some(mode) => trace::pass("control-codes", lua, |lua| patch_control_codes(lua, mode)),
+            None => lua,
+        };
+        // Lua 5.3's `//` would start a comment for the lexer of the passes
+        // before, so integer division is translated after them.
+        let lua = match self.integer_division() {
+            Some(target) => trace::pass("integer-division", lua, |lua| {
+                let before = lua.to_string();
+                self.fold(&before, patch_integer_division(lua, target))
+            }),
+            None => lua,
+        };
+        let lua = match &self.options.format {
+            Some(format) => trace::pass("format", lua, |lua| Cow::Owned(format_lua(&lua, format))),
+            None => lua,
+        };
+        debug_assert!(
+            !self.options.preserve_lines || lines(&lua) == lines(source),
+            "a pass changed the number of lines"
+        );
+        lua
+    }
+
+    /// The target of the bitwise operators, by default [Options::target].
+    fn bitwise(&self) -> Option<LuaTarget> {
+        self.options.bitwise.or(self.options.target)
+    }
+
+    /// The target of integer division, by default [Options::target].
+    fn integer_division(&self) -> Option<LuaTarget> {
+        self.options.integer_division.or(self.options.target)
+    }
+
+    /// Whether the target lacks `feature`, so its uses are rewritten.
+    fn lacks(&self, feature: Feature) -> bool {
+        self.options.target.is_some_and(|t| !t.supports(feature))
+    }
+
+    /// Fold the lines a pass inserted after the comment header if the lines
+    /// are preserved.
+    fn fold<'h>(&self, before: &str, after: Cow<'h, str>) -> Cow<'h, str> {
+        if self.options.preserve_lines {
+            fold_inserted_lines(before, after)
+        } else {
+            after
+        }
+    }
+}
+
+/// Return the number of lines of `lua`.
+pub(crate) fn lines(lua: &str) -> usize {
+    lua.matches('\n').count() + 1
+}
+
+/// Join the lines that were inserted after the comment header of `before`,
+/// like helper definitions, to the first line after them, so `after` has as
+/// many lines as `before`. Inserted comment lines become block comments.
+pub(crate) fn fold_inserted_lines<'h>(before: &str, after: Cow<'h, str>) -> Cow<'h, str> {
+    let extra = lines(&after).saturating_sub(lines(before));
+    if extra == 0 {
+        return after;
+    }
+    let at = comment_header(before).len();
+    let end = after[at..]
+        .match_indices('\n')
+        .nth(extra - 1)
+        .map_or(after.len(), |(i, _)| at + i + 1);
+    let mut folded = String::new();
+    for line in after[at..end].lines() {
+        match line.strip_prefix("--") {
+            Some(comment) => {
+                let mut level = String::new();
+                while comment.contains(&format!("]{level}]")) {
+                    level.push('=');
+                }
+                write!(folded, "--[{level}[{comment} ]{level}] ").expect("writing to a String cannot fail");
+            }
+            None => {
+                folded.push_str(line);
+                folded.push(' ');
+            }
+        }
+    }
+    Cow::Owned(format!("{}{}{}", &after[..at], folded, &after[end..]))
+}
+
+/// Where a pass changed the Lua.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Change {
+    /// The span the pass replaced in the Lua it was given.
+    pub span: Span,
+    /// The one-based line of the span's start.
+    pub line: usize,
+    /// The one-based column in bytes of the span's start.
+    pub column: usize,
+}
+
+/// A `&'static str` that serde does not take for a string to borrow from
+/// the input, since it is read into the name of a pass instead.
+type StaticStr = &'static str;
+
+/// Read the name of a pass in [PASSES].
+#[cfg(feature = "serde")]
+fn deserialize_pass_name<'de, D: serde::Deserializer<'de>>(d: D) -> Result<&'static str, D::Error> {
+    let name = <String as serde::Deserialize>::deserialize(d)?;
+    PASSES
+        .iter()
+        .map(|pass| pass.name)
+        .find(|&n| n == name)
+        .ok_or_else(|| serde::de::Error::custom(format!("unknown pass {name:?}")))
+}
+
+/// The changes of one pass.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PassChanges {
+    /// The name of the pass in [PASSES].
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "deserialize_pass_name"))]
+    pub name: StaticStr,
+    /// One change for each line the pass changed in order.
+    pub changes: Vec<Change>,
+}
+
+/// What each pass of a conversion changed. See
+/// [patch_lua_with_report](crate::patch_lua_with_report).
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PatchReport {
+    /// The passes that changed something in the order they ran.
+    pub passes: Vec<PassChanges>,
+}
+
+impl PatchReport {
+    /// Return how many lines the pass called `name` changed.
+    pub fn count(&self, name: &str) -> usize {
+        self.passes
+            .iter()
+            .find(|p| p.name == name)
+            .map_or(0, |p| p.changes.len())
+    }
+}
+
+impl fmt::Display for PatchReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for pass in &self.passes {
+            let n = pass.changes.len();
+            write!(f, "{}: {} change{} at ", pass.name, n, if n == 1 { "" } else { "s" })?;
+            for (i, change) in pass.changes.iter().enumerate() {
+                if i > 0 {
+                    f.write_str(", ")?;
+                }
+                write!(f, "{}:{}", change.line, change.column)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// What a conversion changed, from
+/// [patch_lua_with_outcome](crate::patch_lua_with_outcome), e.g. for tools
+/// that skip writing unchanged files and log why the others changed.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PatchOutcome {
+    /// The categories of the passes that changed the Lua, each once, in the
+    /// order the passes ran.
+    pub categories: Vec<PassCategory>,
+    /// What each pass changed.
+    pub report: PatchReport,
+    /// The paths of the includes resolved before converting. The conversion
+    /// leaves includes to the caller, which records them here.
+    pub includes: Vec<String>,
+    /// The warnings about the Lua. See
+    /// [diagnose](crate::diagnostics::diagnose).
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl PatchOutcome {
+    /// Create the outcome of a conversion from what its passes changed and
+    /// its warnings.
+    pub fn new(report: PatchReport, diagnostics: Vec<Diagnostic>) -> Self {
+        let mut categories = Vec::new();
+        for pass in &report.passes {
+            let category = PASSES
+                .iter()
+                .find(|p| p.name == pass.name)
+                .map(|p| p.category);
+            if let Some(category) = category.filter(|c| !categories.contains(c)) {
+                categories.push(category);
+            }
+        }
+        PatchOutcome {
+            categories,
+            report,
+            includes: Vec::new(),
+            diagnostics,
+        }
+    }
+
+    /// Return true if a pass or an include changed the Lua.
+    pub fn is_changed(&self) -> bool {
+        !self.report.passes.is_empty() || !self.includes.is_empty()
+    }
+
+    /// Return true if a pass of `category` changed the Lua.
+    pub fn changed(&self, category: PassCategory) -> bool {
+        self.categories.contains(&category)
+    }
+}
+
+impl fmt::Display for PatchOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.is_changed() {
+            f.write_str("unchanged")?;
+        } else {
+            let mut changes: Vec<String> = self.categories.iter().map(|c| c.to_string()).collect();
+            if !self.includes.is_empty() {
+                changes.push("includes".into());
+            }
+            write!(f, "changed {}", changes.join(", "))?;
+        }
+        let n = self.diagnostics.len();
+        if n > 0 {
+            write!(f, "; {} warning{}", n, if n == 1 { "" } else { "s" })?;
+        }
+        Ok(())
+    }
+}
+
+/// Return a change for each line that differs between `before` and `after`.
+///
+/// If a pass added or removed lines, the lines can't be paired, and the whole
+/// difference is one change.
+pub(crate) fn line_changes(before: &str, after: &str) -> Vec<Change> {
+    let change = |start: usize, edit: TextEdit| {
+        let span = Span::new(start + edit.span.start, start + edit.span.end);
+        let (line, column) = span.line_col(before);
+        Change { span, line, column }
+    };
+    if before.matches('\n').count() != after.matches('\n').count() {
+        return TextEdit::diff(before, after)
+            .map(|edit| change(0, edit))
+            .into_iter()
+            .collect();
+    }
+    let mut start = 0;
+    let mut changes = Vec::new();
+    for (old, new) in before.split('\n').zip(after.split('\n')) {
+        if let Some(edit) = TextEdit::diff(old, new) {
+            changes.push(change(start, edit));
+        }
+        start += old.len() + 1;
+    }
+    changes
+}
+
+/// Whether a [Transform] changed the Lua.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PassResult {
+    /// The Lua is as it was.
+    Unchanged,
+    /// The Lua was rewritten.
+    Changed,
+}
+
+impl PassResult {
+    /// Whether the Lua was rewritten.
+    pub fn is_changed(self) -> bool {
+        self == PassResult::Changed
+    }
+}
+
+impl From<bool> for PassResult {
+    fn from(changed: bool) -> Self {
+        if changed {
+            PassResult::Changed
+        } else {
+            PassResult::Unchanged
+        }
+    }
+}
+
+/// A rewrite of Lua that a [Pipeline] runs.
+///
+/// Any `Fn(&mut Cow<str>) -> PassResult` is a transform.
+pub trait Transform {
+    /// Rewrite `lua` in place.
+    fn apply(&self, lua: &mut Cow<'_, str>) -> PassResult;
+}
+
+impl<F: Fn(&mut Cow<'_, str>) -> PassResult> Transform for F {
+    fn apply(&self, lua: &mut Cow<'_, str>) -> PassResult {
+        self(lua)
+    }
+}
+
+impl Transform for Patcher {
+    fn apply(&self, lua: &mut Cow<'_, str>) -> PassResult {
+        let patched = match self.patch(lua) {
+            Cow::Owned(o) => Some(o),
+            Cow::Borrowed(_) => None,
+        };
+        match patched {
+            Some(patched) => {
+                *lua = Cow::Owned(patched);
+                PassResult::Changed
+            }
+            None => PassResult::Unchanged,
+        }
+    }
+}
+
+/// Runs [Transform]s before and after the built-in passes of a [Patcher].
+#[derive(Default)]
+pub struct Pipeline {
+    before: Vec<Box<dyn Transform>>,
+    patcher: Patcher,
+    after: Vec<Box<dyn Transform>>,
+}
+
+impl fmt::Debug for Pipeline {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Pipeline")
+            .field("before", &self.before.len())
+            .field("patcher", &self.patcher)
+            .field("after", &self.after.len())
+            .finish()
+    }
+}
+
+impl Pipeline {
+    /// Create a pipeline with only the built-in passes.
+    pub fn new(options: Options) -> Self {
+        Pipeline {
+            before: Vec::new(),
+            patcher: Patcher::new(options),
+            after: Vec::new(),
+        }
+    }
+
+    /// The patcher that runs the built-in passes.
+    pub fn patcher(&self) -> &Patcher {
+        &self.patcher
+    }
+
+    /// Run `transform` before the built-in passes and after the transforms
+    /// already added before them.
+    pub fn push_before(&mut self, transform: impl Transform + 'static) {
+        self.before.push(Box::new(transform));
+    }
+
+    /// Run `transform` after the built-in passes and the transforms already
+    /// added after them.
+    pub fn push_after(&mut self, transform: impl Transform + 'static) {
+        self.after.push(Box::new(transform));
+    }
+
+    /// Convert `lua` with every transform in order.
+    ///
+    /// ```
+    /// use std::borrow::Cow;
+    /// use pico8_to_lua::{Options, pipeline::{PassResult, Pipeline}};
+    /// let mut pipeline = Pipeline::new(Options::default());
+    /// pipeline.push_before(|lua: &mut Cow<str>| {
+    ///     let changed = lua.contains("@inc");
+    ///     if changed {
+    ///         *lua = Cow::Owned(lua.replace("@inc", "+= 1"));
+    ///     }
+    ///     PassResult::from(changed)
+    /// });
+    /// assert_eq!(pipeline.patch("x @inc"), "x = x + (1)");
+    /// ```
+    pub fn patch<'h>(&self, lua: &'h str) -> Cow<'h, str> {
+        let mut lua = Cow::Borrowed(lua);
+        for transform in &self.before {
+            transform.apply(&mut lua);
+        }
+        let mut lua = match lua {
+            Cow::Borrowed(lua) => self.patcher.patch(lua),
+            Cow::Owned(lua) => Cow::Owned(self.patcher.patch(&lua).into_owned()),
+        };
+        for transform in &self.after {
+            transform.apply(&mut lua);
+        }
+        lua
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{PatchOptions, format::FormatOptions, p8scii::GlyphStyle};
+
+    #[test]
+    fn test_passes_in_order() {
+        for (i, pass) in PASSES.iter().enumerate() {
+            assert_eq!(pass.order, i);
+        }
+    }
+
+    #[test]
+    fn test_glyph_numbers() {
+        let lua = "keys = {⬅️, ➡️}";
+        assert_eq!(
+            Patcher::default().patch(lua),
+            "local __p8_left = 0\nlocal __p8_right = 1\nkeys = {__p8_left, __p8_right}"
+        );
+        let options = Options {
+            glyph_numbers: true,
+            ..Options::default()
+        };
+        assert_eq!(Patcher::new(options).patch(lua), "keys = {0, 1}");
+    }
+
+    #[test]
+    fn test_buttons() {
+        let options = Options {
+            buttons: Some(crate::glyph::ButtonMap::default().with("(x)", 5)),
+            ..Options::default()
+        };
+        let patcher = Patcher::new(options);
+        assert_eq!(patcher.patch("if (btn((x))) x += 1"), "if btn(5) then x = x + (1) end");
+        assert_eq!(patcher.try_patch("b = btnp((x), 1)").unwrap(), "b = btnp(5, 1)");
+    }
+
+    #[test]
+    fn test_patch_outcome() {
+        let (lua, outcome) =
+            crate::patch_lua_with_outcome("x += 1 // one\nif (a b()", &PatchOptions::default());
+        assert_eq!(lua, "x = x + (1) -- one\nif (a b()");
+        assert_eq!(
+            outcome.categories,
+            [PassCategory::Comment, PassCategory::Statement]
+        );
+        assert!(outcome.changed(PassCategory::Statement));
+        assert!(!outcome.changed(PassCategory::Operator));
+        assert_eq!(outcome.to_string(), "changed comment, statement; 1 warning");
+        let outcome = PatchOutcome {
+            includes: vec!["lib.lua".into()],
+            ..PatchOutcome::default()
+        };
+        assert_eq!(outcome.to_string(), "changed includes");
+        assert_eq!(PatchOutcome::default().to_string(), "unchanged");
+    }
+
+    #[test]
+    fn test_describe() {
+        let enabled = |options: Options| -> Vec<&str> {
+            Patcher::new(options)
+                .describe()
+                .into_iter()
+                .filter(|p| p.enabled)
+                .map(|p| p.name)
+                .collect()
+        };
+        assert_eq!(enabled(Options::default()).len(), 10);
+        let options = Options {
+            fixed_point: true,
+            bitwise: Some(LuaTarget::Lua53),
+            integer_division: Some(LuaTarget::Lua53),
+            string_glyphs: Some(GlyphStyle::Chr),
+            format: Some(FormatOptions::default()),
+            license_header: true,
+            ..Options::default()
+        };
+        assert_eq!(
+            enabled(options)[10..],
+            [
+                "fixed-point",
+                "bitwise",
+                "string-glyphs",
+                "integer-division",
+                "format",
+                "license-header"
+            ]
+        );
+        let options = Options {
+            patch: PatchOptions {
+                slash_comments: false,
+                binary_literals: false,
+                ..PatchOptions::default()
+            },
+            ..Options::default()
+        };
+        let target = Options {
+            target: Some(LuaTarget::Lua51),
+            ..Options::default()
+        };
+        assert_eq!(
+            enabled(target)[10..],
+            ["bitwise", "goto-continue", "hex-floats", "integer-division"]
+        );
+        let enabled = enabled(options);
+        assert!(!enabled.contains(&"slash-comments"));
+        assert!(!enabled.contains(&"binary-literals"));
+        assert_eq!(enabled.len(), 8);
+    }
+
+    #[test]
+    fn test_target() {
+        let patch = |target| {
+            let options = Options {
+                target: Some(target),
+                ..Options::default()
+            };
+            Patcher::new(options).patch("x = 0b1.1 & a \\ 2").into_owned()
+        };
+        assert!(patch(LuaTarget::Lua51).ends_with("\nx = __p8_band(1.5, math.floor(a / 2))"));
+        assert!(patch(LuaTarget::LuaJit).ends_with("\nx = __p8_band(0x1.8, math.floor(a / 2))"));
+        assert!(patch(LuaTarget::Lua54).ends_with("\nx = __p8_band(0x1.8, a // 2)"));
+        // The passes after the Pico-8 rewrites read `//` as a comment, so
+        // they must see the code before integer division becomes it.
+        let options = Options {
+            target: Some(LuaTarget::Lua53),
+            inline_math: true,
+            format: Some(FormatOptions::default()),
+            ..Options::default()
+        };
+        assert_eq!(
+            Patcher::new(options).patch("x = t[a \\ 2] + flr(b) -- half\nif x then\ny = 1 end"),
+            "x = t[a // 2] + math.floor(b) -- half\nif x then\n  y = 1 end"
+        );
+    }
+
+    #[test]
+    fn test_pipeline() {
+        let mut pipeline = Pipeline::new(Options::default());
+        pipeline.push_after(|lua: &mut Cow<str>| {
+            lua.to_mut().insert_str(0, "-- converted\n");
+            PassResult::Changed
+        });
+        pipeline.push_before(|_: &mut Cow<str>| PassResult::Unchanged);
+        assert_eq!(pipeline.patch("x != 1"), "-- converted\nx ~= 1");
+        let mut lua = Cow::Borrowed("x = 1");
+        assert_eq!(Patcher::default().apply(&mut lua), PassResult::Unchanged);
+        assert!(matches!(lua, Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn test_line_changes() {
+        let changes = line_changes("a != b\nc\nd != e", "a ~= b\nc\nd ~= e");
+        let at: Vec<_> = changes.iter().map(|c| (c.line, c.column, c.span)).collect();
+        assert_eq!(at, vec![(1, 3, Span::new(2, 3)), (3, 3, Span::new(11, 12))]);
+        let changes = line_changes("a\nb", "a\nb\nc");
+        assert_eq!(changes.len(), 1);
+        assert_eq!((changes[0].line, changes[0].column), (2, 2));
+    }
+
+    #[test]
+    fn test_preserve_lines() {
+        let lua = "-- game\nb = ⬅️\nx = a <<> 1\n";
+        let options = Options {
+            bitwise: Some(LuaTarget::Lua53),
+            preserve_lines: true,
+            ..Options::default()
+        };
+        let patched = Patcher::new(options).patch(lua);
+        assert_eq!(lines(&patched), lines(lua));
+        assert!(patched.starts_with("-- game\nlocal function __p8_rotl"));
+        assert!(patched.ends_with(" local __p8_left = 0 b = __p8_left\nx = __p8_rotl(a, 1)\n"));
+        assert_eq!(
+            fold_inserted_lines("x = 1", "-- a ]]\nlocal y = 2\nx = 1".into()),
+            "--[=[ a ]] ]=] local y = 2 x = 1"
+        );
+    }
+}
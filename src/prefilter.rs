@@ -0,0 +1,93 @@
+//! A scan for what the rewrites of [patch_lua](crate::patch_lua) look for.
+//!
+//! Each rewrite is a regular expression run over the whole code. Most carts
+//! use only a few of the Pico-8 shorthands, so one scan over the bytes first
+//! finds which rewrites could match and the others are skipped. The scan may
+//! report a rewrite that then matches nothing but never misses one.
+
+/// Which rewrites could match the code scanned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) struct Triggers {
+    /// `!=`.
+    pub not_equal: bool,
+    /// `btn` or `btnp`.
+    pub buttons: bool,
+    /// `if` followed by a parenthesis.
+    pub shorthand_if: bool,
+    /// `while` followed by a parenthesis.
+    pub shorthand_while: bool,
+    /// An operator followed by `=`, e.g. `+=` or `>>>=`.
+    pub compound_assignment: bool,
+    /// `?`.
+    pub print_shorthand: bool,
+    /// `0b` or `0B`.
+    pub binary_literals: bool,
+}
+
+/// Return true if `keyword` ends at `end` in `bytes` and is followed by a
+/// parenthesis after any whitespace.
+fn keyword_paren(bytes: &[u8], end: usize, keyword: &[u8]) -> bool {
+    if !bytes[..end].ends_with(keyword) {
+        return false;
+    }
+    bytes[end..]
+        .iter()
+        .find(|b| !b.is_ascii_whitespace())
+        .is_some_and(|&b| b == b'(')
+}
+
+impl Triggers {
+    /// Scan `lua` once for every rewrite.
+    pub fn scan(lua: &str) -> Triggers {
+        let bytes = lua.as_bytes();
+        let mut found = Triggers::default();
+        for (i, &b) in bytes.iter().enumerate() {
+            let prev = if i > 0 { bytes[i - 1] } else { 0 };
+            match b {
+                b'=' => match prev {
+                    b'!' => found.not_equal = true,
+                    b'+' | b'-' | b'*' | b'/' | b'%' | b'\\' | b'^' | b'&' | b'|' | b'.' => {
+                        found.compound_assignment = true
+                    }
+                    b'<' | b'>' if i > 1 && matches!(bytes[i - 2], b'<' | b'>') => {
+                        found.compound_assignment = true
+                    }
+                    _ => {}
+                },
+                b'n' if prev == b't' && i > 1 && bytes[i - 2] == b'b' => found.buttons = true,
+                b'f' => found.shorthand_if |= keyword_paren(bytes, i + 1, b"if"),
+                b'e' => found.shorthand_while |= keyword_paren(bytes, i + 1, b"while"),
+                b'?' => found.print_shorthand = true,
+                b'b' | b'B' if prev == b'0' => found.binary_literals = true,
+                _ => {}
+            }
+        }
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scan() {
+        assert_eq!(
+            Triggers::scan("local x = a == b or c <= d\nif x then f() end"),
+            Triggers::default()
+        );
+        let found = Triggers::scan("if (a != b) x >>>= 1\nwhile\n(btnp(0)) ?0b1");
+        assert_eq!(
+            found,
+            Triggers {
+                not_equal: true,
+                buttons: true,
+                shorthand_if: true,
+                shorthand_while: true,
+                compound_assignment: true,
+                print_shorthand: true,
+                binary_literals: true,
+            }
+        );
+    }
+}
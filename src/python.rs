@@ -0,0 +1,117 @@
+//! Python bindings, with the `python` feature.
+//!
+//! Build with `maturin build` to get a `pico8_to_lua` module:
+//!
+//! ```python
+//! import pico8_to_lua
+//! pico8_to_lua.patch_lua("x += 1")  # "x = x + (1)"
+//! pico8_to_lua.patch_includes("#include a.lua", lambda path: open(path).read())
+//! cart = pico8_to_lua.Cart.parse(open("game.p8").read())
+//! print(cart.title, cart.tokens)
+//! ```
+use pyo3::{exceptions::PyValueError, prelude::*};
+
+use crate::{Options, batch::Converter, cart::CartError, parse_includes, try_patch_includes};
+
+fn value_error(e: CartError) -> PyErr {
+    PyValueError::new_err(e.to_string())
+}
+
+/// Convert Pico-8 Lua to plain Lua.
+#[pyfunction]
+fn patch_lua(code: &str) -> String {
+    crate::patch_lua(code).into_owned()
+}
+
+/// Convert a ".p8" cart or Pico-8 Lua, returning only its Lua if `lua_only`.
+#[pyfunction]
+#[pyo3(signature = (text, lua_only = false))]
+fn convert(text: &str, lua_only: bool) -> PyResult<String> {
+    let options = Options {
+        lua_only,
+        ..Options::default()
+    };
+    Converter::new(options).convert(text).map_err(value_error)
+}
+
+/// Replace each "#include" with what `resolve` returns for its path. An
+/// exception from `resolve` is raised again.
+#[pyfunction]
+fn patch_includes(code: &str, resolve: Bound<'_, PyAny>) -> PyResult<String> {
+    let lua = try_patch_includes(code, |path| resolve.call1((path,))?.extract::<String>())?;
+    Ok(lua.into_owned())
+}
+
+/// Return the path of each "#include" in order.
+#[pyfunction]
+fn include_paths(code: &str) -> Vec<String> {
+    parse_includes(code).map(|include| include.path.to_string()).collect()
+}
+
+/// A ".p8" cart split into its sections.
+#[pyclass(name = "Cart", frozen)]
+struct Cart(crate::Cart);
+
+#[pymethods]
+impl Cart {
+    /// Parse the text of a cart.
+    #[staticmethod]
+    fn parse(text: &str) -> PyResult<Self> {
+        crate::Cart::parse(text).map(Cart).map_err(value_error)
+    }
+
+    /// Parse the bytes of a cart, read as P8SCII if they are not UTF-8.
+    #[staticmethod]
+    fn from_bytes(bytes: &[u8]) -> PyResult<Self> {
+        crate::Cart::from_bytes(bytes)
+            .map(Cart)
+            .map_err(value_error)
+    }
+
+    /// The body of the section `name`, e.g. "lua" or "gfx", or `None`.
+    fn section(&self, name: &str) -> Option<String> {
+        self.0.section(name).map(String::from)
+    }
+
+    /// The names of the sections in order.
+    #[getter]
+    fn sections(&self) -> Vec<String> {
+        self.0.sections.iter().map(|s| s.name.clone()).collect()
+    }
+
+    /// The Lua code, or `None`.
+    #[getter]
+    fn lua(&self) -> Option<String> {
+        self.section("lua")
+    }
+
+    #[getter]
+    fn title(&self) -> Option<String> {
+        self.0.title().map(String::from)
+    }
+
+    #[getter]
+    fn author(&self) -> Option<String> {
+        self.0.author().map(String::from)
+    }
+
+    /// The tokens of the code as Pico-8 counts them.
+    #[getter]
+    fn tokens(&self) -> usize {
+        self.0.tokens()
+    }
+
+    fn __str__(&self) -> String {
+        self.0.to_string()
+    }
+}
+
+#[pymodule]
+fn pico8_to_lua(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(patch_lua, m)?)?;
+    m.add_function(wrap_pyfunction!(convert, m)?)?;
+    m.add_function(wrap_pyfunction!(patch_includes, m)?)?;
+    m.add_function(wrap_pyfunction!(include_paths, m)?)?;
+    m.add_class::<Cart>()?;
+    Ok(())
+}
@@ -0,0 +1,301 @@
+//! Reading carts that are damaged, e.g. cut short by a failed download.
+//!
+//! [Cart::recover] keeps everything that looks intact, drops what does not,
+//! and reports each problem with the line of the text where it was found, so
+//! the Lua of a truncated cart can still be extracted and converted.
+use std::fmt;
+
+use crate::{
+    Cart, CartError,
+    cart::{CART_HEADER, LineError, Pattern, Sfx, check_len, hex_bytes, is_cart},
+    label::LABEL_SIZE,
+    lexer::{TokenKind, tokenize},
+};
+
+/// What is wrong at a line of a damaged cart.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IssueKind {
+    /// The text does not start with "pico-8 cartridge".
+    MissingHeader,
+    /// A line that looks like the start of a section marker, e.g. `__gf`.
+    PartialMarker(String),
+    /// A line of a data section is not valid. It and the rest of its section
+    /// were dropped.
+    BadData(CartError),
+    /// A string or comment in the Lua is not closed.
+    UnterminatedLua,
+    /// The text ends in the middle of a line.
+    MissingNewline,
+}
+
+impl fmt::Display for IssueKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IssueKind::MissingHeader => write!(f, "missing {CART_HEADER:?} header"),
+            IssueKind::PartialMarker(line) => write!(f, "partial section marker {line:?}"),
+            IssueKind::BadData(e) => write!(f, "{e}; dropped the rest of the section"),
+            IssueKind::UnterminatedLua => write!(f, "unterminated string or comment in lua"),
+            IssueKind::MissingNewline => write!(f, "text ends mid-line"),
+        }
+    }
+}
+
+/// A problem found while recovering a cart.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Issue {
+    /// The one-based line of the text where the problem is.
+    pub line: usize,
+    /// What the problem is.
+    pub kind: IssueKind,
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.kind)
+    }
+}
+
+/// A cart read from damaged text and the problems found in it.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Recovered {
+    /// The parts of the cart that look intact.
+    pub cart: Cart,
+    /// The problems in the order of their lines.
+    pub issues: Vec<Issue>,
+}
+
+impl Recovered {
+    /// Return true if no problems were found.
+    pub fn is_intact(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    /// Return the first problem, which is where a strict parse would stop.
+    pub fn stopped_at(&self) -> Option<&Issue> {
+        self.issues.first()
+    }
+}
+
+/// Check one line of the data section `section`.
+fn check_line(section: &str, line: &str) -> Result<(), LineError> {
+    let hex = |len| check_len(line, len).and_then(|_| hex_bytes(line).map(|_| ()));
+    match section {
+        "gfx" => hex(128),
+        "gff" | "map" => hex(256),
+        "sfx" => hex(Sfx::LINE_LEN),
+        "label" => {
+            check_len(line, LABEL_SIZE)?;
+            if line
+                .bytes()
+                .all(|c| c.is_ascii_digit() || (b'a'..=b'v').contains(&c))
+            {
+                Ok(())
+            } else {
+                Err(LineError::InvalidHex)
+            }
+        }
+        "music" => {
+            check_len(line, Pattern::LINE_LEN)?;
+            match (line.get(..2), line.get(2..3), line.get(3..)) {
+                (Some(flags), Some(" "), Some(channels)) => hex_bytes(flags)
+                    .and_then(|_| hex_bytes(channels))
+                    .map(|_| ()),
+                _ => Err(LineError::InvalidHex),
+            }
+        }
+        _ => Ok(()),
+    }
+}
+
+fn partial_marker(line: &str) -> bool {
+    let line = line.trim_end_matches(['\r', '\n']);
+    line.len() > 2
+        && line.starts_with("__")
+        && !line.ends_with("__")
+        && line[2..]
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == ':' || c == '_')
+}
+
+/// Return the offset of a string or comment at the end of `lua` that is not
+/// closed.
+fn unterminated(lua: &str) -> Option<usize> {
+    let last = tokenize(lua).last()?;
+    let text = last.text;
+    let closed = match last.kind {
+        TokenKind::String if text.starts_with('"') || text.starts_with('\'') => {
+            // Closed if it ends with its quote and that quote is not escaped.
+            let body = &text[1..];
+            body.ends_with(&text[..1])
+                && body[..body.len() - 1]
+                    .bytes()
+                    .rev()
+                    .take_while(|&b| b == b'\\')
+                    .count()
+                    % 2
+                    == 0
+        }
+        TokenKind::String => text.ends_with(']'),
+        TokenKind::Comment
+            if text[2..].starts_with('[') && text[3..].trim_start_matches('=').starts_with('[') =>
+        {
+            text.ends_with(']')
+        }
+        _ => true,
+    };
+    (!closed).then_some(last.span.start)
+}
+
+impl Cart {
+    /// Read a cart from text that may be damaged.
+    ///
+    /// Unlike [Cart::parse] this never fails. Data section lines are checked
+    /// and each data section is cut at its first bad line, which is where a
+    /// truncated download usually ends.
+    ///
+    /// ```
+    /// use pico8_to_lua::Cart;
+    /// let recovered = Cart::recover("pico-8 cartridge\n__lua__\nx = 1\n__gfx__\n0000");
+    /// assert_eq!(recovered.cart.lua(), Some("x = 1\n"));
+    /// assert_eq!(recovered.stopped_at().unwrap().line, 5);
+    /// ```
+    pub fn recover(text: &str) -> Recovered {
+        let mut recovered = Recovered::default();
+        if !is_cart(text) {
+            recovered.issues.push(Issue {
+                line: 1,
+                kind: IssueKind::MissingHeader,
+            });
+        }
+        let text_with_header;
+        let parse_text = if is_cart(text) {
+            text
+        } else {
+            text_with_header = format!("{CART_HEADER}\n{text}");
+            &text_with_header
+        };
+        let mut cart = Cart::parse(parse_text).expect("the header was added");
+        if !is_cart(text) {
+            cart.header.drain(..CART_HEADER.len() + 1);
+        }
+
+        // Find the line of the text where each section starts.
+        let mut line = cart.header.lines().count() + 1;
+        let mut sections = Vec::new();
+        for mut section in cart.sections {
+            let body_line = line + 1;
+            line = body_line + section.body.lines().count();
+            let mut cut = None;
+            for (i, l) in section.body.split_inclusive('\n').enumerate() {
+                if partial_marker(l) {
+                    recovered.issues.push(Issue {
+                        line: body_line + i,
+                        kind: IssueKind::PartialMarker(l.trim_end().to_string()),
+                    });
+                    cut = Some(i);
+                    break;
+                }
+                let data = l.trim_end_matches(['\r', '\n']);
+                if data.is_empty() {
+                    continue;
+                }
+                if let Err(e) = check_line(&section.name, data) {
+                    recovered.issues.push(Issue {
+                        line: body_line + i,
+                        kind: IssueKind::BadData(e.at(&section.name, i)),
+                    });
+                    cut = Some(i);
+                    break;
+                }
+            }
+            if let Some(cut) = cut {
+                let len = section
+                    .body
+                    .split_inclusive('\n')
+                    .take(cut)
+                    .map(str::len)
+                    .sum();
+                section.body.truncate(len);
+            }
+            let open = match section.name.as_str() {
+                "lua" => unterminated(&section.body),
+                _ => None,
+            };
+            if let Some(offset) = open {
+                recovered.issues.push(Issue {
+                    line: body_line + section.body[..offset].matches('\n').count(),
+                    kind: IssueKind::UnterminatedLua,
+                });
+            }
+            sections.push(section);
+        }
+        cart.sections = sections;
+        if !text.is_empty() && !text.ends_with('\n') {
+            recovered.issues.push(Issue {
+                line: text.lines().count(),
+                kind: IssueKind::MissingNewline,
+            });
+        }
+        recovered.issues.sort_by_key(|issue| issue.line);
+        recovered.cart = cart;
+        recovered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_intact() {
+        let text =
+            "pico-8 cartridge\n__lua__\nx = 1\n__gfx__\n".to_string() + &"0".repeat(128) + "\n";
+        let recovered = Cart::recover(&text);
+        assert!(recovered.is_intact());
+        assert_eq!(recovered.cart, Cart::parse(&text).unwrap());
+    }
+
+    #[test]
+    fn test_truncated_data() {
+        let text = "pico-8 cartridge\n__lua__\nx = 1\n__gfx__\n".to_string()
+            + &"0".repeat(128)
+            + "\n"
+            + &"0".repeat(50);
+        let recovered = Cart::recover(&text);
+        assert_eq!(recovered.cart.section("gfx").map(str::len), Some(129));
+        let issue = recovered.stopped_at().unwrap();
+        assert_eq!(issue.line, 6);
+        assert!(matches!(
+            issue.kind,
+            IssueKind::BadData(CartError::BadLength { found: 50, .. })
+        ));
+        assert_eq!(recovered.issues[1].kind, IssueKind::MissingNewline);
+    }
+
+    #[test]
+    fn test_truncated_lua() {
+        let recovered = Cart::recover("pico-8 cartridge\n__lua__\nx = 1\ns = [[abc\n__gf");
+        assert_eq!(recovered.cart.lua(), Some("x = 1\ns = [[abc\n"));
+        let kinds: Vec<_> = recovered
+            .issues
+            .iter()
+            .map(|i| (i.line, i.kind.clone()))
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                (4, IssueKind::UnterminatedLua),
+                (5, IssueKind::PartialMarker("__gf".into())),
+                (5, IssueKind::MissingNewline),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_missing_header() {
+        let recovered = Cart::recover("__lua__\nx = 1\n");
+        assert_eq!(recovered.cart.lua(), Some("x = 1\n"));
+        assert_eq!(recovered.cart.header, "");
+        assert_eq!(recovered.issues[0].kind, IssueKind::MissingHeader);
+    }
+}
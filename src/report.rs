@@ -0,0 +1,270 @@
+//! A porting report for one cart.
+//!
+//! A [PortingReport] gathers every known place where a converted cart may
+//! behave differently from Pico-8: damage found while reading the cart, Pico-8
+//! constructs left unconverted, names that clash with generated ones, and
+//! numbers that Pico-8's 16.16 fixed point treats differently from Lua's
+//! floats. It renders as a Markdown checklist or as JSON so porters can review
+//! it instead of discovering the differences at runtime.
+use std::fmt::{self, Write};
+
+use crate::{
+    Cart,
+    cart::is_cart,
+    diagnostics::{diagnose_numbers, number_value},
+    json::json_string,
+    lexer::{Token, TokenKind, tokenize},
+    partial::convert_partial,
+    span::Span,
+    symbols::{DEFAULT_PREFIX, check_prefix},
+};
+
+/// Where a finding comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Category {
+    /// The cart is damaged. Its line is in the cart's text.
+    Cart,
+    /// A Pico-8 construct was left unconverted.
+    Unconverted,
+    /// A name clashes with the names the converter generates.
+    Name,
+    /// A number behaves differently in 16.16 fixed point.
+    FixedPoint,
+}
+
+impl fmt::Display for Category {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Category::Cart => "cart",
+            Category::Unconverted => "unconverted",
+            Category::Name => "name",
+            Category::FixedPoint => "fixed point",
+        })
+    }
+}
+
+/// One place where a converted cart may behave differently.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Finding {
+    /// Where the finding comes from.
+    pub category: Category,
+    /// The one-based line in the converted Lua, or in the cart's text for
+    /// [Category::Cart].
+    pub line: usize,
+    /// What may behave differently.
+    pub message: String,
+}
+
+/// Every known difference of a converted cart.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PortingReport {
+    /// The name of the cart, e.g. its path.
+    pub name: String,
+    /// The converted Lua the findings refer to.
+    pub lua: String,
+    /// The findings ordered by category and line.
+    pub findings: Vec<Finding>,
+}
+
+/// Find the numbers in `lua` that Pico-8 treats differently.
+fn fixed_point_findings(lua: &str) -> Vec<(Span, String)> {
+    let code: Vec<Token> = tokenize(lua)
+        .filter(|t| t.kind != TokenKind::Comment)
+        .collect();
+    let mut findings = Vec::new();
+    for (i, token) in code.iter().enumerate() {
+        if token.kind != TokenKind::Number {
+            continue;
+        }
+        let Some(value) = number_value(token.text) else {
+            continue;
+        };
+        if code[..i].last().is_some_and(|t| t.is("/")) && value == 0.0 {
+            findings.push((
+                token.span,
+                "division by zero gives 32767.99998 in Pico-8 but inf or nan in Lua".into(),
+            ));
+        } else if value != 0.0 && value < 0.5 / 65536.0 {
+            findings.push((
+                token.span,
+                format!(
+                    "{} is nearer 0 than Pico-8's smallest fraction and is 0 there",
+                    token.text
+                ),
+            ));
+        }
+    }
+    findings.extend(
+        diagnose_numbers(lua)
+            .into_iter()
+            .map(|d| (d.span, d.kind.to_string())),
+    );
+    findings.sort_by_key(|(span, _)| *span);
+    findings
+}
+
+fn line_of(text: &str, span: Span) -> usize {
+    span.line_col(text).0
+}
+
+impl PortingReport {
+    /// Convert the ".p8" cart or Pico-8 Lua in `text` and report where the
+    /// result may behave differently.
+    ///
+    /// ```
+    /// use pico8_to_lua::report::{Category, PortingReport};
+    /// let report = PortingReport::new("game.p8", "x = a & b\ny = 1 / 0\n");
+    /// assert_eq!(report.findings[0].category, Category::Unconverted);
+    /// assert_eq!(report.findings[1].category, Category::FixedPoint);
+    /// assert_eq!(report.findings[1].line, 2);
+    /// ```
+    pub fn new(name: &str, text: &str) -> PortingReport {
+        let mut findings = Vec::new();
+        let lua = if is_cart(text) {
+            let recovered = Cart::recover(text);
+            findings.extend(recovered.issues.iter().map(|issue| Finding {
+                category: Category::Cart,
+                line: issue.line,
+                message: issue.kind.to_string(),
+            }));
+            recovered.cart.lua().unwrap_or("").to_string()
+        } else {
+            text.to_string()
+        };
+        let partial = convert_partial(&lua);
+        let lua = partial.lua;
+        findings.extend(partial.residue.iter().map(|residue| Finding {
+            category: Category::Unconverted,
+            line: line_of(&lua, residue.span),
+            message: format!("{} `{}` was not converted", residue.kind, residue.text),
+        }));
+        if let Err(conflict) = check_prefix(&lua, DEFAULT_PREFIX) {
+            findings.push(Finding {
+                category: Category::Name,
+                line: line_of(&lua, conflict.span),
+                message: format!(
+                    "`{}` uses the prefix {DEFAULT_PREFIX:?} of generated names",
+                    conflict.name
+                ),
+            });
+        }
+        findings.extend(
+            fixed_point_findings(&lua)
+                .into_iter()
+                .map(|(span, message)| Finding {
+                    category: Category::FixedPoint,
+                    line: line_of(&lua, span),
+                    message,
+                }),
+        );
+        findings.sort_by_key(|f| (f.category, f.line));
+        PortingReport {
+            name: name.to_string(),
+            lua,
+            findings,
+        }
+    }
+
+    /// Render the report as a Markdown checklist.
+    pub fn to_markdown(&self) -> String {
+        let mut out = format!("# Porting report for {}\n\n", self.name);
+        if self.findings.is_empty() {
+            out.push_str("No known differences from Pico-8.\n");
+            return out;
+        }
+        let _ = writeln!(
+            out,
+            "{} places may behave differently from Pico-8.\n",
+            self.findings.len()
+        );
+        for finding in &self.findings {
+            let _ = writeln!(
+                out,
+                "- [ ] {} line {}: {}",
+                finding.category, finding.line, finding.message
+            );
+        }
+        out
+    }
+
+    /// Render the report as JSON without the converted Lua.
+    pub fn to_json(&self) -> String {
+        let findings: Vec<String> = self
+            .findings
+            .iter()
+            .map(|f| {
+                format!(
+                    "{{\"category\":{},\"line\":{},\"message\":{}}}",
+                    json_string(&f.category.to_string()),
+                    f.line,
+                    json_string(&f.message)
+                )
+            })
+            .collect();
+        format!(
+            "{{\"name\":{},\"findings\":[{}]}}",
+            json_string(&self.name),
+            findings.join(",")
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fixed_point() {
+        let starts: Vec<usize> =
+            fixed_point_findings("a = 40000\nb = 0x7fff.ffff / 0\nc = 0.000001")
+                .into_iter()
+                .map(|(span, _)| span.start)
+                .collect();
+        assert_eq!(starts, vec![4, 14, 28, 34]);
+    }
+
+    #[test]
+    fn test_cart_report() {
+        let report = PortingReport::new(
+            "game.p8",
+            "pico-8 cartridge\n__lua__\n__p8_x = 1\nx = @0\n__gfx__\n00",
+        );
+        let found: Vec<(Category, usize)> = report
+            .findings
+            .iter()
+            .map(|f| (f.category, f.line))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (Category::Cart, 6),
+                (Category::Cart, 6),
+                (Category::Name, 1)
+            ]
+        );
+        assert_eq!(report.lua, "__p8_x = 1\nx = peek(0)\n");
+        assert!(
+            report
+                .to_markdown()
+                .contains("- [ ] name line 1: `__p8_x` uses")
+        );
+        assert!(
+            report
+                .to_json()
+                .starts_with("{\"name\":\"game.p8\",\"findings\":[{\"category\":\"cart\"")
+        );
+    }
+
+    #[test]
+    fn test_no_findings() {
+        let report = PortingReport::new("a.lua", "x += 1");
+        assert_eq!(
+            report.to_markdown(),
+            "# Porting report for a.lua\n\nNo known differences from Pico-8.\n"
+        );
+        assert_eq!(report.to_json(), "{\"name\":\"a.lua\",\"findings\":[]}");
+    }
+}
@@ -0,0 +1,262 @@
+//! A tiny HTTP service that converts carts.
+//!
+//! `POST /convert` with a ".p8" cart, plain Pico-8 Lua, or, with the `png`
+//! feature, a ".p8.png" cart as the body responds with the converted Lua.
+//! `POST /convert?format=json` responds with a JSON object instead:
+//! `{"lua": ..., "tokens": ..., "chars": ..., "report": ...}` on success, where
+//! the report is the [PortingReport] of the code as JSON, or `{"error": ...}`
+//! on failure.
+//!
+//! The server uses only the standard library and handles each connection on
+//! its own thread. Request bodies larger than [MAX_BODY] are refused.
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{TcpListener, ToSocketAddrs},
+    thread,
+};
+
+use crate::{
+    Cart, cart::is_cart, json::json_string, patch_lua, report::PortingReport, size_report,
+};
+
+/// The largest request body accepted, in bytes.
+pub const MAX_BODY: usize = 1 << 20;
+
+/// An HTTP response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    /// The status code.
+    pub status: u16,
+    /// The value of the Content-Type header.
+    pub content_type: &'static str,
+    /// The body.
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    fn text(status: u16, body: impl Into<String>) -> Response {
+        Response {
+            status,
+            content_type: "text/plain; charset=utf-8",
+            body: body.into().into_bytes(),
+        }
+    }
+
+    fn json(status: u16, body: String) -> Response {
+        Response {
+            status,
+            content_type: "application/json",
+            body: body.into_bytes(),
+        }
+    }
+
+    fn error(status: u16, message: &str, json: bool) -> Response {
+        if json {
+            Response::json(status, format!("{{\"error\":{}}}", json_string(message)))
+        } else {
+            Response::text(status, format!("{message}\n"))
+        }
+    }
+
+    fn reason(&self) -> &'static str {
+        match self.status {
+            200 => "OK",
+            400 => "Bad Request",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            413 => "Payload Too Large",
+            _ => "Error",
+        }
+    }
+
+    /// Write the response as HTTP/1.1.
+    pub fn write_to(&self, mut w: impl Write) -> io::Result<()> {
+        write!(
+            w,
+            "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            self.status,
+            self.reason(),
+            self.content_type,
+            self.body.len()
+        )?;
+        w.write_all(&self.body)?;
+        w.flush()
+    }
+}
+
+fn read_code(body: &[u8]) -> Result<String, String> {
+    #[cfg(feature = "png")]
+    {
+        if body.starts_with(b"\x89PNG") {
+            let code = crate::p8png::read_code(body).map_err(|e| e.to_string())?;
+            return Ok(crate::charset::to_utf8(&code));
+        }
+    }
+    let text = std::str::from_utf8(body).map_err(|_| "body is not UTF-8".to_string())?;
+    if is_cart(text) {
+        let cart = Cart::parse(text).map_err(|e| e.to_string())?;
+        Ok(cart.lua().unwrap_or("").to_string())
+    } else {
+        Ok(text.to_string())
+    }
+}
+
+/// Convert the cart or code in `body`, responding with JSON if `json` is true.
+pub fn convert(body: &[u8], json: bool) -> Response {
+    if body.len() > MAX_BODY {
+        return Response::error(413, "cart is too large", json);
+    }
+    let code = match read_code(body) {
+        Ok(code) => code,
+        Err(e) => return Response::error(400, &e, json),
+    };
+    let lua = patch_lua(code.as_str());
+    if json {
+        let size = size_report(&code);
+        let report = PortingReport::new("cart", &code);
+        Response::json(
+            200,
+            format!(
+                "{{\"lua\":{},\"tokens\":{},\"chars\":{},\"report\":{}}}",
+                json_string(&lua),
+                size.tokens,
+                size.chars,
+                report.to_json()
+            ),
+        )
+    } else {
+        Response::text(200, lua)
+    }
+}
+
+/// Read one HTTP request from `stream` and write the response.
+pub fn handle(stream: impl Read + Write) -> io::Result<()> {
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut content_length = 0;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+        let length = header
+            .split_once(':')
+            .filter(|(name, _)| name.trim().eq_ignore_ascii_case("content-length"));
+        if let Some((_, value)) = length {
+            content_length = value.trim().parse().unwrap_or(0);
+        }
+    }
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or("");
+    let target = parts.next().unwrap_or("");
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let json = query.split('&').any(|q| q == "format=json");
+    let response = if path != "/convert" {
+        Response::error(404, "not found", json)
+    } else if method != "POST" {
+        Response::error(405, "use POST", json)
+    } else if content_length > MAX_BODY {
+        Response::error(413, "cart is too large", json)
+    } else {
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body)?;
+        convert(&body, json)
+    };
+    response.write_to(reader.into_inner())
+}
+
+/// Return `addr` with its port replaced by `port`, e.g. "127.0.0.1:9000" for
+/// "127.0.0.1:8008" and 9000, or `addr` as it is if `port` is `None`.
+///
+/// ```
+/// use pico8_to_lua::serve::with_port;
+/// assert_eq!(with_port("127.0.0.1:8008", Some(9000)), "127.0.0.1:9000");
+/// assert_eq!(with_port("[::1]:8008", Some(80)), "[::1]:80");
+/// assert_eq!(with_port("localhost", Some(80)), "localhost:80");
+/// ```
+pub fn with_port(addr: &str, port: Option<u16>) -> String {
+    let Some(port) = port else {
+        return addr.to_string();
+    };
+    let host = match addr.rsplit_once(':') {
+        Some((host, rest)) if !rest.contains(']') => host,
+        _ => addr,
+    };
+    format!("{host}:{port}")
+}
+
+/// Serve conversions at `addr` until an error occurs accepting connections.
+pub fn serve(addr: impl ToSocketAddrs) -> io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    for stream in listener.incoming() {
+        let stream = stream?;
+        thread::spawn(move || {
+            if let Err(e) = handle(stream) {
+                eprintln!("WARNING: {e}");
+            }
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    struct Duplex {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Read for Duplex {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for Duplex {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn request(text: &str) -> String {
+        let mut duplex = Duplex {
+            input: Cursor::new(text.as_bytes().to_vec()),
+            output: Vec::new(),
+        };
+        handle(&mut duplex).unwrap();
+        String::from_utf8(duplex.output).unwrap()
+    }
+
+    #[test]
+    fn test_convert_text() {
+        let response = request("POST /convert HTTP/1.1\r\nContent-Length: 6\r\n\r\nx += 1");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+        assert!(response.ends_with("\r\n\r\nx = x + (1)"), "{response}");
+    }
+
+    #[test]
+    fn test_convert_json() {
+        let response = convert(b"pico-8 cartridge\n__lua__\nprint(\"a\" != b)\n", true);
+        assert_eq!(response.status, 200);
+        assert_eq!(
+            String::from_utf8(response.body).unwrap(),
+            r#"{"lua":"print(\"a\" ~= b)\n","tokens":5,"chars":16,"report":{"name":"cart","findings":[]}}"#
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(request("GET /convert HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 405"));
+        assert!(request("POST /other HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 404"));
+        assert_eq!(convert(&[0xff, 0xfe], true).status, 400);
+        assert_eq!(convert(&vec![b' '; MAX_BODY + 1], false).status, 413);
+    }
+}
@@ -0,0 +1,257 @@
+//! Plain Lua versions of Pico-8's built-in functions.
+//!
+//! Converted code still calls Pico-8's API. A port must provide the graphics,
+//! sound, input, and memory functions itself, but the rest, like `add`, `flr`,
+//! or `split`, can be written in plain Lua with Pico-8's behavior. [preamble]
+//! defines those a cart calls, so a cart that only computes runs with `lua`.
+use std::borrow::Cow;
+
+use crate::{api, cart::comment_header};
+
+/// A plain Lua version of a function of Pico-8's API.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Shim {
+    /// The name of the function, e.g. "add".
+    pub name: &'static str,
+    /// The shims it calls, which must be defined first.
+    pub requires: &'static [&'static str],
+    /// The definition as a global, e.g. `function add(t, v, i) ... end`.
+    pub lua: &'static str,
+}
+
+impl Shim {
+    const fn new(name: &'static str, requires: &'static [&'static str], lua: &'static str) -> Self {
+        Shim {
+            name,
+            requires,
+            lua,
+        }
+    }
+}
+
+/// Every shim, each after the shims it requires.
+pub const SHIMS: &[Shim] = &[
+    Shim::new("flr", &[], "function flr(x) return math.floor(x or 0) end"),
+    Shim::new("ceil", &[], "function ceil(x) return math.ceil(x or 0) end"),
+    Shim::new("abs", &[], "function abs(x) return math.abs(x or 0) end"),
+    Shim::new(
+        "min",
+        &[],
+        "function min(a, b) return math.min(a or 0, b or 0) end",
+    ),
+    Shim::new(
+        "max",
+        &[],
+        "function max(a, b) return math.max(a or 0, b or 0) end",
+    ),
+    Shim::new(
+        "mid",
+        &[],
+        "function mid(x, y, z) x, y, z = x or 0, y or 0, z or 0 \
+         return math.max(math.min(x, y), math.min(math.max(x, y), z)) end",
+    ),
+    Shim::new(
+        "sgn",
+        &[],
+        "function sgn(x) return (x or 0) < 0 and -1 or 1 end",
+    ),
+    Shim::new(
+        "sqrt",
+        &[],
+        "function sqrt(x) return (x or 0) > 0 and math.sqrt(x) or 0 end",
+    ),
+    Shim::new(
+        "sin",
+        &[],
+        "function sin(x) return -math.sin((x or 0) * 2 * math.pi) end",
+    ),
+    Shim::new(
+        "cos",
+        &[],
+        "function cos(x) return math.cos((x or 0) * 2 * math.pi) end",
+    ),
+    Shim::new(
+        "atan2",
+        &[],
+        "function atan2(dx, dy) \
+         return ((math.atan2 or math.atan)(-(dy or 0), dx or 0) / (2 * math.pi)) % 1 end",
+    ),
+    Shim::new(
+        "rnd",
+        &[],
+        "function rnd(x) if type(x) == \"table\" then return x[math.random(#x)] end \
+         return math.random() * (x or 1) end",
+    ),
+    Shim::new(
+        "srand",
+        &[],
+        "function srand(x) math.randomseed(x or 0) end",
+    ),
+    Shim::new(
+        "add",
+        &[],
+        "function add(t, v, i) if not t then return end \
+         if i then table.insert(t, i, v) else t[#t + 1] = v end return v end",
+    ),
+    Shim::new(
+        "del",
+        &[],
+        "function del(t, v) if not t then return end \
+         for i = 1, #t do if t[i] == v then return table.remove(t, i) end end end",
+    ),
+    Shim::new(
+        "deli",
+        &[],
+        "function deli(t, i) if t then return table.remove(t, i or #t) end end",
+    ),
+    Shim::new(
+        "count",
+        &[],
+        "function count(t, v) if not t then return 0 end if v == nil then return #t end \
+         local n = 0 for i = 1, #t do if t[i] == v then n = n + 1 end end return n end",
+    ),
+    // Like Pico-8, the element being visited may be deleted.
+    Shim::new(
+        "all",
+        &[],
+        "function all(t) if not t or #t == 0 then return function() end end \
+         local i, last = 1, nil return function() if t[i] == last then i = i + 1 end \
+         while t[i] == nil and i <= #t do i = i + 1 end last = t[i] return t[i] end end",
+    ),
+    Shim::new(
+        "foreach",
+        &["all"],
+        "function foreach(t, f) for v in all(t) do f(v) end end",
+    ),
+    Shim::new(
+        "sub",
+        &[],
+        "function sub(s, i, j) return string.sub(tostring(s), math.floor(i or 1), \
+         j and math.floor(j) or nil) end",
+    ),
+    Shim::new(
+        "chr",
+        &[],
+        "function chr(...) local t = {...} for i = 1, #t do t[i] = math.floor(t[i]) % 256 end \
+         return string.char((table.unpack or unpack)(t)) end",
+    ),
+    Shim::new(
+        "ord",
+        &[],
+        "function ord(s, i, n) i = i or 1 return string.byte(s, i, i + (n or 1) - 1) end",
+    ),
+    Shim::new(
+        "tostr",
+        &[],
+        "function tostr(v) local k = type(v) if k == \"nil\" then return \"[nil]\" end \
+         if k == \"number\" and v == math.floor(v) then return string.format(\"%d\", v) end \
+         if k == \"table\" or k == \"function\" then return \"[\" .. k .. \"]\" end \
+         return tostring(v) end",
+    ),
+    Shim::new("tonum", &[], "function tonum(v) return tonumber(v) end"),
+    Shim::new(
+        "split",
+        &[],
+        "function split(s, sep, convert) sep = sep or \",\" local t = {} \
+         if sep == \"\" then for i = 1, #s do t[i] = s:sub(i, i) end \
+         elseif type(sep) == \"number\" then for i = 1, #s, sep do t[#t + 1] = s:sub(i, i + sep - 1) end \
+         else local i = 1 while true do local j = string.find(s, sep, i, true) \
+         t[#t + 1] = s:sub(i, (j or #s + 1) - 1) if not j then break end i = j + #sep end end \
+         if convert ~= false then for k, v in ipairs(t) do t[k] = tonumber(v) or v end end \
+         return t end",
+    ),
+    Shim::new(
+        "print",
+        &["tostr"],
+        "function print(s) io.write(tostr(s), \"\\n\") end",
+    ),
+    Shim::new(
+        "printh",
+        &["tostr"],
+        "function printh(s) io.write(tostr(s), \"\\n\") end",
+    ),
+    Shim::new("time", &[], "function time() return os.clock() end"),
+    Shim::new("t", &[], "function t() return os.clock() end"),
+    Shim::new("unpack", &[], "unpack = table.unpack or unpack"),
+    Shim::new(
+        "pack",
+        &[],
+        "pack = table.pack or function(...) return {n = select(\"#\", ...), ...} end",
+    ),
+    Shim::new("cocreate", &[], "cocreate = coroutine.create"),
+    Shim::new("coresume", &[], "coresume = coroutine.resume"),
+    Shim::new("costatus", &[], "costatus = coroutine.status"),
+    Shim::new("yield", &[], "yield = coroutine.yield"),
+];
+
+/// Return the shims that `lua` needs, with the shims they require, in the
+/// order they must be defined.
+pub fn needed(lua: &str) -> Vec<&'static Shim> {
+    let calls = api::calls(lua);
+    let mut wanted: Vec<&str> = calls.keys().copied().collect();
+    // SHIMS lists requirements first, so one pass from the end finds them all.
+    for shim in SHIMS.iter().rev() {
+        if wanted.contains(&shim.name) {
+            wanted.extend(shim.requires);
+        }
+    }
+    SHIMS.iter().filter(|s| wanted.contains(&s.name)).collect()
+}
+
+/// Return the definitions of `shims` as local to the Lua they precede, one
+/// per line.
+pub fn preamble(shims: &[&Shim]) -> String {
+    shims.iter().map(|s| format!("local {}\n", s.lua)).collect()
+}
+
+/// Return the definitions of `shims` as globals, one per line, for a file
+/// like "pico8_compat.lua" that is run before the cart.
+pub fn module(shims: &[&Shim]) -> String {
+    shims.iter().map(|s| format!("{}\n", s.lua)).collect()
+}
+
+/// Define the shims that converted `lua` needs after its comment header.
+///
+/// ```
+/// use pico8_to_lua::shim::with_shims;
+/// assert_eq!(
+///     with_shims("-- game\nx = flr(1.5)".into()),
+///     "-- game\nlocal function flr(x) return math.floor(x or 0) end\nx = flr(1.5)"
+/// );
+/// ```
+pub fn with_shims(lua: Cow<'_, str>) -> Cow<'_, str> {
+    let shims = needed(&lua);
+    if shims.is_empty() {
+        return lua;
+    }
+    let at = comment_header(&lua).len();
+    Cow::Owned(format!("{}{}{}", &lua[..at], preamble(&shims), &lua[at..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_requirements_first() {
+        for (i, shim) in SHIMS.iter().enumerate() {
+            for name in shim.requires {
+                assert!(SHIMS[..i].iter().any(|s| s.name == *name), "{name}");
+            }
+            assert!(api::FUNCTIONS.contains(&shim.name), "{}", shim.name);
+        }
+    }
+
+    #[test]
+    fn test_needed() {
+        let names: Vec<_> = needed("foreach(t, print) print(mid(1, 2, 3)) spr(1)")
+            .into_iter()
+            .map(|s| s.name)
+            .collect();
+        assert_eq!(names, vec!["mid", "all", "foreach", "tostr", "print"]);
+        assert_eq!(
+            module(&needed("x = t()")),
+            "function t() return os.clock() end\n"
+        );
+    }
+}
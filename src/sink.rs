@@ -0,0 +1,238 @@
+//! Where converted files go.
+//!
+//! An [OutputSink] takes named files one at a time, so the same conversion can
+//! write to stdout, a directory, a zip archive, or memory. [Stdout] ignores the
+//! names, [Files] writes each under a root directory, [Archive] stores each in
+//! a zip file, and [Memory] keeps each in a map.
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{self, Write},
+    path::PathBuf,
+};
+
+/// A destination for named files.
+pub trait OutputSink {
+    /// Write a file called `name`, e.g. "game.p8" or "carts/game.lua".
+    fn write(&mut self, name: &str, contents: &[u8]) -> io::Result<()>;
+
+    /// Finish writing. Nothing may be written after.
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Writes the contents of every file to stdout one after another.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Stdout;
+
+impl OutputSink for Stdout {
+    fn write(&mut self, _name: &str, contents: &[u8]) -> io::Result<()> {
+        io::stdout().lock().write_all(contents)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        io::stdout().lock().flush()
+    }
+}
+
+/// Writes each file under a root directory, creating directories as needed.
+#[derive(Debug, Clone, Default)]
+pub struct Files {
+    root: PathBuf,
+}
+
+impl Files {
+    /// Write files under `root`. An empty root writes them relative to the
+    /// working directory.
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Files { root: root.into() }
+    }
+}
+
+impl OutputSink for Files {
+    fn write(&mut self, name: &str, contents: &[u8]) -> io::Result<()> {
+        let path = self.root.join(name);
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, contents)
+    }
+}
+
+/// Keeps each file in memory by name. A file written twice keeps its last
+/// contents.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Memory {
+    /// The contents of each file by name.
+    pub files: BTreeMap<String, Vec<u8>>,
+}
+
+impl OutputSink for Memory {
+    fn write(&mut self, name: &str, contents: &[u8]) -> io::Result<()> {
+        self.files.insert(name.to_string(), contents.to_vec());
+        Ok(())
+    }
+}
+
+/// Return the CRC-32 of `bytes` as zip files use it.
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &b in bytes {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+// An archived file for the central directory.
+#[derive(Debug, Clone)]
+struct Entry {
+    name: String,
+    crc: u32,
+    size: u32,
+    offset: u32,
+}
+
+// The fields of a zip header from "version needed" to the name length, which
+// the local and central headers share.
+fn common_header(out: &mut Vec<u8>, entry: &Entry) {
+    out.extend(20u16.to_le_bytes()); // Version needed, 2.0.
+    out.extend(0x0800u16.to_le_bytes()); // The name is UTF-8.
+    out.extend(0u16.to_le_bytes()); // Stored without compression.
+    out.extend(0u16.to_le_bytes()); // Time, 00:00.
+    out.extend(0x21u16.to_le_bytes()); // Date, 1980-01-01.
+    out.extend(entry.crc.to_le_bytes());
+    out.extend(entry.size.to_le_bytes()); // Compressed size.
+    out.extend(entry.size.to_le_bytes());
+    out.extend((entry.name.len() as u16).to_le_bytes());
+    out.extend(0u16.to_le_bytes()); // Extra field length.
+}
+
+fn too_large() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, "too large for a zip archive")
+}
+
+/// Stores each file uncompressed in a zip archive, which is complete once
+/// [finish](OutputSink::finish) is called.
+#[derive(Debug)]
+pub struct Archive<W: Write> {
+    writer: W,
+    entries: Vec<Entry>,
+    offset: u32,
+}
+
+impl<W: Write> Archive<W> {
+    /// Write a zip archive to `writer`.
+    pub fn new(writer: W) -> Self {
+        Archive {
+            writer,
+            entries: Vec::new(),
+            offset: 0,
+        }
+    }
+
+    /// Return the writer.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    fn emit(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.writer.write_all(bytes)?;
+        self.offset = u32::try_from(bytes.len())
+            .ok()
+            .and_then(|len| self.offset.checked_add(len))
+            .ok_or_else(too_large)?;
+        Ok(())
+    }
+}
+
+impl<W: Write> OutputSink for Archive<W> {
+    fn write(&mut self, name: &str, contents: &[u8]) -> io::Result<()> {
+        let entry = Entry {
+            name: name.to_string(),
+            crc: crc32(contents),
+            size: u32::try_from(contents.len()).map_err(|_| too_large())?,
+            offset: self.offset,
+        };
+        let mut header = 0x0403_4b50u32.to_le_bytes().to_vec();
+        common_header(&mut header, &entry);
+        header.extend(name.as_bytes());
+        self.emit(&header)?;
+        self.emit(contents)?;
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        let start = self.offset;
+        let mut directory = Vec::new();
+        for entry in &self.entries {
+            directory.extend(0x0201_4b50u32.to_le_bytes());
+            directory.extend(20u16.to_le_bytes()); // Version made by.
+            common_header(&mut directory, entry);
+            directory.extend(0u16.to_le_bytes()); // Comment length.
+            directory.extend(0u16.to_le_bytes()); // Disk number.
+            directory.extend(0u16.to_le_bytes()); // Internal attributes.
+            directory.extend(0u32.to_le_bytes()); // External attributes.
+            directory.extend(entry.offset.to_le_bytes());
+            directory.extend(entry.name.as_bytes());
+        }
+        let count = u16::try_from(self.entries.len()).map_err(|_| too_large())?;
+        self.emit(&directory)?;
+        let mut end = 0x0605_4b50u32.to_le_bytes().to_vec();
+        end.extend(0u16.to_le_bytes()); // This disk.
+        end.extend(0u16.to_le_bytes()); // The disk with the directory.
+        end.extend(count.to_le_bytes());
+        end.extend(count.to_le_bytes());
+        end.extend((self.offset - start).to_le_bytes());
+        end.extend(start.to_le_bytes());
+        end.extend(0u16.to_le_bytes()); // Comment length.
+        self.emit(&end)?;
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn test_memory() {
+        let mut memory = Memory::default();
+        memory.write("a.lua", b"x = 1").unwrap();
+        memory.write("a.lua", b"x = 2").unwrap();
+        assert_eq!(memory.files.len(), 1);
+        assert_eq!(memory.files["a.lua"], b"x = 2");
+    }
+
+    #[test]
+    fn test_archive() {
+        let mut archive = Archive::new(Vec::new());
+        archive.write("a.lua", b"x = 1").unwrap();
+        archive.write("b/c.p8", b"pico-8 cartridge\n").unwrap();
+        archive.finish().unwrap();
+        let zip = archive.into_inner();
+        assert_eq!(&zip[..4], b"PK\x03\x04");
+        // The local header is 30 bytes, then the name and the contents.
+        assert_eq!(&zip[30..35], b"a.lua");
+        assert_eq!(&zip[35..40], b"x = 1");
+        let end = &zip[zip.len() - 22..];
+        assert_eq!(&end[..4], b"PK\x05\x06");
+        assert_eq!(u16::from_le_bytes([end[10], end[11]]), 2);
+        let directory = u32::from_le_bytes([end[16], end[17], end[18], end[19]]) as usize;
+        assert_eq!(&zip[directory..directory + 4], b"PK\x01\x02");
+    }
+}
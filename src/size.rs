@@ -0,0 +1,221 @@
+//! Measure code against Pico-8's limits.
+use crate::{
+    charset::byte_of,
+    compress::compress_pxa,
+    glyph::is_selector,
+    lexer::{Token, TokenKind, tokenize},
+    partial::is_shorthand,
+    span::Span,
+};
+
+/// The most tokens Pico-8 allows in a cart's code.
+pub const TOKEN_LIMIT: usize = 8192;
+/// The most characters Pico-8 allows in a cart's code.
+pub const CHAR_LIMIT: usize = 65535;
+/// The most bytes Pico-8 allows for a cart's compressed code.
+pub const COMPRESSED_LIMIT: usize = 15360;
+
+/// The size of some code by each of Pico-8's measures.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SizeReport {
+    /// Characters in Pico-8's charset.
+    pub chars: usize,
+    /// Tokens as counted by [tokens].
+    pub tokens: usize,
+    /// Bytes after "pxa" compression, including its header.
+    pub compressed: usize,
+}
+
+impl SizeReport {
+    /// Return true if every measure is within Pico-8's limits.
+    pub fn fits(&self) -> bool {
+        self.chars <= CHAR_LIMIT
+            && self.tokens <= TOKEN_LIMIT
+            && self.compressed <= COMPRESSED_LIMIT
+    }
+}
+
+/// Encode `lua` in Pico-8's one byte charset like
+/// [from_utf8](crate::charset::from_utf8), but with a `?` for each character
+/// that has no byte, so it is still counted.
+fn charset_bytes(lua: &str) -> Vec<u8> {
+    lua.chars()
+        .filter(|&c| !is_selector(c))
+        .map(|c| byte_of(c).unwrap_or(b'?'))
+        .collect()
+}
+
+/// Measure `lua` by characters, tokens, and compressed size.
+///
+/// The compressed size comes from [compress_pxa], which may be a little
+/// larger than Pico-8's own, so treat it as an upper bound.
+pub fn size_report(lua: &str) -> SizeReport {
+    let bytes = charset_bytes(lua);
+    SizeReport {
+        chars: bytes.len(),
+        tokens: tokens(lua),
+        compressed: compress_pxa(&bytes).len(),
+    }
+}
+
+/// Count the tokens of `lua` the way Pico-8 does.
+///
+/// Every token counts as one except:
+///
+/// - comments;
+/// - `,`, `.`, `:`, `;`, and `::`;
+/// - closing brackets, so a pair of brackets counts as one;
+/// - the keywords `end` and `local`;
+/// - a unary `-` or `~` directly before a number, which is part of the
+///   literal.
+pub fn tokens(lua: &str) -> usize {
+    let mut count = 0;
+    let mut prev: Option<Token> = None;
+    let mut iter = tokenize(lua)
+        .filter(|t| t.kind != TokenKind::Comment)
+        .peekable();
+    while let Some(token) = iter.next() {
+        let free = match token.kind {
+            TokenKind::Keyword => matches!(token.text, "end" | "local"),
+            TokenKind::Punct => match token.text {
+                "," | "." | ":" | ";" | "::" | ")" | "]" | "}" => true,
+                "-" | "~" => {
+                    iter.peek().is_some_and(|t| t.kind == TokenKind::Number)
+                        && !prev.as_ref().is_some_and(Token::ends_value)
+                }
+                _ => false,
+            },
+            _ => false,
+        };
+        if !free {
+            count += 1;
+        }
+        prev = Some(token);
+    }
+    count
+}
+
+/// Count the tokens of each function defined at the top level of `lua`, as
+/// by `function f()`, `local function f()`, or `f = function()`, in the
+/// order they are defined.
+///
+/// ```
+/// use pico8_to_lua::size::function_tokens;
+/// let lua = "function _init() x = 1 end\nlocal function f(a) return a end\nt.g = function() end";
+/// assert_eq!(
+///     function_tokens(lua),
+///     [("_init".to_string(), 6), ("f".to_string(), 6), ("t.g".to_string(), 5)]
+/// );
+/// let lua = "function _update()\n  if (btn(0)) x-=1\n  if x < 0 then x = 0 end\nend\nfunction _draw() end";
+/// assert_eq!(
+///     function_tokens(lua),
+///     [("_update".to_string(), 19), ("_draw".to_string(), 3)]
+/// );
+/// ```
+pub fn function_tokens(lua: &str) -> Vec<(String, usize)> {
+    top_level_functions(lua)
+        .into_iter()
+        .map(|(name, span)| (name, tokens(&lua[span.range()])))
+        .collect()
+}
+
+/// Return the name of each function defined at the top level of `lua`, as
+/// for [function_tokens], and the span of its statement.
+pub(crate) fn top_level_functions(lua: &str) -> Vec<(String, Span)> {
+    let tokens: Vec<Token> = tokenize(lua)
+        .filter(|t| t.kind != TokenKind::Comment)
+        .collect();
+    let mut functions = Vec::new();
+    let mut depth = 0usize;
+    // The name and start of the function being measured.
+    let mut open = None;
+    for (i, token) in tokens.iter().enumerate() {
+        if token.is("function") {
+            if depth == 0 {
+                open = function_name(&tokens, i);
+            }
+            depth += 1;
+        } else if (token.is("if") && !is_shorthand(lua, &tokens, i)) || token.is("do") {
+            // A shorthand `if (a) b()` has no `end`.
+            depth += 1;
+        } else if token.is("end") {
+            depth = depth.saturating_sub(1);
+            if let Some((name, start)) = open.take_if(|_| depth == 0) {
+                functions.push((name, Span::new(start, token.span.end)));
+            }
+        }
+    }
+    functions
+}
+
+/// Return the name of the function whose `function` keyword is `tokens[at]`
+/// and where its statement starts, or `None` if it has no name.
+fn function_name(tokens: &[Token], at: usize) -> Option<(String, usize)> {
+    let name = |start: usize, end: usize| -> String {
+        tokens[start..end].iter().map(|t| t.text).collect()
+    };
+    let is_name = |j: usize| tokens.get(j).is_some_and(|t| t.kind == TokenKind::Name);
+    let is_dot = |j: usize| tokens.get(j).is_some_and(|t| t.is(".") || t.is(":"));
+    let local = |start: usize| match start.checked_sub(1) {
+        Some(j) if tokens[j].is("local") => j,
+        _ => start,
+    };
+    if is_name(at + 1) {
+        let mut end = at + 2;
+        while is_dot(end) && is_name(end + 1) {
+            end += 2;
+        }
+        return Some((name(at + 1, end), tokens[local(at)].span.start));
+    }
+    if at < 2 || !tokens[at - 1].is("=") || !is_name(at - 2) {
+        return None;
+    }
+    let mut start = at - 2;
+    while start >= 2 && is_dot(start - 1) && is_name(start - 2) {
+        start -= 2;
+    }
+    Some((name(start, at - 1), tokens[local(start)].span.start))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokens() {
+        assert_eq!(tokens("x = 1"), 3);
+        assert_eq!(tokens("print(\"hi\") -- comment"), 3);
+        assert_eq!(tokens("local t = {1, 2, 3}"), 6);
+        assert_eq!(tokens("function f(a, b) return a.x end"), 8);
+    }
+
+    #[test]
+    fn test_function_tokens() {
+        let lua = "function a:b()\n for i = 1, 2 do\n  if x then repeat y() until z end\n \
+                   end\n foreach(t, function(e) end)\nend\n\
+                   foo(function() end)\nx = 1";
+        assert_eq!(function_tokens(lua), [("a:b".to_string(), 24)]);
+    }
+
+    #[test]
+    fn test_size_report() {
+        let report = size_report("if btn(⬅️) then x -= 1 end");
+        assert_eq!(report.chars, 25);
+        assert_eq!(report.tokens, 8);
+        assert!(report.compressed > 8);
+        assert!(report.fits());
+    }
+
+    #[test]
+    fn test_charset_bytes() {
+        assert_eq!(charset_bytes("♥ = ⬅️\n"), b"\x87 = \x8b\n");
+        assert_eq!(charset_bytes("€"), b"?");
+    }
+
+    #[test]
+    fn test_negative_literals() {
+        assert_eq!(tokens("x = -1"), 3);
+        assert_eq!(tokens("x = y - 1"), 5);
+        assert_eq!(tokens("x = -y"), 4);
+    }
+}
@@ -0,0 +1,184 @@
+//! Mapping converted lines back to their source.
+//!
+//! Errors in a port point at lines of the converted Lua, which may not be the
+//! lines of the cart once includes are inlined and names are defined at the
+//! top. A [SourceMap] gives the file and line each converted line came from.
+use crate::{Flattened, json::json_string, patch_lua};
+
+/// Where a converted line came from.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SourceLine {
+    /// The path of the "#include" the line came from, or `None` for the text
+    /// that was converted.
+    pub path: Option<String>,
+    /// The one-based line in that file.
+    pub line: usize,
+}
+
+/// The source of each line of converted Lua.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct SourceMap {
+    /// The source of each converted line in order.
+    pub lines: Vec<SourceLine>,
+}
+
+/// Return the zero-based line of `before` that each line of `after` came from.
+///
+/// The passes keep lines where they are, so lines are paired one to one if
+/// there are as many. Otherwise the lines before and after the difference are
+/// paired and the lines in it come from where the difference starts.
+fn align_lines(before: &str, after: &str) -> Vec<usize> {
+    let old: Vec<&str> = before.split('\n').collect();
+    let new: Vec<&str> = after.split('\n').collect();
+    if old.len() == new.len() {
+        return (0..new.len()).collect();
+    }
+    let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    (0..new.len())
+        .map(|i| {
+            if i < prefix {
+                i
+            } else if i >= new.len() - suffix {
+                i + old.len() - new.len()
+            } else {
+                prefix.min(old.len() - 1)
+            }
+        })
+        .collect()
+}
+
+impl SourceMap {
+    /// Map each line of `output`, converted from `flattened`, to its source.
+    pub fn new(flattened: &Flattened, output: &str) -> Self {
+        let lua = &flattened.lua;
+        let starts: Vec<usize> = std::iter::once(0)
+            .chain(lua.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        let sources: Vec<SourceLine> = starts
+            .iter()
+            .enumerate()
+            .map(|(i, &start)| {
+                if let Some(region) = flattened
+                    .regions
+                    .iter()
+                    .find(|r| r.span.start <= start && start < r.span.end)
+                {
+                    return SourceLine {
+                        path: Some(region.path.clone()),
+                        line: lua[region.span.start..start].matches('\n').count() + 1,
+                    };
+                }
+                // Each include replaced one line with its contents.
+                let added: usize = flattened
+                    .regions
+                    .iter()
+                    .filter(|r| r.span.end <= start)
+                    .map(|r| lua[r.span.range()].matches('\n').count())
+                    .sum();
+                SourceLine {
+                    path: None,
+                    line: i + 1 - added,
+                }
+            })
+            .collect();
+        SourceMap {
+            lines: align_lines(lua, output)
+                .into_iter()
+                .map(|i| sources[i].clone())
+                .collect(),
+        }
+    }
+
+    /// Return the source of the one-based converted `line`.
+    pub fn lookup(&self, line: usize) -> Option<&SourceLine> {
+        self.lines.get(line.checked_sub(1)?)
+    }
+
+    /// Render the map as JSON: an object with a "lines" array holding the
+    /// "path" and "line" of each converted line in order.
+    pub fn to_json(&self) -> String {
+        let lines: Vec<String> = self
+            .lines
+            .iter()
+            .map(|l| {
+                let path = l.path.as_deref().map_or("null".into(), json_string);
+                format!("{{\"path\":{path},\"line\":{}}}", l.line)
+            })
+            .collect();
+        format!("{{\"lines\":[{}]}}", lines.join(","))
+    }
+}
+
+/// Resolve the "#include" statements of Pico-8 `lua` or of a cart, convert
+/// it, and map each converted line to its source.
+///
+/// ```
+/// use pico8_to_lua::sourcemap::patch_with_source_map;
+/// let (lua, map) = patch_with_source_map("#include a.lua\nx += 1", |_| "a = 1\nb != 2".into());
+/// assert_eq!(lua, "a = 1\nb ~= 2\nx = x + (1)");
+/// assert_eq!(map.lookup(2).unwrap().path.as_deref(), Some("a.lua"));
+/// assert_eq!(map.lookup(3).unwrap().line, 2);
+/// ```
+pub fn patch_with_source_map(
+    lua: &str,
+    resolve: impl FnMut(&str) -> String,
+) -> (String, SourceMap) {
+    let flattened = Flattened::new(lua, resolve);
+    let output = patch_lua(flattened.lua.as_str()).into_owned();
+    let map = SourceMap::new(&flattened, &output);
+    (output, map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_align_lines() {
+        assert_eq!(align_lines("a\nb", "c\nd"), vec![0, 1]);
+        assert_eq!(align_lines("a\nb\nc", "a\nx\ny\nb\nc"), vec![0, 1, 1, 1, 2]);
+        assert_eq!(align_lines("a\nb\nc", "a\nc"), vec![0, 2]);
+    }
+
+    #[test]
+    fn test_source_map() {
+        let lua = "a = 1\n#include f.lua\nb = 2\n#include g.lua\nc = 3";
+        let (_, map) = patch_with_source_map(lua, |path| {
+            if path == "f.lua" {
+                "x = 1\ny = 2\n".into()
+            } else {
+                "z = 3".into()
+            }
+        });
+        let lines: Vec<_> = map
+            .lines
+            .iter()
+            .map(|l| (l.path.as_deref(), l.line))
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                (None, 1),
+                (Some("f.lua"), 1),
+                (Some("f.lua"), 2),
+                (None, 2),
+                (None, 3),
+                (Some("g.lua"), 1),
+                (None, 5),
+            ]
+        );
+        assert_eq!(
+            SourceMap {
+                lines: map.lines[..1].to_vec()
+            }
+            .to_json(),
+            "{\"lines\":[{\"path\":null,\"line\":1}]}"
+        );
+    }
+}
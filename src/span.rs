@@ -0,0 +1,188 @@
+//! Byte spans and text edits.
+//!
+//! Every API that points into source text uses [Span], and every API that
+//! describes a change uses [TextEdit], so that editor integrations can apply
+//! the converter's changes as minimal edits instead of replacing the whole
+//! buffer.
+use std::{fmt, ops::Range};
+
+/// A half-open byte range `start..end` into a string.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Span {
+    /// The byte offset of the first byte.
+    pub start: usize,
+    /// The byte offset one past the last byte.
+    pub end: usize,
+}
+
+impl Span {
+    /// Create a span. Panics if `start > end`.
+    pub fn new(start: usize, end: usize) -> Span {
+        assert!(start <= end, "span start {start} is after end {end}");
+        Span { start, end }
+    }
+
+    /// The number of bytes covered.
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    /// Return true if the span covers no bytes.
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    /// Return the span as a range suitable for slicing.
+    pub fn range(&self) -> Range<usize> {
+        self.start..self.end
+    }
+
+    /// Return true if `offset` is inside the span.
+    pub fn contains(&self, offset: usize) -> bool {
+        self.start <= offset && offset < self.end
+    }
+
+    /// Return true if the spans share at least one byte.
+    pub fn overlaps(&self, other: Span) -> bool {
+        self.start < other.end && other.start < self.end
+    }
+
+    /// Move the span by `delta` bytes.
+    pub fn shift(&self, delta: isize) -> Span {
+        Span {
+            start: self.start.wrapping_add_signed(delta),
+            end: self.end.wrapping_add_signed(delta),
+        }
+    }
+
+    /// Return the one-based line and column of the span's start in `text`.
+    pub fn line_col(&self, text: &str) -> (usize, usize) {
+        let before = &text[..self.start];
+        let line = before.matches('\n').count() + 1;
+        let col = before.rfind('\n').map_or(self.start, |i| self.start - i - 1) + 1;
+        (line, col)
+    }
+}
+
+impl From<Range<usize>> for Span {
+    fn from(range: Range<usize>) -> Span {
+        Span::new(range.start, range.end)
+    }
+}
+
+impl From<Span> for Range<usize> {
+    fn from(span: Span) -> Range<usize> {
+        span.range()
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}..{}", self.start, self.end)
+    }
+}
+
+/// Replace the text at `span` with `replacement`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TextEdit {
+    /// The span of the original text to replace.
+    pub span: Span,
+    /// The text to put in its place.
+    pub replacement: String,
+}
+
+impl TextEdit {
+    /// Create an edit.
+    pub fn new(span: impl Into<Span>, replacement: impl Into<String>) -> TextEdit {
+        TextEdit {
+            span: span.into(),
+            replacement: replacement.into(),
+        }
+    }
+
+    /// The change in length caused by applying this edit.
+    pub fn delta(&self) -> isize {
+        self.replacement.len() as isize - self.span.len() as isize
+    }
+
+    /// Return the smallest single edit that turns `old` into `new`, or `None`
+    /// if they are equal.
+    pub fn diff(old: &str, new: &str) -> Option<TextEdit> {
+        if old == new {
+            return None;
+        }
+        let prefix = old
+            .char_indices()
+            .zip(new.chars())
+            .find(|((_, a), b)| a != b)
+            .map_or(old.len().min(new.len()), |((i, _), _)| i);
+        let suffix = old[prefix..]
+            .chars()
+            .rev()
+            .zip(new[prefix..].chars().rev())
+            .take_while(|(a, b)| a == b)
+            .map(|(a, _)| a.len_utf8())
+            .sum::<usize>();
+        Some(TextEdit::new(
+            prefix..old.len() - suffix,
+            &new[prefix..new.len() - suffix],
+        ))
+    }
+}
+
+/// Apply `edits` to `text`.
+///
+/// The edits' spans refer to `text` as it was before any of them were applied.
+/// They may be given in any order but must not overlap.
+pub fn apply_edits(text: &str, edits: &[TextEdit]) -> String {
+    let mut sorted: Vec<&TextEdit> = edits.iter().collect();
+    sorted.sort_by_key(|e| e.span);
+    let mut out = String::with_capacity(text.len());
+    let mut last = 0;
+    for edit in sorted {
+        debug_assert!(edit.span.start >= last, "overlapping edits");
+        out.push_str(&text[last..edit.span.start]);
+        out.push_str(&edit.replacement);
+        last = edit.span.end;
+    }
+    out.push_str(&text[last..]);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply_edits() {
+        let edits = [TextEdit::new(5..7, "~="), TextEdit::new(0..2, "--")];
+        assert_eq!(apply_edits("// a != b", &edits), "-- a ~= b");
+    }
+
+    #[test]
+    fn test_diff() {
+        let edit = TextEdit::diff("if a != b then", "if a ~= b then").unwrap();
+        assert_eq!(edit, TextEdit::new(5..6, "~"));
+        assert_eq!(TextEdit::diff("x", "x"), None);
+        assert_eq!(
+            TextEdit::diff("x += 1", "x = x + (1)").map(|e| apply_edits("x += 1", &[e])),
+            Some("x = x + (1)".into())
+        );
+    }
+
+    #[test]
+    fn test_diff_unicode() {
+        let old = "btn(⬅️)";
+        let new = "btn(0)";
+        let edit = TextEdit::diff(old, new).unwrap();
+        assert_eq!(apply_edits(old, &[edit]), new);
+    }
+
+    #[test]
+    fn test_line_col() {
+        let text = "a = 1\nb != 2";
+        assert_eq!(Span::new(8, 10).line_col(text), (2, 3));
+    }
+}
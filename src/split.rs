@@ -0,0 +1,125 @@
+//! Splitting a cart into one module per tab.
+//!
+//! A cart written in Pico-8's editor keeps its code in tabs separated by
+//! "-->8" lines, and converting it gives one long file. [convert_tabs]
+//! converts each tab as a module of its own instead, named from the comment
+//! that opens it, and gives a loader that requires them in order. Each tab is
+//! converted on its own, so the helpers a pass adds are defined in each tab
+//! that needs them, but a `local` at the top of one tab is not seen by the
+//! others as it is in Pico-8.
+use std::path::PathBuf;
+
+use crate::{
+    Cart, CartError, Options,
+    batch::Converter,
+    cart::{comment_lines, is_cart, tabs},
+    includes::require_call,
+};
+
+/// The name of the loader that requires the tabs.
+pub const LOADER: &str = "main";
+
+/// A tab converted as a module of its own.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TabModule {
+    /// The tab's number in Pico-8's editor, from 0.
+    pub index: usize,
+    /// The name that `require` loads it by.
+    pub name: String,
+    /// The converted Lua.
+    pub lua: String,
+}
+
+impl TabModule {
+    /// The path that `require` looks for the module at, e.g. "player.lua".
+    pub fn file(&self) -> PathBuf {
+        PathBuf::from(format!("{}.lua", self.name))
+    }
+}
+
+/// Return the name of tab `index` from the first line of the comment that
+/// opens it, in lowercase with an underscore for each run of other
+/// characters, or "tab" and its number if it has none.
+fn tab_name(index: usize, tab: &str) -> String {
+    let tab = tab.trim_start_matches(['\r', '\n']);
+    let mut name = String::new();
+    for c in comment_lines(tab).next().unwrap_or("").chars() {
+        if c.is_ascii_alphanumeric() {
+            name.push(c.to_ascii_lowercase());
+        } else if !name.is_empty() && !name.ends_with('_') {
+            name.push('_');
+        }
+    }
+    let name = name.trim_end_matches('_');
+    if name.is_empty() {
+        format!("tab{index}")
+    } else {
+        name.to_string()
+    }
+}
+
+/// Convert each tab of a ".p8" cart's Lua, or of Pico-8 Lua, as a module of
+/// its own with `options` and return the Lua of the [LOADER] that requires
+/// them in order, and the modules. A name that two tabs or the loader would
+/// share gets the tab's number after an underscore. Empty tabs are left out.
+///
+/// ```
+/// use pico8_to_lua::{Options, split::convert_tabs};
+/// let (loader, tabs) = convert_tabs("-- game\nx += 1\n-->8\n-- player\np = 1\n", &Options::default()).unwrap();
+/// assert_eq!(loader, "require(\"game\")\nrequire(\"player\")\n");
+/// assert_eq!(tabs[1].lua, "-- player\np = 1\n");
+/// ```
+pub fn convert_tabs(text: &str, options: &Options) -> Result<(String, Vec<TabModule>), CartError> {
+    let lua = if is_cart(text) {
+        Cart::parse(text)?.lua().unwrap_or("").to_string()
+    } else {
+        text.to_string()
+    };
+    let mut converter = Converter::new(Options {
+        lua_only: true,
+        ..options.clone()
+    });
+    let mut loader = String::new();
+    let mut modules: Vec<TabModule> = Vec::new();
+    for (index, tab) in tabs(&lua).into_iter().enumerate() {
+        if tab.trim().is_empty() {
+            continue;
+        }
+        let mut name = tab_name(index, tab);
+        if name == LOADER || modules.iter().any(|m| m.name == name) {
+            name = format!("{name}_{index}");
+        }
+        loader.push_str(&require_call(&name));
+        loader.push('\n');
+        modules.push(TabModule {
+            index,
+            name,
+            lua: converter.convert(tab)?,
+        });
+    }
+    Ok((loader, modules))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_convert_tabs() {
+        let cart = "pico-8 cartridge\n__lua__\n-- my game!\n-->8\n\n-- Player: moves\nx = 1\n\
+                    -->8\n-->8\n-- main\n-->8\n-- player, moves\n";
+        let (loader, tabs) = convert_tabs(cart, &Options::default()).unwrap();
+        let names: Vec<_> = tabs.iter().map(|t| (t.index, t.name.as_str())).collect();
+        assert_eq!(
+            names,
+            [
+                (0, "my_game"),
+                (1, "player_moves"),
+                (3, "main_3"),
+                (4, "player_moves_4")
+            ]
+        );
+        assert!(loader.ends_with("require(\"player_moves_4\")\n"));
+        assert_eq!(tabs[3].file(), PathBuf::from("player_moves_4.lua"));
+    }
+}
@@ -0,0 +1,133 @@
+//! A summary of a conversion for CI jobs to publish or check.
+use std::fmt;
+
+use crate::{
+    PatchOptions,
+    diagnostics::{Diagnostic, diagnose},
+    json::json_string,
+    patch_lua_with_report, size_report,
+};
+
+/// What a conversion did, measured on the code before and after it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConversionStats {
+    /// Pico-8 tokens of the code before and after.
+    pub tokens: (usize, usize),
+    /// Characters of the code before and after.
+    pub chars: (usize, usize),
+    /// The name of each pass that changed something and how many lines it
+    /// changed, in the order they ran.
+    pub replacements: Vec<(&'static str, usize)>,
+    /// The paths of the includes resolved.
+    pub includes: Vec<String>,
+    /// What the converter left alone.
+    pub warnings: Vec<Diagnostic>,
+}
+
+impl ConversionStats {
+    /// Measure the conversion of `source`, with `includes` resolved, into
+    /// `lua`. The replacements are those of the syntax rewrites in `options`.
+    ///
+    /// ```
+    /// use pico8_to_lua::{PatchOptions, patch_lua, stats::ConversionStats};
+    /// let source = "x += 1\nif (x != 2) y += 1";
+    /// let lua = patch_lua(source);
+    /// let stats = ConversionStats::new(source, &lua, Vec::new(), &PatchOptions::default());
+    /// assert_eq!(stats.replacements, [("not-equal", 1), ("shorthand-if", 1), ("compound-assignment", 2)]);
+    /// ```
+    pub fn new(
+        source: &str,
+        lua: &str,
+        includes: Vec<String>,
+        options: &PatchOptions,
+    ) -> ConversionStats {
+        let (before, after) = (size_report(source), size_report(lua));
+        let (_, report) = patch_lua_with_report(source, options);
+        ConversionStats {
+            tokens: (before.tokens, after.tokens),
+            chars: (before.chars, after.chars),
+            replacements: report
+                .passes
+                .iter()
+                .map(|pass| (pass.name, pass.changes.len()))
+                .collect(),
+            includes,
+            warnings: diagnose(source),
+        }
+    }
+
+    /// Render the stats as JSON. Each warning has its `code`, e.g.
+    /// "unmatched-paren", so a job can fail on some kinds only.
+    pub fn to_json(&self) -> String {
+        let replacements: Vec<String> = self
+            .replacements
+            .iter()
+            .map(|(name, count)| format!("{}:{}", json_string(name), count))
+            .collect();
+        let includes: Vec<String> = self.includes.iter().map(|i| json_string(i)).collect();
+        let warnings: Vec<String> = self
+            .warnings
+            .iter()
+            .map(|w| {
+                format!(
+                    "{{\"code\":{},\"line\":{},\"column\":{},\"message\":{}}}",
+                    json_string(w.kind.code()),
+                    w.line,
+                    w.column,
+                    json_string(&w.kind.to_string())
+                )
+            })
+            .collect();
+        format!(
+            "{{\"tokens\":{{\"before\":{},\"after\":{}}},\"chars\":{{\"before\":{},\"after\":{}}},\
+             \"replacements\":{{{}}},\"includes\":[{}],\"warnings\":[{}]}}",
+            self.tokens.0,
+            self.tokens.1,
+            self.chars.0,
+            self.chars.1,
+            replacements.join(","),
+            includes.join(","),
+            warnings.join(",")
+        )
+    }
+}
+
+impl fmt::Display for ConversionStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "tokens: {} -> {}", self.tokens.0, self.tokens.1)?;
+        writeln!(f, "chars: {} -> {}", self.chars.0, self.chars.1)?;
+        for (name, count) in &self.replacements {
+            writeln!(f, "{name}: {count}")?;
+        }
+        for include in &self.includes {
+            writeln!(f, "included: {include}")?;
+        }
+        for warning in &self.warnings {
+            writeln!(f, "{warning}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_json() {
+        let source = "x != 1\nif (a b()";
+        let stats = ConversionStats::new(
+            source,
+            "x ~= 1\nif (a b()",
+            vec!["a.lua".into()],
+            &PatchOptions::default(),
+        );
+        assert_eq!(
+            stats.to_json(),
+            "{\"tokens\":{\"before\":8,\"after\":8},\"chars\":{\"before\":16,\"after\":16},\
+             \"replacements\":{\"not-equal\":1},\"includes\":[\"a.lua\"],\
+             \"warnings\":[{\"code\":\"unmatched-paren\",\"line\":2,\"column\":4,\
+             \"message\":\"no closing parenthesis for this if condition\"}]}"
+        );
+    }
+}
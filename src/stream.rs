@@ -0,0 +1,175 @@
+//! Converting Lua as it is read.
+//!
+//! [patch_stream] reads a line at a time and converts each statement as soon
+//! as the line after it starts another, so no bracket is left open, no long
+//! string or comment runs on, and no operator waits for its operand. Only a
+//! few constructs, like a shorthand if whose condition spans lines, need more
+//! than a line or two, so little is held in memory, and never more than
+//! [MAX_LOOKAHEAD] lines.
+use std::io::{self, BufRead, Write};
+
+use crate::{
+    PatchOptions,
+    cart::{comment_header, is_cart, section_marker},
+    incremental::chunk_ends,
+    newline::{BOM, Newlines},
+    patch_continued, patch_section,
+};
+
+/// The most lines held back waiting for a construct to close.
+pub const MAX_LOOKAHEAD: usize = 256;
+
+/// The lines read but not yet converted.
+#[derive(Debug, Default)]
+struct Pending {
+    lua: String,
+    lines: usize,
+    /// Whether the lines start a `__lua__` section or the input, so may
+    /// start with the comment header.
+    first: bool,
+}
+
+impl Pending {
+    /// Convert and write the lines held up to byte `end`, if any.
+    fn flush_to(
+        &mut self,
+        end: usize,
+        writer: &mut impl Write,
+        options: &PatchOptions,
+    ) -> io::Result<()> {
+        if end == 0 {
+            return Ok(());
+        }
+        let patch = |lua: &str| -> String {
+            if self.first {
+                patch_section(lua, options).into_owned()
+            } else {
+                patch_continued(lua, options).into_owned()
+            }
+        };
+        let lua = &self.lua[..end];
+        let lua = match Newlines::normalize(lua) {
+            Some((lua, newlines)) => newlines.restore(&patch(&lua)),
+            None => patch(lua),
+        };
+        writer.write_all(lua.as_bytes())?;
+        self.lua.drain(..end);
+        self.lines = self.lua.matches('\n').count();
+        self.first = false;
+        Ok(())
+    }
+
+    /// Convert and write the lines held, if any.
+    fn flush(&mut self, writer: &mut impl Write, options: &PatchOptions) -> io::Result<()> {
+        self.flush_to(self.lua.len(), writer, options)
+    }
+
+    /// Convert and write the statements held that the lines after them
+    /// cannot continue, keeping the last, which the next line may.
+    fn flush_whole(&mut self, writer: &mut impl Write, options: &PatchOptions) -> io::Result<()> {
+        let len = self.lua.len();
+        let end = chunk_ends(&self.lua, 0).filter(|&end| end < len).last();
+        self.flush_to(end.unwrap_or(0), writer, options)
+    }
+}
+
+/// Convert Pico-8 Lua read from `reader` like [patch_lua_with](crate::patch_lua_with)
+/// and write it to `writer` as it goes, without holding the whole input.
+///
+/// If the input is a ".p8" cart, only its `__lua__` section is converted and
+/// the rest is copied as it is.
+///
+/// ```
+/// use pico8_to_lua::{PatchOptions, patch_stream};
+/// let mut out = Vec::new();
+/// patch_stream("x += 1\nif (a and\n b) c()\n".as_bytes(), &mut out, &PatchOptions::default()).unwrap();
+/// assert_eq!(out, b"x = x + (1)\nif a and\n b then c() end\n");
+/// ```
+pub fn patch_stream(
+    mut reader: impl BufRead,
+    mut writer: impl Write,
+    options: &PatchOptions,
+) -> io::Result<()> {
+    let mut pending = Pending {
+        first: true,
+        ..Pending::default()
+    };
+    let mut line = String::new();
+    let mut cart = None;
+    let mut bom = false;
+    let mut in_lua = false;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            break;
+        }
+        if cart.is_none() {
+            // The byte order mark is written once, before any line.
+            if let Some(rest) = line.strip_prefix(BOM) {
+                line = rest.to_string();
+                bom = true;
+            }
+            // A cart's code starts after its "__lua__" line.
+            cart = Some(is_cart(&line));
+            in_lua = cart == Some(false);
+        }
+        if bom {
+            writer.write_all(BOM.to_string().as_bytes())?;
+            bom = false;
+        }
+        if let Some(name) = section_marker(&line).filter(|_| cart == Some(true)) {
+            pending.flush(&mut writer, options)?;
+            in_lua = name == "lua";
+            pending.first = true;
+            writer.write_all(line.as_bytes())?;
+            continue;
+        }
+        if !in_lua {
+            writer.write_all(line.as_bytes())?;
+            continue;
+        }
+        pending.lua.push_str(&line);
+        pending.lines += 1;
+        if pending.lines >= MAX_LOOKAHEAD {
+            pending.flush(&mut writer, options)?;
+        } else if pending.first && comment_header(&pending.lua).len() == pending.lua.len() {
+            // The comment header is left alone, so it is converted whole.
+        } else {
+            pending.flush_whole(&mut writer, options)?;
+        }
+    }
+    pending.flush(&mut writer, options)?;
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::patch_lua;
+
+    fn stream(lua: &str) -> String {
+        let mut out = Vec::new();
+        patch_stream(lua.as_bytes(), &mut out, &PatchOptions::default()).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_patch_stream() {
+        let lua = "-- title\n// by me\nx += 1 // inc\n--[[ a (\n]] if (a and\n b) c()\n?s\n";
+        assert_eq!(stream(lua), patch_lua(lua));
+        let cart = "pico-8 cartridge\nversion 41\n__lua__\n-- t\na != b\n__gfx__\n0 != 1\n";
+        assert_eq!(stream(cart), patch_lua(cart));
+        for lua in ["x += t[\n1]\ny = 1\n", "x += a\n + b\ny = {\n2}\n", "s ..= \"a\" ..\n\"b\"\n"] {
+            assert_eq!(stream(lua), patch_lua(lua));
+        }
+    }
+
+    #[test]
+    fn test_crlf_and_bom() {
+        let lua = "\u{feff}x += 1\r\nif (a) b()\r\n";
+        assert_eq!(stream(lua), "\u{feff}x = x + (1)\r\nif a then b() end\r\n");
+        assert_eq!(stream(lua), patch_lua(lua));
+        let cart = "\u{feff}pico-8 cartridge\r\n__lua__\r\nx -= 1\r\n__gfx__\r\n00\r\n";
+        assert_eq!(stream(cart), patch_lua(cart));
+    }
+}
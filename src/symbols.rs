@@ -0,0 +1,197 @@
+//! Names the converter generates.
+//!
+//! Every name the converter adds to a cart starts with a reserved prefix,
+//! [DEFAULT_PREFIX] unless configured otherwise, so it cannot collide with the
+//! cart's own names. A [Namer] checks the prefix against the names the cart
+//! already uses and picks another name when one is taken.
+use std::{
+    borrow::Cow,
+    collections::{BTreeSet, HashMap},
+    error::Error,
+    fmt,
+};
+
+use crate::{
+    cart::comment_header,
+    glyph,
+    lexer::{TokenKind, tokenize},
+    span::{Span, TextEdit, apply_edits},
+};
+
+/// The prefix of generated names unless another is given.
+pub const DEFAULT_PREFIX: &str = "__p8_";
+
+pub use crate::glyph::BUTTON_GLYPHS;
+
+/// A cart already uses a name with the reserved prefix.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SymbolConflict {
+    /// The name that starts with the prefix.
+    pub name: String,
+    /// Where the name is first used.
+    pub span: Span,
+}
+
+impl fmt::Display for SymbolConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "name {:?} at {} uses the prefix reserved for generated names",
+            self.name, self.span
+        )
+    }
+}
+
+impl Error for SymbolConflict {}
+
+/// Return every identifier used in `lua`.
+pub fn symbols(lua: &str) -> BTreeSet<&str> {
+    tokenize(lua)
+        .filter(|t| t.kind == TokenKind::Name)
+        .map(|t| t.text)
+        .collect()
+}
+
+/// Return an error for the first name in `lua` that starts with `prefix`.
+pub fn check_prefix(lua: &str, prefix: &str) -> Result<(), SymbolConflict> {
+    match tokenize(lua).find(|t| t.kind == TokenKind::Name && t.text.starts_with(prefix)) {
+        Some(t) => Err(SymbolConflict {
+            name: t.text.to_string(),
+            span: t.span,
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Hands out prefixed names that the cart does not use.
+#[derive(Debug, Clone)]
+pub struct Namer {
+    prefix: String,
+    taken: BTreeSet<String>,
+    names: HashMap<String, String>,
+}
+
+impl Namer {
+    /// Create a namer for `lua` that prefixes names with `prefix`.
+    pub fn new(prefix: &str, lua: &str) -> Self {
+        Namer {
+            prefix: prefix.to_string(),
+            taken: symbols(lua).into_iter().map(str::to_string).collect(),
+            names: HashMap::new(),
+        }
+    }
+
+    /// The prefix of generated names.
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    /// Return the name for `base`, e.g. "__p8_left" for "left".
+    ///
+    /// The same base always gets the same name. If the cart uses the name
+    /// already, a number is added until it does not, e.g. "__p8_left_2".
+    pub fn name(&mut self, base: &str) -> String {
+        if let Some(name) = self.names.get(base) {
+            return name.clone();
+        }
+        let mut name = format!("{}{}", self.prefix, base);
+        let mut n = 2;
+        while self.taken.contains(&name) {
+            name = format!("{}{}_{}", self.prefix, base, n);
+            n += 1;
+        }
+        self.taken.insert(name.clone());
+        self.names.insert(base.to_string(), name.clone());
+        name
+    }
+}
+
+/// Rename the button glyphs used as values, e.g. `b = ⬅️`, which plain Lua
+/// cannot read, and define the new names after the cart's comment header.
+///
+/// Glyphs in `btn()` and `btnp()` calls are replaced with numbers by
+/// [patch_lua](crate::patch_lua) and other glyphs are left alone.
+pub fn rename_glyphs<'h>(lua: Cow<'h, str>, namer: &mut Namer) -> Cow<'h, str> {
+    let mut edits = Vec::new();
+    let mut defined = Vec::new();
+    for token in tokenize(&lua).filter(|t| t.kind == TokenKind::Name) {
+        if let Some((base, value)) = glyph::button(token.text) {
+            let name = namer.name(base);
+            if !defined.iter().any(|(n, _)| *n == name) {
+                defined.push((name.clone(), value));
+            }
+            edits.push(TextEdit::new(token.span, name));
+        }
+    }
+    if edits.is_empty() {
+        return lua;
+    }
+    defined.sort_by_key(|(_, value)| *value);
+    let at = comment_header(&lua).len();
+    let prelude: String = defined
+        .iter()
+        .map(|(name, value)| format!("local {name} = {value}\n"))
+        .collect();
+    edits.insert(0, TextEdit::new(Span::new(at, at), prelude));
+    Cow::Owned(apply_edits(&lua, &edits))
+}
+
+/// Replace the button glyphs used as values, e.g. `keys = {⬅️, ➡️}`, with
+/// their numbers, leaving strings and comments alone.
+///
+/// Unlike [rename_glyphs] this defines no names, so the numbers show in the
+/// converted code.
+pub fn number_glyphs<'h>(lua: Cow<'h, str>) -> Cow<'h, str> {
+    let edits: Vec<TextEdit> = tokenize(&lua)
+        .filter(|t| t.kind == TokenKind::Name)
+        .filter_map(|token| {
+            let (_, value) = glyph::button(token.text)?;
+            Some(TextEdit::new(token.span, value.to_string()))
+        })
+        .collect();
+    if edits.is_empty() {
+        return lua;
+    }
+    Cow::Owned(apply_edits(&lua, &edits))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_namer() {
+        let mut namer = Namer::new(DEFAULT_PREFIX, "__p8_left = 1");
+        assert_eq!(namer.name("left"), "__p8_left_2");
+        assert_eq!(namer.name("left"), "__p8_left_2");
+        assert_eq!(namer.name("up"), "__p8_up");
+    }
+
+    #[test]
+    fn test_check_prefix() {
+        assert_eq!(check_prefix("x = \"__p8_\"", DEFAULT_PREFIX), Ok(()));
+        let conflict = check_prefix("x = 1\n__p8_y = 2", DEFAULT_PREFIX).unwrap_err();
+        assert_eq!(conflict.name, "__p8_y");
+        assert_eq!(conflict.span, Span::new(6, 12));
+    }
+
+    #[test]
+    fn test_rename_glyphs() {
+        let lua = "-- game\nb = ⬅️\nif (btn(b)) x = ➡️ -- ⬅️\n";
+        let mut namer = Namer::new("_g_", lua);
+        assert_eq!(
+            rename_glyphs(lua.into(), &mut namer),
+            "-- game\nlocal _g_left = 0\nlocal _g_right = 1\nb = _g_left\nif (btn(b)) x = _g_right -- ⬅️\n"
+        );
+        assert!(matches!(rename_glyphs("x = 1".into(), &mut namer), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn test_number_glyphs() {
+        assert_eq!(
+            number_glyphs("keys = {⬅️,➡️,⬆️,⬇️}\nif k == ❎ then s = \"❎\" end -- 🅾️".into()),
+            "keys = {0,1,2,3}\nif k == 5 then s = \"❎\" end -- 🅾️"
+        );
+        assert!(matches!(number_glyphs("x = 1".into()), Cow::Borrowed(_)));
+    }
+}
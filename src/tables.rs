@@ -0,0 +1,167 @@
+//! Rewriting Pico-8's table functions with Lua's `table` library.
+//!
+//! Pico-8's `add`, `del`, `deli`, and `count` need shims in plain Lua.
+//! [patch_table_builtins] rewrites the calls that Lua's standard library can
+//! do alone: `add(t, v)` statements as `table.insert(t, v)`, `deli(t, i)` as
+//! `table.remove(t, i)`, `count(t)` as `#t`, and `del(t, v)` as a call to a
+//! helper defined at the top of the code.
+//!
+//! A call that uses a value `table.insert` does not return, like `x = add(t,
+//! v)`, or that counts a value, like `count(t, v)`, is left for a shim. And
+//! where `deli` with an index past the end of `t` does nothing, Lua 5.3 and
+//! later raise an error.
+use std::borrow::Cow;
+
+use crate::{
+    api::api_calls,
+    cart::comment_header,
+    lexer::{Token, TokenKind, tokenize},
+    loops::{arguments, statement},
+    span::{Span, TextEdit, apply_edits},
+    symbols::{DEFAULT_PREFIX, Namer},
+};
+
+/// Rewrite calls to `add`, `del`, `deli`, and `count` with Lua's `table`
+/// library, naming the helper for `del` with [DEFAULT_PREFIX].
+///
+/// ```
+/// use pico8_to_lua::tables::patch_table_builtins;
+/// assert_eq!(
+///     patch_table_builtins("add(enemies, e) n = count(enemies)"),
+///     "table.insert(enemies, e) n = #enemies"
+/// );
+/// assert_eq!(
+///     patch_table_builtins("del(enemies, e)"),
+///     "local function __p8_del(t, v) for i = 1, #t do if t[i] == v then return table.remove(t, i) end end end\n__p8_del(enemies, e)"
+/// );
+/// ```
+pub fn patch_table_builtins<'h>(lua: impl Into<Cow<'h, str>>) -> Cow<'h, str> {
+    let lua = lua.into();
+    let mut namer = Namer::new(DEFAULT_PREFIX, &lua);
+    patch_table_builtins_with(lua, &mut namer)
+}
+
+/// Rewrite calls to the table functions like [patch_table_builtins], naming
+/// the helper for `del` with `namer`.
+pub fn patch_table_builtins_with<'h>(
+    lua: impl Into<Cow<'h, str>>,
+    namer: &mut Namer,
+) -> Cow<'h, str> {
+    let lua = lua.into();
+    let tokens: Vec<Token> = tokenize(&lua)
+        .filter(|t| t.kind != TokenKind::Comment)
+        .collect();
+    let mut edits = Vec::new();
+    let mut del = None;
+    // The end of the last `count` call replaced whole, whose arguments are
+    // left as they are.
+    let mut replaced = 0;
+    for (name, i) in api_calls(&tokens) {
+        let Some((close, commas)) = arguments(&tokens, i + 1) else {
+            continue;
+        };
+        if i < replaced || close == i + 2 || commas.last().is_some_and(|c| close == c + 1) {
+            continue;
+        }
+        match (name, commas.as_slice()) {
+            ("add", [_]) if statement(&tokens, i) => {
+                edits.push(TextEdit::new(tokens[i].span, "table.insert"));
+            }
+            // `add(t, v, i)` inserts `v` at `i`, which `table.insert` takes
+            // before the value. Only a lone index is moved, so that the order
+            // the arguments are evaluated in does not matter.
+            ("add", &[first, second])
+                if statement(&tokens, i)
+                    && close == second + 2
+                    && matches!(tokens[second + 1].kind, TokenKind::Name | TokenKind::Number) =>
+            {
+                let value = Span::new(tokens[first + 1].span.start, tokens[second - 1].span.end);
+                edits.push(TextEdit::new(tokens[i].span, "table.insert"));
+                edits.push(TextEdit::new(value, tokens[second + 1].text));
+                edits.push(TextEdit::new(tokens[second + 1].span, &lua[value.start..value.end]));
+            }
+            ("deli", [] | [_]) => {
+                edits.push(TextEdit::new(tokens[i].span, "table.remove"));
+            }
+            ("del", [_]) => {
+                let helper = del.get_or_insert_with(|| namer.name("del"));
+                edits.push(TextEdit::new(tokens[i].span, helper.as_str()));
+            }
+            ("count", []) => {
+                let table = Span::new(tokens[i + 2].span.start, tokens[close - 1].span.end);
+                let table = &lua[table.start..table.end];
+                let length = if path(&tokens[i + 2..close]) {
+                    format!("#{table}")
+                } else {
+                    format!("#({table})")
+                };
+                // `^` binds more tightly than `#`.
+                let length = if tokens.get(close + 1).is_some_and(|t| t.is("^")) {
+                    format!("({length})")
+                } else {
+                    length
+                };
+                let call = Span::new(tokens[i].span.start, tokens[close].span.end);
+                edits.push(TextEdit::new(call, length));
+                replaced = close;
+            }
+            _ => {}
+        }
+    }
+    if edits.is_empty() {
+        return lua;
+    }
+    if let Some(helper) = del {
+        let at = comment_header(&lua).len();
+        edits.push(TextEdit::new(
+            Span::new(at, at),
+            format!(
+                "local function {helper}(t, v) for i = 1, #t do if t[i] == v then return table.remove(t, i) end end end\n"
+            ),
+        ));
+    }
+    Cow::Owned(apply_edits(&lua, &edits))
+}
+
+/// Return whether `tokens` are a name or names joined by dots, like `a.b`,
+/// which `#` applies to whole.
+fn path(tokens: &[Token]) -> bool {
+    tokens.iter().enumerate().all(|(i, t)| {
+        if i % 2 == 0 {
+            t.kind == TokenKind::Name
+        } else {
+            t.is(".")
+        }
+    }) && tokens.len() % 2 == 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_patch_table_builtins() {
+        assert_eq!(
+            patch_table_builtins("if x then add(t, {1, 2}) end\nadd(t, v, 1)"),
+            "if x then table.insert(t, {1, 2}) end\ntable.insert(t, 1, v)"
+        );
+        assert_eq!(
+            patch_table_builtins("x = add(t, v)\nadd(t, v, f())\nn = count(t, v)"),
+            "x = add(t, v)\nadd(t, v, f())\nn = count(t, v)"
+        );
+        assert_eq!(
+            patch_table_builtins("e = deli(t) deli(t, 1) x = count(a.b) + count(f(t))^2 + count(deli(t))"),
+            "e = table.remove(t) table.remove(t, 1) x = #a.b + (#(f(t)))^2 + #(deli(t))"
+        );
+        assert_eq!(
+            patch_table_builtins("-- cart\ndel(a, x)\nif del(b, y) then end\n-- add(t, v)"),
+            "-- cart\nlocal function __p8_del(t, v) for i = 1, #t do if t[i] == v then return table.remove(t, i) end end end\n__p8_del(a, x)\nif __p8_del(b, y) then end\n-- add(t, v)"
+        );
+    }
+
+    #[test]
+    fn test_defined() {
+        let lua = "function add(t, v) t[#t + 1] = v end\nadd(t, v) t:add(v) s.count(t)";
+        assert_eq!(patch_table_builtins(lua), lua);
+    }
+}
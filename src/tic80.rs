@@ -0,0 +1,194 @@
+//! Exporting a cart for TIC-80.
+//!
+//! [to_tic] writes a ".tic" cart, TIC-80's binary format of chunks, each a
+//! four-byte header with its type and size followed by its data. The code
+//! chunk holds the converted code after [script]'s header and adapter, which
+//! maps Pico-8's API onto TIC-80's and calls `_init`, `_update`, and `_draw`
+//! from `TIC()`. The spritesheet becomes the tiles, the map the top left of
+//! TIC-80's larger map, and the palette and sprite flags carry over. Sound
+//! does not.
+use std::{error::Error, fmt};
+
+use crate::{
+    Options,
+    batch::Converter,
+    bitwise::LuaTarget,
+    cart::{Cart, CartError, MAP_HEIGHT, MAP_WIDTH, SHEET_SIZE},
+    label::PALETTE,
+    shim::{self, SHIMS},
+};
+
+/// The adapter from Pico-8's API to TIC-80's.
+const RUNTIME: &str = include_str!("tic80/pico8.lua");
+
+/// The width of TIC-80's map in tiles.
+const TIC_MAP_WIDTH: usize = 240;
+
+/// The largest chunk.
+const CHUNK_MAX: usize = 0xffff;
+
+// The types of the chunks that are written.
+const CHUNK_TILES: u8 = 1;
+const CHUNK_MAP: u8 = 4;
+const CHUNK_CODE: u8 = 5;
+const CHUNK_FLAGS: u8 = 6;
+const CHUNK_PALETTE: u8 = 12;
+
+/// An error encountered while exporting a cart for TIC-80.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TicError {
+    /// A section of the cart could not be read.
+    Cart(CartError),
+    /// The code has this many bytes, more than fit in one chunk.
+    CodeTooLong(usize),
+}
+
+impl fmt::Display for TicError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TicError::Cart(e) => write!(f, "cannot read cart: {e}"),
+            TicError::CodeTooLong(len) => write!(
+                f,
+                "the converted code has {len} bytes; a TIC-80 cart holds {CHUNK_MAX}"
+            ),
+        }
+    }
+}
+
+impl Error for TicError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            TicError::Cart(e) => Some(e),
+            TicError::CodeTooLong(_) => None,
+        }
+    }
+}
+
+impl From<CartError> for TicError {
+    fn from(e: CartError) -> Self {
+        TicError::Cart(e)
+    }
+}
+
+/// Return the code of a TIC-80 cart that runs `cart`: TIC-80's metadata
+/// comments, the adapter, the shims it doesn't replace, and the code
+/// converted for TIC-80's Lua 5.3.
+pub fn script(cart: &Cart) -> Result<String, CartError> {
+    let options = Options {
+        lua_only: true,
+        target: Some(LuaTarget::Lua53),
+        ..Options::default()
+    };
+    let lua = Converter::new(options).convert(cart.lua().unwrap_or(""))?;
+    let shims: Vec<_> = SHIMS
+        .iter()
+        .filter(|s| !matches!(s.name, "print" | "printh" | "time" | "t"))
+        .collect();
+    let mut out = String::new();
+    for (tag, value) in [("title", cart.title()), ("author", cart.author())] {
+        if let Some(value) = value {
+            out.push_str(&format!("-- {tag}: {value}\n"));
+        }
+    }
+    out.push_str("-- script: lua\n\n");
+    out.push_str(RUNTIME);
+    out.push_str("\n-- Pico-8's functions that plain Lua can provide.\n");
+    out.push_str(&shim::module(&shims));
+    out.push('\n');
+    out.push_str(&lua);
+    Ok(out)
+}
+
+/// Append a chunk of `kind` holding `data` without its trailing zeros, which
+/// TIC-80 fills in, or nothing if it is all zeros.
+fn push_chunk(out: &mut Vec<u8>, kind: u8, data: &[u8]) {
+    let len = data.iter().rposition(|&b| b != 0).map_or(0, |i| i + 1);
+    if len == 0 {
+        return;
+    }
+    out.push(kind);
+    out.extend((len as u16).to_le_bytes());
+    out.push(0);
+    out.extend(&data[..len]);
+}
+
+/// Return a ".tic" cart that runs `cart` in TIC-80.
+///
+/// ```
+/// use pico8_to_lua::{Cart, tic80::to_tic};
+/// let cart = Cart::parse("pico-8 cartridge\n__lua__\nx += 1\n").unwrap();
+/// let tic = to_tic(&cart).unwrap();
+/// assert_eq!(tic[0], 5);
+/// ```
+pub fn to_tic(cart: &Cart) -> Result<Vec<u8>, TicError> {
+    let code = script(cart)?;
+    if code.len() > CHUNK_MAX {
+        return Err(TicError::CodeTooLong(code.len()));
+    }
+    let mut out = Vec::new();
+    push_chunk(&mut out, CHUNK_CODE, code.as_bytes());
+
+    // Each tile is 8 rows of 4 bytes, each byte two pixels, low nibble first.
+    let sheet = cart.gfx()?;
+    let mut tiles = Vec::with_capacity(SHEET_SIZE * SHEET_SIZE / 2);
+    for tile in 0..256 {
+        let (x, y) = (tile % 16 * 8, tile / 16 * 8);
+        for row in y..y + 8 {
+            for col in (x..x + 8).step_by(2) {
+                tiles.push(sheet.get(col, row) | sheet.get(col + 1, row) << 4);
+            }
+        }
+    }
+    push_chunk(&mut out, CHUNK_TILES, &tiles);
+
+    let map = cart.map()?;
+    let mut tic_map = vec![0; TIC_MAP_WIDTH * MAP_HEIGHT];
+    for (y, row) in map.tiles.chunks(MAP_WIDTH).enumerate() {
+        tic_map[y * TIC_MAP_WIDTH..y * TIC_MAP_WIDTH + MAP_WIDTH].copy_from_slice(row);
+    }
+    push_chunk(&mut out, CHUNK_MAP, &tic_map);
+
+    push_chunk(&mut out, CHUNK_FLAGS, &cart.sprite_flags()?.0);
+    let palette: Vec<u8> = PALETTE.iter().flatten().copied().collect();
+    push_chunk(&mut out, CHUNK_PALETTE, &palette);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Split a ".tic" cart into its chunks' types and data.
+    fn chunks(mut tic: &[u8]) -> Vec<(u8, &[u8])> {
+        let mut chunks = Vec::new();
+        while !tic.is_empty() {
+            let len = u16::from_le_bytes([tic[1], tic[2]]) as usize;
+            chunks.push((tic[0], &tic[4..4 + len]));
+            tic = &tic[4 + len..];
+        }
+        chunks
+    }
+
+    #[test]
+    fn test_to_tic() {
+        let mut text = String::from("pico-8 cartridge\n__lua__\n-- game\n-- by me\nx = 1 & 2\n");
+        text.push_str(&format!("__gfx__\n0a{}\n", "0".repeat(126)));
+        text.push_str(&format!("__map__\n00{}\n", "0".repeat(254)));
+        text.push_str(&format!("0001{}\n", "0".repeat(252)));
+        let tic = to_tic(&Cart::parse(&text).unwrap()).unwrap();
+        let chunks = chunks(&tic);
+        let kinds: Vec<u8> = chunks.iter().map(|c| c.0).collect();
+        assert_eq!(
+            kinds,
+            vec![CHUNK_CODE, CHUNK_TILES, CHUNK_MAP, CHUNK_PALETTE]
+        );
+        let code = std::str::from_utf8(chunks[0].1).unwrap();
+        assert!(code.starts_with("-- title: game\n-- author: by me\n-- script: lua\n"));
+        assert!(code.contains("function TIC()"));
+        assert!(code.ends_with("x = __p8_band(1, 2)\n"));
+        assert_eq!(chunks[1].1, &[0xa0]);
+        assert_eq!(chunks[2].1.len(), TIC_MAP_WIDTH + 2);
+        assert_eq!(chunks[3].1.len(), 48);
+        assert_eq!(&chunks[3].1[3..6], &[0x1d, 0x2b, 0x53]);
+    }
+}
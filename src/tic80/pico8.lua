@@ -0,0 +1,180 @@
+-- Maps Pico-8's API onto TIC-80's.
+--
+-- Pico-8's 128x128 screen is drawn in the middle of TIC-80's 240x136 one, and
+-- its callbacks are called from TIC() at 30 or 60 frames per second. Sound,
+-- the palette functions, fill patterns, and Pico-8's memory layout are not
+-- mapped.
+local tic = {
+  btn = btn, btnp = btnp, circ = circ, circb = circb, clip = clip, cls = cls,
+  fget = fget, fset = fset, line = line, map = map, pix = pix, print = print,
+  rect = rect, rectb = rectb, spr = spr, time = time,
+}
+local ox, oy = 56, 4
+local draw_color = 6
+local camera_x, camera_y = 0, 0
+local cursor_x, cursor_y = 0, 0
+-- Pico-8's buttons are left, right, up, down, O, and X. TIC-80's are up,
+-- down, left, right, A, and B.
+local buttons = {[0] = 2, 3, 0, 1, 4, 5}
+
+local function pick(c)
+  if c then draw_color = c // 1 % 16 end
+  return draw_color
+end
+
+local function sx(x) return x // 1 - camera_x + ox end
+local function sy(y) return y // 1 - camera_y + oy end
+
+function cls(c)
+  tic.cls(c or 0)
+  cursor_x, cursor_y = 0, 0
+end
+
+function color(c) draw_color = (c or 6) // 1 % 16 end
+
+function camera(x, y)
+  local old_x, old_y = camera_x, camera_y
+  camera_x, camera_y = (x or 0) // 1, (y or 0) // 1
+  return old_x, old_y
+end
+
+function clip(x, y, w, h)
+  if x then tic.clip(x + ox, y + oy, w, h) else tic.clip(ox, oy, 128, 128) end
+end
+
+function pset(x, y, c) tic.pix(sx(x), sy(y), pick(c)) end
+
+function pget(x, y) return tic.pix(sx(x), sy(y)) end
+
+function line(x0, y0, x1, y1, c) tic.line(sx(x0), sy(y0), sx(x1), sy(y1), pick(c)) end
+
+function rect(x0, y0, x1, y1, c)
+  tic.rectb(sx(math.min(x0, x1)), sy(math.min(y0, y1)),
+    math.abs(x1 - x0) // 1 + 1, math.abs(y1 - y0) // 1 + 1, pick(c))
+end
+
+function rectfill(x0, y0, x1, y1, c)
+  tic.rect(sx(math.min(x0, x1)), sy(math.min(y0, y1)),
+    math.abs(x1 - x0) // 1 + 1, math.abs(y1 - y0) // 1 + 1, pick(c))
+end
+
+function circ(x, y, r, c) tic.circb(sx(x), sy(y), r or 4, pick(c)) end
+
+function circfill(x, y, r, c) tic.circ(sx(x), sy(y), r or 4, pick(c)) end
+
+-- The sprites are at 0x4000, four bits per pixel, one 8x8 tile after another.
+function sget(x, y)
+  x, y = x // 1, y // 1
+  return peek4(0x8000 + ((y // 8) * 16 + x // 8) * 64 + y % 8 * 8 + x % 8)
+end
+
+function sset(x, y, c)
+  x, y = x // 1, y // 1
+  poke4(0x8000 + ((y // 8) * 16 + x // 8) * 64 + y % 8 * 8 + x % 8, pick(c))
+end
+
+function spr(n, x, y, w, h, flip_x, flip_y)
+  local flip = (flip_x and 1 or 0) + (flip_y and 2 or 0)
+  tic.spr(n // 1, sx(x or 0), sy(y or 0), 0, 1, flip, 0, w or 1, h or 1)
+end
+
+function sspr(sx0, sy0, sw, sh, dx, dy, dw, dh, flip_x, flip_y)
+  dw, dh = dw or sw, dh or sh
+  for y = 0, dh - 1 do
+    for x = 0, dw - 1 do
+      local u = flip_x and dw - 1 - x or x
+      local v = flip_y and dh - 1 - y or y
+      local c = sget(sx0 + u * sw // dw, sy0 + v * sh // dh)
+      if c ~= 0 then tic.pix(sx(dx + x), sy(dy + y), c) end
+    end
+  end
+end
+
+function fget(n, f)
+  if f then return tic.fget(n, f) end
+  local bits = 0
+  for b = 0, 7 do
+    if tic.fget(n, b) then bits = bits | 1 << b end
+  end
+  return bits
+end
+
+function fset(n, f, v)
+  if v ~= nil then return tic.fset(n, f, v) end
+  for b = 0, 7 do tic.fset(n, b, f & 1 << b ~= 0) end
+end
+
+function map(cx, cy, x, y, cw, ch, layers)
+  cx, cy, x, y, cw, ch = cx or 0, cy or 0, x or 0, y or 0, cw or 128, ch or 64
+  if not layers or layers == 0 then
+    return tic.map(cx, cy, cw, ch, sx(x), sy(y), 0)
+  end
+  for ty = 0, ch - 1 do
+    for tx = 0, cw - 1 do
+      local n = mget(cx + tx, cy + ty)
+      if n ~= 0 and fget(n) & layers == layers then spr(n, x + tx * 8, y + ty * 8) end
+    end
+  end
+end
+
+function print(s, x, y, c)
+  if type(x) == "number" then cursor_x, cursor_y = x, y else c = x end
+  tic.print(tostr(s), sx(cursor_x), sy(cursor_y), pick(c))
+  cursor_y = cursor_y + 6
+end
+
+function cursor(x, y, c)
+  cursor_x, cursor_y = x or 0, y or 0
+  if c then color(c) end
+end
+
+function btn(i, p)
+  if i == nil then
+    local bits = 0
+    for b = 0, 5 do
+      if btn(b, p) then bits = bits | 1 << b end
+    end
+    return bits
+  end
+  return buttons[i] ~= nil and tic.btn((p or 0) * 8 + buttons[i])
+end
+
+function btnp(i, p)
+  if i == nil then
+    local bits = 0
+    for b = 0, 5 do
+      if btnp(b, p) then bits = bits | 1 << b end
+    end
+    return bits
+  end
+  return buttons[i] ~= nil and tic.btnp((p or 0) * 8 + buttons[i])
+end
+
+function time() return tic.time() / 1000 end
+t = time
+printh = trace
+
+function sfx() end
+function music() end
+function pal() end
+function palt() end
+function fillp() end
+function flip() end
+function stat() return 0 end
+
+local frame = 0
+
+function TIC()
+  if frame == 0 then
+    tic.clip(ox, oy, 128, 128)
+    if _init then _init() end
+  end
+  frame = frame + 1
+  if _update60 then
+    _update60()
+    if _draw then _draw() end
+  elseif frame % 2 == 1 then
+    if _update then _update() end
+    if _draw then _draw() end
+  end
+end
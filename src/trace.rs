@@ -0,0 +1,124 @@
+//! Tracing the conversion with the `tracing` feature.
+//!
+//! Each pass runs in a "pass" span and ends with an event listing the lines
+//! it changed and how long it took, and each included file that is read ends
+//! with an event with its size and how long reading it took, so a subscriber
+//! shows which pass touched which lines of a cart that converts wrong.
+//! Without the feature these functions only run what they are given.
+use std::borrow::Cow;
+#[cfg(feature = "tracing")]
+use std::time::{Duration, Instant};
+
+use crate::PatchOptions;
+
+/// Emit the event that ends a pass that turned `before` into `after`.
+#[cfg(feature = "tracing")]
+fn pass_done(before: &str, after: &str, elapsed: Duration) {
+    let lines: Vec<usize> = crate::pipeline::line_changes(before, after)
+        .iter()
+        .map(|change| change.line)
+        .collect();
+    tracing::debug!(changed = lines.len(), ?lines, ?elapsed, "pass done");
+}
+
+/// Run the pass called `name` on `lua`.
+#[cfg(feature = "tracing")]
+pub(crate) fn pass<'h>(
+    name: &'static str,
+    lua: Cow<'h, str>,
+    run: impl FnOnce(Cow<'h, str>) -> Cow<'h, str>,
+) -> Cow<'h, str> {
+    let _span = tracing::debug_span!("pass", name).entered();
+    if !tracing::enabled!(tracing::Level::DEBUG) {
+        return run(lua);
+    }
+    let before = lua.to_string();
+    let start = Instant::now();
+    let after = run(lua);
+    pass_done(&before, &after, start.elapsed());
+    after
+}
+
+/// Run the pass called `name` on `lua`.
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn pass<'h>(
+    _name: &'static str,
+    lua: Cow<'h, str>,
+    run: impl FnOnce(Cow<'h, str>) -> Cow<'h, str>,
+) -> Cow<'h, str> {
+    run(lua)
+}
+
+/// Trace each rewrite in `options` by running them one more at a time on
+/// `lua`, if debug events are wanted. The time of a rewrite is how much
+/// longer converting took with it than without it.
+#[cfg(feature = "tracing")]
+pub(crate) fn patch_passes(lua: &str, options: &PatchOptions) {
+    if !tracing::enabled!(tracing::Level::DEBUG) {
+        return;
+    }
+    let mut stage = PatchOptions::none();
+    let mut before = lua.to_string();
+    let mut last = Duration::ZERO;
+    for pass in crate::pipeline::PASSES {
+        let mut wanted = *options;
+        if wanted.pass_mut(pass.name).is_none_or(|on| !*on) {
+            continue;
+        }
+        *stage.pass_mut(pass.name).expect("a patch pass") = true;
+        let _span = tracing::debug_span!("pass", name = pass.name).entered();
+        let start = Instant::now();
+        let after = crate::patch_stage(Cow::Borrowed(lua), &stage).into_owned();
+        let elapsed = start.elapsed();
+        pass_done(&before, &after, elapsed.saturating_sub(last));
+        last = elapsed;
+        before = after;
+    }
+}
+
+/// Trace each rewrite in `options` on `lua`.
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn patch_passes(_lua: &str, _options: &PatchOptions) {}
+
+/// Read the included file `path` with `read`.
+#[cfg(feature = "tracing")]
+pub(crate) fn include<E>(
+    path: &str,
+    read: impl FnOnce() -> Result<String, E>,
+) -> Result<String, E> {
+    let _span = tracing::debug_span!("include", path).entered();
+    let start = Instant::now();
+    let result = read();
+    let elapsed = start.elapsed();
+    match &result {
+        Ok(lua) => tracing::debug!(
+            bytes = lua.len(),
+            lines = lua.lines().count(),
+            ?elapsed,
+            "read"
+        ),
+        Err(_) => tracing::debug!(?elapsed, "not read"),
+    }
+    result
+}
+
+/// Read the included file `path` with `read`.
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn include<E>(
+    _path: &str,
+    read: impl FnOnce() -> Result<String, E>,
+) -> Result<String, E> {
+    read()
+}
+
+/// Note that `count` includes were resolved.
+#[cfg(feature = "tracing")]
+pub(crate) fn includes(count: usize) {
+    if count > 0 {
+        tracing::debug!(count, "resolved includes");
+    }
+}
+
+/// Note that `count` includes were resolved.
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn includes(_count: usize) {}
@@ -0,0 +1,102 @@
+//! Adapting Pico-8's trigonometry to Lua's math library.
+//!
+//! Pico-8's `sin` and `cos` take an angle in turns, from 0 to 1, rather than
+//! radians, and its `sin` is inverted so that angles run clockwise on a screen
+//! whose y axis points down. `atan2(dx, dy)` returns turns the same way.
+//! [patch_trig] calls helpers defined at the top of the code instead, which
+//! give what Pico-8 gives with Lua's `math`, so movement and rotation behave
+//! the same without shims.
+use std::borrow::Cow;
+
+use crate::{
+    api::api_calls,
+    cart::comment_header,
+    lexer::{Token, TokenKind, tokenize},
+    span::{Span, TextEdit, apply_edits},
+    symbols::{DEFAULT_PREFIX, Namer},
+};
+
+/// Pico-8's trigonometric functions.
+const FUNCTIONS: &[&str] = &["sin", "cos", "atan2"];
+
+/// Return the definition of the helper `name` for the Pico-8 function
+/// `function`.
+fn definition(function: &str, name: &str) -> String {
+    match function {
+        "sin" => format!("local function {name}(x) return -math.sin((x or 0) * 2 * math.pi) end\n"),
+        "cos" => format!("local function {name}(x) return math.cos((x or 0) * 2 * math.pi) end\n"),
+        _ => format!(
+            "local function {name}(dx, dy) return ((math.atan2 or math.atan)(-(dy or 0), dx or 0) / (2 * math.pi)) % 1 end\n"
+        ),
+    }
+}
+
+/// Rewrite calls to `sin`, `cos`, and `atan2` as calls to helpers that take
+/// and return turns like Pico-8's, naming them with [DEFAULT_PREFIX].
+///
+/// ```
+/// use pico8_to_lua::trig::patch_trig;
+/// assert_eq!(
+///     patch_trig("x += cos(a) * 2"),
+///     "local function __p8_cos(x) return math.cos((x or 0) * 2 * math.pi) end\nx += __p8_cos(a) * 2"
+/// );
+/// ```
+pub fn patch_trig<'h>(lua: impl Into<Cow<'h, str>>) -> Cow<'h, str> {
+    let lua = lua.into();
+    let mut namer = Namer::new(DEFAULT_PREFIX, &lua);
+    patch_trig_with(lua, &mut namer)
+}
+
+/// Rewrite calls to the trigonometric functions like [patch_trig], naming the
+/// helpers with `namer`.
+pub fn patch_trig_with<'h>(lua: impl Into<Cow<'h, str>>, namer: &mut Namer) -> Cow<'h, str> {
+    let lua = lua.into();
+    let tokens: Vec<Token> = tokenize(&lua)
+        .filter(|t| t.kind != TokenKind::Comment)
+        .collect();
+    let mut helpers: Vec<(&str, String)> = Vec::new();
+    let mut edits = Vec::new();
+    for (function, i) in api_calls(&tokens) {
+        if !FUNCTIONS.contains(&function) {
+            continue;
+        }
+        let name = match helpers.iter().find(|(f, _)| *f == function) {
+            Some((_, name)) => name.clone(),
+            None => {
+                let name = namer.name(function);
+                helpers.push((function, name.clone()));
+                name
+            }
+        };
+        edits.push(TextEdit::new(tokens[i].span, name));
+    }
+    if edits.is_empty() {
+        return lua;
+    }
+    // Define the helpers in the order of FUNCTIONS, whatever order they are
+    // called in.
+    helpers.sort_by_key(|(f, _)| FUNCTIONS.iter().position(|g| g == f));
+    let at = comment_header(&lua).len();
+    let definitions: String = helpers.iter().map(|(f, name)| definition(f, name)).collect();
+    edits.push(TextEdit::new(Span::new(at, at), definitions));
+    Cow::Owned(apply_edits(&lua, &edits))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_patch_trig() {
+        assert_eq!(
+            patch_trig("-- spin\na = atan2(dx, dy)\nx, y = cos(a), sin(a) + sin(b)"),
+            "-- spin\n\
+             local function __p8_sin(x) return -math.sin((x or 0) * 2 * math.pi) end\n\
+             local function __p8_cos(x) return math.cos((x or 0) * 2 * math.pi) end\n\
+             local function __p8_atan2(dx, dy) return ((math.atan2 or math.atan)(-(dy or 0), dx or 0) / (2 * math.pi)) % 1 end\n\
+             a = __p8_atan2(dx, dy)\nx, y = __p8_cos(a), __p8_sin(a) + __p8_sin(b)"
+        );
+        let lua = "function cos(a) return 1 end\nx = cos(a) s = \"sin(a)\" v.sin(a)";
+        assert_eq!(patch_trig(lua), lua);
+    }
+}
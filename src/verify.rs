@@ -0,0 +1,288 @@
+//! Checking converted Lua against a safe subset.
+//!
+//! Shared environments such as classrooms run converted carts side by side
+//! and want a guarantee that none of them can load code, reach the host, or
+//! change the behavior of shared globals. [verify] checks Lua against a
+//! [Policy] and reports every [Violation] with where it is.
+//!
+//! The check is on tokens, not on a full parse, so it errs on the side of
+//! caution: a local variable named like a forbidden global is reported too.
+use std::{error::Error, fmt};
+
+use crate::{
+    lexer::{Token, TokenKind, tokenize_lua},
+    span::Span,
+};
+
+/// What Lua is allowed to use.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Policy {
+    /// Globals that must not be used, e.g. "load" or "os". A dotted name like
+    /// "string.dump" forbids only that field.
+    pub forbidden_globals: Vec<String>,
+    /// Methods that must not be called, e.g. "rep" as in `s:rep(n)`.
+    pub forbidden_methods: Vec<String>,
+    /// Globals that are shared between carts and must not be given a
+    /// metatable or have theirs read, e.g. "_G" or "string".
+    pub shared_globals: Vec<String>,
+    /// The longest string literal allowed in bytes, without its quotes.
+    pub max_string_len: usize,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        let strings = |names: &[&str]| names.iter().map(|s| s.to_string()).collect();
+        Policy {
+            forbidden_globals: strings(&[
+                "load",
+                "loadstring",
+                "loadfile",
+                "dofile",
+                "require",
+                "module",
+                "package",
+                "debug",
+                "os",
+                "io",
+                "collectgarbage",
+                "setfenv",
+                "getfenv",
+                "rawset",
+                "rawget",
+                "rawequal",
+                "_ENV",
+                "string.dump",
+                "string.rep",
+            ]),
+            forbidden_methods: strings(&["dump", "rep"]),
+            shared_globals: strings(&["_G", "string", "table", "math", "coroutine"]),
+            max_string_len: 4096,
+        }
+    }
+}
+
+/// What rule some Lua breaks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ViolationKind {
+    /// A forbidden global is used.
+    ForbiddenGlobal(String),
+    /// A forbidden method is called.
+    ForbiddenMethod(String),
+    /// `setmetatable` or `getmetatable` is called on a shared global or on a
+    /// string, whose metatable all strings share.
+    SharedMetatable(String),
+    /// A string literal is longer than allowed.
+    LongString {
+        /// The length of the string in bytes.
+        len: usize,
+        /// The longest allowed.
+        max: usize,
+    },
+}
+
+impl fmt::Display for ViolationKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ViolationKind::ForbiddenGlobal(name) => write!(f, "{name:?} is not allowed"),
+            ViolationKind::ForbiddenMethod(name) => write!(f, "method {name:?} is not allowed"),
+            ViolationKind::SharedMetatable(name) => {
+                write!(f, "the metatable of shared {name} is not accessible")
+            }
+            ViolationKind::LongString { len, max } => {
+                write!(f, "string of {len} bytes is longer than {max}")
+            }
+        }
+    }
+}
+
+/// A place where Lua breaks a [Policy].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    /// What rule is broken.
+    pub kind: ViolationKind,
+    /// Where it is broken.
+    pub span: Span,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at {}", self.kind, self.span)
+    }
+}
+
+impl Error for Violation {}
+
+// The length of a string literal's contents.
+fn string_len(text: &str) -> usize {
+    if text.starts_with('"') || text.starts_with('\'') {
+        return text.len().saturating_sub(2);
+    }
+    // A long bracket like "[==[" and its closing "]==]".
+    let level = text[1..].find('[').unwrap_or(0) + 2;
+    text.len().saturating_sub(2 * level)
+}
+
+/// Check `lua` against `policy` and return the violations in order.
+///
+/// ```
+/// use pico8_to_lua::verify::{Policy, ViolationKind, verify};
+/// let violations = verify("f = load(s)\nsetmetatable(_G, {})", &Policy::default());
+/// assert_eq!(violations[0].kind, ViolationKind::ForbiddenGlobal("load".into()));
+/// assert_eq!(violations[1].kind, ViolationKind::SharedMetatable("_G".into()));
+/// ```
+pub fn verify(lua: &str, policy: &Policy) -> Vec<Violation> {
+    let code: Vec<Token> = tokenize_lua(lua)
+        .filter(|t| t.kind != TokenKind::Comment)
+        .collect();
+    let mut violations = Vec::new();
+    // The open brackets, to tell table keys from variables.
+    let mut brackets = Vec::new();
+    for (i, token) in code.iter().enumerate() {
+        let prev = i.checked_sub(1).map(|p| &code[p]);
+        let next = code.get(i + 1);
+        match token.kind {
+            TokenKind::Punct if matches!(token.text, "(" | "[" | "{") => brackets.push(token.text),
+            TokenKind::Punct if matches!(token.text, ")" | "]" | "}") => {
+                brackets.pop();
+            }
+            TokenKind::String => {
+                let len = string_len(token.text);
+                if len > policy.max_string_len {
+                    violations.push(Violation {
+                        kind: ViolationKind::LongString {
+                            len,
+                            max: policy.max_string_len,
+                        },
+                        span: token.span,
+                    });
+                }
+            }
+            TokenKind::Name => {
+                if prev.is_some_and(|p| p.is(":")) {
+                    if policy.forbidden_methods.iter().any(|m| m == token.text) {
+                        violations.push(Violation {
+                            kind: ViolationKind::ForbiddenMethod(token.text.to_string()),
+                            span: token.span,
+                        });
+                    }
+                    continue;
+                }
+                let is_field = prev.is_some_and(|p| p.is("."));
+                let is_key = brackets.last() == Some(&"{")
+                    && prev.is_some_and(|p| p.is("{") || p.is(",") || p.is(";"))
+                    && next.is_some_and(|n| n.is("="));
+                if is_field || is_key {
+                    continue;
+                }
+                // The name and, if a field follows, the dotted name.
+                let dotted = match (next, code.get(i + 2)) {
+                    (Some(dot), Some(field)) if dot.is(".") && field.kind == TokenKind::Name => {
+                        Some((
+                            format!("{}.{}", token.text, field.text),
+                            Span::new(token.span.start, field.span.end),
+                        ))
+                    }
+                    _ => None,
+                };
+                let forbidden = |name: &str| policy.forbidden_globals.iter().any(|g| g == name);
+                if forbidden(token.text) {
+                    violations.push(Violation {
+                        kind: ViolationKind::ForbiddenGlobal(token.text.to_string()),
+                        span: token.span,
+                    });
+                } else if let Some((name, span)) = dotted.filter(|(name, _)| forbidden(name)) {
+                    violations.push(Violation {
+                        kind: ViolationKind::ForbiddenGlobal(name),
+                        span,
+                    });
+                }
+                if matches!(token.text, "setmetatable" | "getmetatable")
+                    && next.is_some_and(|n| n.is("("))
+                {
+                    let shared = match code.get(i + 2) {
+                        Some(arg) if arg.kind == TokenKind::String => Some("string".to_string()),
+                        Some(arg)
+                            if arg.kind == TokenKind::Name
+                                && code.get(i + 3).is_some_and(|t| t.is(",") || t.is(")"))
+                                && policy.shared_globals.iter().any(|g| g == arg.text) =>
+                        {
+                            Some(arg.text.to_string())
+                        }
+                        _ => None,
+                    };
+                    if let Some(name) = shared {
+                        violations.push(Violation {
+                            kind: ViolationKind::SharedMetatable(name),
+                            span: token.span,
+                        });
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    violations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(lua: &str) -> Vec<ViolationKind> {
+        verify(lua, &Policy::default())
+            .into_iter()
+            .map(|v| v.kind)
+            .collect()
+    }
+
+    #[test]
+    fn test_allowed() {
+        assert_eq!(
+            kinds(
+                "t = {load = 1, os = 2}\nx = t.load + t.os\n-- load(s)\ns = \"io\"\nsetmetatable(t, mt)"
+            ),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn test_forbidden() {
+        use ViolationKind::*;
+        assert_eq!(
+            kinds("os.exit()\nf = string.dump(g)\nx = string.len(s)\ns = (\"x\"):rep(9)"),
+            vec![
+                ForbiddenGlobal("os".into()),
+                ForbiddenGlobal("string.dump".into()),
+                ForbiddenMethod("rep".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_shared_metatable() {
+        use ViolationKind::*;
+        assert_eq!(
+            kinds("getmetatable(\"\").__index = t\nsetmetatable(math, {})\nsetmetatable(_G.x, {})"),
+            vec![
+                SharedMetatable("string".into()),
+                SharedMetatable("math".into())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_long_string() {
+        let policy = Policy {
+            max_string_len: 3,
+            ..Policy::default()
+        };
+        let lua = "a = \"abc\"\nb = [==[abcd]==]";
+        let violations = verify(lua, &policy);
+        assert_eq!(violations.len(), 1);
+        assert_eq!(
+            violations[0].kind,
+            ViolationKind::LongString { len: 4, max: 3 }
+        );
+        assert_eq!(violations[0].span.line_col(lua), (2, 5));
+    }
+}
@@ -0,0 +1,314 @@
+//! The Lua version converted code needs.
+//!
+//! Converted Lua may use features that older Lua versions lack, such as the
+//! hex fractions that binary literals become or native bitwise operators.
+//! [requirements] finds each such feature, [minimum_version] gives the oldest
+//! Lua that runs the code, and [check_version] fails if that is newer than
+//! the Lua a port is meant for.
+use std::{borrow::Cow, error::Error, fmt};
+
+use crate::{
+    bitwise::LuaTarget,
+    lexer::{Token, TokenKind, tokenize, tokenize_lua},
+    span::Span,
+};
+
+/// A version of Lua, ordered from oldest to newest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum LuaVersion {
+    /// Lua 5.1.
+    Lua51,
+    /// Lua 5.2.
+    Lua52,
+    /// Lua 5.3 or later.
+    Lua53,
+}
+
+impl fmt::Display for LuaVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            LuaVersion::Lua51 => "Lua 5.1",
+            LuaVersion::Lua52 => "Lua 5.2",
+            LuaVersion::Lua53 => "Lua 5.3",
+        })
+    }
+}
+
+impl From<LuaTarget> for LuaVersion {
+    fn from(target: LuaTarget) -> Self {
+        match target {
+            LuaTarget::Lua51 | LuaTarget::Portable => LuaVersion::Lua51,
+            // LuaJIT has every feature of Lua 5.2 that a [Feature] names.
+            LuaTarget::Lua52 | LuaTarget::LuaJit => LuaVersion::Lua52,
+            LuaTarget::Lua53 | LuaTarget::Lua54 => LuaVersion::Lua53,
+        }
+    }
+}
+
+/// A feature that not every Lua version has.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Feature {
+    /// A `goto` statement or a `::label::`.
+    Goto,
+    /// A hex number with a fraction like `0x7fff.ffff`.
+    HexFloat,
+    /// A native bitwise operator like `&` or `<<`.
+    NativeBitwise,
+    /// The integer division operator `//`.
+    IntegerDivision,
+}
+
+impl Feature {
+    /// The oldest Lua version with the feature.
+    pub fn version(self) -> LuaVersion {
+        match self {
+            Feature::Goto | Feature::HexFloat => LuaVersion::Lua52,
+            Feature::NativeBitwise | Feature::IntegerDivision => LuaVersion::Lua53,
+        }
+    }
+}
+
+impl fmt::Display for Feature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Feature::Goto => "goto",
+            Feature::HexFloat => "hex float",
+            Feature::NativeBitwise => "native bitwise operator",
+            Feature::IntegerDivision => "integer division",
+        })
+    }
+}
+
+/// A place where Lua uses a [Feature].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Requirement {
+    /// The feature used.
+    pub feature: Feature,
+    /// Where it is used.
+    pub span: Span,
+}
+
+impl fmt::Display for Requirement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} needs {} at {}",
+            self.feature,
+            self.feature.version(),
+            self.span
+        )
+    }
+}
+
+/// Lua that needs a newer version than its target.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VersionError {
+    /// The version the Lua was meant for.
+    pub target: LuaVersion,
+    /// The uses of features newer than the target in order.
+    pub requirements: Vec<Requirement>,
+}
+
+impl fmt::Display for VersionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "code does not run on {}", self.target)?;
+        for requirement in &self.requirements {
+            write!(f, "; {requirement}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for VersionError {}
+
+/// Find the uses of version dependent features in converted `lua` in order.
+///
+/// `lua` is read as plain Lua, so `//` is integer division and not a comment
+/// as it is in Pico-8.
+pub fn requirements(lua: &str) -> Vec<Requirement> {
+    let tokens: Vec<Token> = tokenize_lua(lua).collect();
+    let mut found = Vec::new();
+    // Labels open and close with "::", which is one use.
+    let mut colons = 0;
+    for token in &tokens {
+        let feature = match token.kind {
+            TokenKind::Comment => continue,
+            TokenKind::Punct if token.text == "//" => Some(Feature::IntegerDivision),
+            TokenKind::Keyword if token.text == "goto" => Some(Feature::Goto),
+            TokenKind::Punct if token.text == "::" => {
+                colons += 1;
+                (colons % 2 == 1).then_some(Feature::Goto)
+            }
+            TokenKind::Number => {
+                let lower = token.text.to_ascii_lowercase();
+                (lower.starts_with("0x") && lower.contains('.')).then_some(Feature::HexFloat)
+            }
+            TokenKind::Punct if matches!(token.text, "&" | "|" | "<<" | ">>" | "~") => {
+                Some(Feature::NativeBitwise)
+            }
+            _ => None,
+        };
+        if let Some(feature) = feature {
+            found.push(Requirement {
+                feature,
+                span: token.span,
+            });
+        }
+    }
+    found
+}
+
+/// Return the value of a hex number with a fraction like `0xa.8`. Pico-8
+/// keeps 16 bits of fraction, so any digits after the fourth are dropped.
+fn hex_float_value(text: &str) -> Option<f64> {
+    let (int, frac) = text.get(2..)?.split_once('.')?;
+    if !frac.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let frac = &frac[..frac.len().min(4)];
+    let mut value = if int.is_empty() {
+        0.0
+    } else {
+        u64::from_str_radix(int, 16).ok()? as f64
+    };
+    let mut scale = 1.0;
+    for c in frac.chars() {
+        scale /= 16.0;
+        value += c.to_digit(16)? as f64 * scale;
+    }
+    Some(value)
+}
+
+/// Rewrite the hex numbers with fractions in converted `lua`, like the
+/// `0xa.8` that binary literals become, as the exact decimals of their Pico-8
+/// values for Lua 5.1, which lacks them.
+///
+/// ```
+/// use pico8_to_lua::version::patch_hex_floats;
+/// assert_eq!(patch_hex_floats("x = 0xa.8 + 0x10"), "x = 10.5 + 0x10");
+/// ```
+pub fn patch_hex_floats<'h>(lua: impl Into<Cow<'h, str>>) -> Cow<'h, str> {
+    let lua = lua.into();
+    let mut out = String::new();
+    let mut last = 0;
+    for token in tokenize(&lua).filter(|t| t.kind == TokenKind::Number) {
+        let lower = token.text.to_ascii_lowercase();
+        if !lower.starts_with("0x") {
+            continue;
+        }
+        if let Some(value) = hex_float_value(&lower) {
+            out.push_str(&lua[last..token.span.start]);
+            out.push_str(&value.to_string());
+            last = token.span.end;
+        }
+    }
+    if last == 0 {
+        return lua;
+    }
+    out.push_str(&lua[last..]);
+    Cow::Owned(out)
+}
+
+/// Return the oldest Lua version that runs converted `lua`.
+///
+/// ```
+/// use pico8_to_lua::version::{LuaVersion, minimum_version};
+/// assert_eq!(minimum_version("x = 1"), LuaVersion::Lua51);
+/// assert_eq!(minimum_version("x = 0xa.8"), LuaVersion::Lua52);
+/// assert_eq!(minimum_version("x = a & b"), LuaVersion::Lua53);
+/// ```
+pub fn minimum_version(lua: &str) -> LuaVersion {
+    requirements(lua)
+        .iter()
+        .map(|r| r.feature.version())
+        .max()
+        .unwrap_or(LuaVersion::Lua51)
+}
+
+/// Return the oldest Lua version that runs converted `lua`, or an error with
+/// the features that `target` lacks.
+pub fn check_version(lua: &str, target: LuaVersion) -> Result<LuaVersion, VersionError> {
+    let requirements = requirements(lua);
+    let newer: Vec<Requirement> = requirements
+        .iter()
+        .filter(|r| r.feature.version() > target)
+        .copied()
+        .collect();
+    if !newer.is_empty() {
+        return Err(VersionError {
+            target,
+            requirements: newer,
+        });
+    }
+    Ok(requirements
+        .iter()
+        .map(|r| r.feature.version())
+        .max()
+        .unwrap_or(LuaVersion::Lua51))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn features(lua: &str) -> Vec<Feature> {
+        requirements(lua).into_iter().map(|r| r.feature).collect()
+    }
+
+    #[test]
+    fn test_features() {
+        use Feature::*;
+        assert_eq!(
+            features("x = 0x10 + 1.5 -- 0x1.8\nif a ~= b then end"),
+            vec![]
+        );
+        assert_eq!(
+            features("::top:: x = 0x1.8\ngoto top"),
+            vec![Goto, HexFloat, Goto]
+        );
+        assert_eq!(
+            features("x = ~a | b << 2\ny = a // 0x1.8"),
+            vec![
+                NativeBitwise,
+                NativeBitwise,
+                NativeBitwise,
+                IntegerDivision,
+                HexFloat
+            ]
+        );
+    }
+
+    #[test]
+    fn test_integer_division_span() {
+        let lua = "y = a // b & c";
+        let spans: Vec<Span> = requirements(lua).into_iter().map(|r| r.span).collect();
+        assert_eq!(spans, vec![Span::new(6, 8), Span::new(11, 12)]);
+    }
+
+    #[test]
+    fn test_patch_hex_floats() {
+        assert_eq!(
+            patch_hex_floats("x = 0x.4 + 0X1.0001 -- 0x1.8\ns = \"0x1.8\""),
+            "x = 0.25 + 1.0000152587890625 -- 0x1.8\ns = \"0x1.8\""
+        );
+        assert_eq!(patch_hex_floats("x = 0x1.00008"), "x = 1");
+        assert!(matches!(patch_hex_floats("x = 0x10"), Cow::Borrowed(_)));
+        assert!(LuaTarget::LuaJit.supports(Feature::HexFloat));
+        assert!(!LuaTarget::Portable.supports(Feature::HexFloat));
+    }
+
+    #[test]
+    fn test_check_version() {
+        assert_eq!(
+            check_version("x = 0xa.8", LuaVersion::Lua52),
+            Ok(LuaVersion::Lua52)
+        );
+        let error = check_version("x = 0xa.8 // 2", LuaVersion::Lua51).unwrap_err();
+        assert_eq!(error.requirements.len(), 2);
+        assert_eq!(
+            error.to_string(),
+            "code does not run on Lua 5.1; hex float needs Lua 5.2 at 4..9; \
+             integer division needs Lua 5.3 at 10..12"
+        );
+    }
+}
@@ -0,0 +1,71 @@
+//! WebAssembly bindings, with the `wasm` feature.
+//!
+//! Build with `cargo rustc --lib --release --target wasm32-unknown-unknown
+//! --features wasm --crate-type cdylib` and pass the ".wasm" file through
+//! `wasm-bindgen` to call the converter from JavaScript in a browser-based
+//! tool without a server:
+//!
+//! ```js
+//! import { patchLua, convertCart } from "./pkg/pico8_to_lua.js";
+//! patchLua("x += 1"); // "x = x + (1)"
+//! const { cart, lua, title } = convertCart(new Uint8Array(bytes));
+//! const { sections } = parseCart(new Uint8Array(bytes));
+//! const { report, diagnostics } = patchReport("x += 1");
+//! ```
+use js_sys::{Object, Reflect};
+use wasm_bindgen::prelude::*;
+
+use crate::{Cart, Options, PatchOptions, batch::Converter, diagnostics::diagnose};
+
+/// Set the property `key` of a plain object.
+fn set(object: &Object, key: &str, value: impl Into<JsValue>) {
+    Reflect::set(object, &key.into(), &value.into()).expect("a plain object takes any property");
+}
+
+/// Convert a serializable value to a plain object.
+fn to_value(value: &impl serde::Serialize) -> Result<JsValue, JsError> {
+    serde_wasm_bindgen::to_value(value).map_err(|e| JsError::new(&e.to_string()))
+}
+
+/// Convert Pico-8 Lua to plain Lua.
+#[wasm_bindgen(js_name = patchLua)]
+pub fn patch_lua(code: &str) -> String {
+    crate::patch_lua(code).into_owned()
+}
+
+/// Convert the bytes of a ".p8" cart and return an object with the converted
+/// `cart` and its `lua`, and the `title`, `author`, and `tokens` of the
+/// original, which are `undefined` if it has none.
+#[wasm_bindgen(js_name = convertCart)]
+pub fn convert_cart(bytes: &[u8]) -> Result<JsValue, JsError> {
+    let cart = Cart::from_bytes(bytes)?;
+    let converted = Converter::new(Options::default()).convert(&cart.to_string())?;
+    let lua = Cart::parse(&converted)?.lua().unwrap_or("").to_string();
+    let object = Object::new();
+    set(&object, "cart", converted);
+    set(&object, "lua", lua);
+    set(&object, "title", cart.title().map(String::from));
+    set(&object, "author", cart.author().map(String::from));
+    set(&object, "tokens", cart.tokens() as u32);
+    Ok(object.into())
+}
+
+/// Parse the bytes of a ".p8" cart into an object with its `header` and
+/// `sections`, each with its `name` and `body`.
+#[wasm_bindgen(js_name = parseCart)]
+pub fn parse_cart(bytes: &[u8]) -> Result<JsValue, JsError> {
+    to_value(&Cart::from_bytes(bytes)?)
+}
+
+/// Convert Pico-8 Lua and return an object with the converted `lua`, the
+/// `report` of what each pass changed, and the `diagnostics` of what was left
+/// alone.
+#[wasm_bindgen(js_name = patchReport)]
+pub fn patch_report(code: &str) -> Result<JsValue, JsError> {
+    let (lua, report) = crate::patch_lua_with_report(code, &PatchOptions::default());
+    let object = Object::new();
+    set(&object, "lua", lua.into_owned());
+    set(&object, "report", to_value(&report)?);
+    set(&object, "diagnostics", to_value(&diagnose(code))?);
+    Ok(object.into())
+}